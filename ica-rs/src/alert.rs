@@ -0,0 +1,263 @@
+//! 后端健康状况告警。
+//!
+//! 某个后端掉线或出错时, 通过其他还健康的后端 (对方的提醒房间, 或者同平台的其他实例)、
+//! webhook 和日志文件通知管理员, 同一实例的同一类告警在冷却时间内只发一次。
+
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::{Level, event};
+
+use crate::MainStatus;
+use crate::config::AlertConfig;
+use crate::data_struct::Platform;
+
+/// 告警级别
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlertLevel {
+    /// 恢复之类的通知
+    Info,
+    /// 出错了, 但还能用
+    Warning,
+    /// 后端已经不可用
+    Critical,
+}
+
+impl AlertLevel {
+    /// 返回 webhook 和日志文件里使用的名称。
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertLevel::Info => "info",
+            AlertLevel::Warning => "warning",
+            AlertLevel::Critical => "critical",
+        }
+    }
+
+    /// 返回发给人看的名称。
+    pub fn label(&self) -> &'static str {
+        match self {
+            AlertLevel::Info => "提示",
+            AlertLevel::Warning => "警告",
+            AlertLevel::Critical => "严重",
+        }
+    }
+}
+
+/// 一条告警
+#[derive(Debug, Clone)]
+pub struct Alert {
+    /// 出问题的后端
+    pub source: Platform,
+    /// 出问题的实例, 不会再通过它转发
+    pub instance: Option<String>,
+    /// 去重用的键, 比如 `ica.offline`
+    pub key: String,
+    /// 级别
+    pub level: AlertLevel,
+    /// 内容
+    pub message: String,
+}
+
+impl Alert {
+    /// 创建一条告警, 实例取当前实例。
+    pub fn new(
+        source: Platform,
+        key: impl Into<String>,
+        level: AlertLevel,
+        message: impl Into<String>,
+    ) -> Self {
+        Self {
+            source,
+            instance: crate::instance::current(),
+            key: key.into(),
+            level,
+            message: message.into(),
+        }
+    }
+
+    /// 返回 `平台(实例)` 形式的来源。
+    pub fn origin(&self) -> String {
+        match &self.instance {
+            Some(instance) => format!("{}({})", self.source, instance),
+            None => self.source.to_string(),
+        }
+    }
+
+    /// 冷却用的键, 不同实例的同一类告警互不影响。
+    pub fn cooldown_key(&self) -> String {
+        match &self.instance {
+            Some(instance) => format!("{}@{}", self.key, instance),
+            None => self.key.clone(),
+        }
+    }
+
+    /// 转发到聊天后端时的文本。
+    pub fn text(&self) -> String {
+        format!("[{}] {}: {}", self.level.label(), self.origin(), self.message)
+    }
+
+    /// 转发到 `platform` 时要跳过的实例, 也就是出问题的那个。
+    fn skipped_on(&self, platform: Platform) -> Option<&str> {
+        if self.source == platform {
+            self.instance.as_deref()
+        } else {
+            None
+        }
+    }
+}
+
+/// 按键记录上次发送时间的告警冷却
+#[derive(Debug, Default)]
+pub struct Cooldown {
+    last: HashMap<String, Instant>,
+}
+
+impl Cooldown {
+    /// 判断这个键现在能不能发送, 能发送时会记下发送时间。
+    pub fn check(&mut self, key: &str, now: Instant, cooldown: Duration) -> bool {
+        match self.last.get(key) {
+            Some(last) if now.saturating_duration_since(*last) < cooldown => false,
+            _ => {
+                self.last.insert(key.to_string(), now);
+                true
+            }
+        }
+    }
+
+    /// 清掉某个键的冷却, 返回之前是否发过。
+    pub fn reset(&mut self, key: &str) -> bool { self.last.remove(key).is_some() }
+}
+
+static COOLDOWN: LazyLock<Mutex<Cooldown>> = LazyLock::new(|| Mutex::new(Cooldown::default()));
+
+/// 返回告警配置。
+fn alert_config() -> AlertConfig { MainStatus::global_config().alert.clone() }
+
+/// 发出一条告警
+///
+/// 总会写进日志, 其余的投递方式受开关和冷却控制
+pub async fn raise(alert: Alert) {
+    match alert.level {
+        AlertLevel::Info => event!(Level::INFO, "{}", alert.text()),
+        AlertLevel::Warning => event!(Level::WARN, "{}", alert.text()),
+        AlertLevel::Critical => event!(Level::ERROR, "{}", alert.text()),
+    }
+    let config = alert_config();
+    if !config.enable {
+        return;
+    }
+    let cooldown = Duration::from_secs(config.cooldown);
+    if !COOLDOWN.lock().unwrap().check(&alert.cooldown_key(), Instant::now(), cooldown) {
+        event!(Level::DEBUG, "告警 {} 还在冷却中, 不再重复发送", alert.cooldown_key());
+        return;
+    }
+    deliver(&alert, &config).await;
+}
+
+/// 解除当前实例的一类告警
+///
+/// 之前发过这类告警时会发一条恢复通知, 并重置冷却
+pub async fn resolve(source: Platform, key: &str, message: impl Into<String>) {
+    let config = alert_config();
+    let alert = Alert::new(source, key, AlertLevel::Info, message);
+    if !config.enable || !COOLDOWN.lock().unwrap().reset(&alert.cooldown_key()) {
+        return;
+    }
+    event!(Level::INFO, "{}", alert.text());
+    deliver(&alert, &config).await;
+}
+
+/// 按配置投递告警。
+async fn deliver(alert: &Alert, config: &AlertConfig) {
+    if let Some(path) = &config.log_file {
+        append_log_file(path, alert);
+    }
+    if let Some(url) = &config.webhook {
+        post_webhook(url, alert).await;
+    }
+    if config.forward && !forward(alert).await {
+        event!(Level::WARN, "没有其他可用的后端来转发告警 {}", alert.key);
+    }
+}
+
+/// 追加写入告警日志文件。
+fn append_log_file(path: &str, alert: &Alert) {
+    let line = format!(
+        "{} [{}] {} {}: {}\n",
+        chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+        alert.level.as_str(),
+        alert.origin(),
+        alert.key,
+        alert.message
+    );
+    let result = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(line.as_bytes()));
+    if let Err(e) = result {
+        event!(Level::ERROR, "写入告警日志 {} 失败: {}", path, e);
+    }
+}
+
+/// 把告警 POST 到 webhook。
+async fn post_webhook(url: &str, alert: &Alert) {
+    let body = serde_json::json!({
+        "source": alert.source.as_str(),
+        "instance": alert.instance,
+        "key": alert.key,
+        "level": alert.level.as_str(),
+        "message": alert.message,
+        "time": chrono::Local::now().to_rfc3339(),
+    });
+    match reqwest::Client::new().post(url).json(&body).send().await {
+        Ok(resp) if !resp.status().is_success() => {
+            event!(Level::WARN, "告警 webhook 返回了 {}", resp.status());
+        }
+        Ok(_) => (),
+        Err(e) => event!(Level::WARN, "告警 webhook 发送失败: {}", e),
+    }
+}
+
+/// 通过出问题的实例以外的后端转发, 返回是否转发成功。
+async fn forward(alert: &Alert) -> bool {
+    let text = alert.text();
+    #[cfg(feature = "tailchat")]
+    {
+        let skipped = alert.skipped_on(Platform::Tailchat);
+        if crate::tailchat::client::send_notice(&text, skipped).await {
+            return true;
+        }
+    }
+    #[cfg(feature = "ica")]
+    {
+        if crate::ica::client::send_notice(&text, alert.skipped_on(Platform::Ica)).await {
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cooldown() {
+        let mut cooldown = Cooldown::default();
+        let start = Instant::now();
+        let window = Duration::from_secs(60);
+        assert!(cooldown.check("ica.offline", start, window));
+        assert!(!cooldown.check("ica.offline", start + Duration::from_secs(30), window));
+        // 不同的键互不影响
+        assert!(cooldown.check("ica.fatal", start + Duration::from_secs(30), window));
+        assert!(cooldown.check("ica.offline", start + Duration::from_secs(61), window));
+
+        assert!(cooldown.reset("ica.offline"));
+        assert!(!cooldown.reset("ica.offline"));
+        assert!(cooldown.check("ica.offline", start + Duration::from_secs(62), window));
+    }
+}
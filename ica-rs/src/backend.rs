@@ -0,0 +1,98 @@
+//! 后端实例的启动和停止。
+//!
+//! 每个实例运行在自己的任务里, 按 (平台, 实例 id) 记下停止信号和任务句柄,
+//! 退出时一起停止, 配置重载时也可以只重连其中几个。
+
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+use colored::Colorize;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::{Level, event};
+
+use crate::config::{IcaConfig, TailchatConfig};
+use crate::data_struct::Platform;
+use crate::{MainStatus, alert, instance};
+
+/// 等待实例退出的时间, 超时后直接取消
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 一个正在运行的实例
+struct Running {
+    stop: oneshot::Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+/// 正在运行的实例
+static RUNNING: LazyLock<Mutex<HashMap<(Platform, String), Running>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// 启动一个 Icalingua 实例。
+pub fn start_ica(config: IcaConfig) {
+    event!(Level::INFO, "{}", format!("开始启动 ICA 实例 {}", config.id).green());
+    MainStatus::init_ica_status(&config.id, true);
+    let (stop, stop_reciver) = oneshot::channel::<()>();
+    let id = config.id.clone();
+    let handle = tokio::spawn(instance::scope(id.clone(), async move {
+        if let Err(e) = crate::ica::start_ica(&config, stop_reciver).await {
+            alert::raise(alert::Alert::new(
+                Platform::Ica,
+                "ica.connect",
+                alert::AlertLevel::Critical,
+                format!("ica 客户端异常退出: {e}"),
+            ))
+            .await;
+        }
+    }));
+    RUNNING.lock().unwrap().insert((Platform::Ica, id), Running { stop, handle });
+}
+
+/// 启动一个 Tailchat 实例。
+pub fn start_tailchat(config: TailchatConfig) {
+    event!(Level::INFO, "{}", format!("开始启动 tailchat 实例 {}", config.id).green());
+    let (stop, stop_reciver) = oneshot::channel::<()>();
+    let id = config.id.clone();
+    let handle = tokio::spawn(instance::scope(id.clone(), async move {
+        if let Err(e) = crate::tailchat::start_tailchat(config, stop_reciver).await {
+            alert::raise(alert::Alert::new(
+                Platform::Tailchat,
+                "tailchat.connect",
+                alert::AlertLevel::Critical,
+                format!("tailchat 客户端异常退出: {e}"),
+            ))
+            .await;
+        }
+    }));
+    RUNNING
+        .lock()
+        .unwrap()
+        .insert((Platform::Tailchat, id), Running { stop, handle });
+}
+
+/// 停止一个实例并等待它退出。
+pub async fn stop(platform: Platform, id: &str) {
+    let Some(mut running) = RUNNING.lock().unwrap().remove(&(platform, id.to_string())) else {
+        return;
+    };
+    event!(Level::INFO, "正在停止 {} 实例 {}", platform.as_str(), id);
+    running.stop.send(()).ok();
+    if tokio::time::timeout(STOP_TIMEOUT, &mut running.handle).await.is_err() {
+        event!(
+            Level::WARN,
+            "{} 实例 {} 在 {:?} 内没有退出, 直接取消",
+            platform.as_str(),
+            id,
+            STOP_TIMEOUT
+        );
+        running.handle.abort();
+    }
+}
+
+/// 通知所有实例停止, 不等待退出。
+pub fn stop_all() {
+    for (_, running) in RUNNING.lock().unwrap().drain() {
+        running.stop.send(()).ok();
+    }
+}
@@ -0,0 +1,444 @@
+//! 命令行参数解析, 以及不需要启动 bot 的子命令。
+
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, OnceLock};
+
+use colored::Colorize;
+use regex::Regex;
+use tracing::Level;
+
+use crate::MainStatus;
+use crate::config::{PyConfig, check};
+use crate::error::CliError;
+use crate::py::storage::{CONFIG_FILE_NAME, PluginOverrides, PluginStatus};
+
+/// 默认的配置文件路径
+pub const DEFAULT_CONFIG_PATH: &str = "./config.toml";
+
+const HELP_MSG: &str = r#"{VERSION}
+用法: ica-rs [子命令] [选项]
+
+子命令:
+    run
+        启动 bot (默认)
+    check-config
+        只检查配置文件, 有错误时以非 0 退出
+    gen-key
+        生成一对 Icalingua 使用的 ed25519 密钥
+    plugins [list]
+        查看插件的启用状态
+    plugins enable <plugin-id>
+    plugins disable <plugin-id>
+        全局启用/禁用插件 (bot 运行时请用 /bot-enable /bot-disable)
+    new-plugin <plugin-id>
+        在插件目录里生成一个插件模板
+    help
+        显示帮助信息
+
+选项:
+    -c, --config <path>
+        指定配置文件路径, 默认为 ./config.toml
+    --venv <path>
+        指定 python 虚拟环境路径, 旧写法 -env 仍然可用
+    --log-level <level>
+        日志等级 error/warn/info/debug/trace, 默认为 info
+    -d / -t
+        等同于 --log-level debug / --log-level trace
+    -h, --help
+        显示帮助信息
+"#;
+
+/// `new-plugin` 生成的插件模板
+const PLUGIN_TEMPLATE: &str = r#"from __future__ import annotations
+
+from shenbot_api import PluginManifest
+
+PLUGIN_MANIFEST = PluginManifest(
+    plugin_id="{id}",
+    name="{id}",
+    version="0.1.0",
+    description="",
+    authors=[],
+)
+
+
+def on_load() -> None:
+    pass
+
+
+def on_ica_message(msg, client) -> None:
+    if msg.is_from_self:
+        return
+    if msg.content == "/{id}":
+        client.send_message(msg.reply_with("hello from {id}"))
+
+
+def on_tailchat_message(msg, client) -> None:
+    if msg.is_from_self:
+        return
+    if msg.content == "/{id}":
+        client.send_message(msg.reply_with("hello from {id}"))
+"#;
+
+/// 子命令
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// 启动 bot
+    Run,
+    /// 只检查配置文件
+    CheckConfig,
+    /// 生成 Icalingua 使用的密钥
+    GenKey,
+    /// 查看插件的启用状态
+    PluginList,
+    /// 全局启用/禁用插件
+    /// 插件 id, 是否启用
+    PluginSet(String, bool),
+    /// 生成插件模板
+    NewPlugin(String),
+    /// 显示帮助信息
+    Help,
+}
+
+/// 解析后的命令行参数
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cli {
+    pub command: Command,
+    /// 配置文件路径
+    pub config: String,
+    /// python 虚拟环境路径
+    pub venv: Option<String>,
+    /// 日志等级
+    pub log_level: Level,
+}
+
+impl Default for Cli {
+    /// 不带参数时启动 bot。
+    fn default() -> Self {
+        Self {
+            command: Command::Run,
+            config: DEFAULT_CONFIG_PATH.to_string(),
+            venv: None,
+            log_level: Level::INFO,
+        }
+    }
+}
+
+static CLI: OnceLock<Cli> = OnceLock::new();
+
+/// 记下解析后的命令行参数。
+pub fn init(cli: Cli) { CLI.get_or_init(|| cli); }
+
+/// 返回命令行参数, 没有初始化时返回默认值。
+pub fn args() -> &'static Cli { CLI.get_or_init(Cli::default) }
+
+/// 返回命令行帮助信息。
+pub fn help_msg() -> String { HELP_MSG.replace("{VERSION}", &crate::version_str()) }
+
+/// 取出选项的值, `--name=value` 的值已经在 `inline` 里。
+fn option_value(
+    name: &str,
+    inline: Option<&str>,
+    args: &mut impl Iterator<Item = String>,
+) -> Result<String, CliError> {
+    match inline {
+        Some(value) => Ok(value.to_string()),
+        None => args.next().ok_or_else(|| CliError::MissingValue(name.to_string())),
+    }
+}
+
+/// 解析命令行参数, 不包括程序路径。
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Cli, CliError> {
+    let mut cli = Cli::default();
+    let mut help = false;
+    let mut positional = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) if arg.starts_with("--") => (name, Some(value)),
+            _ => (arg.as_str(), None),
+        };
+        match name {
+            "-c" | "--config" => cli.config = option_value(name, inline, &mut args)?,
+            "--venv" | "-env" => cli.venv = Some(option_value(name, inline, &mut args)?),
+            "--log-level" => {
+                let level = option_value(name, inline, &mut args)?;
+                cli.log_level = level.parse().map_err(|_| CliError::InvalidLogLevel(level))?;
+            }
+            "-d" => cli.log_level = Level::DEBUG,
+            "-t" => cli.log_level = Level::TRACE,
+            "-h" | "--help" => help = true,
+            _ if name.starts_with('-') && name.len() > 1 => {
+                return Err(CliError::UnknownOption(name.to_string()));
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    cli.command = match positional.next().as_deref() {
+        None | Some("run") => Command::Run,
+        Some("check-config") => Command::CheckConfig,
+        Some("gen-key") => Command::GenKey,
+        Some("help") => Command::Help,
+        Some("plugins") => match positional.next().as_deref() {
+            None | Some("list") => Command::PluginList,
+            Some(action @ ("enable" | "disable")) => {
+                let id = positional.next().ok_or_else(|| {
+                    CliError::MissingArgument(
+                        format!("plugins {action}"),
+                        "<plugin-id>".to_string(),
+                    )
+                })?;
+                Command::PluginSet(id, action == "enable")
+            }
+            Some(other) => return Err(CliError::UnknownCommand(format!("plugins {other}"))),
+        },
+        Some("new-plugin") => Command::NewPlugin(positional.next().ok_or_else(|| {
+            CliError::MissingArgument("new-plugin".to_string(), "<plugin-id>".to_string())
+        })?),
+        Some(other) => return Err(CliError::UnknownCommand(other.to_string())),
+    };
+    if let Some(extra) = positional.next() {
+        return Err(CliError::UnexpectedArgument(extra));
+    }
+    if help {
+        cli.command = Command::Help;
+    }
+    Ok(cli)
+}
+
+/// 生成一对 Icalingua 使用的密钥并打印出来。
+#[cfg(feature = "ica")]
+pub fn gen_key() -> bool {
+    match crate::ica::client::generate_keypair() {
+        Ok((private_key, public_key)) => {
+            println!("私钥 (填到 config.toml 的 [ica] private_key):");
+            println!("{}", private_key.green());
+            println!("公钥 (填到 Icalingua bridge 配置的 pubKey):");
+            println!("{}", public_key.green());
+            true
+        }
+        Err(e) => {
+            eprintln!("{}", format!("读取系统随机数失败: {e}").red());
+            false
+        }
+    }
+}
+
+/// 没有启用 ica 时不能生成密钥。
+#[cfg(not(feature = "ica"))]
+pub fn gen_key() -> bool {
+    eprintln!("{}", "编译时没有启用 ica feature, 不能生成密钥".red());
+    false
+}
+
+/// 读取配置文件里的 `[py]`, 并设为全局配置。
+fn load_py_config(config_path: &str) -> Option<PyConfig> {
+    let config = match check::check_file(config_path) {
+        Ok((config, _)) => config,
+        Err(issues) => {
+            eprintln!("{}", format!("配置文件 {config_path} 有问题:").red());
+            for issue in issues {
+                eprintln!("{issue}");
+            }
+            return None;
+        }
+    };
+    let Some(py) = config.py.clone() else {
+        eprintln!("{}", format!("配置文件 {config_path} 里没有 [py]").red());
+        return None;
+    };
+    MainStatus::update_static_config(config);
+    Some(py)
+}
+
+/// 不运行插件, 从源码里找出 manifest 的 `plugin_id`。
+fn scan_plugin_id(source: &str) -> Option<String> {
+    static PLUGIN_ID: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r#"(?:plugin_id\s*=\s*|PluginManifest\(\s*)["']([^"']+)["']"#).unwrap()
+    });
+    PLUGIN_ID.captures(source).map(|captures| captures[1].to_string())
+}
+
+/// 返回插件目录里的 (插件 id, 文件名)。
+fn scan_plugins(plugin_path: &str) -> Vec<(Option<String>, String)> {
+    let Ok(dir) = Path::new(plugin_path).read_dir() else {
+        return Vec::new();
+    };
+    let mut plugins: Vec<_> = dir
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "py"))
+        .map(|path| {
+            let id = std::fs::read_to_string(&path).ok().and_then(|source| scan_plugin_id(&source));
+            let file = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+            (id, file)
+        })
+        .collect();
+    plugins.sort();
+    plugins
+}
+
+/// 打印插件的启用状态。
+pub fn list_plugins(config_path: &str) -> bool {
+    let Some(py) = load_py_config(config_path) else {
+        return false;
+    };
+    let status = PluginStatus::load_from_file();
+    let overrides = PluginOverrides {
+        platform: status.platform.clone(),
+        room: status.room.clone(),
+    };
+    let files = scan_plugins(&py.plugin_path);
+    println!("插件目录: {}", py.plugin_path);
+    println!("状态文件: {}", PathBuf::from(&py.config_path).join(CONFIG_FILE_NAME).display());
+
+    let mut ids: Vec<&String> = status.plugins.keys().collect();
+    ids.extend(files.iter().filter_map(|(id, _)| id.as_ref()));
+    ids.sort();
+    ids.dedup();
+    for id in ids {
+        let state = match status.plugins.get(id) {
+            Some(true) => "启用".green(),
+            Some(false) => "禁用".red(),
+            None => "新插件".cyan(),
+        };
+        let file = files
+            .iter()
+            .find(|(file_id, _)| file_id.as_ref() == Some(id))
+            .map_or_else(|| "文件不存在".yellow().to_string(), |(_, file)| file.clone());
+        let count = overrides.count(id);
+        if count > 0 {
+            println!("  [{state}] {id} ({file}), 另有 {count} 个平台/房间的单独设置");
+        } else {
+            println!("  [{state}] {id} ({file})");
+        }
+    }
+    for (_, file) in files.iter().filter(|(id, _)| id.is_none()) {
+        println!("  [{}] {file}", "没找到 plugin_id".yellow());
+    }
+    true
+}
+
+/// 全局启用/禁用一个插件。
+pub fn set_plugin(config_path: &str, plugin_id: &str, enable: bool) -> bool {
+    let Some(py) = load_py_config(config_path) else {
+        return false;
+    };
+    let mut status = PluginStatus::load_from_file();
+    let known = status.plugins.contains_key(plugin_id)
+        || scan_plugins(&py.plugin_path)
+            .iter()
+            .any(|(id, _)| id.as_deref() == Some(plugin_id));
+    if !known {
+        eprintln!("{}", format!("没有找到插件 {plugin_id}").red());
+        return false;
+    }
+    status.plugins.insert(plugin_id.to_string(), enable);
+    status.save_to_file();
+    println!(
+        "已全局{}插件 {plugin_id}, 平台/房间上的单独设置不变",
+        if enable { "启用" } else { "禁用" }
+    );
+    println!(
+        "{}",
+        "bot 正在运行时会在下次启动后生效, 想立即生效请用 /bot-enable /bot-disable".yellow()
+    );
+    true
+}
+
+/// 判断插件 id 能不能用作文件名。
+fn valid_plugin_id(plugin_id: &str) -> bool {
+    !plugin_id.is_empty()
+        && plugin_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// 在插件目录里生成一个插件模板。
+pub fn new_plugin(config_path: &str, plugin_id: &str) -> bool {
+    if !valid_plugin_id(plugin_id) {
+        eprintln!("{}", format!("插件 id {plugin_id} 只能包含字母、数字、_ 和 -").red());
+        return false;
+    }
+    let Some(py) = load_py_config(config_path) else {
+        return false;
+    };
+    let path = PathBuf::from(&py.plugin_path).join(format!("{plugin_id}.py"));
+    if path.exists() {
+        eprintln!("{}", format!("{} 已经存在", path.display()).red());
+        return false;
+    }
+    if let Err(e) = std::fs::write(&path, PLUGIN_TEMPLATE.replace("{id}", plugin_id)) {
+        eprintln!("{}", format!("写入 {} 失败: {e}", path.display()).red());
+        return false;
+    }
+    println!("已生成 {}", path.display().to_string().green());
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &str) -> Result<Cli, CliError> {
+        parse(args.split_whitespace().map(str::to_string))
+    }
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(parse_args("").unwrap(), Cli::default());
+        assert_eq!(parse_args("check-config").unwrap().command, Command::CheckConfig);
+        assert_eq!(parse_args("plugins").unwrap().command, Command::PluginList);
+        assert_eq!(
+            parse_args("plugins disable foo").unwrap().command,
+            Command::PluginSet("foo".to_string(), false)
+        );
+        assert_eq!(
+            parse_args("new-plugin foo").unwrap().command,
+            Command::NewPlugin("foo".to_string())
+        );
+        assert_eq!(parse_args("run -h").unwrap().command, Command::Help);
+
+        assert_eq!(parse_args("stop"), Err(CliError::UnknownCommand("stop".to_string())));
+        assert_eq!(
+            parse_args("plugins enable"),
+            Err(CliError::MissingArgument(
+                "plugins enable".to_string(),
+                "<plugin-id>".to_string()
+            ))
+        );
+        assert_eq!(parse_args("run foo"), Err(CliError::UnexpectedArgument("foo".to_string())));
+    }
+
+    #[test]
+    fn parse_options() {
+        let cli = parse_args("--config=a.toml --venv ./venv --log-level warn").unwrap();
+        assert_eq!(cli.config, "a.toml");
+        assert_eq!(cli.venv.as_deref(), Some("./venv"));
+        assert_eq!(cli.log_level, Level::WARN);
+
+        // 旧的写法
+        let cli = parse_args("-c b.toml -env ./venv -d").unwrap();
+        assert_eq!(cli.config, "b.toml");
+        assert_eq!(cli.venv.as_deref(), Some("./venv"));
+        assert_eq!(cli.log_level, Level::DEBUG);
+
+        assert_eq!(parse_args("-c"), Err(CliError::MissingValue("-c".to_string())));
+        assert_eq!(
+            parse_args("--log-level loud"),
+            Err(CliError::InvalidLogLevel("loud".to_string()))
+        );
+        assert_eq!(parse_args("--debug"), Err(CliError::UnknownOption("--debug".to_string())));
+    }
+
+    #[test]
+    fn scan_manifest_id() {
+        let source =
+            "PLUGIN_MANIFEST = PluginManifest(\n    plugin_id=\"foo\",\n    name=\"Foo\",\n)";
+        assert_eq!(scan_plugin_id(source).as_deref(), Some("foo"));
+        assert_eq!(scan_plugin_id("PluginManifest('bar', 'Bar', '1.0')").as_deref(), Some("bar"));
+        assert_eq!(scan_plugin_id(&PLUGIN_TEMPLATE.replace("{id}", "baz")).as_deref(), Some("baz"));
+        assert_eq!(scan_plugin_id("print(1)"), None);
+        assert!(valid_plugin_id("my_plugin-2") && !valid_plugin_id("../x") && !valid_plugin_id(""));
+    }
+}
@@ -1,17 +1,22 @@
 //! 机器人、Icalingua、Tailchat 和 Python 插件配置模型。
 
-use std::env;
-use std::fs;
+/// 加载 `check` 子模块。
+pub mod check;
+
+use std::collections::HashMap;
 
 use colored::Colorize;
 use serde::Deserialize;
-use toml::from_str;
+use tracing::{Level, event};
 
 use crate::data_struct::{ica, tailchat};
 
 /// Icalingua bot 的配置
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct IcaConfig {
+    /// 实例 id, 配置了多个账号时用来区分
+    #[serde(default = "default_instance_id")]
+    pub id: String,
     /// icalingua 私钥
     pub private_key: String,
     /// icalingua 服务器地址
@@ -30,10 +35,108 @@ pub struct IcaConfig {
     /// 过滤列表
     #[serde(default = "default_empty_i64_vec")]
     pub filter_list: Vec<ica::UserId>,
+    /// 发送限速
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    /// 加群申请的自动审批策略, 按群配置
+    #[serde(default)]
+    pub join_policy: Vec<JoinPolicyConfig>,
+    /// 群成员缓存的有效期, 单位为秒
+    #[serde(default = "default_member_cache_ttl")]
+    pub member_cache_ttl: u64,
+    /// QQ 登录验证的处理方式
+    #[serde(default)]
+    pub login: LoginConfig,
+    /// 解析失败的原始数据写入的隔离文件, 不填则只写日志
+    #[serde(default)]
+    pub quarantine_path: Option<String>,
+    /// 手动指定 bridge 是否支持某个接口, 覆盖按版本推算的结果
+    #[serde(default)]
+    pub capabilities: HashMap<String, bool>,
+}
+
+/// QQ 登录验证 (二维码/滑块/短信) 的处理方式
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct LoginConfig {
+    /// 登录二维码图片的保存路径
+    pub qrcode_path: String,
+    /// 是否在终端里显示二维码
+    pub qrcode_terminal: bool,
+    /// 是否把登录验证转发到 Tailchat 的提醒房间
+    pub forward_to_tailchat: bool,
+    /// 是否从控制台读取验证码/ticket
+    pub console: bool,
+}
+
+impl Default for LoginConfig {
+    /// 默认保存二维码并从控制台读取, 不转发。
+    fn default() -> Self {
+        Self {
+            qrcode_path: "./qrcode.png".to_string(),
+            qrcode_terminal: true,
+            forward_to_tailchat: false,
+            console: true,
+        }
+    }
+}
+
+/// 单个群的加群申请自动审批策略
+///
+/// 没有配置的群不会自动处理, 全部交给插件
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct JoinPolicyConfig {
+    /// 群号 (正数)
+    pub group_id: ica::RoomId,
+    /// 入群答案匹配这个正则时自动同意
+    pub answer_regex: Option<String>,
+    /// 答案不匹配时是否自动拒绝, 否则交给插件/人工处理
+    #[serde(default = "default_false")]
+    pub reject_unmatched: bool,
+    /// 直接拒绝的 QQ 号
+    #[serde(default = "default_empty_i64_vec")]
+    pub blocklist: Vec<ica::UserId>,
+    /// 自动拒绝时的理由
+    pub reject_reason: Option<String>,
+}
+
+/// Icalingua 发送队列的限速配置
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// 全局每分钟最多发送的消息数
+    pub global_per_minute: u32,
+    /// 全局允许的突发消息数
+    pub global_burst: u32,
+    /// 单个房间每分钟最多发送的消息数
+    pub room_per_minute: u32,
+    /// 单个房间允许的突发消息数
+    pub room_burst: u32,
+    /// 发送失败后的最大重试次数
+    pub max_retries: u8,
+    /// 队列最多排队的消息数
+    pub max_queue_len: usize,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+impl Default for RateLimitConfig {
+    /// 返回保守的默认限速。
+    fn default() -> Self {
+        Self {
+            global_per_minute: 30,
+            global_burst: 5,
+            room_per_minute: 12,
+            room_burst: 3,
+            max_retries: 2,
+            max_queue_len: 200,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct TailchatConfig {
+    /// 实例 id, 配置了多个账号时用来区分
+    #[serde(default = "default_instance_id")]
+    pub id: String,
     /// 服务器地址
     pub host: String,
     /// 机器人 App ID
@@ -51,6 +154,35 @@ pub struct TailchatConfig {
     /// 过滤列表
     #[serde(default = "default_empty_str_vec")]
     pub filter_list: Vec<tailchat::UserId>,
+    /// HTTP 回调模式, 启用后不再连接 Socket.IO
+    #[serde(default)]
+    pub callback: TailchatCallbackConfig,
+}
+
+/// Tailchat OpenAPI 机器人的 HTTP 回调模式
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct TailchatCallbackConfig {
+    /// 是否启用回调模式
+    pub enable: bool,
+    /// 本地监听的地址
+    pub listen: String,
+    /// 接收回调的路径
+    pub path: String,
+    /// 回调地址里的 `token` 参数, 启用回调模式时必填
+    pub token: Option<String>,
+}
+
+impl Default for TailchatCallbackConfig {
+    /// 默认不启用, 只监听本地。
+    fn default() -> Self {
+        Self {
+            enable: false,
+            listen: "127.0.0.1:8090".to_string(),
+            path: "/tailchat/callback".to_string(),
+            token: None,
+        }
+    }
 }
 
 /// 返回默认插件目录。
@@ -58,7 +190,7 @@ fn default_plugin_path() -> String { "./plugins".to_string() }
 /// 返回默认配置目录。
 fn default_config_path() -> String { "./config".to_string() }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct PyConfig {
     /// 插件路径
     #[serde(default = "default_plugin_path")]
@@ -68,6 +200,18 @@ pub struct PyConfig {
     pub config_path: String,
 }
 
+/// 返回默认的实例 id。
+fn default_instance_id() -> String { crate::instance::DEFAULT_ID.to_string() }
+
+/// 返回默认的群成员缓存有效期。
+fn default_member_cache_ttl() -> u64 { 600 }
+
+/// 返回默认权限文件路径。
+fn default_permission_path() -> String { "./permissions.toml".to_string() }
+
+/// 返回默认过滤文件路径。
+fn default_moderation_path() -> String { "./moderation.toml".to_string() }
+
 /// 返回默认的空整数列表。
 fn default_empty_i64_vec() -> Vec<i64> { Vec::new() }
 /// 返回默认的空字符串列表。
@@ -76,52 +220,118 @@ fn default_empty_str_vec() -> Vec<String> { Vec::new() }
 fn default_false() -> bool { false }
 
 /// 主配置
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct BotConfig {
     /// 是否启用 icalingua
     #[serde(default = "default_false")]
     pub enable_ica: bool,
     /// Ica 配置
     pub ica: Option<IcaConfig>,
+    /// 更多的 Ica 账号, 和 `ica` 一起启动
+    #[serde(default)]
+    pub ica_instances: Vec<IcaConfig>,
 
     /// 是否启用 Tailchat
     #[serde(default = "default_false")]
     pub enable_tailchat: bool,
     /// Tailchat 配置
     pub tailchat: Option<TailchatConfig>,
+    /// 更多的 Tailchat 账号, 和 `tailchat` 一起启动
+    #[serde(default)]
+    pub tailchat_instances: Vec<TailchatConfig>,
 
     /// 是否启用 Python 插件
     #[serde(default = "default_false")]
     pub enable_py: bool,
     /// Python 插件配置
     pub py: Option<PyConfig>,
+
+    /// 权限数据文件路径
+    #[serde(default = "default_permission_path")]
+    pub permission_path: String,
+
+    /// 过滤数据文件路径
+    #[serde(default = "default_moderation_path")]
+    pub moderation_path: String,
+
+    /// 后端健康告警
+    #[serde(default)]
+    pub alert: AlertConfig,
+
+    /// 配置热重载
+    #[serde(default)]
+    pub reload: ReloadConfig,
+}
+
+/// 配置热重载的触发方式
+///
+/// SIGHUP 和 `/bot-config-reload` 命令总是可用的
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ReloadConfig {
+    /// 是否监视配置文件, 修改后自动重载
+    pub watch: bool,
+    /// 检查配置文件是否修改的间隔, 单位为秒
+    pub interval: u64,
+}
+
+impl Default for ReloadConfig {
+    /// 默认不监视, 每 5 秒检查一次。
+    fn default() -> Self {
+        Self {
+            watch: false,
+            interval: 5,
+        }
+    }
+}
+
+/// 后端健康告警的投递方式
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct AlertConfig {
+    /// 是否启用告警 (关闭后只写日志)
+    pub enable: bool,
+    /// 同一类告警的冷却时间, 单位为秒
+    pub cooldown: u64,
+    /// 是否转发到其他后端的提醒房间
+    pub forward: bool,
+    /// 接收告警的 webhook 地址, 会 POST 一段 JSON
+    pub webhook: Option<String>,
+    /// 追加写入告警的日志文件
+    pub log_file: Option<String>,
+}
+
+impl Default for AlertConfig {
+    /// 默认启用并转发, 冷却 10 分钟。
+    fn default() -> Self {
+        Self {
+            enable: true,
+            cooldown: 600,
+            forward: true,
+            webhook: None,
+            log_file: None,
+        }
+    }
 }
 
 impl BotConfig {
-    /// 创建并初始化对应的数据结构。
+    /// 读取并检查配置文件, 有错误时列出所有问题后退出进程。
     pub fn new_from_path(config_file_path: String) -> Self {
-        // try read config from file
-        let config = fs::read_to_string(&config_file_path).expect("Failed to read config file");
-        let ret: Self = from_str(&config).unwrap_or_else(|e| {
-            panic!("Failed to parse config file {}\ne:{:?}", &config_file_path, e)
-        });
-        ret
-    }
-    /// 创建并初始化对应的数据结构。
-    pub fn new_from_cli() -> Self {
-        // let config_file_path = env::args().nth(1).expect("No config path given");
-        // -c <config_file_path>
-        let mut config_file_path = "./config.toml".to_string();
-        let mut args = env::args();
-        while let Some(arg) = args.next() {
-            if arg == "-c" {
-                config_file_path = args.next().unwrap_or_else(|| {
-                    panic!("{}", "No config path given\nUsage: -c <config_file_path>".red())
-                });
-                break;
+        match check::check_file(&config_file_path) {
+            Ok((config, warnings)) => {
+                for warning in warnings {
+                    event!(Level::WARN, "{}", warning);
+                }
+                config
+            }
+            Err(issues) => {
+                event!(Level::ERROR, "{}", format!("配置文件 {config_file_path} 有问题:").red());
+                for issue in issues {
+                    event!(Level::ERROR, "{}", issue);
+                }
+                std::process::exit(1);
             }
         }
-        Self::new_from_path(config_file_path)
     }
 
     /// 检查是否启用 ica
@@ -133,12 +343,38 @@ impl BotConfig {
     /// 检查是否启用 Python 插件
     pub fn check_py(&self) -> bool { self.enable_py }
 
-    /// 返回 Icalingua 配置。
-    pub fn ica(&self) -> IcaConfig { self.ica.clone().expect("No ica config found") }
-    /// 返回 Tailchat 配置。
-    pub fn tailchat(&self) -> TailchatConfig {
-        self.tailchat.clone().expect("No tailchat config found")
+    /// 返回所有 Icalingua 实例的配置, `[ica]` 排在最前面。
+    pub fn ica_list(&self) -> Vec<&IcaConfig> {
+        self.ica.iter().chain(self.ica_instances.iter()).collect()
+    }
+    /// 返回所有 Tailchat 实例的配置, `[tailchat]` 排在最前面。
+    pub fn tailchat_list(&self) -> Vec<&TailchatConfig> {
+        self.tailchat.iter().chain(self.tailchat_instances.iter()).collect()
+    }
+
+    /// 返回当前 Icalingua 实例的配置引用, 不在实例作用域里时返回第一个。
+    fn current_ica(&self) -> &IcaConfig {
+        let list = self.ica_list();
+        crate::instance::pick(&list, |config| &config.id)
+            .copied()
+            .expect("No ica config found")
+    }
+    /// 返回当前 Tailchat 实例的配置引用, 不在实例作用域里时返回第一个。
+    fn current_tailchat(&self) -> &TailchatConfig {
+        let list = self.tailchat_list();
+        crate::instance::pick(&list, |config| &config.id)
+            .copied()
+            .expect("No tailchat config found")
     }
+
+    /// 返回当前 Icalingua 实例的配置。
+    pub fn ica(&self) -> IcaConfig { self.current_ica().clone() }
+    /// 返回当前 Tailchat 实例的配置。
+    pub fn tailchat(&self) -> TailchatConfig { self.current_tailchat().clone() }
+    /// 返回当前 Icalingua 实例的 id。
+    pub fn ica_id(&self) -> String { self.current_ica().id.clone() }
+    /// 返回当前 Tailchat 实例的 id。
+    pub fn tailchat_id(&self) -> String { self.current_tailchat().id.clone() }
     /// 返回 Python 插件配置。
     pub fn py(&self) -> PyConfig { self.py.clone().expect("No py config found") }
 }
@@ -0,0 +1,496 @@
+//! 启动前的配置检查。
+//!
+//! 一次性找出配置里的所有问题 (缺少的段、格式不对的私钥和地址、写反符号的房间号、不存在的插件目录等),
+//! 并尽量给出所在的行号, 而不是等到用到的时候才 panic。
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::path::Path;
+
+use colored::Colorize;
+
+use crate::config::{BotConfig, IcaConfig, TailchatConfig};
+use crate::instance;
+
+/// 问题的严重程度
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// 可以启动, 但多半不是想要的效果
+    Warning,
+    /// 无法正常启动
+    Error,
+}
+
+/// 配置里的一个问题
+#[derive(Debug, Clone)]
+pub struct ConfigIssue {
+    /// 严重程度
+    pub severity: Severity,
+    /// 出问题的配置项, 如 `ica.private_key`
+    pub key: String,
+    /// 所在的行 (从 1 开始), 找不到时为 `None`
+    pub line: Option<usize>,
+    /// 问题描述
+    pub message: String,
+}
+
+impl ConfigIssue {
+    /// 判断是否为错误。
+    pub fn is_error(&self) -> bool { self.severity == Severity::Error }
+}
+
+impl Display for ConfigIssue {
+    /// 将当前值写入格式化输出。
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let level = match self.severity {
+            Severity::Error => "错误".red(),
+            Severity::Warning => "警告".yellow(),
+        };
+        match self.line {
+            Some(line) => write!(f, "[{level}] 第 {line} 行 `{}`: {}", self.key, self.message),
+            None => write!(f, "[{level}] `{}`: {}", self.key, self.message),
+        }
+    }
+}
+
+/// 配置文件里各个键所在的行
+///
+/// 只认识这个配置用到的写法: `[table]`、`[[array]]` 和 `key = value`
+struct Locator {
+    lines: HashMap<String, usize>,
+}
+
+impl Locator {
+    /// 扫描一遍配置文件。
+    fn new(source: &str) -> Self {
+        let mut lines = HashMap::new();
+        let mut table = String::new();
+        let mut arrays: HashMap<String, usize> = HashMap::new();
+        for (index, line) in source.lines().enumerate() {
+            let number = index + 1;
+            let line = line.trim();
+            if line.starts_with('#') {
+                continue;
+            }
+            if let Some((name, _)) = line.strip_prefix("[[").and_then(|rest| rest.split_once("]]"))
+            {
+                let name = name.trim();
+                let count = arrays.entry(name.to_string()).or_default();
+                table = format!("{name}[{count}]");
+                *count += 1;
+                lines.entry(table.clone()).or_insert(number);
+            } else if let Some((name, _)) =
+                line.strip_prefix('[').and_then(|rest| rest.split_once(']'))
+            {
+                let name = name.trim();
+                // `[ica_instances.rate_limit]` 属于最近的一个 `[[ica_instances]]`
+                table = match name.split_once('.') {
+                    Some((parent, child)) if arrays.contains_key(parent) => {
+                        format!("{parent}[{}].{child}", arrays[parent] - 1)
+                    }
+                    _ => name.to_string(),
+                };
+                lines.entry(table.clone()).or_insert(number);
+            } else if let Some((key, _)) = line.split_once('=') {
+                let key = key.trim().trim_matches('"');
+                let key = if table.is_empty() {
+                    key.to_string()
+                } else {
+                    format!("{table}.{key}")
+                };
+                lines.entry(key).or_insert(number);
+            }
+        }
+        Self { lines }
+    }
+
+    /// 返回键所在的行, 找不到时依次退回到上一级。
+    fn line_of(&self, key: &str) -> Option<usize> {
+        let mut key = key;
+        loop {
+            if let Some(line) = self.lines.get(key) {
+                return Some(*line);
+            }
+            key = &key[..key.rfind(['.', '['])?];
+        }
+    }
+}
+
+/// 收集检查出的问题
+struct Checker {
+    locator: Locator,
+    issues: Vec<ConfigIssue>,
+}
+
+impl Checker {
+    /// 记录一个问题。
+    fn push(&mut self, severity: Severity, key: &str, message: impl Into<String>) {
+        self.issues.push(ConfigIssue {
+            severity,
+            key: key.to_string(),
+            line: self.locator.line_of(key),
+            message: message.into(),
+        });
+    }
+
+    /// 记录一个错误。
+    fn error(&mut self, key: &str, message: impl Into<String>) {
+        self.push(Severity::Error, key, message);
+    }
+
+    /// 记录一个警告。
+    fn warning(&mut self, key: &str, message: impl Into<String>) {
+        self.push(Severity::Warning, key, message);
+    }
+
+    /// 检查一个 http(s) 地址的格式。
+    fn url(&mut self, key: &str, url: &str) {
+        if let Some(problem) = url_problem(url) {
+            self.error(key, problem);
+        }
+    }
+}
+
+/// 返回地址格式上的问题, 只检查写法, 不会真的去连
+fn url_problem(url: &str) -> Option<String> {
+    if url.is_empty() {
+        return Some("没有填写地址".to_string());
+    }
+    let Some((scheme, rest)) = url.split_once("://") else {
+        return Some(format!("{url} 缺少协议, 应以 http:// 或 https:// 开头"));
+    };
+    if !matches!(scheme, "http" | "https") {
+        return Some(format!("不支持的协议 {scheme}, 应为 http 或 https"));
+    }
+    let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    if host.is_empty() || url.contains(char::is_whitespace) {
+        return Some(format!("{url} 没有主机名或者包含空白字符"));
+    }
+    if let Some((_, port)) = host.rsplit_once(':')
+        && !host.ends_with(']')
+        && port.parse::<u16>().is_err()
+    {
+        return Some(format!("{port} 不是有效的端口号"));
+    }
+    None
+}
+
+/// 判断是否为 64 位十六进制 (32 字节) 的私钥。
+fn is_hex_key(key: &str) -> bool { key.len() == 64 && key.chars().all(|c| c.is_ascii_hexdigit()) }
+
+/// 判断是否像 Tailchat 的 ID (24 位十六进制)。
+fn is_object_id(id: &str) -> bool { id.len() == 24 && id.chars().all(|c| c.is_ascii_hexdigit()) }
+
+/// 检查一个 Icalingua 实例。
+fn check_ica(checker: &mut Checker, key: &str, config: &IcaConfig) {
+    if config.private_key.is_empty() {
+        checker.error(&format!("{key}.private_key"), "没有填写私钥");
+    } else if !is_hex_key(&config.private_key) {
+        checker.error(
+            &format!("{key}.private_key"),
+            format!(
+                "私钥应为 64 位十六进制 (32 字节), 当前为 {} 个字符",
+                config.private_key.chars().count()
+            ),
+        );
+    }
+    checker.url(&format!("{key}.host"), &config.host);
+    if config.self_id <= 0 {
+        checker.error(&format!("{key}.self_id"), "bot 的 QQ 号应为正数");
+    }
+    for (index, room) in config.notice_room.iter().enumerate() {
+        let room_key = format!("{key}.notice_room[{index}]");
+        if *room == 0 {
+            checker.error(&room_key, "房间号不能为 0");
+        } else if *room == config.self_id {
+            checker.error(&room_key, "不能通知 bot 自己");
+        } else if *room > 0 {
+            checker.warning(
+                &room_key,
+                format!("{room} 是正数, 会被当成私聊的 QQ 号; 群聊请使用群号的负数"),
+            );
+        }
+    }
+    for (list, ids) in [
+        ("admin_list", &config.admin_list),
+        ("filter_list", &config.filter_list),
+    ] {
+        for (index, id) in ids.iter().enumerate() {
+            if *id <= 0 {
+                checker
+                    .warning(&format!("{key}.{list}[{index}]"), format!("{id} 不是有效的 QQ 号"));
+            }
+        }
+    }
+    for (index, policy) in config.join_policy.iter().enumerate() {
+        let policy_key = format!("{key}.join_policy[{index}]");
+        if policy.group_id <= 0 {
+            checker.error(
+                &format!("{policy_key}.group_id"),
+                "这里的群号应为正数 (和 notice_room 不同)",
+            );
+        }
+        if let Some(regex) = &policy.answer_regex
+            && let Err(e) = regex::Regex::new(regex)
+        {
+            checker.error(&format!("{policy_key}.answer_regex"), format!("正则无效: {e}"));
+        }
+    }
+    let limit = &config.rate_limit;
+    if limit.global_per_minute == 0 || limit.room_per_minute == 0 {
+        checker.error(&format!("{key}.rate_limit"), "每分钟的发送数不能为 0, 否则永远发不出消息");
+    }
+}
+
+/// 检查一个 Tailchat 实例。
+fn check_tailchat(checker: &mut Checker, key: &str, config: &TailchatConfig) {
+    checker.url(&format!("{key}.host"), &config.host);
+    if config.app_id.is_empty() {
+        checker.error(&format!("{key}.app_id"), "没有填写机器人的 App ID");
+    }
+    if config.app_secret.is_empty() {
+        checker.error(&format!("{key}.app_secret"), "没有填写机器人的 App Secret");
+    }
+    for (index, (group, converse)) in config.notice_room.iter().enumerate() {
+        let room_key = format!("{key}.notice_room[{index}]");
+        if group.is_empty() || converse.is_empty() {
+            checker.error(&room_key, "提醒房间应为 [服务器ID, 会话ID], 两个都不能为空");
+        } else if !is_object_id(group) || !is_object_id(converse) {
+            checker.warning(&room_key, format!("[{group}, {converse}] 看起来不是 tailchat 的 ID"));
+        }
+    }
+    let callback = &config.callback;
+    if callback.enable {
+        if callback.listen.parse::<SocketAddr>().is_err() {
+            checker.error(
+                &format!("{key}.callback.listen"),
+                format!("{} 不是有效的监听地址, 应为 ip:端口", callback.listen),
+            );
+        }
+        if !callback.path.starts_with('/') {
+            checker.error(&format!("{key}.callback.path"), "回调路径应以 / 开头");
+        }
+        if callback.token.as_deref().is_none_or(str::is_empty) {
+            checker.error(
+                &format!("{key}.callback.token"),
+                "启用回调模式时必须填写 token, 否则任何人都能冒充用户发消息",
+            );
+        }
+    }
+}
+
+/// 检查解析好的配置。
+fn check_config(checker: &mut Checker, config: &BotConfig) {
+    if config.enable_ica {
+        if config.ica_list().is_empty() {
+            checker.error("enable_ica", "启用了 ica, 但是没有 [ica] 或 [[ica_instances]]");
+        }
+        let entries = config.ica.iter().map(|ica| ("ica".to_string(), ica));
+        let instances = config
+            .ica_instances
+            .iter()
+            .enumerate()
+            .map(|(index, ica)| (format!("ica_instances[{index}]"), ica));
+        for (key, ica) in entries.chain(instances) {
+            check_ica(checker, &key, ica);
+        }
+        let ids = config.ica_list().into_iter().map(|ica| ica.id.as_str());
+        if let Some(id) = instance::find_duplicate(ids) {
+            checker.error("ica_instances", format!("ica 实例 id 重复: {id}"));
+        }
+    }
+    if config.enable_tailchat {
+        if config.tailchat_list().is_empty() {
+            checker.error(
+                "enable_tailchat",
+                "启用了 tailchat, 但是没有 [tailchat] 或 [[tailchat_instances]]",
+            );
+        }
+        let entries = config.tailchat.iter().map(|tailchat| ("tailchat".to_string(), tailchat));
+        let instances = config
+            .tailchat_instances
+            .iter()
+            .enumerate()
+            .map(|(index, tailchat)| (format!("tailchat_instances[{index}]"), tailchat));
+        for (key, tailchat) in entries.chain(instances) {
+            check_tailchat(checker, &key, tailchat);
+        }
+        let ids = config.tailchat_list().into_iter().map(|tailchat| tailchat.id.as_str());
+        if let Some(id) = instance::find_duplicate(ids) {
+            checker.error("tailchat_instances", format!("tailchat 实例 id 重复: {id}"));
+        }
+    }
+    if config.enable_py {
+        match &config.py {
+            None => checker.error("enable_py", "启用了 python 插件, 但是没有 [py]"),
+            Some(py) => {
+                if !Path::new(&py.plugin_path).is_dir() {
+                    checker.error("py.plugin_path", format!("插件目录 {} 不存在", py.plugin_path));
+                }
+                if !Path::new(&py.config_path).is_dir() {
+                    checker.warning(
+                        "py.config_path",
+                        format!("插件配置目录 {} 不存在", py.config_path),
+                    );
+                }
+            }
+        }
+    }
+    if let Some(webhook) = &config.alert.webhook {
+        checker.url("alert.webhook", webhook);
+    }
+    if config.reload.watch && config.reload.interval == 0 {
+        checker.error("reload.interval", "检查间隔不能为 0");
+    }
+}
+
+/// 解析并检查配置文件的内容
+///
+/// 没有错误时返回配置和警告, 有错误时返回所有问题
+pub fn check_source(source: &str) -> Result<(BotConfig, Vec<ConfigIssue>), Vec<ConfigIssue>> {
+    let mut checker = Checker {
+        locator: Locator::new(source),
+        issues: Vec::new(),
+    };
+    let config: BotConfig = match toml::from_str(source) {
+        Ok(config) => config,
+        Err(e) => {
+            let line = e.span().map(|span| source[..span.start].matches('\n').count() + 1);
+            return Err(vec![ConfigIssue {
+                severity: Severity::Error,
+                key: "<toml>".to_string(),
+                line,
+                message: format!("解析失败: {}", e.message()),
+            }]);
+        }
+    };
+    check_config(&mut checker, &config);
+    checker
+        .issues
+        .sort_by_key(|issue| (issue.line.unwrap_or(usize::MAX), issue.severity));
+    if checker.issues.iter().any(ConfigIssue::is_error) {
+        Err(checker.issues)
+    } else {
+        Ok((config, checker.issues))
+    }
+}
+
+/// 读取并检查配置文件。
+pub fn check_file(path: &str) -> Result<(BotConfig, Vec<ConfigIssue>), Vec<ConfigIssue>> {
+    match std::fs::read_to_string(path) {
+        Ok(source) => check_source(&source),
+        Err(e) => Err(vec![ConfigIssue {
+            severity: Severity::Error,
+            key: path.to_string(),
+            line: None,
+            message: format!("无法读取配置文件: {e}"),
+        }]),
+    }
+}
+
+/// 检查配置文件并打印结果, 没有错误时返回 `true`
+///
+/// 给 `check-config` 用
+pub fn print_report(path: &str) -> bool {
+    let (ok, issues) = match check_file(path) {
+        Ok((_, warnings)) => (true, warnings),
+        Err(issues) => (false, issues),
+    };
+    for issue in &issues {
+        println!("{issue}");
+    }
+    let errors = issues.iter().filter(|issue| issue.is_error()).count();
+    let summary = format!("{path}: {errors} 个错误, {} 个警告", issues.len() - errors);
+    if ok {
+        println!("{}", summary.green());
+    } else {
+        println!("{}", summary.red());
+    }
+    ok
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+enable_ica = true
+enable_tailchat = true
+enable_py = false
+
+[ica]
+private_key = "abcd"
+host = "localhost:6789"
+self_id = 10001
+notice_room = [-1234, 5678, 0]
+
+[[ica.join_policy]]
+group_id = -1234
+answer_regex = "("
+
+[[ica_instances]]
+id = "second"
+private_key = "0000000000000000000000000000000000000000000000000000000000000000"
+host = "http://127.0.0.1:abc"
+self_id = 10002
+
+[[tailchat_instances]]
+host = "https://chat.example.com"
+app_id = "bot"
+app_secret = ""
+notice_room = [["", "6602e20d7b8d10675758e36b"]]
+"#;
+
+    #[test]
+    fn locate_keys() {
+        let locator = Locator::new(CONFIG);
+        assert_eq!(locator.line_of("enable_py"), Some(4));
+        assert_eq!(locator.line_of("ica.private_key"), Some(7));
+        assert_eq!(locator.line_of("ica.notice_room[2]"), Some(10));
+        assert_eq!(locator.line_of("ica.join_policy[0].answer_regex"), Some(14));
+        assert_eq!(locator.line_of("ica_instances[0].host"), Some(19));
+        // 没写的键退回到所在的段
+        assert_eq!(locator.line_of("ica_instances[0].rate_limit"), Some(16));
+        assert_eq!(locator.line_of("missing"), None);
+    }
+
+    #[test]
+    fn collect_issues() {
+        let issues = check_source(CONFIG).unwrap_err();
+        let found: Vec<_> = issues
+            .iter()
+            .map(|issue| (issue.line, issue.severity, issue.key.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (Some(7), Severity::Error, "ica.private_key"),
+                (Some(8), Severity::Error, "ica.host"),
+                (Some(10), Severity::Warning, "ica.notice_room[1]"),
+                (Some(10), Severity::Error, "ica.notice_room[2]"),
+                (Some(13), Severity::Error, "ica.join_policy[0].group_id"),
+                (Some(14), Severity::Error, "ica.join_policy[0].answer_regex"),
+                (Some(19), Severity::Error, "ica_instances[0].host"),
+                (Some(25), Severity::Error, "tailchat_instances[0].app_secret"),
+                (Some(26), Severity::Error, "tailchat_instances[0].notice_room[0]"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_error_line() {
+        let issues = check_source("enable_ica = true\nenable_py = yes\n").unwrap_err();
+        assert_eq!(issues[0].line, Some(2));
+    }
+
+    #[test]
+    fn url_format() {
+        assert_eq!(url_problem("https://chat.example.com/"), None);
+        assert_eq!(url_problem("http://[::1]:8080"), None);
+        assert!(url_problem("localhost:6789").is_some());
+        assert!(url_problem("ws://localhost").is_some());
+        assert!(url_problem("http://").is_some());
+        assert!(url_problem("http://host:99999").is_some());
+    }
+}
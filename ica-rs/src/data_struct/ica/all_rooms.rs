@@ -1,7 +1,7 @@
 //! Icalingua 房间和好友、群申请数据结构。
 
 use crate::data_struct::ica::messages::{At, LastMessage, SendMessage};
-use crate::data_struct::ica::{RoomId, UserId};
+use crate::data_struct::ica::{RoomId, RoomIdTrait, UserId};
 
 use serde::{Deserialize, Serialize};
 use serde_json::{Number, Value as JsonValue};
@@ -40,26 +40,21 @@ pub struct Room {
 }
 
 impl Room {
-    /// 创建并初始化对应的数据结构。
-    pub fn new_from_json(raw_json: &JsonValue) -> Self {
+    /// 从 bridge 的 json 解析房间, 结构对不上时返回错误。
+    pub fn try_from_json(raw_json: &JsonValue) -> Result<Self, serde_json::Error> {
         let mut parse_json = raw_json.clone();
         // 手动 patch 一下 roomId
         // ica issue: https://github.com/Icalingua-plus-plus/Icalingua-plus-plus/issues/793
         if parse_json.get("roomId").is_none_or(|id| id.is_null()) {
             use tracing::warn;
-            warn!("Room::new_from_json roomId is None, patching it to -1, raw: {:?}", raw_json);
+            warn!("Room::try_from_json roomId is None, patching it to -1, raw: {:?}", raw_json);
             parse_json["roomId"] = JsonValue::Number(Number::from(-1));
         }
         // 现在 fix 了
 
-        let inner = match serde_json::from_value::<InnerRoom>(parse_json) {
-            Ok(data) => data,
-            Err(e) => {
-                panic!("Room::new_from_json error: {e}, raw: {raw_json:#?}");
-            }
-        };
+        let inner = serde_json::from_value::<InnerRoom>(parse_json)?;
         let at = At::new_from_json(&raw_json["at"]);
-        Self {
+        Ok(Self {
             room_id: inner.room_id,
             room_name: inner.room_name,
             index: inner.index,
@@ -70,7 +65,7 @@ impl Room {
             at,
             last_message: inner.last_message,
             // download_path: inner.download_path,
-        }
+        })
     }
     /// 创建并初始化对应的数据结构。
     pub fn new_message_to(&self, content: String) -> SendMessage {
@@ -140,3 +135,29 @@ pub struct JoinRequestRoom {
     pub tips: String,
     pub flag: String,
 }
+
+impl JoinRequestRoom {
+    /// 是否为加群申请 (否则为好友申请)
+    pub fn is_group(&self) -> bool { self.request_type == "group" }
+
+    /// 申请对应的房间 id, 群聊为群号的负数, 好友申请为对方 QQ 号
+    pub fn room_id(&self) -> RoomId {
+        if self.is_group() {
+            self.group_id.as_room_id()
+        } else {
+            self.user_id
+        }
+    }
+
+    /// 从 `comment` 里取出入群答案
+    ///
+    /// 没有 `答案：` 前缀时返回整个 `comment`
+    pub fn answer(&self) -> &str {
+        self.comment
+            .split_once("答案：")
+            .or_else(|| self.comment.split_once("答案:"))
+            .map(|(_, answer)| answer)
+            .unwrap_or(&self.comment)
+            .trim()
+    }
+}
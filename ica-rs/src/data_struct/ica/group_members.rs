@@ -1,10 +1,14 @@
 //! Icalingua bridge 群成员信息。
 
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use serde::Deserialize;
 use serde_json::Value as JsonValue;
 
+use crate::data_struct::ica::{RoomId, UserId};
+
 fn deserialize_string_or_default<'de, D>(deserializer: D) -> Result<String, D::Error>
 where
     D: serde::Deserializer<'de>,
@@ -92,6 +96,89 @@ impl GroupMember {
     }
 }
 
+/// 一个群缓存的成员列表
+#[derive(Debug, Clone)]
+struct CachedMembers {
+    members: Vec<GroupMember>,
+    fetched_at: Instant,
+}
+
+/// 按群缓存的成员目录
+///
+/// 超过 TTL 的缓存视为不存在, 入群/退群等事件会直接让对应群的缓存失效
+#[derive(Debug, Clone, Default)]
+pub struct MemberDirectory {
+    rooms: Arc<Mutex<HashMap<RoomId, CachedMembers>>>,
+}
+
+impl MemberDirectory {
+    /// 返回未过期的成员列表。
+    pub fn get_at(&self, room_id: RoomId, ttl: Duration, now: Instant) -> Option<Vec<GroupMember>> {
+        let rooms = self.rooms.lock().unwrap();
+        let cached = rooms.get(&room_id)?;
+        (now.saturating_duration_since(cached.fetched_at) < ttl).then(|| cached.members.clone())
+    }
+
+    /// 返回未过期的成员列表。
+    pub fn get(&self, room_id: RoomId, ttl: Duration) -> Option<Vec<GroupMember>> {
+        self.get_at(room_id, ttl, Instant::now())
+    }
+
+    /// 写入一个群的成员列表。
+    pub fn insert_at(&self, room_id: RoomId, members: Vec<GroupMember>, now: Instant) {
+        self.rooms.lock().unwrap().insert(
+            room_id,
+            CachedMembers {
+                members,
+                fetched_at: now,
+            },
+        );
+    }
+
+    /// 写入一个群的成员列表。
+    pub fn insert(&self, room_id: RoomId, members: Vec<GroupMember>) {
+        self.insert_at(room_id, members, Instant::now());
+    }
+
+    /// 让一个群的缓存失效, 返回之前是否有缓存。
+    pub fn invalidate(&self, room_id: RoomId) -> bool {
+        self.rooms.lock().unwrap().remove(&room_id).is_some()
+    }
+
+    /// 清空所有缓存。
+    pub fn clear(&self) { self.rooms.lock().unwrap().clear(); }
+
+    /// 返回当前缓存了多少个群。
+    pub fn len(&self) -> usize { self.rooms.lock().unwrap().len() }
+
+    /// 判断是否没有任何缓存。
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+}
+
+/// 在成员列表里按 QQ 号查找。
+pub fn find_member_by_id(members: &[GroupMember], user_id: UserId) -> Option<&GroupMember> {
+    members.iter().find(|member| member.user_id == user_id)
+}
+
+/// 在成员列表里按群名片或昵称查找
+///
+/// 忽略首尾空白和大小写, 群名片匹配的排在前面
+pub fn find_members_by_name<'a>(members: &'a [GroupMember], name: &str) -> Vec<&'a GroupMember> {
+    let name = name.trim().trim_start_matches('@').to_lowercase();
+    if name.is_empty() {
+        return Vec::new();
+    }
+    let matches = |text: &str| text.trim().to_lowercase() == name;
+    let mut found: Vec<&GroupMember> =
+        members.iter().filter(|member| matches(&member.card)).collect();
+    found.extend(
+        members
+            .iter()
+            .filter(|member| !matches(&member.card) && matches(&member.nickname)),
+    );
+    found
+}
+
 fn current_unix_timestamp() -> i64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -102,9 +189,11 @@ fn current_unix_timestamp() -> i64 {
 
 #[cfg(test)]
 mod tests {
+    use std::time::{Duration, Instant};
+
     use serde_json::json;
 
-    use super::GroupMember;
+    use super::{GroupMember, MemberDirectory, find_member_by_id, find_members_by_name};
 
     #[test]
     fn defaults_missing_fields_and_obeys_mute_boundary() {
@@ -122,4 +211,43 @@ mod tests {
         assert!(!member.is_muted_at(100));
         assert_eq!(member.remaining_mute_seconds_at(98), 2);
     }
+
+    fn member(user_id: i64, nickname: &str, card: &str) -> GroupMember {
+        GroupMember {
+            user_id,
+            nickname: nickname.to_string(),
+            card: card.to_string(),
+            remark: String::new(),
+            title: String::new(),
+            level: String::new(),
+            role: "member".to_string(),
+            shutup_time: 0,
+        }
+    }
+
+    #[test]
+    fn directory_expires_and_looks_up() {
+        let directory = MemberDirectory::default();
+        let start = Instant::now();
+        let ttl = Duration::from_secs(60);
+        let members = vec![
+            member(1, "shenjack", ""),
+            member(2, "Alice", "shenjack"),
+            member(3, "bob", "Bob"),
+        ];
+        directory.insert_at(-100, members, start);
+
+        let cached = directory.get_at(-100, ttl, start + Duration::from_secs(30)).unwrap();
+        assert_eq!(find_member_by_id(&cached, 3).unwrap().nickname, "bob");
+        assert!(find_member_by_id(&cached, 4).is_none());
+
+        let found: Vec<i64> =
+            find_members_by_name(&cached, "@ShenJack").iter().map(|m| m.user_id).collect();
+        assert_eq!(found, vec![2, 1]);
+        assert!(find_members_by_name(&cached, " ").is_empty());
+
+        assert!(directory.get_at(-100, ttl, start + Duration::from_secs(60)).is_none());
+        assert!(directory.invalidate(-100));
+        assert!(directory.get_at(-100, ttl, start).is_none());
+    }
 }
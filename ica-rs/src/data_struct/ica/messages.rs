@@ -13,6 +13,8 @@ pub mod msg_trait;
 #[allow(unused)]
 /// 加载 `raw` 子模块。
 pub mod raw;
+/// 加载 `system` 子模块。
+pub mod system;
 
 pub use msg_trait::MessageTrait;
 
@@ -144,16 +146,24 @@ pub struct Message {
 }
 
 impl Message {
-    /// 创建并初始化对应的数据结构。
-    pub fn new_from_json(json: &JsonValue) -> Self {
+    /// 从 bridge 的 json 解析消息
+    ///
+    /// 只有消息 id 是必须的, 其余字段缺失时使用默认值
+    pub fn try_from_json(json: &JsonValue) -> Result<Self, serde_json::Error> {
+        use serde::de::Error as _;
         // 消息 id
-        let msg_id = json["_id"].as_str().unwrap();
+        let msg_id = json["_id"]
+            .as_str()
+            .ok_or_else(|| serde_json::Error::custom("消息缺少字符串类型的 `_id`"))?;
         // 发送者 id (Optional)
         let sender_id = json["senderId"].as_i64().unwrap_or(-1);
-        // 发送者名字 必有
-        let sender_name = json["username"].as_str().unwrap();
-        // 消息内容
-        let content = json["content"].as_str().unwrap();
+        // 发送者名字 (应该必有, 但不能因为它 panic)
+        let sender_name = json["username"].as_str().unwrap_or_else(|| {
+            warn!("消息 {} 缺少 username", msg_id);
+            ""
+        });
+        // 消息内容 (只有文件的消息可能没有)
+        let content = json["content"].as_str().unwrap_or("");
         // xml / json 内容
         let code = json["code"].clone();
         // 消息时间 (怎么这个也是可选啊(恼))
@@ -217,7 +227,7 @@ impl Message {
         let head_img = json["head_img"].clone();
         // 原始消息
         let raw_msg = json["message"].clone();
-        Self {
+        Ok(Self {
             msg_id: msg_id.to_string(),
             sender_id,
             sender_name: sender_name.to_string(),
@@ -240,7 +250,7 @@ impl Message {
             subid,
             head_img,
             raw_msg,
-        }
+        })
     }
 
     /// 向 Python 插件日志输出消息。
@@ -340,6 +350,19 @@ impl SendMessage {
     /// 判断当前值是否包含 `b64img` 数据。
     pub fn has_b64img(&self) -> bool { self.file_data.is_some() }
 
+    /// 在消息开头 @ 某人
+    ///
+    /// Icalingua 按 `text` 在内容里找到对应的 @ 再换成真正的 at
+    pub fn add_mention(&mut self, user_id: UserId, name: &str) {
+        let text = format!("@{name}");
+        self.content = format!("{text} {}", self.content);
+        let item = json!({"text": text, "id": user_id});
+        match self.at.as_array_mut() {
+            Some(at) => at.push(item),
+            None => self.at = json!([item]),
+        }
+    }
+
     /// 设置消息的图片
     ///
     /// as_sticker: 是否当作表情发送
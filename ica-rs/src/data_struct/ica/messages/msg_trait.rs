@@ -41,8 +41,7 @@ pub trait MessageTrait {
     fn is_reply(&self) -> bool;
     /// 判断当前值是否满足 `from_self` 条件。
     fn is_from_self(&self) -> bool {
-        let qq_id = MainStatus::global_ica_status().online_status.qqid;
-        self.sender_id() == qq_id
+        MainStatus::with_ica_status(|status| status.online_status.qqid) == Some(self.sender_id())
     }
     /// 返回消息 ID。
     fn msg_id(&self) -> &MessageId;
@@ -122,7 +121,7 @@ impl<'de> Deserialize<'de> for Message {
         D: serde::de::Deserializer<'de>,
     {
         let value = JsonValue::deserialize(deserializer)?;
-        Ok(Message::new_from_json(&value))
+        Message::try_from_json(&value).map_err(serde::de::Error::custom)
     }
 }
 
@@ -0,0 +1,322 @@
+//! 把 Icalingua 系统消息的通知文本解析成结构化事件。
+//!
+//! bridge 只会给系统消息一段拼好的中文文本, 这里按常见的几种写法匹配,
+//! 认不出来的一律归为 [`SystemEvent::Unknown`], 原文依然可以从消息里拿到。
+
+/// 系统消息对应的事件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SystemEvent {
+    /// 有人加入了群
+    MemberJoin { target: String },
+    /// 有人主动退群
+    MemberLeave { target: String },
+    /// 有人被踢出群
+    MemberKick { target: String, operator: String },
+    /// 有人被禁言, `duration` 为通知里的原始时长文本
+    MemberMute {
+        target: String,
+        operator: String,
+        duration: String,
+    },
+    /// 有人被解除禁言
+    MemberUnmute { target: String, operator: String },
+    /// 全员禁言开关
+    WholeMute { operator: String, enabled: bool },
+    /// 管理员变动, `enabled` 为是否成为管理员
+    AdminChange { target: String, enabled: bool },
+    /// 撤回通知, 撤回自己的消息时 `target` 为 `None`
+    Recall {
+        operator: String,
+        target: Option<String>,
+    },
+    /// 群名变更
+    GroupRename {
+        operator: Option<String>,
+        name: String,
+    },
+    /// 认不出来的系统消息
+    Unknown,
+}
+
+/// 按顺序尝试去掉后缀, 返回剩下的非空部分。
+fn strip_any_suffix<'a>(text: &'a str, suffixes: &[&str]) -> Option<&'a str> {
+    suffixes
+        .iter()
+        .find_map(|suffix| text.strip_suffix(suffix))
+        .map(str::trim)
+        .filter(|rest| !rest.is_empty())
+}
+
+/// 按顺序尝试用分隔符切开, 两边都非空时返回。
+fn split_any<'a>(text: &'a str, separators: &[&str]) -> Option<(&'a str, &'a str)> {
+    separators.iter().find_map(|sep| {
+        let (left, right) = text.split_once(sep)?;
+        let (left, right) = (left.trim(), right.trim());
+        (!left.is_empty() && !right.is_empty()).then_some((left, right))
+    })
+}
+
+impl SystemEvent {
+    /// 解析系统消息的文本
+    pub fn parse(content: &str) -> Self {
+        let text = content.trim();
+
+        if let Some(operator) = strip_any_suffix(text, &["开启了全员禁言", "开启全员禁言"])
+        {
+            return Self::WholeMute {
+                operator: operator.to_string(),
+                enabled: true,
+            };
+        }
+        if let Some(operator) = strip_any_suffix(text, &["关闭了全员禁言", "关闭全员禁言"])
+        {
+            return Self::WholeMute {
+                operator: operator.to_string(),
+                enabled: false,
+            };
+        }
+        if let Some(rest) = strip_any_suffix(text, &["的禁言"])
+            && let Some((operator, target)) = split_any(rest, &[" 解除了 ", "解除了"])
+        {
+            return Self::MemberUnmute {
+                target: target.to_string(),
+                operator: operator.to_string(),
+            };
+        }
+        if let Some((operator, rest)) = split_any(text, &[" 禁言了 ", "禁言了"]) {
+            // 时长在最后一个空格后面, 没有时长就整段都是名字
+            let (target, duration) = match rest.rsplit_once(' ') {
+                Some((target, duration)) if !target.trim().is_empty() => {
+                    (target.trim(), duration.trim())
+                }
+                _ => (rest, ""),
+            };
+            return Self::MemberMute {
+                target: target.to_string(),
+                operator: operator.to_string(),
+                duration: duration.to_string(),
+            };
+        }
+        if let Some(rest) =
+            strip_any_suffix(text, &["踢出了本群", "移出了本群", "踢出群聊", "踢了"])
+            && let Some((target, operator)) = split_any(rest, &[" 被 ", "被"])
+        {
+            return Self::MemberKick {
+                target: target.to_string(),
+                operator: operator.to_string(),
+            };
+        }
+        if let Some(target) = strip_any_suffix(text, &["离开了本群", "退出了本群", "退出了群聊"])
+        {
+            return Self::MemberLeave {
+                target: target.to_string(),
+            };
+        }
+        if let Some(target) = strip_any_suffix(text, &["加入了本群", "加入本群", "加入了群聊"])
+        {
+            return Self::MemberJoin {
+                target: target.to_string(),
+            };
+        }
+        if let Some(target) = strip_any_suffix(text, &["被取消了管理员", "不再是管理员"])
+        {
+            return Self::AdminChange {
+                target: target.to_string(),
+                enabled: false,
+            };
+        }
+        if let Some(target) = strip_any_suffix(text, &["成为了管理员", "被设置为管理员"])
+        {
+            return Self::AdminChange {
+                target: target.to_string(),
+                enabled: true,
+            };
+        }
+        if let Some(rest) = strip_any_suffix(text, &["的一条消息"])
+            && let Some((operator, target)) = split_any(rest, &[" 撤回了 ", "撤回了"])
+        {
+            return Self::Recall {
+                operator: operator.to_string(),
+                target: Some(target.to_string()),
+            };
+        }
+        if let Some(operator) = strip_any_suffix(text, &["撤回了一条消息"]) {
+            return Self::Recall {
+                operator: operator.to_string(),
+                target: None,
+            };
+        }
+        if let Some((operator, name)) =
+            split_any(text, &[" 修改群名为 ", "修改群名为", " 将群名修改为 ", "将群名修改为"])
+        {
+            return Self::GroupRename {
+                operator: Some(operator.to_string()),
+                name: name.to_string(),
+            };
+        }
+        if let Some(name) = text
+            .strip_prefix("群名已修改为")
+            .or_else(|| text.strip_prefix("群名称已更改为"))
+            && !name.trim().is_empty()
+        {
+            return Self::GroupRename {
+                operator: None,
+                name: name.trim().to_string(),
+            };
+        }
+        Self::Unknown
+    }
+
+    /// 返回事件类型的名称, 给 Python 侧区分用。
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::MemberJoin { .. } => "member_join",
+            Self::MemberLeave { .. } => "member_leave",
+            Self::MemberKick { .. } => "member_kick",
+            Self::MemberMute { .. } => "member_mute",
+            Self::MemberUnmute { .. } => "member_unmute",
+            Self::WholeMute { .. } => "whole_mute",
+            Self::AdminChange { .. } => "admin_change",
+            Self::Recall { .. } => "recall",
+            Self::GroupRename { .. } => "group_rename",
+            Self::Unknown => "unknown",
+        }
+    }
+
+    /// 返回被操作的人。
+    pub fn target(&self) -> Option<&str> {
+        match self {
+            Self::MemberJoin { target }
+            | Self::MemberLeave { target }
+            | Self::MemberKick { target, .. }
+            | Self::MemberMute { target, .. }
+            | Self::MemberUnmute { target, .. }
+            | Self::AdminChange { target, .. } => Some(target),
+            Self::Recall { target, .. } => target.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// 返回操作者。
+    pub fn operator(&self) -> Option<&str> {
+        match self {
+            Self::MemberKick { operator, .. }
+            | Self::MemberMute { operator, .. }
+            | Self::MemberUnmute { operator, .. }
+            | Self::WholeMute { operator, .. }
+            | Self::Recall { operator, .. } => Some(operator),
+            Self::GroupRename { operator, .. } => operator.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// 是否是成员离开 (包括被踢)
+    pub fn is_leave(&self) -> bool {
+        matches!(self, Self::MemberLeave { .. } | Self::MemberKick { .. })
+    }
+
+    /// 是否会让群成员列表 (包括名片/管理员) 发生变化
+    pub fn changes_members(&self) -> bool {
+        matches!(
+            self,
+            Self::MemberJoin { .. }
+                | Self::MemberLeave { .. }
+                | Self::MemberKick { .. }
+                | Self::MemberMute { .. }
+                | Self::MemberUnmute { .. }
+                | Self::AdminChange { .. }
+        )
+    }
+
+    /// 是否是禁言相关
+    pub fn is_mute(&self) -> bool {
+        matches!(
+            self,
+            Self::MemberMute { .. } | Self::MemberUnmute { .. } | Self::WholeMute { .. }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_member_changes() {
+        assert_eq!(
+            SystemEvent::parse("shenjack 加入了本群"),
+            SystemEvent::MemberJoin {
+                target: "shenjack".to_string()
+            }
+        );
+        assert_eq!(
+            SystemEvent::parse("shenjack 离开了本群"),
+            SystemEvent::MemberLeave {
+                target: "shenjack".to_string()
+            }
+        );
+        assert_eq!(
+            SystemEvent::parse("shenjack 被 管理员 踢出了本群"),
+            SystemEvent::MemberKick {
+                target: "shenjack".to_string(),
+                operator: "管理员".to_string()
+            }
+        );
+        assert_eq!(
+            SystemEvent::parse("shenjack 成为了管理员"),
+            SystemEvent::AdminChange {
+                target: "shenjack".to_string(),
+                enabled: true
+            }
+        );
+    }
+
+    #[test]
+    fn parse_mute_and_others() {
+        assert_eq!(
+            SystemEvent::parse("管理员 禁言了 shen jack 10分钟"),
+            SystemEvent::MemberMute {
+                target: "shen jack".to_string(),
+                operator: "管理员".to_string(),
+                duration: "10分钟".to_string()
+            }
+        );
+        assert_eq!(
+            SystemEvent::parse("管理员 解除了 shenjack 的禁言"),
+            SystemEvent::MemberUnmute {
+                target: "shenjack".to_string(),
+                operator: "管理员".to_string()
+            }
+        );
+        assert_eq!(
+            SystemEvent::parse("群主 开启了全员禁言"),
+            SystemEvent::WholeMute {
+                operator: "群主".to_string(),
+                enabled: true
+            }
+        );
+        assert_eq!(
+            SystemEvent::parse("群主 撤回了 shenjack 的一条消息"),
+            SystemEvent::Recall {
+                operator: "群主".to_string(),
+                target: Some("shenjack".to_string())
+            }
+        );
+        assert_eq!(
+            SystemEvent::parse("shenjack 撤回了一条消息"),
+            SystemEvent::Recall {
+                operator: "shenjack".to_string(),
+                target: None
+            }
+        );
+        assert_eq!(
+            SystemEvent::parse("群主 修改群名为 新的群"),
+            SystemEvent::GroupRename {
+                operator: Some("群主".to_string()),
+                name: "新的群".to_string()
+            }
+        );
+        assert_eq!(SystemEvent::parse("今天天气不错"), SystemEvent::Unknown);
+        assert!(SystemEvent::parse("a 被 b 踢了").is_leave());
+    }
+}
@@ -1,6 +1,43 @@
 //! 各聊天后端共享及专用的数据结构入口。
 
+use std::fmt::Display;
+
 /// 加载 `ica` 子模块。
 pub mod ica;
 /// 加载 `tailchat` 子模块。
 pub mod tailchat;
+
+/// 消息来源的聊天后端
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Platform {
+    /// Icalingua (QQ)
+    Ica,
+    /// Tailchat
+    Tailchat,
+}
+
+impl Platform {
+    /// 返回配置文件和命令里使用的平台名称。
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Platform::Ica => "ica",
+            Platform::Tailchat => "tailchat",
+        }
+    }
+
+    /// 从平台名称解析平台，大小写不敏感。
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "ica" | "icalingua" => Some(Platform::Ica),
+            "tailchat" => Some(Platform::Tailchat),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Platform {
+    /// 将当前值写入格式化输出。
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
@@ -2,6 +2,8 @@
 
 /// 加载 `api` 子模块。
 pub mod api;
+/// 加载 `bbcode` 子模块。
+pub mod bbcode;
 /// 加载 `messages` 子模块。
 pub mod messages;
 /// 加载 `status` 子模块。
@@ -0,0 +1,372 @@
+//! Tailchat 消息内容用的 BBCode, 包括构建和解析。
+//!
+//! 支持的标签: `[img]`、`[card type=file]`、`[at]`、`[url]`、`[code]` 和 `[emoji]`,
+//! 不认识的标签解析时保留原文。BBCode 没有转义, 文本里的 `[xxx]...[/xxx]` 也会被当成标签。
+
+use std::fmt::Display;
+
+use crate::data_struct::tailchat::UserId;
+
+/// 消息内容里的一段
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    /// 普通文本
+    Text(String),
+    /// `[at=<user-id>]昵称[/at]`
+    Mention { user_id: UserId, nickname: String },
+    /// `[img width=<w> height=<h>]地址[/img]`
+    Image {
+        url: String,
+        width: Option<u32>,
+        height: Option<u32>,
+    },
+    /// `[card type=file url=<地址>]文件名[/card]`
+    FileCard { url: String, name: String },
+    /// `[url=<地址>]文字[/url]` 或 `[url]地址[/url]`
+    Url { url: String, label: Option<String> },
+    /// `[code]代码[/code]`
+    Code(String),
+    /// `[emoji]名称[/emoji]`
+    Emoji(String),
+    /// 不认识的标签, `raw` 为原文, `body` 为标签里的内容
+    Raw { raw: String, body: String },
+}
+
+impl Segment {
+    /// 返回这一段的纯文本表示。
+    pub fn plain_text(&self) -> String {
+        match self {
+            Segment::Text(text) | Segment::Code(text) => text.clone(),
+            Segment::Raw { body, .. } => body.clone(),
+            Segment::Mention { nickname, .. } => format!("@{nickname}"),
+            Segment::Image { .. } => "[图片]".to_string(),
+            Segment::FileCard { name, .. } => format!("[文件 {name}]"),
+            Segment::Url { url, label } => label.clone().unwrap_or_else(|| url.clone()),
+            Segment::Emoji(name) => format!(":{name}:"),
+        }
+    }
+}
+
+impl Display for Segment {
+    /// 将当前值写入格式化输出。
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Segment::Text(text) | Segment::Raw { raw: text, .. } => write!(f, "{text}"),
+            Segment::Mention { user_id, nickname } => write!(f, "[at={user_id}]{nickname}[/at]"),
+            Segment::Image { url, width, height } => {
+                write!(f, "[img")?;
+                if let Some(width) = width {
+                    write!(f, " width={width}")?;
+                }
+                if let Some(height) = height {
+                    write!(f, " height={height}")?;
+                }
+                write!(f, "]{url}[/img]")
+            }
+            Segment::FileCard { url, name } => write!(f, "[card type=file url={url}]{name}[/card]"),
+            Segment::Url {
+                url,
+                label: Some(label),
+            } => write!(f, "[url={url}]{label}[/url]"),
+            Segment::Url { url, label: None } => write!(f, "[url]{url}[/url]"),
+            Segment::Code(code) => write!(f, "[code]{code}[/code]"),
+            Segment::Emoji(name) => write!(f, "[emoji]{name}[/emoji]"),
+        }
+    }
+}
+
+/// 由若干段组成的消息内容
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RichContent {
+    pub segments: Vec<Segment>,
+}
+
+impl RichContent {
+    /// 创建一个空的内容。
+    pub fn new() -> Self { Self::default() }
+
+    /// 追加一段。
+    pub fn push(mut self, segment: Segment) -> Self {
+        self.segments.push(segment);
+        self
+    }
+    /// 追加文本。
+    pub fn text(self, text: impl Into<String>) -> Self { self.push(Segment::Text(text.into())) }
+    /// 追加 @。
+    pub fn mention(self, user_id: impl Into<UserId>, nickname: impl Into<String>) -> Self {
+        self.push(Segment::Mention {
+            user_id: user_id.into(),
+            nickname: nickname.into(),
+        })
+    }
+    /// 追加图片, 尺寸未知时填 `None`。
+    pub fn image(self, url: impl Into<String>, size: Option<(u32, u32)>) -> Self {
+        self.push(Segment::Image {
+            url: url.into(),
+            width: size.map(|(width, _)| width),
+            height: size.map(|(_, height)| height),
+        })
+    }
+    /// 追加文件卡片。
+    pub fn file_card(self, url: impl Into<String>, name: impl Into<String>) -> Self {
+        self.push(Segment::FileCard {
+            url: url.into(),
+            name: name.into(),
+        })
+    }
+    /// 追加链接, `label` 为 `None` 时直接显示地址。
+    pub fn url(self, url: impl Into<String>, label: Option<String>) -> Self {
+        self.push(Segment::Url {
+            url: url.into(),
+            label,
+        })
+    }
+    /// 追加代码块。
+    pub fn code(self, code: impl Into<String>) -> Self { self.push(Segment::Code(code.into())) }
+    /// 追加表情。
+    pub fn emoji(self, name: impl Into<String>) -> Self { self.push(Segment::Emoji(name.into())) }
+
+    /// 返回内容里 @ 的所有用户。
+    pub fn mentions(&self) -> Vec<&UserId> {
+        self.segments
+            .iter()
+            .filter_map(|segment| match segment {
+                Segment::Mention { user_id, .. } => Some(user_id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// 返回去掉标签后的纯文本。
+    pub fn plain_text(&self) -> String { self.segments.iter().map(Segment::plain_text).collect() }
+
+    /// 解析 BBCode, 不认识或者没有闭合的标签保留原文。
+    pub fn parse(content: &str) -> Self {
+        let mut segments = Vec::new();
+        let mut text = String::new();
+        let mut rest = content;
+        while let Some(start) = rest.find('[') {
+            text.push_str(&rest[..start]);
+            rest = &rest[start..];
+            match parse_tag(rest) {
+                Some((segment, len)) => {
+                    if !text.is_empty() {
+                        segments.push(Segment::Text(std::mem::take(&mut text)));
+                    }
+                    segments.push(segment);
+                    rest = &rest[len..];
+                }
+                None => {
+                    text.push('[');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        text.push_str(rest);
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+        Self { segments }
+    }
+}
+
+impl Display for RichContent {
+    /// 将当前值写入格式化输出。
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for segment in &self.segments {
+            write!(f, "{segment}")?;
+        }
+        Ok(())
+    }
+}
+
+/// 从 `[` 开始解析一个标签, 返回这一段和占用的字节数。
+fn parse_tag(input: &str) -> Option<(Segment, usize)> {
+    let head_end = input.find(']')?;
+    let head = &input[1..head_end];
+    let (name, main, attrs) = parse_head(head)?;
+    let close = format!("[/{name}]");
+    let body_start = head_end + 1;
+    let body_len = input[body_start..].find(&close)?;
+    let body = &input[body_start..body_start + body_len];
+    let len = body_start + body_len + close.len();
+    let attr = |key: &str| attrs.iter().find(|(k, _)| *k == key).map(|(_, v)| *v);
+
+    let segment = match name {
+        "at" => Segment::Mention {
+            user_id: main?.to_string(),
+            nickname: body.to_string(),
+        },
+        "img" => Segment::Image {
+            url: body.to_string(),
+            width: attr("width").and_then(|v| v.parse().ok()),
+            height: attr("height").and_then(|v| v.parse().ok()),
+        },
+        "card" if attr("type") == Some("file") => Segment::FileCard {
+            url: attr("url")?.to_string(),
+            name: body.to_string(),
+        },
+        "url" => match main {
+            Some(url) => Segment::Url {
+                url: url.to_string(),
+                label: Some(body.to_string()),
+            },
+            None => Segment::Url {
+                url: body.to_string(),
+                label: None,
+            },
+        },
+        "code" => Segment::Code(body.to_string()),
+        "emoji" => Segment::Emoji(body.to_string()),
+        _ => Segment::Raw {
+            raw: input[..len].to_string(),
+            body: body.to_string(),
+        },
+    };
+    Some((segment, len))
+}
+
+/// 标签名, `=` 后面的主值, 其他属性
+type TagHead<'a> = (&'a str, Option<&'a str>, Vec<(&'a str, &'a str)>);
+
+/// 解析 `name=main key=value ...`。
+fn parse_head(head: &str) -> Option<TagHead<'_>> {
+    let mut parts = head.split_whitespace();
+    let first = parts.next()?;
+    let (name, main) = match first.split_once('=') {
+        Some((name, main)) => (name, Some(main)),
+        None => (first, None),
+    };
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+    let attrs = parts.filter_map(|part| part.split_once('=')).collect();
+    Some((name, main, attrs))
+}
+
+/// 从图片数据里读出宽高, 支持 PNG、GIF、JPEG 和 WebP。
+pub fn image_size(data: &[u8]) -> Option<(u32, u32)> {
+    let be16 = |at: usize| Some(u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?) as u32);
+    let le16 = |at: usize| Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?) as u32);
+    let be32 = |at: usize| Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?));
+    let le24 = |at: usize| {
+        let bytes = data.get(at..at + 3)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]))
+    };
+
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Some((be32(16)?, be32(20)?));
+    }
+    if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        return Some((le16(6)?, le16(8)?));
+    }
+    if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+        return match data.get(12..16)? {
+            b"VP8 " => Some((le16(26)? & 0x3fff, le16(28)? & 0x3fff)),
+            b"VP8L" => {
+                let bits = u32::from_le_bytes(data.get(21..25)?.try_into().ok()?);
+                Some(((bits & 0x3fff) + 1, ((bits >> 14) & 0x3fff) + 1))
+            }
+            b"VP8X" => Some((le24(24)? + 1, le24(27)? + 1)),
+            _ => None,
+        };
+    }
+    if data.starts_with(&[0xff, 0xd8]) {
+        // 逐个跳过 JPEG 段, 直到 SOF
+        let mut at = 2;
+        while at + 4 <= data.len() {
+            if data[at] != 0xff {
+                return None;
+            }
+            let marker = data[at + 1];
+            if marker == 0xff {
+                at += 1;
+                continue;
+            }
+            let len = be16(at + 2)? as usize;
+            let is_sof = matches!(marker, 0xc0..=0xcf) && !matches!(marker, 0xc4 | 0xc8 | 0xcc);
+            if is_sof {
+                return Some((be16(at + 7)?, be16(at + 5)?));
+            }
+            at += 2 + len;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build() {
+        let content = RichContent::new()
+            .mention("u1", "shenjack")
+            .text(" 看这个 ")
+            .image("http://a/b.png", Some((1918, 1329)))
+            .file_card("http://a/c.wav", "Engine.wav")
+            .url("http://a", None)
+            .emoji("smile");
+        assert_eq!(
+            content.to_string(),
+            "[at=u1]shenjack[/at] 看这个 [img width=1918 height=1329]http://a/b.png[/img]\
+             [card type=file url=http://a/c.wav]Engine.wav[/card][url]http://a[/url][emoji]smile[/emoji]"
+        );
+        assert_eq!(RichContent::parse(&content.to_string()), content);
+        assert_eq!(content.mentions(), [&"u1".to_string()]);
+    }
+
+    #[test]
+    fn parse() {
+        let content = RichContent::parse(
+            "[at=u1]a[/at] [b]粗体[/b] [url=http://x]链接[/url] [img]http://y[/img] [1] [code]x[/cod",
+        );
+        assert_eq!(
+            content.segments,
+            vec![
+                Segment::Mention {
+                    user_id: "u1".to_string(),
+                    nickname: "a".to_string()
+                },
+                Segment::Text(" ".to_string()),
+                Segment::Raw {
+                    raw: "[b]粗体[/b]".to_string(),
+                    body: "粗体".to_string()
+                },
+                Segment::Text(" ".to_string()),
+                Segment::Url {
+                    url: "http://x".to_string(),
+                    label: Some("链接".to_string())
+                },
+                Segment::Text(" ".to_string()),
+                Segment::Image {
+                    url: "http://y".to_string(),
+                    width: None,
+                    height: None
+                },
+                Segment::Text(" [1] [code]x[/cod".to_string()),
+            ]
+        );
+        assert_eq!(content.plain_text(), "@a 粗体 链接 [图片] [1] [code]x[/cod");
+    }
+
+    #[test]
+    fn probe_size() {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend_from_slice(&640u32.to_be_bytes());
+        png.extend_from_slice(&480u32.to_be_bytes());
+        assert_eq!(image_size(&png), Some((640, 480)));
+
+        let gif = b"GIF89a\x20\x00\x10\x00";
+        assert_eq!(image_size(gif), Some((32, 16)));
+
+        // SOI, APP0 (长度 4), SOF0
+        let jpeg = [
+            0xff, 0xd8, 0xff, 0xe0, 0x00, 0x04, 0x00, 0x00, 0xff, 0xc0, 0x00, 0x11, 0x08, 0x01,
+            0x00, 0x02, 0x00,
+        ];
+        assert_eq!(image_size(&jpeg), Some((512, 256)));
+
+        assert_eq!(image_size(b"not an image"), None);
+    }
+}
@@ -1,10 +1,12 @@
 //! Tailchat 收发消息及消息更新数据结构。
 
 use std::fmt::Display;
+use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
 use serde_json::{Value as JsonValue, json};
 
+use crate::data_struct::tailchat::bbcode::{RichContent, Segment};
 use crate::data_struct::tailchat::{ConverseId, GroupId, MessageId, UserId};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -27,9 +29,8 @@ pub struct ReceiveMessage {
     /// 是否有回复?
     #[serde(rename = "hasRecall")]
     pub has_recall: bool,
-    /// 暂时懒得解析这玩意
-    /// 准确来说是不确定内容, 毕竟没细看 API
-    pub meta: Option<JsonValue>,
+    /// 消息的元数据 (提及和回复)
+    pub meta: Option<MessageMeta>,
     /// 也懒得解析这玩意
     pub reactions: Vec<JsonValue>,
     /// 创建时间
@@ -40,19 +41,71 @@ pub struct ReceiveMessage {
     pub updated_at: String,
 }
 
+/// 收到的消息的元数据
+///
+/// 字段都可能缺失, 缺失时为空
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct MessageMeta {
+    /// 被 @ 的人的ID
+    #[serde(default)]
+    pub mentions: Vec<UserId>,
+    /// 被回复的消息
+    #[serde(default)]
+    pub reply: Option<ReplyInfo>,
+}
+
+/// 收到的消息里被回复的消息
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReplyInfo {
+    /// 被回复的消息ID
+    #[serde(rename = "_id")]
+    pub msg_id: MessageId,
+    /// 被回复的消息的发送者ID
+    pub author: UserId,
+    /// 被回复的消息内容
+    #[serde(default)]
+    pub content: String,
+}
+
 impl ReceiveMessage {
     /// 判断当前值是否满足 `reply` 条件。
-    pub fn is_reply(&self) -> bool {
-        if let Some(meta) = &self.meta {
-            meta.get("reply").is_some()
-        } else {
-            false
-        }
+    pub fn is_reply(&self) -> bool { self.reply().is_some() }
+
+    /// 返回被回复的消息。
+    pub fn reply(&self) -> Option<&ReplyInfo> { self.meta.as_ref()?.reply.as_ref() }
+
+    /// 返回被 @ 的人的ID。
+    pub fn mentions(&self) -> &[UserId] {
+        self.meta.as_ref().map(|meta| meta.mentions.as_slice()).unwrap_or_default()
     }
 
+    /// 判断某个用户是否被 @ 了
+    ///
+    /// `meta.mentions` 和正文里的 `[at=<id>]` 都算
+    pub fn mentions_user(&self, user_id: &str) -> bool {
+        self.mentions().iter().any(|id| id == user_id)
+            || self.content.contains(&format!("[at={user_id}]"))
+    }
+
+    /// 判断 bot 自己是否被 @ 了。
+    pub fn is_mentioned(&self) -> bool {
+        crate::MainStatus::with_tailchat_status(|status| self.mentions_user(&status.user_id))
+            .unwrap_or(false)
+    }
+
+    /// 把消息内容解析为结构化的段。
+    pub fn rich_content(&self) -> RichContent { RichContent::parse(&self.content) }
+
+    /// 判断是否为私信/多人会话里的消息, 也就是没有服务器ID。
+    pub fn is_dm(&self) -> bool { self.group_id.is_none() }
+
+    /// 判断是否为服务器里的消息。
+    pub fn is_group(&self) -> bool { self.group_id.is_some() }
+
     /// 判断当前值是否满足 `from_self` 条件。
     pub fn is_from_self(&self) -> bool {
-        crate::MainStatus::global_tailchat_status().user_id == self.sender_id
+        crate::MainStatus::with_tailchat_status(|status| status.user_id == self.sender_id)
+            .unwrap_or(false)
     }
 
     /// 创建一个对这条消息的回复
@@ -88,53 +141,154 @@ impl Display for ReceiveMessage {
     }
 }
 
-#[derive(Debug, Clone, Default)]
+/// 收件箱 (`notify:chat.inbox.append`) 里的一条记录, 被 @ 时会收到
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InboxItem {
+    /// 记录ID
+    #[serde(rename = "_id")]
+    pub inbox_id: String,
+    /// 收件人ID, 也就是 bot 自己
+    #[serde(rename = "userId")]
+    pub user_id: UserId,
+    /// 记录类型, 被 @ 时为 `message`
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// 是否已读
+    #[serde(default)]
+    pub readed: bool,
+    /// `message` 类型的记录带的消息摘要
+    ///
+    /// HTTP 回调里叫 `payload`
+    #[serde(alias = "payload")]
+    pub message: Option<InboxMessage>,
+    /// 创建时间
+    #[serde(rename = "createdAt", default)]
+    pub created_at: String,
+}
+
+/// 收件箱记录里的消息摘要
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InboxMessage {
+    /// 服务器ID
+    /// 在私聊中不存在
+    #[serde(rename = "groupId")]
+    pub group_id: Option<GroupId>,
+    /// 会话ID
+    #[serde(rename = "converseId")]
+    pub converse_id: ConverseId,
+    /// 消息ID
+    #[serde(rename = "messageId")]
+    pub msg_id: MessageId,
+    /// 发送者ID
+    #[serde(rename = "messageAuthor")]
+    pub author: UserId,
+    /// 消息摘要
+    #[serde(rename = "messageSnippet", default)]
+    pub snippet: String,
+    /// 去掉格式后的消息内容
+    #[serde(rename = "messagePlainContent")]
+    pub plain_content: Option<String>,
+}
+
+impl InboxItem {
+    /// 把 `message` 类型的记录转换成收到的消息
+    ///
+    /// 记录里只有纯文本内容, 没有原始的 BBCode 和回复信息; 记录本身就是被 @ 产生的, 所以提及收件人
+    pub fn to_receive_message(&self) -> Option<ReceiveMessage> {
+        let msg = self.message.as_ref()?;
+        Some(ReceiveMessage {
+            msg_id: msg.msg_id.clone(),
+            content: msg.content().to_string(),
+            sender_id: msg.author.clone(),
+            group_id: msg.group_id.clone(),
+            converse_id: msg.converse_id.clone(),
+            has_recall: false,
+            meta: Some(MessageMeta {
+                mentions: vec![self.user_id.clone()],
+                reply: None,
+            }),
+            reactions: Vec::new(),
+            created_at: self.created_at.clone(),
+            updated_at: self.created_at.clone(),
+        })
+    }
+}
+
+impl InboxMessage {
+    /// 返回消息内容, 没有纯文本时用摘要。
+    pub fn content(&self) -> &str { self.plain_content.as_deref().unwrap_or(&self.snippet) }
+}
+
+impl Display for InboxItem {
+    /// 将当前值写入格式化输出。
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.message {
+            Some(msg) => write!(
+                f,
+                "{}|{:?}-{}|{}|{}",
+                msg.msg_id,
+                msg.group_id,
+                msg.converse_id,
+                msg.author,
+                msg.content()
+            ),
+            None => write!(f, "{}|{}", self.inbox_id, self.kind),
+        }
+    }
+}
+
+/// 附件数据的来源
+#[derive(Debug, Clone)]
+pub enum FileSource {
+    /// 内存里的数据
+    Bytes(Vec<u8>),
+    /// 本地文件, 上传时边读边发, 不整个读进内存
+    Path(PathBuf),
+}
+
+#[derive(Debug, Clone)]
 pub enum SendingFile {
-    #[default]
-    None,
     /// 需要生成
-    /// [img height=1329 width=1918]{BACKEND}/static/files/6602e20d7b8d10675758e36b/8db505b87bdf9fb309467abcec4d8e2a.png[/img]
-    Image { file: Vec<u8>, name: String },
+    /// [img width=1918 height=1329]{BACKEND}/static/files/6602e20d7b8d10675758e36b/8db505b87bdf9fb309467abcec4d8e2a.png[/img]
+    Image { file: FileSource, name: String },
     /// [card type=file url={BACKEND}/static/files/6602e20d7b8d10675758e36b/9df28943d17b9713cb0ea9625f37d015.wav]Engine.wav[/card]
-    File { file: Vec<u8>, name: String },
+    File { file: FileSource, name: String },
 }
 
 impl SendingFile {
-    /// 判断当前值是否满足 `some` 条件。
-    pub fn is_some(&self) -> bool { !matches!(self, Self::None) }
     /// 判断当前值是否满足 `image` 条件。
     pub fn is_image(&self) -> bool { matches!(self, Self::Image { .. }) }
     /// 判断当前值是否满足 `file` 条件。
     pub fn is_file(&self) -> bool { matches!(self, Self::File { .. }) }
 
-    /// 返回附件文件内容。
-    pub fn file_data(&self) -> Vec<u8> {
+    /// 返回附件数据的来源。
+    pub fn source(&self) -> &FileSource {
         match self {
-            Self::Image { file, .. } => file.clone(),
-            Self::File { file, .. } => file.clone(),
-            _ => vec![],
+            Self::Image { file, .. } | Self::File { file, .. } => file,
         }
     }
 
     /// 返回附件文件名。
-    pub fn file_name(&self) -> String {
+    pub fn file_name(&self) -> &str {
         match self {
-            Self::Image { name, .. } => name.clone(),
-            Self::File { name, .. } => name.clone(),
-            _ => "".to_string(),
+            Self::Image { name, .. } | Self::File { name, .. } => name,
         }
     }
-    /// 生成插件清单的 Markdown 描述。
-    pub fn gen_markdown(&self, backend_path: &str) -> String {
-        match self {
-            Self::Image { .. } => {
-                format!("[img]{backend_path}[/img]")
-            }
-            Self::File { name, .. } => {
-                format!("[card type=file url={backend_path}]{name}[/card]")
-            }
-            _ => unreachable!(),
-        }
+
+    /// 生成上传后的附件在消息里的 BBCode, `size` 为图片的宽高。
+    pub fn gen_markdown(&self, backend_path: &str, size: Option<(u32, u32)>) -> String {
+        let segment = match self {
+            Self::Image { .. } => Segment::Image {
+                url: backend_path.to_string(),
+                width: size.map(|(width, _)| width),
+                height: size.map(|(_, height)| height),
+            },
+            Self::File { name, .. } => Segment::FileCard {
+                url: backend_path.to_string(),
+                name: name.clone(),
+            },
+        };
+        segment.to_string()
     }
 }
 
@@ -164,9 +318,9 @@ pub struct SendingMessage {
     pub group_id: Option<GroupId>,
     /// 消息的元数据
     pub meta: Option<ReplyMeta>,
-    /// 额外携带的文件
+    /// 额外携带的附件, 按顺序接在内容后面
     #[serde(skip)]
-    pub file: SendingFile,
+    pub files: Vec<SendingFile>,
 }
 
 impl SendingMessage {
@@ -182,7 +336,7 @@ impl SendingMessage {
             converse_id,
             group_id,
             meta,
-            file: SendingFile::None,
+            files: Vec::new(),
         }
     }
     /// 创建并初始化对应的数据结构。
@@ -196,14 +350,17 @@ impl SendingMessage {
             converse_id,
             group_id,
             meta: None,
-            file: SendingFile::None,
+            files: Vec::new(),
         }
     }
     /// 判断消息是否包含文件。
-    pub fn contain_file(&self) -> bool { self.file.is_some() }
+    pub fn contain_file(&self) -> bool { !self.files.is_empty() }
 
-    /// 向消息追加图片。
-    pub fn add_img(&mut self, file: SendingFile) { self.file = file; }
+    /// 向消息追加一个附件 (图片或文件)。
+    pub fn add_file(&mut self, file: SendingFile) { self.files.push(file); }
+
+    /// 用一个附件替换掉已有的所有附件。
+    pub fn set_file(&mut self, file: SendingFile) { self.files = vec![file]; }
 
     /// 返回当前值的 `value` 表示。
     pub fn as_value(&self) -> JsonValue { serde_json::to_value(self).unwrap() }
@@ -256,3 +413,92 @@ impl Serialize for ReplyMeta {
         map.serialize(serializer)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_meta() {
+        let value = json!({
+            "_id": "m1",
+            "content": "[at=bot]bot[/at] 你好",
+            "author": "u1",
+            "groupId": "g1",
+            "converseId": "c1",
+            "hasRecall": false,
+            "meta": {"mentions": ["bot"], "reply": {"_id": "m0", "author": "u2", "content": "hi"}},
+            "reactions": [],
+            "createdAt": "",
+            "updatedAt": ""
+        });
+        let msg: ReceiveMessage = serde_json::from_value(value).unwrap();
+        assert!(msg.is_reply());
+        assert_eq!(msg.reply().unwrap().author, "u2");
+        assert!(msg.mentions_user("bot"));
+        assert!(!msg.mentions_user("u3"));
+
+        // meta 为空或只在正文里 @
+        let value = json!({
+            "_id": "m2",
+            "content": "[at=u3]u3[/at]",
+            "author": "u1",
+            "converseId": "c1",
+            "hasRecall": false,
+            "meta": {},
+            "reactions": [],
+            "createdAt": "",
+            "updatedAt": ""
+        });
+        let msg: ReceiveMessage = serde_json::from_value(value).unwrap();
+        assert!(!msg.is_reply());
+        assert!(msg.mentions().is_empty());
+        assert!(msg.mentions_user("u3"));
+    }
+
+    #[test]
+    fn parse_inbox() {
+        let value = json!({
+            "_id": "i1",
+            "userId": "bot",
+            "type": "message",
+            "readed": false,
+            "message": {
+                "groupId": "g1",
+                "converseId": "c1",
+                "messageId": "m1",
+                "messageAuthor": "u1",
+                "messageSnippet": "@bot 你好"
+            },
+            "createdAt": ""
+        });
+        let item: InboxItem = serde_json::from_value(value).unwrap();
+        let message = item.to_receive_message().unwrap();
+        assert_eq!(message.sender_id, "u1");
+        assert!(message.mentions_user("bot"));
+        let msg = item.message.unwrap();
+        assert_eq!(msg.author, "u1");
+        assert_eq!(msg.content(), "@bot 你好");
+    }
+
+    #[test]
+    fn parse_callback() {
+        // HTTP 回调里消息摘要放在 `payload`
+        let value = json!({
+            "_id": "i2",
+            "userId": "bot",
+            "type": "message",
+            "payload": {
+                "converseId": "c2",
+                "messageId": "m2",
+                "messageAuthor": "u2",
+                "messageSnippet": "@bot 帮助",
+                "messagePlainContent": "@bot /bot-help"
+            }
+        });
+        let item: InboxItem = serde_json::from_value(value).unwrap();
+        let message = item.to_receive_message().unwrap();
+        assert_eq!(message.content, "@bot /bot-help");
+        assert!(message.is_dm());
+    }
+}
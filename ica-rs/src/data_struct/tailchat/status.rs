@@ -5,7 +5,7 @@ use std::sync::Arc;
 use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
 
-use crate::data_struct::tailchat::UserId;
+use crate::data_struct::tailchat::{ConverseId, GroupId, UserId};
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct LoginData {
@@ -28,6 +28,8 @@ impl LoginData {
             email: self.email.clone(),
             jwt_token: self.jwt.clone(),
             avatar: self.avatar.clone(),
+            groups: Vec::new(),
+            converses: Vec::new(),
         };
         crate::MainStatus::update_tailchat_status(status);
     }
@@ -51,6 +53,107 @@ pub struct UpdateDMConverse {
     pub updated_at: String,
 }
 
+/// 服务器 (`group.getUserGroups` 返回的一项)
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GroupInfo {
+    /// 服务器ID
+    #[serde(rename = "_id")]
+    pub id: GroupId,
+    /// 服务器名称
+    #[serde(default)]
+    pub name: String,
+    /// 服务器所有者
+    #[serde(default)]
+    pub owner: UserId,
+    /// 成员
+    #[serde(default)]
+    pub members: Vec<GroupMember>,
+    /// 面板, 文字频道也是面板
+    #[serde(default)]
+    pub panels: Vec<GroupPanel>,
+}
+
+impl GroupInfo {
+    /// 返回所有文字频道, 它们的ID就是会话ID。
+    pub fn text_panels(&self) -> impl Iterator<Item = &GroupPanel> {
+        self.panels.iter().filter(|panel| panel.is_text())
+    }
+
+    /// 判断某个用户是否在这个服务器里。
+    pub fn has_member(&self, user_id: &str) -> bool {
+        self.members.iter().any(|member| member.user_id == user_id)
+    }
+}
+
+/// 服务器成员
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GroupMember {
+    /// 用户ID
+    #[serde(rename = "userId")]
+    pub user_id: UserId,
+    /// 身份组ID
+    #[serde(default)]
+    pub roles: Vec<String>,
+    /// 禁言到什么时候, 没有禁言时为 `None`
+    #[serde(rename = "muteUntil", default)]
+    pub mute_until: Option<String>,
+}
+
+/// 服务器里的面板
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GroupPanel {
+    /// 面板ID, 文字频道的面板ID就是会话ID
+    pub id: ConverseId,
+    /// 面板名称
+    #[serde(default)]
+    pub name: String,
+    /// 面板类型, 0 为文字频道, 1 为分组, 2 为插件面板
+    #[serde(rename = "type", default)]
+    pub panel_type: i64,
+    /// 所属分组的面板ID
+    #[serde(rename = "parentId", default)]
+    pub parent_id: Option<String>,
+}
+
+impl GroupPanel {
+    /// 判断是否为文字频道。
+    pub fn is_text(&self) -> bool { self.panel_type == 0 }
+}
+
+/// 私信/多人会话 (`chat.converse.findConverseInfo` 的返回)
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ConverseInfo {
+    /// 会话ID
+    #[serde(rename = "_id")]
+    pub id: ConverseId,
+    /// 类型, `DM` / `Multi`
+    #[serde(rename = "type", default)]
+    pub converse_type: String,
+    /// 成员
+    #[serde(default)]
+    pub members: Vec<UserId>,
+    /// 多人会话的名称
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+impl ConverseInfo {
+    /// 判断是否为一对一私信。
+    pub fn is_dm(&self) -> bool { self.converse_type == "DM" }
+}
+
+impl From<UpdateDMConverse> for ConverseInfo {
+    /// 将来源值转换为当前类型。
+    fn from(update: UpdateDMConverse) -> Self {
+        Self {
+            id: update.id,
+            converse_type: update.converse_type,
+            members: update.members,
+            name: None,
+        }
+    }
+}
+
 #[allow(unused)]
 pub type Writeable<T> = Arc<RwLock<T>>;
 
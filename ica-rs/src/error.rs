@@ -12,12 +12,22 @@ pub enum IcaError {
     SocketIoError(rust_socketio::error::Error),
     /// 登录失败
     LoginFailed(String),
-    /// 群成员接口只接受负数群聊房间 ID。
+    /// 群成员/群管理接口只接受负数群聊房间 ID。
     InvalidGroupRoomId(i64),
     /// 群成员请求在期限内没有收到 ACK。
     GroupMembersTimeout(i64),
     /// 群成员 ACK 无法按 Bridge 契约解析。
     InvalidGroupMembersResponse(String),
+    /// 消息没能发出去 (队列已满/发送失败)
+    SendFailed(String),
+    /// 消息已发出, 但在期限内没有收到 bridge 的回显
+    DeliveryTimeout(i64),
+    /// bridge 返回了 `messageError`
+    DeliveryRejected(String),
+    /// 当前没有等待处理的 QQ 登录验证
+    NoLoginChallenge,
+    /// 当前 bridge 不支持这个接口
+    Unsupported(String),
 }
 
 #[derive(Debug)]
@@ -28,6 +38,24 @@ pub enum TailchatError {
     ReqwestError(reqwest::Error),
     /// 登录失败
     LoginFailed(String),
+    /// 请求在期限内没有收到 ACK
+    /// action
+    RequestTimeout(String),
+    /// 服务端返回了失败
+    /// action, 错误信息
+    RequestFailed(String, String),
+    /// ACK 无法解析
+    /// action, 原始数据
+    InvalidResponse(String, String),
+    /// 读取要上传的附件失败
+    /// 文件名, io 错误
+    ReadFileFailed(String, std::io::Error),
+    /// 附件上传失败
+    /// 文件名, 错误信息
+    UploadFailed(String, String),
+    /// 回调模式监听端口失败
+    /// 监听地址, io 错误
+    CallbackBindFailed(String, std::io::Error),
 }
 
 #[derive(Debug)]
@@ -75,6 +103,24 @@ pub enum PyPluginInitError {
     PyError(pyo3::PyErr),
 }
 
+/// 命令行参数错误
+#[derive(Debug, PartialEq, Eq)]
+pub enum CliError {
+    /// 未知的子命令
+    UnknownCommand(String),
+    /// 未知的选项
+    UnknownOption(String),
+    /// 选项缺少值
+    MissingValue(String),
+    /// 子命令缺少参数
+    /// 子命令, 参数
+    MissingArgument(String, String),
+    /// 多出来的参数
+    UnexpectedArgument(String),
+    /// 无效的日志等级
+    InvalidLogLevel(String),
+}
+
 // #[derive(Debug)]
 // pub enum PyPluginManifestError {
 //     ///
@@ -112,7 +158,7 @@ impl Display for IcaError {
             IcaError::SocketIoError(e) => write!(f, "Socket IO 链接错误: {e}"),
             IcaError::LoginFailed(e) => write!(f, "登录失败: {e}"),
             IcaError::InvalidGroupRoomId(room_id) => {
-                write!(f, "群成员查询只接受负数群聊 room_id，收到 {room_id}")
+                write!(f, "群成员/群管理接口只接受负数群聊 room_id，收到 {room_id}")
             }
             IcaError::GroupMembersTimeout(room_id) => {
                 write!(f, "群 {room_id} 的成员列表 ACK 等待超时")
@@ -120,6 +166,13 @@ impl Display for IcaError {
             IcaError::InvalidGroupMembersResponse(message) => {
                 write!(f, "群成员列表 ACK 解析失败: {message}")
             }
+            IcaError::SendFailed(message) => write!(f, "消息发送失败: {message}"),
+            IcaError::DeliveryTimeout(room_id) => {
+                write!(f, "发往 {room_id} 的消息等待送达确认超时")
+            }
+            IcaError::DeliveryRejected(message) => write!(f, "bridge 拒绝了消息: {message}"),
+            IcaError::NoLoginChallenge => write!(f, "当前没有等待处理的 QQ 登录验证"),
+            IcaError::Unsupported(feature) => write!(f, "当前 bridge 不支持 `{feature}`"),
         }
     }
 }
@@ -131,6 +184,20 @@ impl Display for TailchatError {
             TailchatError::SocketIoError(e) => write!(f, "Socket IO 链接错误: {e}"),
             TailchatError::ReqwestError(e) => write!(f, "Reqwest 错误: {e}"),
             TailchatError::LoginFailed(e) => write!(f, "登录失败: {e}"),
+            TailchatError::RequestTimeout(action) => write!(f, "{action} 等待 ACK 超时"),
+            TailchatError::RequestFailed(action, message) => {
+                write!(f, "{action} 失败: {message}")
+            }
+            TailchatError::InvalidResponse(action, raw) => {
+                write!(f, "{action} 的 ACK 无法解析: {raw}")
+            }
+            TailchatError::ReadFileFailed(name, e) => write!(f, "读取附件 {name} 失败: {e}"),
+            TailchatError::UploadFailed(name, message) => {
+                write!(f, "附件 {name} 上传失败: {message}")
+            }
+            TailchatError::CallbackBindFailed(listen, e) => {
+                write!(f, "回调模式监听 {listen} 失败: {e}")
+            }
         }
     }
 }
@@ -212,6 +279,22 @@ impl Display for PyPluginInitError {
     }
 }
 
+impl Display for CliError {
+    /// 将当前值写入格式化输出。
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CliError::UnknownCommand(command) => write!(f, "未知的子命令: {command}"),
+            CliError::UnknownOption(option) => write!(f, "未知的选项: {option}"),
+            CliError::MissingValue(option) => write!(f, "选项 {option} 缺少值"),
+            CliError::MissingArgument(command, arg) => write!(f, "{command} 缺少参数 {arg}"),
+            CliError::UnexpectedArgument(arg) => write!(f, "多出来的参数: {arg}"),
+            CliError::InvalidLogLevel(level) => {
+                write!(f, "无效的日志等级: {level}, 可选 error/warn/info/debug/trace")
+            }
+        }
+    }
+}
+
 impl Error for IcaError {
     /// 返回插件源码。
     fn source(&self) -> Option<&(dyn Error + 'static)> {
@@ -220,7 +303,12 @@ impl Error for IcaError {
             IcaError::LoginFailed(_)
             | IcaError::InvalidGroupRoomId(_)
             | IcaError::GroupMembersTimeout(_)
-            | IcaError::InvalidGroupMembersResponse(_) => None,
+            | IcaError::InvalidGroupMembersResponse(_)
+            | IcaError::SendFailed(_)
+            | IcaError::DeliveryTimeout(_)
+            | IcaError::DeliveryRejected(_)
+            | IcaError::NoLoginChallenge
+            | IcaError::Unsupported(_) => None,
         }
     }
 }
@@ -231,7 +319,14 @@ impl Error for TailchatError {
         match self {
             TailchatError::SocketIoError(e) => Some(e),
             TailchatError::ReqwestError(e) => Some(e),
-            TailchatError::LoginFailed(_) => None,
+            TailchatError::ReadFileFailed(_, e) | TailchatError::CallbackBindFailed(_, e) => {
+                Some(e)
+            }
+            TailchatError::LoginFailed(_)
+            | TailchatError::RequestTimeout(_)
+            | TailchatError::RequestFailed(..)
+            | TailchatError::InvalidResponse(..)
+            | TailchatError::UploadFailed(..) => None,
         }
     }
 }
@@ -269,6 +364,8 @@ impl Error for PyPluginInitError {
     }
 }
 
+impl Error for CliError {}
+
 impl From<PyPluginError> for PyErr {
     /// 将来源值转换为当前类型。
     fn from(value: PyPluginError) -> Self {
@@ -1,9 +1,17 @@
 //! Icalingua bridge 的 Socket.IO 客户端入口和事件注册。
 
+/// 按协议版本推算 bridge 支持的接口。
+pub mod capability;
 /// bridge 请求发送、鉴权及群管理接口。
 pub mod client;
+/// bridge 推送数据的防御性解析。
+pub mod decode;
 /// bridge 主动推送事件和 ACK 响应处理器。
 pub mod events;
+/// 加群申请的自动审批策略。
+pub mod join_policy;
+/// QQ 登录验证的交互处理。
+pub mod login;
 
 // use std::sync::OnceLock;
 
@@ -15,7 +23,8 @@ use tracing::{Level, event, span};
 
 use crate::config::IcaConfig;
 use crate::error::{ClientResult, IcaError};
-use crate::{StopGetter, version_str};
+use crate::ica::client::queue::Priority;
+use crate::{StopGetter, instance, version_str};
 
 /// icalingua 客户端的兼容版本号
 pub const ICA_PROTOCOL_VERSION: &str = "2.26.0";
@@ -48,48 +57,65 @@ pub const ICA_PROTOCOL_VERSION: &str = "2.26.0";
 
 /// 连接 Icalingua bridge、注册协议事件，并持续运行到收到停止信号。
 pub async fn start_ica(config: &IcaConfig, stop_reciver: StopGetter) -> ClientResult<(), IcaError> {
-    let span = span!(Level::INFO, "Icalingua Client");
+    let span = span!(Level::INFO, "Icalingua Client", instance = %config.id);
     let _enter = span.enter();
 
     event!(Level::INFO, "ica-async-rs v{} initing", crate::ICA_VERSION);
+    client::queue::start_queue(config.rate_limit.clone());
 
+    // 所有事件回调都运行在这个实例的作用域里
+    let id = config.id.as_str();
     let start_connect_time = std::time::Instant::now();
     let socket = match ClientBuilder::new(config.host.clone())
         .transport_type(TransportType::Websocket)
-        .on_any(async_any_callback!(events::any_event))
-        .on("requireAuth", async_callback!(client::sign_callback))
-        .on("message", async_callback!(events::connect_callback))
-        .on("authSucceed", async_callback!(events::connect_callback))
-        .on("authFailed", async_callback!(events::connect_callback))
-        .on("messageSuccess", async_callback!(events::success_message))
-        .on("messageError", async_callback!(events::failed_message))
+        .on_any(instance::any_callback(id, async_any_callback!(events::any_event)))
+        .on("requireAuth", instance::callback(id, async_callback!(client::sign_callback)))
+        .on("message", instance::callback(id, async_callback!(events::connect_callback)))
+        .on("authSucceed", instance::callback(id, async_callback!(events::connect_callback)))
+        .on("authFailed", instance::callback(id, async_callback!(events::connect_callback)))
+        .on(
+            "messageSuccess",
+            instance::callback(id, async_callback!(events::success_message)),
+        )
+        .on("messageError", instance::callback(id, async_callback!(events::failed_message)))
         // 在线状态
-        .on("onlineData", async_callback!(events::get_online_data))
-        .on("setOnline", async_callback!(events::set_online))
-        .on("setOffline", async_callback!(events::set_offline))
-        .on("setShutUp", async_callback!(events::set_shut_up))
+        .on("onlineData", instance::callback(id, async_callback!(events::get_online_data)))
+        .on("setOnline", instance::callback(id, async_callback!(events::set_online)))
+        .on("setOffline", instance::callback(id, async_callback!(events::set_offline)))
+        .on("setShutUp", instance::callback(id, async_callback!(events::set_shut_up)))
         // 房间和消息状态
-        .on("setAllRooms", async_callback!(events::update_all_room))
-        .on("updateRoom", async_callback!(events::update_room))
-        .on("setMessages", async_callback!(events::set_messages))
-        .on("addMessage", async_callback!(events::add_message))
-        .on("deleteMessage", async_callback!(events::delete_message))
-        .on("renewMessage", async_callback!(events::renew_message))
-        .on("renewMessageURL", async_callback!(events::renew_message_url))
-        // 好友和群申请。handleRequest 是 client -> bridge，不是推送事件。
-        .on("sendAddRequest", async_callback!(events::join_request))
+        .on("setAllRooms", instance::callback(id, async_callback!(events::update_all_room)))
+        .on("updateRoom", instance::callback(id, async_callback!(events::update_room)))
+        .on("setMessages", instance::callback(id, async_callback!(events::set_messages)))
+        .on("addMessage", instance::callback(id, async_callback!(events::add_message)))
+        .on("deleteMessage", instance::callback(id, async_callback!(events::delete_message)))
+        .on("renewMessage", instance::callback(id, async_callback!(events::renew_message)))
+        .on(
+            "renewMessageURL",
+            instance::callback(id, async_callback!(events::renew_message_url)),
+        )
+        // 好友和群申请。处理结果通过 client::handle_request 发回 bridge。
+        .on("sendAddRequest", instance::callback(id, async_callback!(events::join_request)))
         // bridge 错误
-        .on("notifyError", async_callback!(events::notify_error))
-        .on("fatal", async_callback!(events::fatal_error))
+        .on("notifyError", instance::callback(id, async_callback!(events::notify_error)))
+        .on("fatal", instance::callback(id, async_callback!(events::fatal_error)))
+        // 连接出错和重连失败
+        .on(Event::Error, instance::callback(id, async_callback!(events::connection_error)))
         // bridge 登录流程
-        .on("requestSetup", async_callback!(events::request_setup))
-        .on("login-verify", async_callback!(events::login_verify))
-        .on("login-qrcodeLogin", async_callback!(events::login_qrcode))
-        .on("login-smsCodeVerify", async_callback!(events::login_sms_code))
-        .on("login-error", async_callback!(events::login_error))
-        .on("login-slider", async_callback!(events::login_slider))
+        .on("requestSetup", instance::callback(id, async_callback!(events::request_setup)))
+        .on("login-verify", instance::callback(id, async_callback!(events::login_verify)))
+        .on(
+            "login-qrcodeLogin",
+            instance::callback(id, async_callback!(events::login_qrcode)),
+        )
+        .on(
+            "login-smsCodeVerify",
+            instance::callback(id, async_callback!(events::login_sms_code)),
+        )
+        .on("login-error", instance::callback(id, async_callback!(events::login_error)))
+        .on("login-slider", instance::callback(id, async_callback!(events::login_slider)))
         // Milky adapter 目前会额外发送该事件；onlineData 仍是主要状态来源。
-        .on("login", async_callback!(events::bridge_login))
+        .on("login", instance::callback(id, async_callback!(events::bridge_login)))
         // 面向 GUI，用不到：
         // setAllChatGroups, notify, notifyMessage, addMessageText, closeLoading, syncRead
         .connect()
@@ -101,6 +127,7 @@ pub async fn start_ica(config: &IcaConfig, stop_reciver: StopGetter) -> ClientRe
                 "{}",
                 format!("socketio connected time: {:?}", start_connect_time.elapsed()).on_cyan()
             );
+            client::set_notice_client(Some(client.clone()));
             client
         }
         Err(e) => {
@@ -117,20 +144,18 @@ pub async fn start_ica(config: &IcaConfig, stop_reciver: StopGetter) -> ClientRe
                 None,
             );
             // 这可是 qq, 要保命
-            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-
+            // 交给发送队列限速
             event!(Level::INFO, "发送启动消息到房间: {}", room);
 
-            if let Err(e) =
-                socket.emit("sendMessage", serde_json::to_value(startup_msg).unwrap()).await
-            {
-                event!(Level::INFO, "启动信息发送失败 房间:{}|e:{}", room, e);
+            if !client::send_message_with_priority(&socket, &startup_msg, Priority::Low).await {
+                event!(Level::INFO, "启动信息发送失败 房间:{}", room);
             }
         }
     }
     // 等待停止信号
     event!(Level::INFO, "{}", "ica client waiting for stop signal".purple());
     stop_reciver.await.ok();
+    client::set_notice_client(None);
     event!(Level::INFO, "{}", "socketio client stopping".yellow());
     match socket.disconnect().await {
         Ok(_) => {
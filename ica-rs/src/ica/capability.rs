@@ -0,0 +1,281 @@
+//! 按 bridge 报告的协议版本和适配器推算可用的接口。
+//!
+//! 鉴权时 bridge 会发来 `protocolVersion` 等信息, 这里据此算出哪些事件/ACK 接口可用,
+//! 不可用的接口在发请求前就返回 [`IcaError::Unsupported`], 不用等 bridge 没反应。
+//! 版本未知时按全部支持处理, 推算不准时可以在 `[ica.capabilities]` 里手动覆盖。
+
+use std::collections::HashMap;
+use std::sync::{LazyLock, RwLock};
+
+use serde_json::Value as JsonValue;
+use tracing::{Level, event};
+
+use crate::MainStatus;
+use crate::error::{ClientResult, IcaError};
+
+/// 会检查可用性的接口和事件名
+pub mod features {
+    /// 查询群成员 (ACK)
+    pub const GET_GROUP_MEMBERS: &str = "getGroupMembers";
+    /// 群禁言
+    pub const SET_GROUP_BAN: &str = "setGroupBan";
+    /// 全员禁言
+    pub const SET_GROUP_WHOLE_BAN: &str = "setGroupWholeBan";
+    /// 踢人
+    pub const SET_GROUP_KICK: &str = "setGroupKick";
+    /// 群名片
+    pub const SET_GROUP_CARD: &str = "setGroupCard";
+    /// 群头衔
+    pub const SET_GROUP_SPECIAL_TITLE: &str = "setGroupSpecialTitle";
+    /// 设置管理员
+    pub const SET_GROUP_ADMIN: &str = "setGroupAdmin";
+    /// 退群
+    pub const SET_GROUP_LEAVE: &str = "setGroupLeave";
+    /// 戳一戳
+    pub const SEND_GROUP_POKE: &str = "sendGroupPoke";
+    /// 群签到
+    pub const SEND_GROUP_SIGN: &str = "sendGroupSign";
+    /// 处理好友/加群申请
+    pub const HANDLE_REQUEST: &str = "handleRequest";
+    /// Milky 适配器额外推送的 `login` 事件
+    pub const LOGIN_EVENT: &str = "login";
+}
+
+/// bridge 使用的 QQ 协议适配器
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Adapter {
+    /// oicq / icqq
+    Oicq,
+    /// Milky
+    Milky,
+    /// 没有报告
+    Unknown,
+}
+
+impl Adapter {
+    /// 从 bridge 的版本信息里识别适配器。
+    pub fn detect(version: &JsonValue) -> Self {
+        let name = ["adapter", "adapterName", "protocol"]
+            .iter()
+            .find_map(|key| version.get(key).and_then(JsonValue::as_str))
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        if name.contains("milky") {
+            Self::Milky
+        } else if name.contains("oicq") || name.contains("icqq") {
+            Self::Oicq
+        } else {
+            Self::Unknown
+        }
+    }
+
+    /// 返回适配器名称。
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Oicq => "oicq",
+            Self::Milky => "milky",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+/// `major.minor.patch` 形式的协议版本
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ProtocolVersion(pub u32, pub u32, pub u32);
+
+impl ProtocolVersion {
+    /// 解析 `2.26.0` / `v2.26` 这样的版本号, 缺少的部分视为 0。
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut parts = text.trim().trim_start_matches('v').split('.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next().map_or(Some(0), |part| part.parse().ok())?;
+        let patch = parts.next().map_or(Some(0), |part| part.parse().ok())?;
+        Some(Self(major, minor, patch))
+    }
+}
+
+/// 单个接口的要求
+struct Requirement {
+    feature: &'static str,
+    /// 最低协议版本
+    min: ProtocolVersion,
+    /// 只有这些适配器支持, 为空表示都支持
+    adapters: &'static [Adapter],
+}
+
+impl Requirement {
+    /// 创建一条要求。
+    const fn new(
+        feature: &'static str,
+        min: ProtocolVersion,
+        adapters: &'static [Adapter],
+    ) -> Self {
+        Self {
+            feature,
+            min,
+            adapters,
+        }
+    }
+}
+
+/// 群成员和群管理接口是跟着当前兼容的协议版本 (2.26.0) 一起用上的
+const GROUP_API_MIN: ProtocolVersion = ProtocolVersion(2, 26, 0);
+/// 老接口
+const BASELINE: ProtocolVersion = ProtocolVersion(2, 0, 0);
+
+/// 不在表里的接口一律视为可用
+const REQUIREMENTS: &[Requirement] = &[
+    Requirement::new(features::GET_GROUP_MEMBERS, GROUP_API_MIN, &[]),
+    Requirement::new(features::SET_GROUP_BAN, GROUP_API_MIN, &[]),
+    Requirement::new(features::SET_GROUP_WHOLE_BAN, GROUP_API_MIN, &[]),
+    Requirement::new(features::SET_GROUP_KICK, GROUP_API_MIN, &[]),
+    Requirement::new(features::SET_GROUP_CARD, GROUP_API_MIN, &[]),
+    Requirement::new(features::SET_GROUP_SPECIAL_TITLE, GROUP_API_MIN, &[]),
+    Requirement::new(features::SET_GROUP_ADMIN, GROUP_API_MIN, &[]),
+    Requirement::new(features::SET_GROUP_LEAVE, GROUP_API_MIN, &[]),
+    Requirement::new(features::SEND_GROUP_POKE, BASELINE, &[Adapter::Oicq]),
+    Requirement::new(features::SEND_GROUP_SIGN, BASELINE, &[Adapter::Oicq]),
+    Requirement::new(features::HANDLE_REQUEST, BASELINE, &[]),
+    Requirement::new(features::LOGIN_EVENT, BASELINE, &[Adapter::Milky]),
+];
+
+/// 推算出来的 bridge 能力
+#[derive(Debug, Clone)]
+pub struct Capabilities {
+    /// 协议版本, 解析不了时为 `None`
+    pub protocol: Option<ProtocolVersion>,
+    /// 适配器
+    pub adapter: Adapter,
+    /// 配置里的手动覆盖
+    overrides: HashMap<String, bool>,
+}
+
+impl Capabilities {
+    /// 根据鉴权时 bridge 发来的版本信息推算。
+    pub fn from_bridge(version: &JsonValue, overrides: HashMap<String, bool>) -> Self {
+        Self {
+            protocol: version
+                .get("protocolVersion")
+                .and_then(JsonValue::as_str)
+                .and_then(ProtocolVersion::parse),
+            adapter: Adapter::detect(version),
+            overrides,
+        }
+    }
+
+    /// 判断某个接口/事件是否可用
+    ///
+    /// 配置覆盖优先; 版本或适配器未知时不限制
+    pub fn supports(&self, feature: &str) -> bool {
+        if let Some(enabled) = self.overrides.get(feature) {
+            return *enabled;
+        }
+        let Some(requirement) = REQUIREMENTS.iter().find(|req| req.feature == feature) else {
+            return true;
+        };
+        if self.adapter != Adapter::Unknown
+            && !requirement.adapters.is_empty()
+            && !requirement.adapters.contains(&self.adapter)
+        {
+            return false;
+        }
+        self.protocol.is_none_or(|protocol| protocol >= requirement.min)
+    }
+
+    /// 返回表里所有不可用的接口。
+    pub fn unsupported(&self) -> Vec<&'static str> {
+        REQUIREMENTS
+            .iter()
+            .map(|req| req.feature)
+            .filter(|feature| !self.supports(feature))
+            .collect()
+    }
+}
+
+/// 实例 id -> 连接的 bridge 的能力, 鉴权之前没有
+static CAPABILITIES: LazyLock<RwLock<HashMap<String, Capabilities>>> =
+    LazyLock::new(Default::default);
+
+/// 鉴权时根据 bridge 的版本信息更新当前实例的能力。
+pub fn negotiate(version: &JsonValue) {
+    let config = MainStatus::global_config().ica();
+    let overrides = config.capabilities;
+    let capabilities = Capabilities::from_bridge(version, overrides);
+    event!(
+        Level::INFO,
+        "bridge 协议版本: {:?}, 适配器: {}, 不可用的接口: {:?}",
+        capabilities.protocol,
+        capabilities.adapter.as_str(),
+        capabilities.unsupported()
+    );
+    CAPABILITIES.write().unwrap().insert(config.id, capabilities);
+}
+
+/// 收到只有某个适配器才会发的事件时, 补上没报告的适配器。
+pub fn note_adapter(adapter: Adapter) {
+    let id = MainStatus::global_config().ica_id();
+    if let Some(capabilities) = CAPABILITIES.write().unwrap().get_mut(&id)
+        && capabilities.adapter == Adapter::Unknown
+    {
+        event!(Level::INFO, "识别到 bridge 适配器: {}", adapter.as_str());
+        capabilities.adapter = adapter;
+    }
+}
+
+/// 判断当前实例的 bridge 是否支持某个接口/事件, 还没鉴权时视为支持。
+pub fn supports(feature: &str) -> bool {
+    let id = MainStatus::global_config().ica_id();
+    CAPABILITIES
+        .read()
+        .unwrap()
+        .get(&id)
+        .is_none_or(|capabilities| capabilities.supports(feature))
+}
+
+/// 不支持时返回 [`IcaError::Unsupported`]。
+pub fn require(feature: &str) -> ClientResult<(), IcaError> {
+    if supports(feature) {
+        Ok(())
+    } else {
+        Err(IcaError::Unsupported(feature.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parse_version() {
+        assert_eq!(ProtocolVersion::parse("2.26.0"), Some(ProtocolVersion(2, 26, 0)));
+        assert_eq!(ProtocolVersion::parse("v2.3"), Some(ProtocolVersion(2, 3, 0)));
+        assert_eq!(ProtocolVersion::parse("unknow"), None);
+        assert!(ProtocolVersion(2, 26, 1) > ProtocolVersion(2, 26, 0));
+    }
+
+    #[test]
+    fn negotiate_features() {
+        let old = Capabilities::from_bridge(&json!({"protocolVersion": "2.10.0"}), HashMap::new());
+        assert!(!old.supports(features::GET_GROUP_MEMBERS));
+        assert!(old.supports(features::HANDLE_REQUEST));
+        assert!(old.supports("sendMessage"));
+
+        let milky = Capabilities::from_bridge(
+            &json!({"protocolVersion": "2.26.0", "adapter": "Milky"}),
+            HashMap::new(),
+        );
+        assert_eq!(milky.adapter, Adapter::Milky);
+        assert!(milky.supports(features::GET_GROUP_MEMBERS));
+        assert!(milky.supports(features::LOGIN_EVENT));
+        assert!(!milky.supports(features::SEND_GROUP_POKE));
+
+        // 什么都不知道时不限制
+        let unknown = Capabilities::from_bridge(&json!({}), HashMap::new());
+        assert!(unknown.unsupported().is_empty());
+
+        let overrides = HashMap::from([(features::SEND_GROUP_POKE.to_string(), true)]);
+        let forced = Capabilities::from_bridge(&json!({"adapter": "milky"}), overrides);
+        assert!(forced.supports(features::SEND_GROUP_POKE));
+    }
+}
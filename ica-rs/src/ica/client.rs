@@ -1,10 +1,18 @@
 //! Icalingua bridge 的鉴权、消息发送和群管理请求封装。
 
+/// 带限速的消息发送队列。
+pub mod queue;
+
 use crate::MainStatus;
-use crate::data_struct::ica::group_members::GroupMember;
+use crate::data_struct::ica::group_members::{
+    GroupMember, find_member_by_id, find_members_by_name,
+};
 use crate::data_struct::ica::messages::{DeleteMessage, SendMessage};
-use crate::data_struct::ica::{RoomId, RoomIdTrait, UserId};
+use crate::data_struct::ica::{MessageId, RoomId, RoomIdTrait, UserId};
 use crate::error::{ClientResult, IcaError};
+use crate::ica::capability::{self, features};
+use crate::instance;
+use queue::{Outbound, Priority};
 
 use colored::Colorize;
 use ed25519_dalek::{Signature, Signer, SigningKey};
@@ -12,11 +20,54 @@ use futures_util::future::BoxFuture;
 use rust_socketio::Payload;
 use rust_socketio::asynchronous::Client;
 use serde_json::{Value as JsonValue, json};
-use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::sync::oneshot;
 use tracing::{Level, event, span};
 
+/// 实例 id -> 连上的 bridge, 给其他平台和其他实例往提醒房间转发消息用
+static NOTICE_CLIENTS: LazyLock<Mutex<HashMap<String, Client>>> = LazyLock::new(Default::default);
+
+/// 记录 (或清掉) 当前实例的 bridge 连接。
+pub fn set_notice_client(client: Option<Client>) {
+    let id = MainStatus::global_config().ica_id();
+    let mut clients = NOTICE_CLIENTS.lock().unwrap();
+    match client {
+        Some(client) => clients.insert(id, client),
+        None => clients.remove(&id),
+    };
+}
+
+/// 通过除了 `skipped` 以外的实例往各自的提醒房间发送一条文本, 返回是否至少有一条进入了发送队列
+///
+/// 没有连上的 bridge 或者 QQ 都不在线时直接返回 `false`
+pub async fn send_notice(text: &str, skipped: Option<&str>) -> bool {
+    let clients: Vec<(String, Client)> = NOTICE_CLIENTS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(id, _)| Some(id.as_str()) != skipped)
+        .map(|(id, client)| (id.clone(), client.clone()))
+        .collect();
+    let mut sent = false;
+    for (id, client) in clients {
+        sent |= instance::scope(id, async {
+            if !MainStatus::with_ica_status(|status| status.qq_login).unwrap_or(false) {
+                return false;
+            }
+            let mut sent = false;
+            for room in MainStatus::global_config().ica().notice_room {
+                let message = SendMessage::new(text.to_string(), room, None);
+                sent |= send_message_with_priority(&client, &message, Priority::High).await;
+            }
+            sent
+        })
+        .await;
+    }
+    sent
+}
+
 /// bridge 允许的最长群禁言时长，单位为秒。
 pub const GROUP_BAN_MAX_DURATION: u64 = 30 * 24 * 60 * 60;
 const GROUP_MEMBERS_ACK_TIMEOUT: Duration = Duration::from_secs(15);
@@ -134,8 +185,35 @@ async fn send_message_via_http(client: &Client, value: &JsonValue) -> Result<(),
 
 /// "安全" 的 发送一条消息
 ///
-/// 发送结构化 Icalingua 消息，并根据图片类型选择 Socket.IO 或 HTTP 通道。
+/// 以普通优先级放入发送队列，并等待发送结果。
 pub async fn send_message(client: &Client, message: &SendMessage) -> bool {
+    send_message_with_priority(client, message, Priority::Normal).await
+}
+
+/// 以指定优先级放入发送队列，并等待发送结果。
+pub async fn send_message_with_priority(
+    client: &Client,
+    message: &SendMessage,
+    priority: Priority,
+) -> bool {
+    queue::enqueue(client, Outbound::Message(message.clone()), priority).await
+}
+
+/// 放入发送队列，并等待 bridge 的 `addMessage` 回显，返回消息 ID
+///
+/// 不能在 socketio 事件回调里调用 (回显也是通过事件回调送达的)
+pub async fn send_message_confirmed(
+    client: &Client,
+    message: &SendMessage,
+    priority: Priority,
+) -> Result<MessageId, IcaError> {
+    queue::enqueue_confirmed(client, Outbound::Message(message.clone()), priority).await
+}
+
+/// 绕过发送队列直接发送
+///
+/// 发送结构化 Icalingua 消息，并根据图片类型选择 Socket.IO 或 HTTP 通道。
+pub async fn send_message_now(client: &Client, message: &SendMessage) -> bool {
     let value = message.as_value();
     if message.has_b64img() {
         match send_message_via_http(client, &value).await {
@@ -164,8 +242,15 @@ pub async fn send_message(client: &Client, message: &SendMessage) -> bool {
 
 /// "安全" 的 发一个 json 消息
 ///
-/// 发送原始 JSON 消息，并根据图片类型选择 Socket.IO 或 HTTP 通道。
+/// 以普通优先级放入发送队列，并等待发送结果。
 pub async fn send_string_message(client: &Client, message: &JsonValue) -> bool {
+    queue::enqueue(client, Outbound::Raw(message.clone()), Priority::Normal).await
+}
+
+/// 绕过发送队列直接发送
+///
+/// 发送原始 JSON 消息，并根据图片类型选择 Socket.IO 或 HTTP 通道。
+pub async fn send_string_message_now(client: &Client, message: &JsonValue) -> bool {
     if json_has_b64img(message) {
         match send_message_via_http(client, message).await {
             Ok(_) => {
@@ -252,20 +337,24 @@ async fn inner_sign(payload: Payload, client: &Client) -> ClientResult<(), IcaEr
             crate::ica::ICA_PROTOCOL_VERSION
         );
     }
+    capability::negotiate(version);
 
     let auth_key = match &require_data.first() {
         Some(JsonValue::String(auth_key)) => Ok(auth_key),
         _ => Err(IcaError::LoginFailed("Got a invalid auth_key".to_string())),
     }?;
 
-    let salt = hex::decode(auth_key).expect("Got an invalid salt from the server");
+    let salt = hex::decode(auth_key)
+        .map_err(|e| IcaError::LoginFailed(format!("服务器发来的 salt 无效: {e}")))?;
     // 签名
     let private_key = MainStatus::global_config().ica().private_key.clone();
 
     let array_key: [u8; 32] = hex::decode(private_key)
-        .expect("配置文件设置的私钥不是一个有效的私钥, 无法使用hex解析")
+        .map_err(|e| IcaError::LoginFailed(format!("配置文件设置的私钥无法使用 hex 解析: {e}")))?
         .try_into()
-        .expect("配置文件设置的私钥不是一个有效的私钥, 无法转换为[u8; 32]数组");
+        .map_err(|key: Vec<u8>| {
+            IcaError::LoginFailed(format!("配置文件设置的私钥应为 32 字节, 实际为 {}", key.len()))
+        })?;
     let signing_key: SigningKey = SigningKey::from_bytes(&array_key);
     let signature: Signature = signing_key.sign(salt.as_slice());
 
@@ -275,12 +364,23 @@ async fn inner_sign(payload: Payload, client: &Client) -> ClientResult<(), IcaEr
     Ok(())
 }
 
+/// 生成一对新的 ed25519 密钥, 返回 hex 格式的 (私钥, 公钥)
+///
+/// 私钥直接取自系统的随机数源
+pub fn generate_keypair() -> Result<(String, String), getrandom::Error> {
+    let mut seed = [0u8; 32];
+    getrandom::fill(&mut seed)?;
+    let signing_key = SigningKey::from_bytes(&seed);
+    Ok((hex::encode(seed), hex::encode(signing_key.verifying_key().to_bytes())))
+}
+
 /// 签名回调
-/// 失败的时候得 panic
 ///
-/// 处理 `requireAuth` 事件；签名或鉴权参数无效时终止当前任务。
+/// 处理 `requireAuth` 事件；签名或鉴权参数无效时记录原因, 不会 panic。
 pub async fn sign_callback(payload: Payload, client: Client) {
-    inner_sign(payload, &client).await.expect("Faild to sign");
+    if let Err(e) = inner_sign(payload, &client).await {
+        event!(Level::ERROR, "{}", format!("签名失败: {e}").red());
+    }
 }
 
 /// 向指定群发送签到信息
@@ -293,8 +393,12 @@ pub async fn send_room_sign_in(client: &Client, room_id: RoomId) -> bool {
         event!(Level::WARN, "不能向私聊发送签到信息");
         return false;
     }
+    if let Err(e) = capability::require(features::SEND_GROUP_SIGN) {
+        event!(Level::WARN, "{}", e);
+        return false;
+    }
     let data = json!(room_id.abs());
-    match client.emit("sendGroupSign", data).await {
+    match client.emit(features::SEND_GROUP_SIGN, data).await {
         Ok(_) => {
             event!(Level::INFO, "已向群 {} 发送签到信息", room_id);
             true
@@ -310,8 +414,12 @@ pub async fn send_room_sign_in(client: &Client, room_id: RoomId) -> bool {
 ///
 /// 向指定房间中的用户发送戳一戳。
 pub async fn send_poke(client: &Client, room_id: RoomId, target: UserId) -> bool {
+    if let Err(e) = capability::require(features::SEND_GROUP_POKE) {
+        event!(Level::WARN, "{}", e);
+        return false;
+    }
     let data = vec![json!(room_id), json!(target)];
-    match client.emit("sendGroupPoke", data).await {
+    match client.emit(features::SEND_GROUP_POKE, data).await {
         Ok(_) => {
             event!(Level::INFO, "已向 {} 的 {} 发送戳一戳", room_id, target);
             true
@@ -345,10 +453,15 @@ pub async fn set_group_ban(
         );
         return false;
     }
+    if let Err(e) = capability::require(features::SET_GROUP_BAN) {
+        event!(Level::WARN, "{}", e);
+        return false;
+    }
     let data = vec![json!(room_id.abs()), json!(target), json!(duration)];
-    match client.emit("setGroupBan", data).await {
+    match client.emit(features::SET_GROUP_BAN, data).await {
         Ok(_) => {
             event!(Level::INFO, "已在群 {} 禁言 {}，时长 {} 秒", room_id, target, duration);
+            invalidate_group_members(room_id);
             true
         }
         Err(e) => {
@@ -358,22 +471,188 @@ pub async fn set_group_ban(
     }
 }
 
+/// 处理好友/加群申请
+///
+/// ```typescript
+/// handleRequest(type: 'friend' | 'group', flag: string, accept: boolean, reason?: string)
+/// ```
+///
+/// `flag` 来自 `sendAddRequest` 推送, `reason` 只在拒绝加群申请时有效。
+pub async fn handle_request(
+    client: &Client,
+    request_type: &str,
+    flag: &str,
+    approve: bool,
+    reason: Option<&str>,
+) -> bool {
+    let mut data = vec![json!(request_type), json!(flag), json!(approve)];
+    if !approve && let Some(reason) = reason {
+        data.push(json!(reason));
+    }
+    let action = if approve { "同意" } else { "拒绝" };
+    if let Err(e) = capability::require(features::HANDLE_REQUEST) {
+        event!(Level::WARN, "{}", e);
+        return false;
+    }
+    match client.emit(features::HANDLE_REQUEST, data).await {
+        Ok(_) => {
+            event!(Level::INFO, "已{}申请 {} ({})", action, flag, request_type);
+            true
+        }
+        Err(e) => {
+            event!(Level::ERROR, "{}申请 {} 失败: {}", action, flag, e);
+            false
+        }
+    }
+}
+
+/// 把群聊房间 id 转换成群号, 私聊房间 id 会被拒绝。
+fn group_id_of(room_id: RoomId) -> ClientResult<RoomId, IcaError> {
+    if !room_id.is_negative() {
+        return Err(IcaError::InvalidGroupRoomId(room_id));
+    }
+    room_id.checked_abs().ok_or(IcaError::InvalidGroupRoomId(room_id))
+}
+
+/// 发送一个群管理请求, `action` 只用于日志。
+///
+/// 成功后会让该群的成员缓存失效。
+async fn emit_group_action(
+    client: &Client,
+    room_id: RoomId,
+    event_name: &str,
+    data: Vec<JsonValue>,
+    action: String,
+) -> ClientResult<(), IcaError> {
+    capability::require(event_name)?;
+    match client.emit(event_name, data).await {
+        Ok(_) => {
+            event!(Level::INFO, "已{}", action);
+            invalidate_group_members(room_id);
+            Ok(())
+        }
+        Err(e) => {
+            event!(Level::ERROR, "{}失败: {}", action, e);
+            Err(e.into())
+        }
+    }
+}
+
+/// 把指定成员踢出群
+///
+/// `reject_add_request` 为 `true` 时不再接受此人的加群申请。
+pub async fn kick_group_member(
+    client: &Client,
+    room_id: RoomId,
+    target: UserId,
+    reject_add_request: bool,
+) -> ClientResult<(), IcaError> {
+    let group_id = group_id_of(room_id)?;
+    let data = vec![json!(group_id), json!(target), json!(reject_add_request)];
+    emit_group_action(
+        client,
+        room_id,
+        features::SET_GROUP_KICK,
+        data,
+        format!("将 {target} 踢出群 {group_id}"),
+    )
+    .await
+}
+
+/// 开启或关闭全员禁言。
+pub async fn set_group_whole_ban(
+    client: &Client,
+    room_id: RoomId,
+    enable: bool,
+) -> ClientResult<(), IcaError> {
+    let group_id = group_id_of(room_id)?;
+    let action = format!("{}群 {group_id} 的全员禁言", if enable { "开启" } else { "关闭" });
+    emit_group_action(
+        client,
+        room_id,
+        features::SET_GROUP_WHOLE_BAN,
+        vec![json!(group_id), json!(enable)],
+        action,
+    )
+    .await
+}
+
+/// 设置群名片, `card` 为空时清除名片。
+pub async fn set_group_card(
+    client: &Client,
+    room_id: RoomId,
+    target: UserId,
+    card: &str,
+) -> ClientResult<(), IcaError> {
+    let group_id = group_id_of(room_id)?;
+    let data = vec![json!(group_id), json!(target), json!(card)];
+    emit_group_action(
+        client,
+        room_id,
+        features::SET_GROUP_CARD,
+        data,
+        format!("设置群 {group_id} 中 {target} 的名片"),
+    )
+    .await
+}
+
+/// 设置群专属头衔
+///
+/// 需要 bot 是群主, `duration` 单位为秒, -1 为永久, `title` 为空时清除头衔。
+pub async fn set_group_special_title(
+    client: &Client,
+    room_id: RoomId,
+    target: UserId,
+    title: &str,
+    duration: i64,
+) -> ClientResult<(), IcaError> {
+    let group_id = group_id_of(room_id)?;
+    let data = vec![json!(group_id), json!(target), json!(title), json!(duration)];
+    let action = format!("设置群 {group_id} 中 {target} 的头衔");
+    emit_group_action(client, room_id, features::SET_GROUP_SPECIAL_TITLE, data, action).await
+}
+
+/// 设置或取消管理员, 需要 bot 是群主。
+pub async fn set_group_admin(
+    client: &Client,
+    room_id: RoomId,
+    target: UserId,
+    enable: bool,
+) -> ClientResult<(), IcaError> {
+    let group_id = group_id_of(room_id)?;
+    let data = vec![json!(group_id), json!(target), json!(enable)];
+    let action =
+        format!("{}群 {group_id} 中 {target} 的管理员", if enable { "设置" } else { "取消" });
+    emit_group_action(client, room_id, features::SET_GROUP_ADMIN, data, action).await
+}
+
+/// 退出群聊。
+pub async fn leave_group(client: &Client, room_id: RoomId) -> ClientResult<(), IcaError> {
+    let group_id = group_id_of(room_id)?;
+    emit_group_action(
+        client,
+        room_id,
+        features::SET_GROUP_LEAVE,
+        vec![json!(group_id)],
+        format!("退出群 {group_id}"),
+    )
+    .await
+}
+
 /// 查询群聊的完整成员列表。
 pub async fn get_group_members(
     client: &Client,
     room_id: RoomId,
 ) -> Result<Vec<GroupMember>, IcaError> {
-    if !room_id.is_negative() {
-        return Err(IcaError::InvalidGroupRoomId(room_id));
-    }
-    let group_id = room_id.checked_abs().ok_or(IcaError::InvalidGroupRoomId(room_id))?;
+    let group_id = group_id_of(room_id)?;
+    capability::require(features::GET_GROUP_MEMBERS)?;
     let (sender, receiver) = oneshot::channel();
     let sender = Arc::new(Mutex::new(Some(sender)));
     let callback_sender = sender.clone();
 
     client
         .emit_with_ack(
-            "getGroupMembers",
+            features::GET_GROUP_MEMBERS,
             vec![json!(group_id)],
             GROUP_MEMBERS_ACK_TIMEOUT,
             move |payload: Payload, _client: Client| -> BoxFuture<'static, ()> {
@@ -395,7 +674,69 @@ pub async fn get_group_members(
         .map_err(|error| {
             IcaError::InvalidGroupMembersResponse(format!("ACK 通道提前关闭: {error}"))
         })?;
-    parse_group_members_ack(payload)
+    let members = parse_group_members_ack(payload)?;
+    MainStatus::with_ica_status(|status| status.members.insert(room_id, members.clone()));
+    Ok(members)
+}
+
+/// 返回群成员缓存的有效期。
+fn member_cache_ttl() -> Duration {
+    Duration::from_secs(MainStatus::global_config().ica().member_cache_ttl)
+}
+
+/// 获取群成员列表, 缓存有效时不会请求 bridge。
+pub async fn get_group_members_cached(
+    client: &Client,
+    room_id: RoomId,
+) -> Result<Vec<GroupMember>, IcaError> {
+    let ttl = member_cache_ttl();
+    if let Some(members) =
+        MainStatus::with_ica_status(|status| status.members.get(room_id, ttl)).flatten()
+    {
+        return Ok(members);
+    }
+    get_group_members(client, room_id).await
+}
+
+/// 按 QQ 号查找群成员, 优先使用缓存。
+pub async fn get_group_member(
+    client: &Client,
+    room_id: RoomId,
+    user_id: UserId,
+) -> Result<Option<GroupMember>, IcaError> {
+    let members = get_group_members_cached(client, room_id).await?;
+    Ok(find_member_by_id(&members, user_id).cloned())
+}
+
+/// 按群名片或昵称查找群成员, 优先使用缓存。
+pub async fn find_group_members(
+    client: &Client,
+    room_id: RoomId,
+    name: &str,
+) -> Result<Vec<GroupMember>, IcaError> {
+    let members = get_group_members_cached(client, room_id).await?;
+    Ok(find_members_by_name(&members, name).into_iter().cloned().collect())
+}
+
+/// 让某个群的成员缓存失效, 返回之前是否有缓存。
+pub fn invalidate_group_members(room_id: RoomId) -> bool {
+    MainStatus::with_ica_status(|status| status.members.invalidate(room_id)).unwrap_or(false)
+}
+
+/// 在消息开头 @ 某个群成员
+///
+/// 名称优先使用群名片, 找不到这个人时使用 QQ 号。
+pub async fn mention_member(
+    client: &Client,
+    message: &mut SendMessage,
+    user_id: UserId,
+) -> Result<(), IcaError> {
+    let name = get_group_member(client, message.room_id, user_id)
+        .await?
+        .map(|member| member.display_name().to_string())
+        .unwrap_or_else(|| user_id.to_string());
+    message.add_mention(user_id, &name);
+    Ok(())
 }
 
 /// 查询当前仍处于禁言中的群成员。
@@ -0,0 +1,656 @@
+//! 发往 Icalingua 的消息队列, 带全局/房间令牌桶限速、优先级通道、失败重试和送达确认。
+//!
+//! 每个实例 (QQ 账号) 各有一个队列, 互不影响。
+//!
+//! 这可是 qq, 要保命
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use rust_socketio::asynchronous::Client;
+use serde_json::Value as JsonValue;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{Level, event};
+
+use crate::MainStatus;
+use crate::config::RateLimitConfig;
+use crate::data_struct::ica::messages::SendMessage;
+use crate::data_struct::ica::{MessageId, RoomId};
+use crate::error::IcaError;
+
+/// 发出后等待回显的最长时间
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(30);
+/// 房间令牌桶数量超过这个值时清理空闲的桶
+const ROOM_BUCKET_PRUNE_THRESHOLD: usize = 256;
+
+/// 发送优先级
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// 管理员回复等, 最先发送
+    High,
+    /// 普通消息
+    Normal,
+    /// 启动通知等不着急的消息
+    Low,
+}
+
+impl Priority {
+    /// 从名称解析优先级，大小写不敏感。
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "high" => Some(Priority::High),
+            "normal" => Some(Priority::Normal),
+            "low" => Some(Priority::Low),
+            _ => None,
+        }
+    }
+
+    /// 返回对应优先级通道的下标。
+    fn lane(&self) -> usize {
+        match self {
+            Priority::High => 0,
+            Priority::Normal => 1,
+            Priority::Low => 2,
+        }
+    }
+}
+
+/// 令牌桶
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    /// 每秒补充的令牌数
+    refill_rate: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// 创建一个装满的令牌桶
+    ///
+    /// `per_minute` 为每分钟补充的令牌数, `burst` 为桶容量
+    pub fn new(per_minute: u32, burst: u32, now: Instant) -> Self {
+        let capacity = burst.max(1) as f64;
+        Self {
+            capacity,
+            tokens: capacity,
+            refill_rate: per_minute.max(1) as f64 / 60.0,
+            last_refill: now,
+        }
+    }
+
+    /// 按经过的时间补充令牌。
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_rate).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    /// 返回还要等多久才有一个令牌, 有令牌时返回 0。
+    pub fn wait_time(&mut self, now: Instant) -> Duration {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) / self.refill_rate)
+        }
+    }
+
+    /// 取走一个令牌, 调用前需要先确认 `wait_time` 为 0。
+    pub fn take(&mut self, now: Instant) {
+        self.refill(now);
+        self.tokens = (self.tokens - 1.0).max(0.0);
+    }
+
+    /// 判断令牌桶是否已经装满 (即长时间没有使用)。
+    fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= self.capacity
+    }
+}
+
+/// 队列里等待发送的内容
+#[derive(Debug, Clone)]
+pub enum Outbound {
+    /// 结构化消息
+    Message(SendMessage),
+    /// 原始 JSON 消息
+    Raw(JsonValue),
+}
+
+impl Outbound {
+    /// 返回目标房间 ID。
+    fn room_id(&self) -> RoomId {
+        match self {
+            Outbound::Message(message) => message.room_id,
+            Outbound::Raw(value) => value.get("roomId").and_then(JsonValue::as_i64).unwrap_or(0),
+        }
+    }
+
+    /// 返回消息文本内容, 用于匹配回显。
+    fn content(&self) -> String {
+        match self {
+            Outbound::Message(message) => message.content.clone(),
+            Outbound::Raw(value) => {
+                value.get("content").and_then(JsonValue::as_str).unwrap_or_default().to_string()
+            }
+        }
+    }
+}
+
+/// 队列中的一条消息
+struct QueuedMessage {
+    client: Client,
+    payload: Outbound,
+    room_id: RoomId,
+    priority: Priority,
+    attempts: u8,
+    /// 消息发出 (或放弃) 时通知
+    emitted: Option<oneshot::Sender<bool>>,
+    /// 收到回显 (或失败) 时通知
+    delivered: Option<oneshot::Sender<Result<MessageId, IcaError>>>,
+}
+
+impl QueuedMessage {
+    /// 放弃发送这条消息, 通知所有等待者。
+    fn give_up(mut self, error: IcaError) {
+        if let Some(emitted) = self.emitted.take() {
+            let _ = emitted.send(false);
+        }
+        if let Some(delivered) = self.delivered.take() {
+            let _ = delivered.send(Err(error));
+        }
+    }
+}
+
+/// 已经发出、等待 bridge 回显确认的消息
+struct PendingDelivery {
+    id: u64,
+    item: QueuedMessage,
+    content: String,
+    sent_at: Instant,
+}
+
+/// 等待确认的消息列表, 按发送顺序排列
+#[derive(Default)]
+struct PendingDeliveries {
+    next_id: u64,
+    list: VecDeque<PendingDelivery>,
+}
+
+impl PendingDeliveries {
+    /// 登记一条即将发出的消息, 返回登记号。
+    fn register(&mut self, item: QueuedMessage, now: Instant) -> u64 {
+        self.next_id += 1;
+        let content = item.payload.content();
+        self.list.push_back(PendingDelivery {
+            id: self.next_id,
+            item,
+            content,
+            sent_at: now,
+        });
+        self.next_id
+    }
+
+    /// 按登记号取回消息。
+    fn take(&mut self, id: u64) -> Option<QueuedMessage> {
+        let index = self.list.iter().position(|pending| pending.id == id)?;
+        self.list.remove(index).map(|pending| pending.item)
+    }
+
+    /// 按回显匹配等待确认的消息
+    ///
+    /// 只认同房间同内容的消息, 匹配不上的 (比如回显内容被 bridge 改过) 等它超时,
+    /// 免得把别的消息的 ID 确认给它
+    fn match_echo(&mut self, room_id: RoomId, content: &str) -> Option<QueuedMessage> {
+        let index = self
+            .list
+            .iter()
+            .position(|pending| pending.item.room_id == room_id && pending.content == content)?;
+        self.list.remove(index).map(|pending| pending.item)
+    }
+
+    /// 取出所有等待超过 `timeout` 的消息。
+    fn take_expired(&mut self, now: Instant, timeout: Duration) -> Vec<QueuedMessage> {
+        let mut expired = Vec::new();
+        while let Some(pending) = self.list.front() {
+            if now.saturating_duration_since(pending.sent_at) < timeout {
+                break;
+            }
+            expired.push(self.list.pop_front().unwrap().item);
+        }
+        expired
+    }
+}
+
+/// 队列统计数据
+#[derive(Debug, Default)]
+pub struct QueueMetrics {
+    enqueued: AtomicU64,
+    sent: AtomicU64,
+    confirmed: AtomicU64,
+    unconfirmed: AtomicU64,
+    failed: AtomicU64,
+    retried: AtomicU64,
+    dropped: AtomicU64,
+    pending: AtomicU64,
+}
+
+/// 某一时刻的队列统计数据
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MetricsSnapshot {
+    /// 进入队列的消息数
+    pub enqueued: u64,
+    /// 发出的消息数
+    pub sent: u64,
+    /// 收到回显确认的消息数
+    pub confirmed: u64,
+    /// 等待回显超时的消息数
+    pub unconfirmed: u64,
+    /// 重试后依然失败的消息数
+    pub failed: u64,
+    /// 重试次数
+    pub retried: u64,
+    /// 因为队列已满而丢弃的消息数
+    pub dropped: u64,
+    /// 当前排队中的消息数
+    pub pending: u64,
+}
+
+impl QueueMetrics {
+    /// 返回当前统计数据的快照。
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            enqueued: self.enqueued.load(Ordering::Relaxed),
+            sent: self.sent.load(Ordering::Relaxed),
+            confirmed: self.confirmed.load(Ordering::Relaxed),
+            unconfirmed: self.unconfirmed.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            retried: self.retried.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            pending: self.pending.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl MetricsSnapshot {
+    /// 转换为 名称 -> 数值 的列表, 方便暴露给 Python。
+    pub fn as_pairs(&self) -> [(&'static str, u64); 8] {
+        [
+            ("enqueued", self.enqueued),
+            ("sent", self.sent),
+            ("confirmed", self.confirmed),
+            ("unconfirmed", self.unconfirmed),
+            ("failed", self.failed),
+            ("retried", self.retried),
+            ("dropped", self.dropped),
+            ("pending", self.pending),
+        ]
+    }
+}
+
+impl Display for MetricsSnapshot {
+    /// 将当前值写入格式化输出。
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "排队中: {}\n已入队: {}\n已发送: {}\n已确认: {}\n未确认: {}\n失败: {}\n重试: {}\n丢弃: {}",
+            self.pending,
+            self.enqueued,
+            self.sent,
+            self.confirmed,
+            self.unconfirmed,
+            self.failed,
+            self.retried,
+            self.dropped
+        )
+    }
+}
+
+/// 单个实例的发送队列的句柄
+struct OutboundQueue {
+    sender: mpsc::UnboundedSender<QueuedMessage>,
+    metrics: Arc<QueueMetrics>,
+    deliveries: Arc<Mutex<PendingDeliveries>>,
+    config: RateLimitConfig,
+}
+
+impl OutboundQueue {
+    /// 把消息重新放回队列, 失败时通知等待者。
+    fn requeue(&self, mut item: QueuedMessage) {
+        item.attempts += 1;
+        self.metrics.retried.fetch_add(1, Ordering::Relaxed);
+        self.metrics.pending.fetch_add(1, Ordering::Relaxed);
+        if let Err(mpsc::error::SendError(item)) = self.sender.send(item) {
+            self.metrics.pending.fetch_sub(1, Ordering::Relaxed);
+            item.give_up(IcaError::SendFailed("发送队列已关闭".to_string()));
+        }
+    }
+}
+
+/// 实例 id -> 发送队列
+static OUTBOUND: LazyLock<Mutex<HashMap<String, Arc<OutboundQueue>>>> =
+    LazyLock::new(Default::default);
+
+/// 返回当前实例的发送队列。
+fn current_queue() -> Option<Arc<OutboundQueue>> {
+    let id = MainStatus::global_config().ica_id();
+    OUTBOUND.lock().unwrap().get(&id).cloned()
+}
+
+/// 发送队列的工作状态
+struct QueueWorker {
+    lanes: [VecDeque<QueuedMessage>; 3],
+    global: TokenBucket,
+    rooms: HashMap<RoomId, TokenBucket>,
+    config: RateLimitConfig,
+}
+
+impl QueueWorker {
+    /// 创建并初始化对应的数据结构。
+    fn new(config: RateLimitConfig) -> Self {
+        Self {
+            lanes: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+            global: TokenBucket::new(config.global_per_minute, config.global_burst, Instant::now()),
+            rooms: HashMap::new(),
+            config,
+        }
+    }
+
+    /// 把消息放入对应优先级的通道。
+    fn push(&mut self, item: QueuedMessage) { self.lanes[item.priority.lane()].push_back(item); }
+
+    /// 把重试的消息放回通道最前面。
+    fn push_front(&mut self, item: QueuedMessage) {
+        self.lanes[item.priority.lane()].push_front(item);
+    }
+
+    /// 取出下一条可以发送的消息
+    ///
+    /// 没有可发送的消息时返回需要等待的时间, 队列为空时返回 `Err(None)`
+    fn pick(&mut self, now: Instant) -> Result<QueuedMessage, Option<Duration>> {
+        let global_wait = self.global.wait_time(now);
+        let mut min_wait: Option<Duration> = None;
+        for lane in 0..self.lanes.len() {
+            for index in 0..self.lanes[lane].len() {
+                let room_id = self.lanes[lane][index].room_id;
+                let config = &self.config;
+                let bucket = self.rooms.entry(room_id).or_insert_with(|| {
+                    TokenBucket::new(config.room_per_minute, config.room_burst, now)
+                });
+                let wait = bucket.wait_time(now).max(global_wait);
+                if wait.is_zero() {
+                    bucket.take(now);
+                    self.global.take(now);
+                    return Ok(self.lanes[lane].remove(index).unwrap());
+                }
+                min_wait = Some(min_wait.map_or(wait, |min| min.min(wait)));
+            }
+        }
+        if self.rooms.len() > ROOM_BUCKET_PRUNE_THRESHOLD {
+            self.rooms.retain(|_, bucket| !bucket.is_full(now));
+        }
+        Err(min_wait)
+    }
+}
+
+/// 启动当前实例的发送队列, 已经启动过时什么都不做
+///
+/// 需要在主运行时里调用
+pub fn start_queue(config: RateLimitConfig) {
+    let id = MainStatus::global_config().ica_id();
+    let mut queues = OUTBOUND.lock().unwrap();
+    if queues.contains_key(&id) {
+        return;
+    }
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let queue = OutboundQueue {
+        sender,
+        metrics: Arc::new(QueueMetrics::default()),
+        deliveries: Arc::new(Mutex::new(PendingDeliveries::default())),
+        config: config.clone(),
+    };
+    let metrics = queue.metrics.clone();
+    let deliveries = queue.deliveries.clone();
+    queues.insert(id.clone(), Arc::new(queue));
+    let worker = run_worker(receiver, QueueWorker::new(config), metrics, deliveries);
+    tokio::spawn(crate::instance::scope(id, worker));
+}
+
+/// 发送队列的主循环。
+async fn run_worker(
+    mut receiver: mpsc::UnboundedReceiver<QueuedMessage>,
+    mut worker: QueueWorker,
+    metrics: Arc<QueueMetrics>,
+    deliveries: Arc<Mutex<PendingDeliveries>>,
+) {
+    event!(Level::INFO, "ica 发送队列已启动");
+    loop {
+        while let Ok(item) = receiver.try_recv() {
+            worker.push(item);
+        }
+        let now = Instant::now();
+        let expired = deliveries.lock().unwrap().take_expired(now, DELIVERY_TIMEOUT);
+        for item in expired {
+            metrics.unconfirmed.fetch_add(1, Ordering::Relaxed);
+            event!(Level::DEBUG, "发往 {} 的消息没有收到回显", item.room_id);
+            let room_id = item.room_id;
+            item.give_up(IcaError::DeliveryTimeout(room_id));
+        }
+
+        let mut item = match worker.pick(now) {
+            Ok(item) => item,
+            Err(wait) => {
+                // 至少每秒醒一次, 检查送达超时
+                let wait = wait.unwrap_or(Duration::MAX).min(Duration::from_secs(1));
+                tokio::select! {
+                    item = receiver.recv() => match item {
+                        Some(item) => worker.push(item),
+                        None => break,
+                    },
+                    _ = tokio::time::sleep(wait) => {}
+                }
+                continue;
+            }
+        };
+
+        // 先登记再发送, 免得回显比登记先到
+        let client = item.client.clone();
+        let payload = item.payload.clone();
+        let emitted = item.emitted.take();
+        let id = deliveries.lock().unwrap().register(item, Instant::now());
+        let success = match &payload {
+            Outbound::Message(message) => super::send_message_now(&client, message).await,
+            Outbound::Raw(value) => super::send_string_message_now(&client, value).await,
+        };
+        metrics.pending.fetch_sub(1, Ordering::Relaxed);
+        if success {
+            metrics.sent.fetch_add(1, Ordering::Relaxed);
+            if let Some(emitted) = emitted {
+                let _ = emitted.send(true);
+            }
+            continue;
+        }
+        let Some(mut item) = deliveries.lock().unwrap().take(id) else {
+            continue;
+        };
+        item.emitted = emitted;
+        if item.attempts < worker.config.max_retries {
+            item.attempts += 1;
+            metrics.retried.fetch_add(1, Ordering::Relaxed);
+            metrics.pending.fetch_add(1, Ordering::Relaxed);
+            event!(Level::WARN, "发送到 {} 失败, 第 {} 次重试", item.room_id, item.attempts);
+            worker.push_front(item);
+        } else {
+            metrics.failed.fetch_add(1, Ordering::Relaxed);
+            event!(Level::WARN, "发送到 {} 失败, 已放弃", item.room_id);
+            item.give_up(IcaError::SendFailed("重试次数已用完".to_string()));
+        }
+    }
+    event!(Level::INFO, "ica 发送队列已停止");
+}
+
+/// 把消息放入发送队列。
+fn push(
+    client: &Client,
+    payload: Outbound,
+    priority: Priority,
+    delivered: Option<oneshot::Sender<Result<MessageId, IcaError>>>,
+) -> Result<oneshot::Receiver<bool>, IcaError> {
+    let Some(queue) = current_queue() else {
+        return Err(IcaError::SendFailed("发送队列未启动".to_string()));
+    };
+    let room_id = payload.room_id();
+    if queue.metrics.pending.load(Ordering::Relaxed) >= queue.config.max_queue_len as u64 {
+        queue.metrics.dropped.fetch_add(1, Ordering::Relaxed);
+        event!(Level::WARN, "ica 发送队列已满, 丢弃发往 {} 的消息", room_id);
+        return Err(IcaError::SendFailed("发送队列已满".to_string()));
+    }
+    let (emitted, receiver) = oneshot::channel();
+    let item = QueuedMessage {
+        client: client.clone(),
+        payload,
+        room_id,
+        priority,
+        attempts: 0,
+        emitted: Some(emitted),
+        delivered,
+    };
+    queue.metrics.enqueued.fetch_add(1, Ordering::Relaxed);
+    queue.metrics.pending.fetch_add(1, Ordering::Relaxed);
+    if queue.sender.send(item).is_err() {
+        queue.metrics.pending.fetch_sub(1, Ordering::Relaxed);
+        return Err(IcaError::SendFailed("发送队列已关闭".to_string()));
+    }
+    Ok(receiver)
+}
+
+/// 把一条消息放入发送队列, 并等待它被发出
+///
+/// 队列未启动时直接发送, 队列已满时返回 `false`
+pub async fn enqueue(client: &Client, payload: Outbound, priority: Priority) -> bool {
+    if current_queue().is_none() {
+        return match &payload {
+            Outbound::Message(message) => super::send_message_now(client, message).await,
+            Outbound::Raw(value) => super::send_string_message_now(client, value).await,
+        };
+    }
+    match push(client, payload, priority, None) {
+        Ok(emitted) => emitted.await.unwrap_or(false),
+        Err(_) => false,
+    }
+}
+
+/// 把一条消息放入发送队列, 并等待 bridge 回显确认
+///
+/// 回显走的是 `addMessage` 事件, 所以 **不要** 在 socketio 事件回调里等待这个函数,
+/// 否则会一直等到超时
+pub async fn enqueue_confirmed(
+    client: &Client,
+    payload: Outbound,
+    priority: Priority,
+) -> Result<MessageId, IcaError> {
+    let (delivered, receiver) = oneshot::channel();
+    push(client, payload, priority, Some(delivered))?;
+    receiver
+        .await
+        .unwrap_or_else(|_| Err(IcaError::SendFailed("发送队列已关闭".to_string())))
+}
+
+/// 处理自己发出的消息的回显, 返回是否匹配到了等待确认的消息。
+pub fn confirm_delivery(room_id: RoomId, content: &str, msg_id: &MessageId) -> bool {
+    let Some(queue) = current_queue() else {
+        return false;
+    };
+    let Some(mut item) = queue.deliveries.lock().unwrap().match_echo(room_id, content) else {
+        return false;
+    };
+    queue.metrics.confirmed.fetch_add(1, Ordering::Relaxed);
+    if let Some(delivered) = item.delivered.take() {
+        let _ = delivered.send(Ok(msg_id.clone()));
+    }
+    true
+}
+
+/// 从 `messageError` 的内容里找出失败消息的房间和内容
+///
+/// 兼容直接带 `roomId`/`content` 的对象, 以及包在 `message`/`data` 里的情况
+fn message_error_target(value: &JsonValue) -> Option<(RoomId, &str)> {
+    let target = [value, &value["message"], &value["data"]]
+        .into_iter()
+        .find(|target| target["roomId"].is_i64() && target["content"].is_string())?;
+    Some((target["roomId"].as_i64()?, target["content"].as_str()?))
+}
+
+/// 处理 `messageError`
+///
+/// 只处理能按房间和内容对上的等待确认的消息,
+/// 对不上的 (bridge 别的操作失败也会发这个事件) 只记日志, 不重试
+pub fn on_message_error(value: &JsonValue) {
+    let Some(queue) = current_queue() else {
+        return;
+    };
+    let Some((room_id, content)) = message_error_target(value) else {
+        event!(Level::WARN, "messageError 对应不上发出的消息, 不重试: {value}");
+        return;
+    };
+    let item = queue.deliveries.lock().unwrap().match_echo(room_id, content);
+    let Some(item) = item else {
+        event!(Level::WARN, "messageError 对应不上发往 {room_id} 的等待确认的消息, 不重试");
+        return;
+    };
+    if item.attempts >= queue.config.max_retries {
+        queue.metrics.failed.fetch_add(1, Ordering::Relaxed);
+        event!(Level::WARN, "发往 {} 的消息发送失败, 已放弃", item.room_id);
+        item.give_up(IcaError::DeliveryRejected(value.to_string()));
+        return;
+    }
+    event!(Level::WARN, "发往 {} 的消息发送失败, 重新排队", item.room_id);
+    queue.requeue(item);
+}
+
+/// 返回当前实例发送队列的统计数据, 队列未启动时返回 `None`。
+pub fn metrics() -> Option<MetricsSnapshot> {
+    current_queue().map(|queue| queue.metrics.snapshot())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_bucket_burst_and_refill() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(60, 2, start);
+        assert!(bucket.wait_time(start).is_zero());
+        bucket.take(start);
+        bucket.take(start);
+        assert_eq!(bucket.wait_time(start), Duration::from_secs(1));
+
+        let later = start + Duration::from_millis(500);
+        assert_eq!(bucket.wait_time(later), Duration::from_millis(500));
+        let later = start + Duration::from_secs(10);
+        assert!(bucket.wait_time(later).is_zero());
+        assert!(bucket.is_full(later));
+    }
+
+    #[test]
+    fn priority_order() {
+        assert!(Priority::High < Priority::Normal);
+        assert_eq!(Priority::from_name("LOW"), Some(Priority::Low));
+        assert_eq!(Priority::from_name("urgent"), None);
+    }
+
+    #[test]
+    fn message_error_target_lookup() {
+        let value = serde_json::json!({"roomId": -123, "content": "hi"});
+        assert_eq!(message_error_target(&value), Some((-123, "hi")));
+        let value = serde_json::json!({"message": {"roomId": 456, "content": "hello"}});
+        assert_eq!(message_error_target(&value), Some((456, "hello")));
+        let value = serde_json::json!("发送失败");
+        assert_eq!(message_error_target(&value), None);
+    }
+}
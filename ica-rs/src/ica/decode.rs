@@ -0,0 +1,101 @@
+//! bridge 推送数据的防御性解析。
+//!
+//! 新版 bridge 改了字段时, 解析失败不再让事件处理 panic: 记录原始数据,
+//! 按事件计数, 并按配置写进隔离文件方便提 bug, 然后继续处理后面的事件。
+
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::{LazyLock, Mutex};
+
+use serde::de::DeserializeOwned;
+use serde_json::{Value as JsonValue, json};
+use tracing::{Level, event};
+
+use crate::MainStatus;
+
+/// 日志里最多显示的原始数据长度
+const LOG_PAYLOAD_LIMIT: usize = 512;
+
+/// 实例 id -> 事件名 -> 解析失败次数
+static FAILURES: LazyLock<Mutex<HashMap<String, HashMap<String, u64>>>> =
+    LazyLock::new(Default::default);
+
+/// 解析 bridge 推送的数据, 失败时记录下来并返回 `None`。
+pub fn decode<T: DeserializeOwned>(event_name: &str, value: &JsonValue) -> Option<T> {
+    match T::deserialize(value) {
+        Ok(data) => Some(data),
+        Err(e) => {
+            record_failure(event_name, &e.to_string(), value);
+            None
+        }
+    }
+}
+
+/// 截断过长的文本, 保证在字符边界上截断。
+fn truncate(text: &str, limit: usize) -> &str {
+    match text.char_indices().nth(limit) {
+        Some((index, _)) => &text[..index],
+        None => text,
+    }
+}
+
+/// 记录一次解析失败
+///
+/// 计数并写日志, 配置了 `quarantine_path` 时把原始数据追加写入隔离文件
+pub fn record_failure(event_name: &str, error: &str, raw: &JsonValue) {
+    let config = MainStatus::global_config().ica();
+    *FAILURES
+        .lock()
+        .unwrap()
+        .entry(config.id.clone())
+        .or_default()
+        .entry(event_name.to_string())
+        .or_insert(0) += 1;
+
+    let raw_text = raw.to_string();
+    event!(
+        Level::WARN,
+        "[{}] 解析 {} 失败: {}, 原始数据: {}",
+        config.id,
+        event_name,
+        error,
+        truncate(&raw_text, LOG_PAYLOAD_LIMIT)
+    );
+
+    let Some(path) = config.quarantine_path else {
+        return;
+    };
+    let line = json!({
+        "time": chrono::Local::now().to_rfc3339(),
+        "instance": config.id,
+        "event": event_name,
+        "error": error,
+        "payload": raw,
+    });
+    let result = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| writeln!(file, "{line}"));
+    if let Err(e) = result {
+        event!(Level::ERROR, "写入隔离文件 {} 失败: {}", path, e);
+    }
+}
+
+/// 返回当前实例每种事件的解析失败次数。
+pub fn failure_counts() -> HashMap<String, u64> {
+    let id = MainStatus::global_config().ica_id();
+    FAILURES.lock().unwrap().get(&id).cloned().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_on_char_boundary() {
+        assert_eq!(truncate("abc", 5), "abc");
+        assert_eq!(truncate("你好世界", 2), "你好");
+    }
+}
@@ -4,6 +4,7 @@ use colored::Colorize;
 use futures_util::future::BoxFuture;
 use rust_socketio::asynchronous::Client;
 use rust_socketio::{Event, Payload};
+use serde::Deserialize;
 use serde_json::Value as JsonValue;
 use serde_json::json;
 use std::sync::{
@@ -13,37 +14,99 @@ use std::sync::{
 use std::time::Duration;
 use tracing::{Level, event, info, span, warn};
 
-use crate::data_struct::ica::RoomId;
+use crate::alert::{self, Alert, AlertLevel};
+use crate::data_struct::Platform;
 use crate::data_struct::ica::all_rooms::{JoinRequestRoom, Room};
-use crate::data_struct::ica::messages::{Message, MessageTrait, NewMessage};
+use crate::data_struct::ica::messages::system::SystemEvent;
+use crate::data_struct::ica::messages::{Message, MessageTrait, NewMessage, SendMessage};
 use crate::data_struct::ica::online_data::OnlineData;
-use crate::ica::client::send_message;
+use crate::data_struct::ica::{RoomId, RoomIdTrait};
+use crate::ica::capability::{self, Adapter};
+use crate::ica::client::queue::{self, Priority};
+use crate::ica::client::{handle_request, invalidate_group_members, send_message_with_priority};
+use crate::ica::decode;
+use crate::ica::join_policy::{self, JoinDecision};
+use crate::ica::login::{self, LoginChallenge};
+use crate::moderation;
+use crate::permission::{self, PERMISSIONS, Role, nodes};
 use crate::py::PY_PLUGIN_STORAGE;
-use crate::{MainStatus, VERSION, client_id, help_msg, py, version_str};
+use crate::{MainStatus, VERSION, client_id, help_msg, instance, py, reload, version_str};
+
+/// QQ 离线告警的键
+const ALERT_OFFLINE: &str = "ica.offline";
+/// bridge 连接出错告警的键
+const ALERT_CONNECTION: &str = "ica.connection";
 
 /// 获取在线数据
-pub async fn get_online_data(payload: Payload, _client: Client) {
+pub async fn get_online_data(payload: Payload, client: Client) {
     if let Payload::Text(values) = payload
         && let Some(value) = values.first()
     {
         let online_data = OnlineData::new_from_json(value);
         event!(Level::DEBUG, "update_online_data {}", format!("{online_data:?}").cyan());
-        let status = MainStatus::global_ica_status_mut();
-        status.qq_login = online_data.online;
-        status.update_online_status(online_data);
+        let online = online_data.online;
+        MainStatus::with_ica_status_mut(|status| status.update_online_status(online_data));
+        if online {
+            went_online(&client).await;
+        } else {
+            went_offline("onlineData 显示 QQ 不在线", &client).await;
+        }
     }
 }
 
-/// 处理 `setOnline`，把本地 QQ 登录状态标记为在线。
-pub async fn set_online(_payload: Payload, _client: Client) {
-    MainStatus::global_ica_status_mut().qq_login = true;
+/// 取出 payload 里的文本, 用作告警和钩子里的原因。
+fn payload_text(payload: &Payload) -> String {
+    match payload {
+        Payload::Text(values) => values
+            .iter()
+            .map(|value| match value.as_str() {
+                Some(text) => text.to_string(),
+                None => value.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(" "),
+        other => format!("{other:?}"),
+    }
+}
+
+/// QQ 从离线变为在线时调用一次插件钩子, 并解除离线告警。
+async fn went_online(client: &Client) {
+    login::clear();
+    // 已经在线时不重复处理
+    if MainStatus::with_ica_status_mut(|status| std::mem::replace(&mut status.qq_login, true))
+        .unwrap_or(true)
+    {
+        return;
+    }
     event!(Level::INFO, "Icalingua 已上线");
+    alert::resolve(Platform::Ica, ALERT_OFFLINE, "QQ 已恢复在线").await;
+    py::call::ica_online_py(client).await;
 }
 
-/// 处理 `setOffline`，把本地 QQ 登录状态标记为离线并记录原因。
-pub async fn set_offline(payload: Payload, _client: Client) {
-    MainStatus::global_ica_status_mut().qq_login = false;
-    event!(Level::WARN, "Icalingua 已离线: {payload:?}");
+/// QQ 从在线变为离线时告警, 并调用一次插件钩子。
+async fn went_offline(reason: &str, client: &Client) {
+    // 已经离线时不重复处理
+    if !MainStatus::with_ica_status_mut(|status| std::mem::replace(&mut status.qq_login, false))
+        .unwrap_or(false)
+    {
+        return;
+    }
+    alert::raise(Alert::new(
+        Platform::Ica,
+        ALERT_OFFLINE,
+        AlertLevel::Critical,
+        format!("QQ 已离线: {reason}"),
+    ))
+    .await;
+    py::call::ica_offline_py(reason, client).await;
+}
+
+/// 处理 `setOnline`，把本地 QQ 登录状态标记为在线。
+pub async fn set_online(_payload: Payload, client: Client) { went_online(&client).await; }
+
+/// 处理 `setOffline`，把本地 QQ 登录状态标记为离线并告警。
+pub async fn set_offline(payload: Payload, client: Client) {
+    went_offline(&payload_text(&payload), &client).await;
 }
 
 /// 处理 `setShutUp`，记录当前会话的禁言状态变化。
@@ -51,25 +114,55 @@ pub async fn set_shut_up(payload: Payload, _client: Client) {
     event!(Level::INFO, "setShutUp: {payload:?}");
 }
 
+/// 在单独的任务里发送命令的回复
+///
+/// 发送队列限速时会等待, 不能在事件回调里等, 否则会卡住后续事件 (包括用来确认送达的回显)
+fn spawn_reply(client: &Client, reply: SendMessage, priority: Priority) {
+    let client = client.clone();
+    let id = MainStatus::global_config().ica_id();
+    tokio::spawn(instance::scope(id, async move {
+        send_message_with_priority(&client, &reply, priority).await;
+    }));
+}
+
 /// 接收消息
 pub async fn add_message(payload: Payload, client: Client) {
     if let Payload::Text(values) = payload
         && let Some(value) = values.first()
     {
-        let message: NewMessage = serde_json::from_value(value.clone()).unwrap();
-        // 检测是否在过滤列表内
-        if MainStatus::global_config().ica().filter_list.contains(&message.msg.sender_id) {
+        let Some(message) = decode::decode::<NewMessage>("addMessage", value) else {
+            return;
+        };
+        let sender = message.sender_id().to_string();
+        let room = message.room_id.to_string();
+        // 过滤, 自己发的消息还要用来确认送达, 不过滤
+        if !message.is_from_self()
+            && !moderation::check_message(Platform::Ica, &sender, &room, message.content())
+                .is_pass()
+        {
             return;
         }
 
         println!("new_msg {}", message.to_string().cyan());
+        if message.is_from_self() {
+            queue::confirm_delivery(message.room_id, message.content(), message.msg_id());
+        }
+        if message.room_id.is_room() {
+            permission::observe_group_role(&room, &sender, message.role());
+        }
+        let role = permission::role_of(Platform::Ica, &sender, Some(&room), Some(message.role()));
+        // 管理员的回复优先发送
+        let priority = if role >= Role::Admin {
+            Priority::High
+        } else {
+            Priority::Normal
+        };
         // 就在这里处理掉最基本的消息
         // 之后的处理交给插件
-        let admin_list = &MainStatus::global_config().ica().admin_list;
-        if !message.is_from_self() && !message.is_reply() {
+        if !message.is_from_self() && !message.is_reply() && role != Role::Banned {
             if message.content() == "/bot-rs" {
                 let reply = message.reply_with(&version_str());
-                send_message(&client, &reply).await;
+                spawn_reply(&client, reply, priority);
             } else if message.content() == "/bot-ls" {
                 let reply = message.reply_with(&format!(
                     "shenbot-py v{}-{}\n{}",
@@ -82,20 +175,13 @@ pub async fn add_message(payload: Payload, client: Client) {
                         "未启用 Python 插件".to_string()
                     }
                 ));
-                send_message(&client, &reply).await;
+                spawn_reply(&client, reply, priority);
             } else if message.content() == "/bot-permission" {
-                let reply = message.reply_with(&format!(
-                    "您的权限: {}",
-                    if admin_list.contains(&message.sender_id()) {
-                        "管理员"
-                    } else {
-                        "没啥"
-                    }
-                ));
-                send_message(&client, &reply).await;
+                let reply = message.reply_with(&format!("您的权限: {role}"));
+                spawn_reply(&client, reply, priority);
             } else if message.content() == "/bot-help" {
                 let reply = message.reply_with(&help_msg());
-                send_message(&client, &reply).await;
+                spawn_reply(&client, reply, priority);
             }
             // else if message.content() == "/bot-uptime" {
             //     let duration = match start_up_time().elapsed() {
@@ -107,54 +193,146 @@ pub async fn add_message(payload: Payload, client: Client) {
             //     ));
             //     send_message(&client, &reply).await;
             // }
-            else if admin_list.contains(&message.sender_id()) {
-                // admin 区
-                // 先判定是否为 admin
+            else if role >= Role::Moderator {
+                // 管理区
+                // 每个命令再单独检查权限节点
                 let client_id = client_id();
-                let mut storage = PY_PLUGIN_STORAGE.lock().await;
+                let allows = |node: &str| PERMISSIONS.read().unwrap().role_allows(role, node);
 
-                if message.content().starts_with(&format!("/bot-enable-{client_id}")) {
+                if message.content().starts_with(&format!("/bot-enable-{client_id}"))
+                    && allows(nodes::PLUGIN_MANAGE)
+                {
                     // 尝试获取后面的信息
-                    if let Some((_, name)) = message.content().split_once(" ") {
-                        let reply = match storage.get_status(name) {
-                            None => message.reply_with("未找到插件"),
-                            Some(true) => message.reply_with("无变化, 插件已经启用"),
-                            Some(false) => match storage.set_status(name, true) {
-                                Ok(_) => message.reply_with("启用插件完成"),
-                                Err(e) => message.reply_with(&format!("启用插件失败, 错误: \n{e}")),
-                            },
-                        };
-                        send_message(&client, &reply).await;
+                    if let Some((_, args)) = message.content().split_once(" ") {
+                        let mut storage = PY_PLUGIN_STORAGE.lock().await;
+                        let reply = message.reply_with(&storage.handle_status_command(
+                            args,
+                            Some(true),
+                            Platform::Ica,
+                            &room,
+                        ));
+                        spawn_reply(&client, reply, priority);
+                    }
+                } else if message.content().starts_with(&format!("/bot-disable-{client_id}"))
+                    && allows(nodes::PLUGIN_MANAGE)
+                {
+                    if let Some((_, args)) = message.content().split_once(" ") {
+                        let mut storage = PY_PLUGIN_STORAGE.lock().await;
+                        let reply = message.reply_with(&storage.handle_status_command(
+                            args,
+                            Some(false),
+                            Platform::Ica,
+                            &room,
+                        ));
+                        spawn_reply(&client, reply, priority);
                     }
-                } else if message.content().starts_with(&format!("/bot-disable-{client_id}")) {
+                } else if message.content().starts_with(&format!("/bot-inherit-{client_id}"))
+                    && allows(nodes::PLUGIN_MANAGE)
+                {
+                    if let Some((_, args)) = message.content().split_once(" ") {
+                        let mut storage = PY_PLUGIN_STORAGE.lock().await;
+                        let reply = message.reply_with(&storage.handle_status_command(
+                            args,
+                            None,
+                            Platform::Ica,
+                            &room,
+                        ));
+                        spawn_reply(&client, reply, priority);
+                    }
+                } else if message.content().starts_with(&format!("/bot-reload-{client_id}"))
+                    && allows(nodes::PLUGIN_RELOAD)
+                {
                     if let Some((_, name)) = message.content().split_once(" ") {
+                        let mut storage = PY_PLUGIN_STORAGE.lock().await;
                         let reply = match storage.get_status(name) {
                             None => message.reply_with("未找到插件"),
-                            Some(false) => message.reply_with("无变化, 插件已经禁用"),
-                            Some(true) => match storage.set_status(name, false) {
-                                Ok(_) => message.reply_with("禁用插件完成"),
-                                Err(e) => message.reply_with(&format!("禁用插件失败, 错误: \n{e}")),
+                            Some(_) => match storage.reload_plugin(name, Some(false)) {
+                                Ok(_) => message.reply_with("重载成功"),
+                                Err(e) => message.reply_with(&format!("重载失败, 错误: \n{e}")),
                             },
                         };
-                        send_message(&client, &reply).await;
+                        spawn_reply(&client, reply, priority);
                     }
-                } else if message.content().starts_with(&format!("/bot-reload-{client_id}")) {
-                    if let Some((_, name)) = message.content().split_once(" ") {
-                        let reply = match storage.get_status(name) {
-                            None => message.reply_with("未找到插件"),
-                            Some(_) => {
-                                let plugin = storage.storage.get_mut(name).unwrap();
-                                match plugin.reload_self(Some(false)) {
-                                    Ok(_) => message.reply_with("重载成功"),
-                                    Err(e) => message.reply_with(&format!("重载失败, 错误: \n{e}")),
-                                }
-                            }
-                        };
-                        send_message(&client, &reply).await;
+                } else if message.content() == format!("/bot-config-reload-{client_id}")
+                    && allows(nodes::CONFIG_RELOAD)
+                {
+                    // 重载时可能会重连这个实例, 不在它的事件回调里等
+                    let client = client.clone();
+                    let message = message.clone();
+                    let id = MainStatus::global_config().ica_id();
+                    tokio::spawn(instance::scope(id, async move {
+                        let summary = reload::reload("管理员命令").await;
+                        let reply = message.reply_with(&summary);
+                        send_message_with_priority(&client, &reply, priority).await;
+                    }));
+                } else if message.content().starts_with(&format!("/bot-grant-{client_id}"))
+                    && allows(nodes::PERMISSION_GRANT)
+                {
+                    if let Some((_, args)) = message.content().split_once(" ") {
+                        let reply = message.reply_with(&permission::handle_grant_command(
+                            args,
+                            true,
+                            Platform::Ica,
+                            &sender,
+                            Some(message.role()),
+                            &room,
+                        ));
+                        spawn_reply(&client, reply, priority);
+                    }
+                } else if message.content().starts_with(&format!("/bot-revoke-{client_id}"))
+                    && allows(nodes::PERMISSION_GRANT)
+                {
+                    if let Some((_, args)) = message.content().split_once(" ") {
+                        let reply = message.reply_with(&permission::handle_grant_command(
+                            args,
+                            false,
+                            Platform::Ica,
+                            &sender,
+                            Some(message.role()),
+                            &room,
+                        ));
+                        spawn_reply(&client, reply, priority);
                     }
-                } else if message.content() == "/bot-fetch" {
+                } else if (message.content().starts_with(&format!("/bot-mute-{client_id}"))
+                    || message.content().starts_with(&format!("/bot-unmute-{client_id}")))
+                    && allows(nodes::MODERATION)
+                {
+                    if let Some((command, args)) = message.content().split_once(" ") {
+                        let mute = command.starts_with("/bot-mute");
+                        let reply = message.reply_with(&moderation::handle_mute_command(
+                            args,
+                            mute,
+                            Platform::Ica,
+                        ));
+                        spawn_reply(&client, reply, priority);
+                    }
+                } else if message.content().starts_with(&format!("/bot-filter-{client_id}"))
+                    && allows(nodes::MODERATION)
+                {
+                    let args = message.content().split_once(" ").map_or("", |(_, args)| args);
+                    let reply = message.reply_with(&moderation::handle_filter_command(
+                        args,
+                        Platform::Ica,
+                        &room,
+                    ));
+                    spawn_reply(&client, reply, priority);
+                } else if message.content() == format!("/bot-queue-{client_id}")
+                    && allows(nodes::QUEUE)
+                {
+                    let status = match queue::metrics() {
+                        Some(metrics) => metrics.to_string(),
+                        None => "发送队列未启动".to_string(),
+                    };
+                    let reply = message.reply_with(&format!("发送队列状态:\n{status}"));
+                    spawn_reply(&client, reply, priority);
+                } else if message.content() == format!("/bot-jobs-{client_id}")
+                    && allows(nodes::JOBS)
+                {
+                    let reply = message.reply_with(&py::scheduler::display_jobs());
+                    spawn_reply(&client, reply, priority);
+                } else if message.content() == "/bot-fetch" && allows(nodes::FETCH) {
                     let reply = message.reply_with("正在更新当前群消息");
-                    send_message(&client, &reply).await;
+                    spawn_reply(&client, reply, priority);
                     fetch_messages(&client, message.room_id).await;
                 }
             }
@@ -163,7 +341,15 @@ pub async fn add_message(payload: Payload, client: Client) {
         // 检测 sys
         if message.system() {
             py::call::ica_system_message_py(&message, &client).await;
-        } else {
+            let system_event = SystemEvent::parse(message.content());
+            if system_event == SystemEvent::Unknown {
+                event!(Level::DEBUG, "无法识别的系统消息: {}", message.content());
+            }
+            if system_event.changes_members() {
+                invalidate_group_members(message.room_id);
+            }
+            py::call::ica_system_event_py(&system_event, &message, &client).await;
+        } else if role != Role::Banned {
             py::call::ica_new_message_py(&message, &client).await;
         }
     }
@@ -176,8 +362,14 @@ pub async fn set_messages(payload: Payload, _client: Client) {
     if let Payload::Text(values) = payload
         && let Some(value) = values.first()
     {
-        let messages: Vec<Message> = serde_json::from_value(value["messages"].clone()).unwrap();
-        let room_id = value["roomId"].as_i64().unwrap();
+        let Some(room_id) = value["roomId"].as_i64() else {
+            decode::record_failure("setMessages", "缺少整数类型的 `roomId`", value);
+            return;
+        };
+        let Some(messages) = decode::decode::<Vec<Message>>("setMessages", &value["messages"])
+        else {
+            return;
+        };
         println!("set_messages {} len: {}", room_id.to_string().cyan(), messages.len());
     }
 }
@@ -202,9 +394,17 @@ pub async fn update_all_room(payload: Payload, _client: Client) {
         && let Some(value) = values.first()
         && let Some(raw_rooms) = value.as_array()
     {
-        let rooms: Vec<Room> = raw_rooms.iter().map(Room::new_from_json).collect();
+        // 解析失败的房间单独跳过, 不影响其他房间
+        let rooms: Vec<Room> = raw_rooms
+            .iter()
+            .filter_map(|raw| {
+                Room::try_from_json(raw)
+                    .map_err(|e| decode::record_failure("setAllRooms", &e.to_string(), raw))
+                    .ok()
+            })
+            .collect();
         event!(Level::DEBUG, "update_all_room {}", rooms.len());
-        MainStatus::global_ica_status_mut().update_rooms(rooms);
+        MainStatus::with_ica_status_mut(|status| status.update_rooms(rooms));
     }
 }
 
@@ -213,13 +413,22 @@ pub async fn update_room(payload: Payload, _client: Client) {
     if let Payload::Text(values) = payload
         && let Some(value) = values.first()
     {
-        let room = Room::new_from_json(value);
-        let rooms = &mut MainStatus::global_ica_status_mut().rooms;
-        if let Some(current) = rooms.iter_mut().find(|current| current.room_id == room.room_id) {
-            *current = room;
-        } else {
-            rooms.push(room);
-        }
+        let room = match Room::try_from_json(value) {
+            Ok(room) => room,
+            Err(e) => {
+                decode::record_failure("updateRoom", &e.to_string(), value);
+                return;
+            }
+        };
+        MainStatus::with_ica_status_mut(|status| {
+            let rooms = &mut status.rooms;
+            if let Some(current) = rooms.iter_mut().find(|current| current.room_id == room.room_id)
+            {
+                *current = room;
+            } else {
+                rooms.push(room);
+            }
+        });
     }
 }
 
@@ -238,6 +447,7 @@ pub async fn failed_message(payload: Payload, _client: Client) {
         && let Some(value) = values.first()
     {
         warn!("messageError {}", value.to_string().red());
+        queue::on_message_error(value);
     }
 }
 
@@ -251,34 +461,57 @@ pub async fn renew_message_url(payload: Payload, _client: Client) {
     event!(Level::DEBUG, "renewMessageURL: {payload:?}");
 }
 
-/// 处理 `notifyError`，记录普通 bridge 错误通知。
+/// 处理 `notifyError`，把普通 bridge 错误作为警告告警。
 pub async fn notify_error(payload: Payload, _client: Client) {
-    event!(Level::WARN, "notifyError: {payload:?}");
+    let alert = Alert::new(
+        Platform::Ica,
+        "ica.notify_error",
+        AlertLevel::Warning,
+        format!("bridge 报错: {}", payload_text(&payload)),
+    );
+    alert::raise(alert).await;
 }
 
-/// 处理 `fatal`，记录要求客户端停止工作的 bridge 致命错误。
+/// 处理 `fatal`，把要求客户端停止工作的 bridge 致命错误作为严重告警。
 pub async fn fatal_error(payload: Payload, _client: Client) {
-    event!(Level::ERROR, "bridge fatal: {payload:?}");
+    let alert = Alert::new(
+        Platform::Ica,
+        "ica.fatal",
+        AlertLevel::Critical,
+        format!("bridge 出现致命错误: {}", payload_text(&payload)),
+    );
+    alert::raise(alert).await;
+}
+
+/// 处理 socket.io 连接错误 (包括重连失败)。
+pub async fn connection_error(payload: Payload, _client: Client) {
+    let alert = Alert::new(
+        Platform::Ica,
+        ALERT_CONNECTION,
+        AlertLevel::Critical,
+        format!("与 bridge 的连接出错: {}", payload_text(&payload)),
+    );
+    alert::raise(alert).await;
 }
 
 /// 处理 `requestSetup`，提示 bridge 尚未配置 QQ 账号。
-pub async fn request_setup(payload: Payload, _client: Client) {
-    event!(Level::WARN, "bridge 尚未完成账号配置: {payload:?}");
+pub async fn request_setup(_payload: Payload, client: Client) {
+    login::on_challenge(LoginChallenge::Setup, client).await;
 }
 
-/// 处理 `login-verify`，记录设备验证登录请求。
-pub async fn login_verify(payload: Payload, _client: Client) {
-    event!(Level::WARN, "bridge 登录需要设备验证: {payload:?}");
+/// 处理 `login-verify`，展示设备验证链接并等待确认。
+pub async fn login_verify(payload: Payload, client: Client) {
+    login::on_challenge(LoginChallenge::from_verify(&payload), client).await;
 }
 
-/// 处理 `login-qrcodeLogin`，记录二维码登录请求。
-pub async fn login_qrcode(payload: Payload, _client: Client) {
-    event!(Level::WARN, "bridge 登录需要扫码: {payload:?}");
+/// 处理 `login-qrcodeLogin`，展示并保存登录二维码。
+pub async fn login_qrcode(payload: Payload, client: Client) {
+    login::on_challenge(LoginChallenge::from_qrcode(&payload), client).await;
 }
 
-/// 处理 `login-smsCodeVerify`，记录短信验证码登录请求。
-pub async fn login_sms_code(payload: Payload, _client: Client) {
-    event!(Level::WARN, "bridge 登录需要短信验证码: {payload:?}");
+/// 处理 `login-smsCodeVerify`，等待短信验证码。
+pub async fn login_sms_code(payload: Payload, client: Client) {
+    login::on_challenge(LoginChallenge::from_sms(&payload), client).await;
 }
 
 /// 处理 `login-error`，记录 bridge 登录失败原因。
@@ -286,15 +519,17 @@ pub async fn login_error(payload: Payload, _client: Client) {
     event!(Level::ERROR, "bridge 登录失败: {payload:?}");
 }
 
-/// 处理 `login-slider`，记录滑块验证登录请求。
-pub async fn login_slider(payload: Payload, _client: Client) {
-    event!(Level::WARN, "bridge 登录需要滑块验证: {payload:?}");
+/// 处理 `login-slider`，展示滑块验证链接并等待 ticket。
+pub async fn login_slider(payload: Payload, client: Client) {
+    login::on_challenge(LoginChallenge::from_slider(&payload), client).await;
 }
 
 /// 兼容 Milky adapter 的额外 `login` 推送，并标记 QQ 已登录。
-pub async fn bridge_login(payload: Payload, _client: Client) {
-    MainStatus::global_ica_status_mut().qq_login = true;
+pub async fn bridge_login(payload: Payload, client: Client) {
     event!(Level::INFO, "Milky bridge 已登录: {payload:?}");
+    // 只有 Milky 适配器会发这个事件
+    capability::note_adapter(Adapter::Milky);
+    went_online(&client).await;
 }
 
 /// 处理加群申请
@@ -304,19 +539,38 @@ pub async fn join_request(payload: Payload, client: Client) {
     if let Payload::Text(values) = payload
         && let Some(value) = values.first()
     {
-        match serde_json::from_value::<JoinRequestRoom>(value.clone()) {
+        match JoinRequestRoom::deserialize(value) {
             Ok(join_room) => {
                 event!(Level::INFO, "{}", format!("收到加群申请 {join_room:?}").on_blue());
-                py::call::ica_join_request_py(join_room, &client).await;
-            }
-            Err(e) => {
-                event!(
-                    Level::WARN,
-                    "呼叫 shenjack! JoinRequestRoom 的 serde 没写好! {}\nraw: {:#?}",
-                    e,
-                    value
-                )
+                let policies = MainStatus::global_config().ica().join_policy;
+                let decision = join_policy::decide(&policies, &join_room);
+                let response = match &decision {
+                    JoinDecision::Pass => None,
+                    JoinDecision::Approve => Some((true, None)),
+                    JoinDecision::Reject(reason) => Some((false, Some(reason.as_str()))),
+                };
+                let mut handled = None;
+                if let Some((approve, reason)) = response
+                    && handle_request(
+                        &client,
+                        &join_room.request_type,
+                        &join_room.flag,
+                        approve,
+                        reason,
+                    )
+                    .await
+                {
+                    event!(
+                        Level::INFO,
+                        "已按策略自动处理 {} 的加群申请: {:?}",
+                        join_room.user_id,
+                        decision
+                    );
+                    handled = Some(approve);
+                }
+                py::call::ica_join_request_py(join_room, handled, &client).await;
             }
+            Err(e) => decode::record_failure("sendAddRequest", &e.to_string(), value),
         }
     }
 }
@@ -346,6 +600,8 @@ pub async fn fetch_messages(client: &Client, room: RoomId) {
     let timeout = Duration::from_secs(10);
     let ack_received = Arc::new(AtomicBool::new(false));
     let ack_received_cb = ack_received.clone();
+    // ACK 回调不在事件回调的作用域里, 需要带上实例
+    let id = MainStatus::global_config().ica_id();
 
     match client
         .emit_with_ack(
@@ -354,7 +610,7 @@ pub async fn fetch_messages(client: &Client, room: RoomId) {
             timeout,
             move |payload: Payload, _client: Client| -> BoxFuture<'static, ()> {
                 let ack_received = ack_received_cb.clone();
-                Box::pin(async move {
+                Box::pin(instance::scope(id.clone(), async move {
                     ack_received.store(true, Ordering::SeqCst);
                     let ack_values = ack_payload_values(&payload);
                     let messages =
@@ -364,14 +620,13 @@ pub async fn fetch_messages(client: &Client, room: RoomId) {
                         Ok(messages) => {
                             event!(Level::INFO, "fetch_messages {room} len: {}", messages.len());
                         }
-                        Err(e) => {
-                            event!(
-                                Level::WARN,
-                                "fetch_messages {room} ACK 格式错误: {e}; raw: {ack_values:#?}"
-                            );
-                        }
+                        Err(e) => decode::record_failure(
+                            "fetchMessages",
+                            &e.to_string(),
+                            &JsonValue::Array(ack_values),
+                        ),
                     }
-                })
+                }))
             },
         )
         .await
@@ -440,7 +695,7 @@ pub async fn any_event(event: Event, payload: Payload, _client: Client) {
             if let Payload::Text(values) = payload
                 && let Some(value) = values.first()
             {
-                if handled.contains(&value.as_str().unwrap()) {
+                if value.as_str().is_some_and(|name| handled.contains(&name)) {
                     return;
                 }
                 info!("收到消息 {}", value.to_string().yellow());
@@ -472,7 +727,8 @@ pub async fn connect_callback(payload: Payload, _client: Client) {
     {
         match value.as_str() {
             Some("authSucceed") => {
-                event!(Level::INFO, "{}", "已经登录到 icalingua!".green())
+                event!(Level::INFO, "{}", "已经登录到 icalingua!".green());
+                alert::resolve(Platform::Ica, ALERT_CONNECTION, "已重新连接到 bridge").await;
             }
             Some("authFailed") => {
                 event!(Level::ERROR, "{}", "登录到 icalingua 失败!".red());
@@ -0,0 +1,143 @@
+//! 按群配置的加群申请自动审批。
+
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+
+use regex::Regex;
+use tracing::{Level, event};
+
+use crate::config::JoinPolicyConfig;
+use crate::data_struct::ica::all_rooms::JoinRequestRoom;
+
+/// 默认的自动拒绝理由
+pub const DEFAULT_REJECT_REASON: &str = "答案错误";
+
+/// 编译过的 `answer_regex`, 按原文缓存, 重载配置后换了的规则会重新编译
+static ANSWER_REGEX: LazyLock<Mutex<HashMap<String, Regex>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// 返回编译好的 `answer_regex`。
+fn answer_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    let mut cache = ANSWER_REGEX.lock().unwrap();
+    if let Some(regex) = cache.get(pattern) {
+        return Ok(regex.clone());
+    }
+    let regex = Regex::new(pattern)?;
+    cache.insert(pattern.to_string(), regex.clone());
+    Ok(regex)
+}
+
+/// 自动审批的结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JoinDecision {
+    /// 自动同意
+    Approve,
+    /// 自动拒绝, 附带理由
+    Reject(String),
+    /// 不处理, 交给插件或人工
+    Pass,
+}
+
+/// 根据策略判断一条加群申请
+///
+/// 黑名单优先于答案匹配, 好友申请和没有配置策略的群一律不处理
+pub fn decide(policies: &[JoinPolicyConfig], request: &JoinRequestRoom) -> JoinDecision {
+    if !request.is_group() {
+        return JoinDecision::Pass;
+    }
+    let Some(policy) =
+        policies.iter().find(|policy| policy.group_id.abs() == request.group_id.abs())
+    else {
+        return JoinDecision::Pass;
+    };
+    let reject = || {
+        JoinDecision::Reject(
+            policy
+                .reject_reason
+                .clone()
+                .unwrap_or_else(|| DEFAULT_REJECT_REASON.to_string()),
+        )
+    };
+    if policy.blocklist.contains(&request.user_id) {
+        return reject();
+    }
+    // 空字符串和没写一样, 免得照抄模板后变成全部同意
+    let Some(pattern) = policy.answer_regex.as_deref().filter(|pattern| !pattern.is_empty()) else {
+        return JoinDecision::Pass;
+    };
+    let regex = match answer_regex(pattern) {
+        Ok(regex) => regex,
+        Err(e) => {
+            event!(
+                Level::WARN,
+                "群 {} 的 answer_regex 无效, 已跳过自动审批: {}",
+                policy.group_id,
+                e
+            );
+            return JoinDecision::Pass;
+        }
+    };
+    if regex.is_match(request.answer()) {
+        JoinDecision::Approve
+    } else if policy.reject_unmatched {
+        reject()
+    } else {
+        JoinDecision::Pass
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(group_id: i64, user_id: i64, comment: &str) -> JoinRequestRoom {
+        JoinRequestRoom {
+            comment: comment.to_string(),
+            group_id,
+            group_name: "test".to_string(),
+            user_id,
+            nickname: "someone".to_string(),
+            request_type: "group".to_string(),
+            post_type: "request".to_string(),
+            sub_type: "add".to_string(),
+            time: 0,
+            tips: String::new(),
+            flag: "flag".to_string(),
+        }
+    }
+
+    fn policy(reject_unmatched: bool) -> JoinPolicyConfig {
+        JoinPolicyConfig {
+            group_id: 123,
+            answer_regex: Some("(?i)^python$".to_string()),
+            reject_unmatched,
+            blocklist: vec![42],
+            reject_reason: None,
+        }
+    }
+
+    #[test]
+    fn answer_and_blocklist() {
+        let policies = vec![policy(false)];
+        let ok = request(123, 1, "问题：用什么语言\n答案：Python");
+        assert_eq!(decide(&policies, &ok), JoinDecision::Approve);
+
+        let wrong = request(123, 1, "问题：用什么语言\n答案：rust");
+        assert_eq!(decide(&policies, &wrong), JoinDecision::Pass);
+        assert_eq!(
+            decide(&[policy(true)], &wrong),
+            JoinDecision::Reject(DEFAULT_REJECT_REASON.to_string())
+        );
+
+        // 黑名单里的人答对了也拒绝
+        let blocked = request(123, 42, "问题：用什么语言\n答案：python");
+        assert!(matches!(decide(&policies, &blocked), JoinDecision::Reject(_)));
+
+        let other_group = request(456, 1, "答案：python");
+        assert_eq!(decide(&policies, &other_group), JoinDecision::Pass);
+
+        let mut friend = request(123, 1, "答案：python");
+        friend.request_type = "friend".to_string();
+        assert_eq!(decide(&policies, &friend), JoinDecision::Pass);
+    }
+}
@@ -0,0 +1,491 @@
+//! QQ 登录验证 (二维码/滑块/短信/设备锁) 的交互处理。
+//!
+//! bridge 登录 QQ 遇到验证时会推送 `login-*` 事件, 这里负责把验证展示出来
+//! (终端、PNG 文件、Tailchat 提醒房间), 再把控制台或管理员命令给的结果发回 bridge。
+
+/// 加载 `qrcode` 子模块。
+pub mod qrcode;
+
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::sync::{LazyLock, Mutex, OnceLock};
+
+use base64::{Engine as _, engine::general_purpose};
+use colored::Colorize;
+use rust_socketio::Payload;
+use rust_socketio::asynchronous::Client;
+use serde_json::{Value as JsonValue, json};
+use tokio::runtime::Handle;
+use tracing::{Level, event};
+
+use crate::MainStatus;
+use crate::config::LoginConfig;
+use crate::error::{ClientResult, IcaError};
+
+/// 发回 bridge 的登录事件
+pub mod bridge_events {
+    /// 提交滑块验证得到的 ticket
+    pub const SLIDER_LOGIN: &str = "sliderLogin";
+    /// 提交短信验证码
+    pub const SUBMIT_SMS_CODE: &str = "submitSmsCode";
+    /// 请求 bridge 发送短信验证码
+    pub const REQUEST_SMS_CODE: &str = "requestSmsCode";
+    /// 扫码确认后让 bridge 继续登录
+    pub const QRCODE_LOGIN: &str = "qrcodeLogin";
+    /// 设备验证完成后重新登录
+    pub const RE_LOGIN: &str = "reLogin";
+}
+
+/// bridge 推送过来的登录验证
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoginChallenge {
+    /// 需要扫码, `url` 为二维码内容, `image` 为 bridge 给的图片
+    Qrcode {
+        url: Option<String>,
+        image: Option<Vec<u8>>,
+    },
+    /// 需要滑块验证
+    Slider { url: Option<String> },
+    /// 需要短信验证码
+    Sms { phone: Option<String> },
+    /// 需要设备锁验证
+    DeviceVerify { url: Option<String> },
+    /// bridge 还没配置 QQ 账号
+    Setup,
+}
+
+/// 取出 payload 里的第一个值。
+fn first_value(payload: &Payload) -> Option<&JsonValue> {
+    match payload {
+        Payload::Text(values) => values.first(),
+        _ => None,
+    }
+}
+
+/// 值本身是字符串时直接返回, 是对象时按顺序找第一个字符串字段。
+fn find_str(value: &JsonValue, keys: &[&str]) -> Option<String> {
+    if let Some(text) = value.as_str() {
+        return Some(text.to_string()).filter(|text| !text.is_empty());
+    }
+    keys.iter()
+        .find_map(|key| value.get(key).and_then(JsonValue::as_str))
+        .filter(|text| !text.is_empty())
+        .map(str::to_string)
+}
+
+/// 解析图片数据
+///
+/// 支持 data URL / 纯 base64 字符串、字节数组和 Node 的 `Buffer` JSON
+fn decode_image(value: &JsonValue) -> Option<Vec<u8>> {
+    match value {
+        JsonValue::String(text) => {
+            let data = match text.split_once(";base64,") {
+                Some((_, data)) => data,
+                None => text,
+            };
+            general_purpose::STANDARD
+                .decode(data.trim())
+                .ok()
+                .filter(|data| !data.is_empty())
+        }
+        JsonValue::Array(items) => items
+            .iter()
+            .map(|item| item.as_u64().and_then(|byte| u8::try_from(byte).ok()))
+            .collect(),
+        JsonValue::Object(map) => map.get("data").and_then(decode_image),
+        _ => None,
+    }
+}
+
+impl LoginChallenge {
+    /// 解析 `login-qrcodeLogin`
+    pub fn from_qrcode(payload: &Payload) -> Self {
+        if let Payload::Binary(data) = payload {
+            return Self::Qrcode {
+                url: None,
+                image: Some(data.to_vec()),
+            };
+        }
+        let Some(value) = first_value(payload) else {
+            return Self::Qrcode {
+                url: None,
+                image: None,
+            };
+        };
+        if let Some(text) = value.as_str()
+            && text.starts_with("http")
+        {
+            return Self::Qrcode {
+                url: Some(text.to_string()),
+                image: None,
+            };
+        }
+        // 对象要么带图片字段, 要么本身就是 `Buffer`
+        let image = ["image", "qrcode", "img"]
+            .iter()
+            .find_map(|key| value.get(key).and_then(decode_image))
+            .or_else(|| decode_image(value));
+        let url = if value.is_object() {
+            find_str(value, &["url", "qrcodeUrl", "qrcode_url"])
+        } else {
+            None
+        };
+        Self::Qrcode { url, image }
+    }
+
+    /// 解析 `login-slider`
+    pub fn from_slider(payload: &Payload) -> Self {
+        Self::Slider {
+            url: first_value(payload).and_then(|value| find_str(value, &["url"])),
+        }
+    }
+
+    /// 解析 `login-smsCodeVerify`
+    pub fn from_sms(payload: &Payload) -> Self {
+        Self::Sms {
+            phone: first_value(payload)
+                .and_then(|value| find_str(value, &["phone", "phoneNumber"])),
+        }
+    }
+
+    /// 解析 `login-verify`
+    pub fn from_verify(payload: &Payload) -> Self {
+        Self::DeviceVerify {
+            url: first_value(payload).and_then(|value| find_str(value, &["url"])),
+        }
+    }
+
+    /// 返回给人看的说明, 包括怎么回复
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Qrcode { url, .. } => {
+                let mut text = "QQ 登录需要扫码".to_string();
+                if let Some(url) = url {
+                    text.push_str(&format!("\n二维码链接: {url}"));
+                }
+                text.push_str("\n扫码确认后回复 `qrcode`");
+                text
+            }
+            Self::Slider { url } => format!(
+                "QQ 登录需要滑块验证\n验证链接: {}\n完成后回复 `slider <ticket>`",
+                url.as_deref().unwrap_or("(bridge 没有给出链接)")
+            ),
+            Self::Sms { phone } => format!(
+                "QQ 登录需要短信验证码{}\n回复 `sms` 发送验证码, 收到后回复 `sms <验证码>`",
+                phone.as_deref().map(|phone| format!(" (手机号 {phone})")).unwrap_or_default()
+            ),
+            Self::DeviceVerify { url } => format!(
+                "QQ 登录需要设备验证\n验证链接: {}\n完成后回复 `verified`",
+                url.as_deref().unwrap_or("(bridge 没有给出链接)")
+            ),
+            Self::Setup => "bridge 还没有配置 QQ 账号, 请先在 bridge 里完成配置".to_string(),
+        }
+    }
+}
+
+/// 回复给 bridge 的登录验证结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoginResponse {
+    /// 滑块验证的 ticket
+    SliderTicket(String),
+    /// 短信验证码
+    SmsCode(String),
+    /// 请求发送短信验证码
+    RequestSms,
+    /// 已经扫码确认
+    QrcodeScanned,
+    /// 已经完成设备验证
+    Verified,
+}
+
+impl LoginResponse {
+    /// 解析 `slider <ticket>` / `sms [code]` / `qrcode` / `verified`
+    pub fn parse(input: &str) -> Option<Self> {
+        let input = input.trim();
+        let (command, arg) = match input.split_once(char::is_whitespace) {
+            Some((command, arg)) => (command, arg.trim()),
+            None => (input, ""),
+        };
+        match (command, arg) {
+            ("slider", ticket) if !ticket.is_empty() => {
+                Some(Self::SliderTicket(ticket.to_string()))
+            }
+            ("sms", "") => Some(Self::RequestSms),
+            ("sms", code) => Some(Self::SmsCode(code.to_string())),
+            ("qrcode", "") => Some(Self::QrcodeScanned),
+            ("verified", "") => Some(Self::Verified),
+            _ => None,
+        }
+    }
+
+    /// 对应的 bridge 事件名
+    pub fn event_name(&self) -> &'static str {
+        match self {
+            Self::SliderTicket(_) => bridge_events::SLIDER_LOGIN,
+            Self::SmsCode(_) => bridge_events::SUBMIT_SMS_CODE,
+            Self::RequestSms => bridge_events::REQUEST_SMS_CODE,
+            Self::QrcodeScanned => bridge_events::QRCODE_LOGIN,
+            Self::Verified => bridge_events::RE_LOGIN,
+        }
+    }
+
+    /// 事件附带的数据
+    pub fn data(&self) -> JsonValue {
+        match self {
+            Self::SliderTicket(value) | Self::SmsCode(value) => json!(value),
+            _ => json!([]),
+        }
+    }
+}
+
+/// 实例 id -> 正在等待处理的登录验证和推送它的 bridge 连接
+static PENDING: LazyLock<Mutex<HashMap<String, (LoginChallenge, Client)>>> =
+    LazyLock::new(Default::default);
+/// 控制台读取线程只启动一次
+static CONSOLE: OnceLock<()> = OnceLock::new();
+
+/// 返回当前实例正在等待处理的登录验证。
+pub fn pending() -> Option<LoginChallenge> {
+    let id = MainStatus::global_config().ica_id();
+    PENDING.lock().unwrap().get(&id).map(|(challenge, _)| challenge.clone())
+}
+
+/// QQ 登录成功后清掉当前实例等待中的验证。
+pub fn clear() {
+    let id = MainStatus::global_config().ica_id();
+    if PENDING.lock().unwrap().remove(&id).is_some() {
+        event!(Level::INFO, "{}", format!("[{id}] QQ 登录验证已完成").green());
+    }
+}
+
+/// 拆出回复开头的 `@实例`, 没有指定时返回 `None`。
+fn split_target(args: &str) -> (Option<&str>, &str) {
+    let args = args.trim();
+    match args.strip_prefix('@') {
+        Some(rest) => {
+            let (target, rest) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            (Some(target), rest.trim_start())
+        }
+        None => (None, args),
+    }
+}
+
+/// 选出回复要发给的实例: 指定了就用指定的, 否则用 id 最小的有验证在等待的实例, 都没有时用当前实例。
+fn resolve_target(target: Option<&str>) -> String {
+    if let Some(target) = target {
+        return target.to_string();
+    }
+    PENDING
+        .lock()
+        .unwrap()
+        .keys()
+        .min()
+        .cloned()
+        .unwrap_or_else(|| MainStatus::global_config().ica_id())
+}
+
+/// 返回登录验证的配置。
+fn login_config() -> LoginConfig { MainStatus::global_config().ica().login }
+
+/// 保存并展示二维码。
+fn show_qrcode(url: Option<&str>, image: Option<&[u8]>, config: &LoginConfig) {
+    let rendered = url.and_then(qrcode::encode);
+    if config.qrcode_terminal
+        && let Some(qr) = &rendered
+    {
+        println!("{}", qrcode::render_terminal(qr));
+    }
+    // bridge 给了图片就用原图, 否则用链接生成一张
+    let png = match (image, &rendered) {
+        (Some(image), _) => image.to_vec(),
+        (None, Some(qr)) => qrcode::render_png(qr),
+        (None, None) => {
+            event!(Level::WARN, "bridge 没有给出可用的二维码");
+            return;
+        }
+    };
+    match std::fs::write(&config.qrcode_path, png) {
+        Ok(_) => event!(Level::INFO, "登录二维码已保存到 {}", config.qrcode_path),
+        Err(e) => event!(Level::ERROR, "保存登录二维码到 {} 失败: {}", config.qrcode_path, e),
+    }
+}
+
+/// 处理 bridge 推送的登录验证
+///
+/// 记录下来等待回复, 并按配置展示/转发
+pub async fn on_challenge(challenge: LoginChallenge, client: Client) {
+    let config = login_config();
+    let id = MainStatus::global_config().ica_id();
+    let text = format!("[{id}] {}", challenge.describe());
+    event!(Level::WARN, "{}", text.yellow());
+
+    if let LoginChallenge::Qrcode { url, image } = &challenge {
+        show_qrcode(url.as_deref(), image.as_deref(), &config);
+    }
+    let needs_reply = challenge != LoginChallenge::Setup;
+    PENDING.lock().unwrap().insert(id, (challenge, client));
+
+    if needs_reply && config.console {
+        start_console();
+    }
+    if config.forward_to_tailchat {
+        forward(&text).await;
+    }
+}
+
+/// 把登录验证转发到 Tailchat 的提醒房间。
+#[cfg(feature = "tailchat")]
+async fn forward(text: &str) {
+    let text = format!("{text}\n(在这里回复时使用 `/bot-ica-login [@实例] <回复>`)");
+    if !crate::tailchat::client::send_notice(&text, None).await {
+        event!(Level::WARN, "登录验证没能转发到 Tailchat, 请检查 tailchat 是否已连接");
+    }
+}
+
+/// 没有启用 tailchat 时无处可转发。
+#[cfg(not(feature = "tailchat"))]
+async fn forward(_text: &str) {
+    event!(Level::WARN, "启用了 forward_to_tailchat, 但编译时没有启用 tailchat");
+}
+
+/// 把登录验证的结果发回 `target` 实例的 bridge
+pub async fn respond(target: &str, response: LoginResponse) -> ClientResult<(), IcaError> {
+    let Some((_, client)) = PENDING.lock().unwrap().get(target).cloned() else {
+        return Err(IcaError::NoLoginChallenge);
+    };
+    client.emit(response.event_name(), response.data()).await?;
+    event!(Level::INFO, "已向 {} 的 bridge 发送 {}", target, response.event_name());
+    Ok(())
+}
+
+/// 处理管理员的 `/bot-ica-login` 命令, 返回回复内容。
+pub async fn handle_command(args: &str) -> String {
+    let (target, args) = split_target(args);
+    let target = resolve_target(target);
+    if args.is_empty() {
+        return match PENDING.lock().unwrap().get(&target) {
+            Some((challenge, _)) => format!("[{target}] {}", challenge.describe()),
+            None => format!("{target} 当前没有等待处理的 QQ 登录验证"),
+        };
+    }
+    let Some(response) = LoginResponse::parse(args) else {
+        return "用法: /bot-ica-login [@实例] [slider <ticket>|sms [验证码]|qrcode|verified]"
+            .to_string();
+    };
+    match respond(&target, response).await {
+        Ok(_) => format!("已提交给 {target} 的 bridge"),
+        Err(e) => format!("提交失败: {e}"),
+    }
+}
+
+/// 启动读取控制台输入的线程
+///
+/// 只会启动一次, 读到的每一行都按 [`LoginResponse::parse`] 解析, 可以用 `@实例` 开头指定实例
+fn start_console() {
+    let Ok(handle) = Handle::try_current() else {
+        return;
+    };
+    CONSOLE.get_or_init(|| {
+        event!(Level::INFO, "可以直接在控制台输入登录验证的回复");
+        std::thread::spawn(move || {
+            for line in std::io::stdin().lock().lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                let (target, args) = split_target(&line);
+                if args.is_empty() {
+                    continue;
+                }
+                let Some(response) = LoginResponse::parse(args) else {
+                    event!(Level::WARN, "无法识别的输入: {}", line);
+                    continue;
+                };
+                let target = resolve_target(target);
+                handle.spawn(async move {
+                    if let Err(e) = respond(&target, response).await {
+                        event!(Level::WARN, "{}", e);
+                    }
+                });
+            }
+        });
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_responses() {
+        assert_eq!(
+            LoginResponse::parse("slider  t-123 "),
+            Some(LoginResponse::SliderTicket("t-123".to_string()))
+        );
+        assert_eq!(LoginResponse::parse("sms"), Some(LoginResponse::RequestSms));
+        assert_eq!(
+            LoginResponse::parse("sms 114514"),
+            Some(LoginResponse::SmsCode("114514".to_string()))
+        );
+        assert_eq!(LoginResponse::parse("qrcode"), Some(LoginResponse::QrcodeScanned));
+        assert_eq!(LoginResponse::parse("verified"), Some(LoginResponse::Verified));
+        assert_eq!(LoginResponse::parse("slider"), None);
+        assert_eq!(LoginResponse::parse("hello"), None);
+        assert_eq!(
+            LoginResponse::SmsCode("1".to_string()).event_name(),
+            bridge_events::SUBMIT_SMS_CODE
+        );
+    }
+
+    #[test]
+    fn split_instance() {
+        assert_eq!(split_target(" @alt  sms 1234"), (Some("alt"), "sms 1234"));
+        assert_eq!(split_target("@alt"), (Some("alt"), ""));
+        assert_eq!(split_target("qrcode"), (None, "qrcode"));
+    }
+
+    #[test]
+    fn parse_challenges() {
+        let payload = Payload::Text(vec![json!("https://ti.qq.com/qrcode")]);
+        assert_eq!(
+            LoginChallenge::from_qrcode(&payload),
+            LoginChallenge::Qrcode {
+                url: Some("https://ti.qq.com/qrcode".to_string()),
+                image: None
+            }
+        );
+
+        let payload =
+            Payload::Text(vec![json!({"image": "data:image/png;base64,iVBORw0=", "url": "u"})]);
+        assert_eq!(
+            LoginChallenge::from_qrcode(&payload),
+            LoginChallenge::Qrcode {
+                url: Some("u".to_string()),
+                image: Some(vec![0x89, b'P', b'N', b'G', 0x0d])
+            }
+        );
+
+        let payload = Payload::Text(vec![json!({"type": "Buffer", "data": [1, 2, 3]})]);
+        assert_eq!(
+            LoginChallenge::from_qrcode(&payload),
+            LoginChallenge::Qrcode {
+                url: None,
+                image: Some(vec![1, 2, 3])
+            }
+        );
+
+        let payload = Payload::Text(vec![json!({"url": "https://slider"})]);
+        assert_eq!(
+            LoginChallenge::from_slider(&payload),
+            LoginChallenge::Slider {
+                url: Some("https://slider".to_string())
+            }
+        );
+        let payload = Payload::Text(vec![json!({"phone": "138****0000"})]);
+        assert_eq!(
+            LoginChallenge::from_sms(&payload),
+            LoginChallenge::Sms {
+                phone: Some("138****0000".to_string())
+            }
+        );
+    }
+}
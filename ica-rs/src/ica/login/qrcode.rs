@@ -0,0 +1,158 @@
+//! 登录二维码的终端渲染和 PNG 编码。
+
+use qrcodegen::{QrCode, QrCodeEcc};
+
+/// 二维码四周留白的模块数
+const QUIET_ZONE: i32 = 2;
+/// PNG 里每个模块的像素数
+const PNG_SCALE: usize = 8;
+
+/// 把文本编码成二维码, 文本过长时返回 `None`。
+pub fn encode(text: &str) -> Option<QrCode> { QrCode::encode_text(text, QrCodeEcc::Medium).ok() }
+
+/// 判断某个位置是否为深色模块, 留白区域为浅色。
+fn is_dark(qr: &QrCode, x: i32, y: i32) -> bool { qr.get_module(x, y) }
+
+/// 用半角方块把二维码渲染成终端文本
+///
+/// 一个字符对应上下两个模块, 浅色模块画成方块, 适合深色背景的终端
+pub fn render_terminal(qr: &QrCode) -> String {
+    let size = qr.size();
+    let mut out = String::new();
+    let mut y = -QUIET_ZONE;
+    while y < size + QUIET_ZONE {
+        for x in -QUIET_ZONE..size + QUIET_ZONE {
+            let top = !is_dark(qr, x, y);
+            let bottom = y + 1 < size + QUIET_ZONE && !is_dark(qr, x, y + 1);
+            out.push(match (top, bottom) {
+                (true, true) => '█',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (false, false) => ' ',
+            });
+        }
+        out.push('\n');
+        y += 2;
+    }
+    out
+}
+
+/// 把二维码编码成灰度 PNG。
+pub fn render_png(qr: &QrCode) -> Vec<u8> {
+    let modules = (qr.size() + QUIET_ZONE * 2) as usize;
+    let width = modules * PNG_SCALE;
+    let mut raw = Vec::with_capacity((width + 1) * width);
+    for row in 0..width {
+        // 每行开头是过滤类型, 0 表示不过滤
+        raw.push(0);
+        let y = (row / PNG_SCALE) as i32 - QUIET_ZONE;
+        for col in 0..width {
+            let x = (col / PNG_SCALE) as i32 - QUIET_ZONE;
+            raw.push(if is_dark(qr, x, y) { 0x00 } else { 0xff });
+        }
+    }
+    encode_png_gray(width as u32, width as u32, &raw)
+}
+
+/// 计算 PNG 块使用的 CRC32。
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffff_u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// 计算 zlib 使用的 Adler-32。
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1_u32, 0_u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+/// 用不压缩的 deflate 块包装成 zlib 数据流。
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut chunks = data.chunks(u16::MAX as usize).peekable();
+    if chunks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(chunk) = chunks.next() {
+        out.push(if chunks.peek().is_none() { 1 } else { 0 });
+        let len = chunk.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+/// 写入一个 PNG 块。
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// 把已经带好过滤字节的 8 位灰度扫描线编码成 PNG。
+fn encode_png_gray(width: u32, height: u32, raw: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // 位深 8, 灰度, 默认压缩/过滤, 不隔行
+    header.extend_from_slice(&[8, 0, 0, 0, 0]);
+    write_chunk(&mut out, b"IHDR", &header);
+    write_chunk(&mut out, b"IDAT", &zlib_stored(raw));
+    write_chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+
+    #[test]
+    fn png_layout() {
+        let qr = encode("https://example.com/login").unwrap();
+        let png = render_png(&qr);
+        let side = ((qr.size() + QUIET_ZONE * 2) as usize * PNG_SCALE) as u32;
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(u32::from_be_bytes(png[16..20].try_into().unwrap()), side);
+        assert!(png.ends_with(&[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]));
+
+        // 大于一个 deflate 块的数据也要能正确切分
+        let stored = zlib_stored(&vec![7; 70_000]);
+        assert_eq!(stored.len(), 2 + (5 + 65535) + (5 + 70_000 - 65535) + 4);
+    }
+
+    #[test]
+    fn terminal_size() {
+        let qr = encode("hello").unwrap();
+        let text = render_terminal(&qr);
+        let side = (qr.size() + QUIET_ZONE * 2) as usize;
+        assert_eq!(text.lines().count(), side.div_ceil(2));
+        assert!(text.lines().all(|line| line.chars().count() == side));
+    }
+}
@@ -0,0 +1,97 @@
+//! 同一平台下多个账号 (后端实例) 的区分。
+//!
+//! 每个实例用配置里的 `id` 区分。socket 事件回调、实例自己的后台任务和插件通过客户端发起的调用
+//! 都运行在对应实例的作用域里, 状态、配置、发送队列等按当前实例查找;
+//! 不在任何实例作用域里时使用该平台配置的第一个实例。
+
+#[cfg(any(feature = "ica", feature = "tailchat"))]
+use futures_util::{FutureExt, future::BoxFuture};
+#[cfg(any(feature = "ica", feature = "tailchat"))]
+use rust_socketio::{Event, Payload, asynchronous::Client};
+
+/// 不填 `id` 时使用的实例 id
+pub const DEFAULT_ID: &str = "default";
+
+tokio::task_local! {
+    /// 当前实例的 id
+    static CURRENT: String;
+}
+
+/// 返回当前实例的 id, 不在实例作用域里时返回 `None`。
+pub fn current() -> Option<String> { CURRENT.try_with(Clone::clone).ok() }
+
+/// 在指定实例的作用域里运行异步任务。
+pub async fn scope<F: Future>(id: String, future: F) -> F::Output {
+    CURRENT.scope(id, future).await
+}
+
+/// 在指定实例的作用域里运行同步代码。
+pub fn sync_scope<R>(id: String, f: impl FnOnce() -> R) -> R { CURRENT.sync_scope(id, f) }
+
+/// 让 socket 事件回调运行在指定实例的作用域里。
+#[cfg(any(feature = "ica", feature = "tailchat"))]
+pub fn callback<F>(
+    id: &str,
+    mut f: F,
+) -> impl FnMut(Payload, Client) -> BoxFuture<'static, ()> + Send + Sync + 'static
+where
+    F: FnMut(Payload, Client) -> BoxFuture<'static, ()> + Send + Sync + 'static,
+{
+    let id = id.to_string();
+    move |payload, client| scope(id.clone(), f(payload, client)).boxed()
+}
+
+/// 让 `on_any` 回调运行在指定实例的作用域里。
+#[cfg(any(feature = "ica", feature = "tailchat"))]
+pub fn any_callback<F>(
+    id: &str,
+    mut f: F,
+) -> impl FnMut(Event, Payload, Client) -> BoxFuture<'static, ()> + Send + Sync + 'static
+where
+    F: FnMut(Event, Payload, Client) -> BoxFuture<'static, ()> + Send + Sync + 'static,
+{
+    let id = id.to_string();
+    move |event, payload, client| scope(id.clone(), f(event, payload, client)).boxed()
+}
+
+/// 从一组实例 id 里选出当前实例, 当前实例不在其中时选第一个。
+pub fn pick<T>(items: &[T], id_of: impl Fn(&T) -> &str) -> Option<&T> {
+    let current = current();
+    current
+        .and_then(|current| items.iter().find(|item| id_of(item) == current))
+        .or_else(|| items.first())
+}
+
+/// 检查 id 是否有重复, 返回第一个重复的 id。
+pub fn find_duplicate<'a>(ids: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let mut seen = std::collections::HashSet::new();
+    ids.into_iter().find(|id| !seen.insert(*id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pick_current() {
+        let ids = ["main", "alt"];
+        assert_eq!(pick(&ids, |id| id), Some(&"main"));
+        assert_eq!(sync_scope("alt".to_string(), || pick(&ids, |id| id)), Some(&"alt"));
+        // 不认识的实例回落到第一个
+        assert_eq!(sync_scope("other".to_string(), || pick(&ids, |id| id)), Some(&"main"));
+        assert_eq!(pick::<&str>(&[], |id| id), None);
+    }
+
+    #[test]
+    fn duplicate_ids() {
+        assert_eq!(find_duplicate(["a", "b", "a"]), Some("a"));
+        assert_eq!(find_duplicate(["a", "b"]), None);
+    }
+
+    #[tokio::test]
+    async fn async_scope() {
+        assert_eq!(current(), None);
+        let inner = scope("alt".to_string(), async { current() }).await;
+        assert_eq!(inner.as_deref(), Some("alt"));
+    }
+}
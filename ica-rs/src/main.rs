@@ -6,14 +6,28 @@ use std::{
     time::{Duration, SystemTime},
 };
 
+/// 加载 `alert` 子模块。
+mod alert;
+/// 加载 `backend` 子模块。
+mod backend;
+/// 加载 `cli` 子模块。
+mod cli;
 /// 加载 `config` 子模块。
 mod config;
 /// 加载 `data_struct` 子模块。
 mod data_struct;
 /// 加载 `error` 子模块。
 mod error;
+/// 加载 `instance` 子模块。
+mod instance;
+/// 加载 `moderation` 子模块。
+mod moderation;
+/// 加载 `permission` 子模块。
+mod permission;
 /// 加载 `py` 子模块。
 mod py;
+/// 加载 `reload` 子模块。
+mod reload;
 /// 加载 `status` 子模块。
 mod status;
 /// 加载 `wasms` 子模块。
@@ -26,24 +40,19 @@ mod ica;
 /// 加载 `tailchat` 子模块。
 mod tailchat;
 
+use cli::Command;
 use colored::Colorize;
 use config::BotConfig;
 use error::PyPluginError;
 use tracing::{Level, event, span};
 
-pub static mut MAIN_STATUS: status::BotStatus = status::BotStatus {
-    config: None,
-    ica_status: None,
-    tailchat_status: None,
-};
-
 pub type MainStatus = status::BotStatus;
 
 pub type StopGetter = tokio::sync::oneshot::Receiver<()>;
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
-pub const ICA_VERSION: &str = "2.0.3";
-pub const TAILCHAT_VERSION: &str = "2.0.0";
+pub const ICA_VERSION: &str = "2.0.4";
+pub const TAILCHAT_VERSION: &str = "2.0.1";
 
 const HELP_MSG: &str = r#"/bot-rs
     展示 rust 侧信息
@@ -53,12 +62,32 @@ const HELP_MSG: &str = r#"/bot-rs
     显示所有插件信息
 /bot-permission
     显示自己的权限组
-/bot-enable-<client-id> <plugin>
-    启用某个插件(具体到客户端)
-/bot-disable-<client-id> <plugin>
-    禁用某个插件(具体到客户端)
+/bot-grant-<client-id> <user-id> <role> [room [room-id]]
+    授予某人角色(owner/admin/moderator/user/banned), 可以限定在某个房间
+/bot-revoke-<client-id> <user-id> [room [room-id]]
+    撤销某人的角色
+/bot-mute-<client-id> <user-id> [时长]
+    让 bot 忽略某人的消息, 时长如 30m/2h/1d, 不写为永久
+/bot-unmute-<client-id> <user-id>
+    解除忽略
+/bot-filter-<client-id> [list|rule add <regex>|rule del <序号>|allow|deny|clear <user-id> [room-id]]
+    查看/修改内容规则和房间的允许/拒绝名单
+/bot-queue-<client-id>
+    显示 ica 发送队列状态
+/bot-jobs-<client-id>
+    显示所有插件的定时任务
+/bot-ica-login [@实例] [slider <ticket>|sms [验证码]|qrcode|verified]
+    查看/回复 QQ 登录验证(仅 tailchat)
+/bot-enable-<client-id> <plugin> [global|platform|room [room-id]]
+    启用某个插件(具体到客户端), 默认为全局
+/bot-disable-<client-id> <plugin> [global|platform|room [room-id]]
+    禁用某个插件(具体到客户端), 默认为全局
+/bot-inherit-<client-id> <plugin> <platform|room [room-id]>
+    移除某个范围内的插件设置, 恢复继承
 /bot-reload-<client-id> <plugin>
     重载某个插件(具体到客户端)
+/bot-config-reload-<client-id>
+    重新读取主配置文件(具体到客户端)
 
 by shenjackyuanjie"#;
 
@@ -119,43 +148,41 @@ macro_rules! async_any_callback_with_state {
     }};
 }
 
-const CLI_HELP_MSG: &str = r#"{VERSION}
-    -d
-        debug 模式
-    -t
-        trace 模式
-    -h
-        显示帮助信息
-    -env <env>
-        指定虚拟环境路径
-    -c <config_file_path>
-        指定配置文件路径
-"#;
-
 /// 解析命令行和配置、初始化日志，并启动异步运行时。
 fn main() -> anyhow::Result<()> {
     let start_up_time = SystemTime::now();
     STARTUP_TIME.set(start_up_time).expect("WTF, why did you panic?");
 
-    // -d -> debug
-    // none -> info
-    let args = std::env::args();
-    let args = args.collect::<Vec<String>>();
-    if args.contains(&"-h".to_string()) {
-        println!("{}", CLI_HELP_MSG.replace("{VERSION}", version_str().as_str()));
-        return Ok(());
-    }
-    let level = {
-        if args.contains(&"-d".to_string()) {
-            Level::DEBUG
-        } else if args.contains(&"-t".to_string()) {
-            Level::TRACE
-        } else {
-            Level::INFO
+    let args = match cli::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n使用 -h 查看帮助", e.to_string().red());
+            std::process::exit(2);
         }
     };
+    cli::init(args);
+    let args = cli::args();
+
+    tracing_subscriber::fmt().with_max_level(args.log_level).init();
 
-    tracing_subscriber::fmt().with_max_level(level).init();
+    // 不需要启动 bot 的子命令
+    let ok = match &args.command {
+        Command::Run => None,
+        Command::Help => {
+            println!("{}", cli::help_msg());
+            Some(true)
+        }
+        Command::CheckConfig => Some(config::check::print_report(&args.config)),
+        Command::GenKey => Some(cli::gen_key()),
+        Command::PluginList => Some(cli::list_plugins(&args.config)),
+        Command::PluginSet(plugin_id, enable) => {
+            Some(cli::set_plugin(&args.config, plugin_id, *enable))
+        }
+        Command::NewPlugin(plugin_id) => Some(cli::new_plugin(&args.config, plugin_id)),
+    };
+    if let Some(ok) = ok {
+        std::process::exit(if ok { 0 } else { 1 });
+    }
 
     let rt = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
@@ -193,46 +220,44 @@ async fn inner_main() -> anyhow::Result<()> {
         event!(Level::WARN, "这是一个开发版本, 有问题记得找 shenjack");
     }
 
-    let bot_config = BotConfig::new_from_cli();
+    let config_path = cli::args().config.clone();
+    let bot_config = BotConfig::new_from_path(config_path.clone());
     MainStatus::static_init(bot_config);
+    reload::set_config_path(config_path);
     let bot_config = MainStatus::global_config();
 
+    permission::init_permission();
+    moderation::init_moderation();
+
     if bot_config.check_py() {
         py::init_py().await;
     }
 
-    // 准备一个用于停止 socket 的变量
-    let (ica_send, ica_recv) = tokio::sync::oneshot::channel::<()>();
-
     if bot_config.check_ica() {
-        event!(Level::INFO, "{}", "开始启动 ICA".green());
-        let config = bot_config.ica();
-        tokio::spawn(async move {
-            ica::start_ica(&config, ica_recv).await.unwrap();
-        });
+        for config in bot_config.ica_list() {
+            backend::start_ica(config.clone());
+        }
     } else {
         event!(Level::INFO, "{}", "ica 未启用, 不管他".cyan());
     }
 
-    let (tailchat_send, tailchat_recv) = tokio::sync::oneshot::channel::<()>();
-
     if bot_config.check_tailchat() {
-        event!(Level::INFO, "{}", "开始启动 tailchat".green());
-        let config = bot_config.tailchat();
-        tokio::spawn(async move {
-            tailchat::start_tailchat(config, tailchat_recv).await.unwrap();
-        });
+        for config in bot_config.tailchat_list() {
+            backend::start_tailchat(config.clone());
+        }
     } else {
         event!(Level::INFO, "{}", "tailchat 未启用, 不管他".bright_magenta());
     }
 
+    tokio::spawn(reload::listen_sighup());
+    tokio::spawn(reload::watch_config());
+
     tokio::time::sleep(Duration::from_secs(1)).await;
     // 等待一个输入
     event!(Level::INFO, "Press ctrl+c to exit, second ctrl+c to force exit");
     tokio::signal::ctrl_c().await.ok();
 
-    ica_send.send(()).ok();
-    tailchat_send.send(()).ok();
+    backend::stop_all();
 
     event!(Level::INFO, "Disconnected");
 
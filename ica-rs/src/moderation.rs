@@ -0,0 +1,535 @@
+//! 消息过滤层, 在内置命令和插件之前决定是否处理一条消息。
+//!
+//! 包括配置里的 `filter_list`、屏蔽/临时禁言的用户、房间内的允许/拒绝名单和内容正则规则,
+//! 每个后端收到消息后都先经过这里。除了 `filter_list` 以外都可以通过 `/bot-mute`、
+//! `/bot-unmute` 和 `/bot-filter` 修改, 并保存在 `moderation_path` 指向的文件里。
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::sync::{LazyLock, RwLock};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tracing::{Level, event};
+
+use crate::MainStatus;
+use crate::data_struct::Platform;
+use crate::permission::is_config_owner;
+
+pub const DEFAULT_MODERATION_FILE: &str = r#"# 这个文件是由 shenbot 自动生成的, 可以通过 /bot-mute /bot-unmute 和 /bot-filter 修改
+# 手动修改后需要用 /bot-config-reload 或 SIGHUP 重载配置才会生效"#;
+
+/// 过滤的结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// 正常处理
+    Pass,
+    /// 在配置文件的 `filter_list` 里
+    Filtered,
+    /// 被禁言, `until` 为 `None` 时为永久
+    Muted { until: Option<i64> },
+    /// 在房间的拒绝名单里
+    RoomDenied,
+    /// 房间设置了允许名单, 但不在其中
+    NotAllowed,
+    /// 命中内容规则
+    Content(String),
+}
+
+impl Verdict {
+    /// 是否放行。
+    pub fn is_pass(&self) -> bool { *self == Verdict::Pass }
+}
+
+impl Display for Verdict {
+    /// 将当前值写入格式化输出。
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Verdict::Pass => write!(f, "放行"),
+            Verdict::Filtered => write!(f, "在 filter_list 里"),
+            Verdict::Muted { until: None } => write!(f, "被永久禁言"),
+            Verdict::Muted { until: Some(until) } => write!(f, "被禁言到 {}", format_time(*until)),
+            Verdict::RoomDenied => write!(f, "在房间的拒绝名单里"),
+            Verdict::NotAllowed => write!(f, "不在房间的允许名单里"),
+            Verdict::Content(rule) => write!(f, "命中内容规则 {rule}"),
+        }
+    }
+}
+
+/// 单个房间的允许/拒绝名单
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RoomRule {
+    /// 不为空时只处理这些用户的消息
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub allow: BTreeSet<String>,
+    /// 不处理这些用户的消息
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub deny: BTreeSet<String>,
+}
+
+impl RoomRule {
+    /// 两个名单都为空。
+    fn is_empty(&self) -> bool { self.allow.is_empty() && self.deny.is_empty() }
+}
+
+/// 持久化在过滤文件里的数据
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModerationStore {
+    /// 平台 -> 用户 -> 禁言到期时间 (Unix 秒), 0 为永久
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub mutes: BTreeMap<String, BTreeMap<String, i64>>,
+    /// 平台 -> 房间 -> 名单
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub rooms: BTreeMap<String, BTreeMap<String, RoomRule>>,
+    /// 内容正则规则, 对所有平台生效
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<String>,
+    /// 编译好的内容规则, 无效的正则会被跳过
+    #[serde(skip)]
+    compiled: Vec<(String, Regex)>,
+}
+
+impl ModerationStore {
+    /// 重新编译内容规则。
+    pub fn compile(&mut self) {
+        self.compiled = self
+            .rules
+            .iter()
+            .filter_map(|rule| match Regex::new(rule) {
+                Ok(regex) => Some((rule.clone(), regex)),
+                Err(e) => {
+                    event!(Level::WARN, "内容规则 {} 无效, 已跳过: {}", rule, e);
+                    None
+                }
+            })
+            .collect();
+    }
+
+    /// 检查一条消息
+    ///
+    /// 优先级: 禁言 > 房间名单 > 内容规则, 不包括 `filter_list`
+    pub fn check(
+        &self,
+        platform: Platform,
+        user: &str,
+        room: &str,
+        content: &str,
+        now: i64,
+    ) -> Verdict {
+        if let Some(&until) = self.mutes.get(platform.as_str()).and_then(|users| users.get(user)) {
+            if until == 0 {
+                return Verdict::Muted { until: None };
+            }
+            if until > now {
+                return Verdict::Muted { until: Some(until) };
+            }
+        }
+        if let Some(rule) = self.rooms.get(platform.as_str()).and_then(|rooms| rooms.get(room)) {
+            if rule.deny.contains(user) {
+                return Verdict::RoomDenied;
+            }
+            if !rule.allow.is_empty() && !rule.allow.contains(user) {
+                return Verdict::NotAllowed;
+            }
+        }
+        match self.compiled.iter().find(|(_, regex)| regex.is_match(content)) {
+            Some((rule, _)) => Verdict::Content(rule.clone()),
+            None => Verdict::Pass,
+        }
+    }
+
+    /// 禁言某个用户, `until` 为 `None` 时为永久, 返回之前的到期时间。
+    pub fn mute(&mut self, platform: Platform, user: &str, until: Option<i64>) -> Option<i64> {
+        self.mutes
+            .entry(platform.as_str().to_string())
+            .or_default()
+            .insert(user.to_string(), until.unwrap_or(0))
+    }
+
+    /// 解除禁言, 返回之前是否被禁言。
+    pub fn unmute(&mut self, platform: Platform, user: &str) -> bool {
+        let Some(users) = self.mutes.get_mut(platform.as_str()) else {
+            return false;
+        };
+        let removed = users.remove(user).is_some();
+        if users.is_empty() {
+            self.mutes.remove(platform.as_str());
+        }
+        removed
+    }
+
+    /// 移除已经过期的临时禁言, 返回移除的数量。
+    pub fn prune(&mut self, now: i64) -> usize {
+        let mut removed = 0;
+        self.mutes.retain(|_, users| {
+            let before = users.len();
+            users.retain(|_, until| *until == 0 || *until > now);
+            removed += before - users.len();
+            !users.is_empty()
+        });
+        removed
+    }
+
+    /// 修改房间名单, `list` 为 `None` 时从两个名单里都移除。
+    pub fn set_room(
+        &mut self,
+        platform: Platform,
+        room: &str,
+        user: &str,
+        list: Option<RoomList>,
+    ) -> bool {
+        let platform = platform.as_str().to_string();
+        let rule = self
+            .rooms
+            .entry(platform.clone())
+            .or_default()
+            .entry(room.to_string())
+            .or_default();
+        let changed = match list {
+            Some(RoomList::Allow) => {
+                rule.deny.remove(user);
+                rule.allow.insert(user.to_string())
+            }
+            Some(RoomList::Deny) => {
+                rule.allow.remove(user);
+                rule.deny.insert(user.to_string())
+            }
+            None => rule.allow.remove(user) | rule.deny.remove(user),
+        };
+        if rule.is_empty() {
+            let rooms = self.rooms.get_mut(&platform).unwrap();
+            rooms.remove(room);
+            if rooms.is_empty() {
+                self.rooms.remove(&platform);
+            }
+        }
+        changed
+    }
+
+    /// 展示当前所有的过滤设置。
+    pub fn display(&self) -> String {
+        let mut lines = Vec::new();
+        for (platform, users) in &self.mutes {
+            for (user, until) in users {
+                let until = if *until == 0 {
+                    "永久".to_string()
+                } else {
+                    format!("到 {}", format_time(*until))
+                };
+                lines.push(format!("禁言 {platform}:{user} {until}"));
+            }
+        }
+        for (platform, rooms) in &self.rooms {
+            for (room, rule) in rooms {
+                if !rule.allow.is_empty() {
+                    let users = rule.allow.iter().cloned().collect::<Vec<_>>().join(", ");
+                    lines.push(format!("房间 {platform}:{room} 只允许 {users}"));
+                }
+                if !rule.deny.is_empty() {
+                    let users = rule.deny.iter().cloned().collect::<Vec<_>>().join(", ");
+                    lines.push(format!("房间 {platform}:{room} 拒绝 {users}"));
+                }
+            }
+        }
+        for (index, rule) in self.rules.iter().enumerate() {
+            lines.push(format!("规则 {index}: {rule}"));
+        }
+        if lines.is_empty() {
+            "没有过滤设置".to_string()
+        } else {
+            lines.join("\n")
+        }
+    }
+
+    /// 从过滤文件读取
+    pub fn load_from_file() -> Self {
+        let path = moderation_file_path();
+        if !path.exists() {
+            return Self::default();
+        }
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) => {
+                event!(Level::WARN, "读取过滤文件 {:?} 失败: {}", path, e);
+                return Self::default();
+            }
+        };
+        match toml::from_str::<Self>(&content) {
+            Ok(mut store) => {
+                store.compile();
+                store
+            }
+            Err(e) => {
+                event!(Level::WARN, "解析过滤文件 {:?} 失败, 将使用空的过滤数据: {}", path, e);
+                Self::default()
+            }
+        }
+    }
+
+    /// 保存到过滤文件
+    pub fn save_to_file(&self) {
+        let path = moderation_file_path();
+        let data = match toml::to_string_pretty(self) {
+            Ok(data) => data,
+            Err(e) => {
+                event!(Level::WARN, "序列化过滤数据失败: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = std::fs::write(&path, format!("{DEFAULT_MODERATION_FILE}\n{data}")) {
+            event!(Level::WARN, "写入过滤文件 {:?} 失败: {}", path, e);
+        }
+    }
+}
+
+/// 房间名单的种类
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoomList {
+    /// 允许名单
+    Allow,
+    /// 拒绝名单
+    Deny,
+}
+
+/// 全局过滤数据
+pub static MODERATION: LazyLock<RwLock<ModerationStore>> =
+    LazyLock::new(|| RwLock::new(ModerationStore::default()));
+
+/// 返回过滤文件路径。
+fn moderation_file_path() -> PathBuf { PathBuf::from(&MainStatus::global_config().moderation_path) }
+
+/// 从过滤文件加载全局过滤数据
+pub fn init_moderation() {
+    let store = ModerationStore::load_from_file();
+    event!(
+        Level::INFO,
+        "已加载过滤数据: {} 个禁言, {} 个房间名单, {} 条内容规则",
+        store.mutes.values().map(|users| users.len()).sum::<usize>(),
+        store.rooms.values().map(|rooms| rooms.len()).sum::<usize>(),
+        store.rules.len()
+    );
+    *MODERATION.write().unwrap() = store;
+}
+
+/// 返回当前的 Unix 时间戳 (秒)。
+fn now() -> i64 { chrono::Utc::now().timestamp() }
+
+/// 把 Unix 时间戳格式化为本地时间。
+fn format_time(timestamp: i64) -> String {
+    match chrono::DateTime::from_timestamp(timestamp, 0) {
+        Some(time) => time.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S").to_string(),
+        None => timestamp.to_string(),
+    }
+}
+
+/// 检查用户是否在当前实例配置的 `filter_list` 里。
+fn in_filter_list(platform: Platform, user: &str) -> bool {
+    let config = MainStatus::global_config();
+    match platform {
+        Platform::Ica => config.ica().filter_list.iter().any(|id| id.to_string() == user),
+        Platform::Tailchat => config.tailchat().filter_list.iter().any(|id| id == user),
+    }
+}
+
+/// 检查一条消息是否应该被处理, 被拦下时记录日志
+///
+/// `filter_list` 最优先; 配置里的所有者只受 `filter_list` 影响
+pub fn check_message(platform: Platform, user: &str, room: &str, content: &str) -> Verdict {
+    let verdict = if in_filter_list(platform, user) {
+        Verdict::Filtered
+    } else if is_config_owner(platform, user) {
+        Verdict::Pass
+    } else {
+        MODERATION.read().unwrap().check(platform, user, room, content, now())
+    };
+    if !verdict.is_pass() {
+        event!(Level::DEBUG, "已过滤 {}:{} 在 {} 的消息: {}", platform, user, room, verdict);
+    }
+    verdict
+}
+
+/// 解析 `30s` / `10m` / `2h` / `7d` 这样的时长, 返回秒数, 不带单位时按分钟算。
+pub fn parse_duration(text: &str) -> Option<i64> {
+    let text = text.trim();
+    let (number, unit) = match text.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => text.split_at(index),
+        None => (text, "m"),
+    };
+    let number: i64 = number.parse().ok()?;
+    let unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+    number.checked_mul(unit).filter(|seconds| *seconds > 0)
+}
+
+/// 处理 `/bot-mute` 和 `/bot-unmute` 命令
+///
+/// `args` 为 `<user-id> [时长]`, 不写时长时为永久禁言
+pub fn handle_mute_command(args: &str, mute: bool, platform: Platform) -> String {
+    let args: Vec<&str> = args.split_whitespace().collect();
+    let (user, duration) = match args.as_slice() {
+        [user] => (*user, None),
+        [user, duration] if mute => match parse_duration(duration) {
+            Some(seconds) => (*user, Some(seconds)),
+            None => return format!("无法解析时长: {duration}"),
+        },
+        [] => return "请指定用户".to_string(),
+        _ => return format!("无法解析参数: {}", args.join(" ")),
+    };
+    if is_config_owner(platform, user) {
+        return "无法禁言配置文件里的所有者".to_string();
+    }
+
+    let mut store = MODERATION.write().unwrap();
+    let now = now();
+    store.prune(now);
+    let reply = if mute {
+        let until = duration.map(|seconds| now + seconds);
+        store.mute(platform, user, until);
+        match until {
+            Some(until) => format!("已禁言 {user} 到 {}", format_time(until)),
+            None => format!("已永久禁言 {user}"),
+        }
+    } else if store.unmute(platform, user) {
+        format!("已解除 {user} 的禁言")
+    } else {
+        format!("{user} 没有被禁言, 无变化")
+    };
+    // 写文件时不要占着锁, 否则会卡住所有消息的检查
+    let snapshot = store.clone();
+    drop(store);
+    snapshot.save_to_file();
+    reply
+}
+
+/// 处理 `/bot-filter` 命令
+///
+/// `args` 为 `list`、`rule add <regex>`、`rule del <序号>`
+/// 或 `allow|deny|clear <user-id> [room-id]`
+pub fn handle_filter_command(args: &str, platform: Platform, current_room: &str) -> String {
+    let args = args.trim();
+    let (action, rest) = args.split_once(' ').unwrap_or((args, ""));
+    let rest = rest.trim();
+    let mut store = MODERATION.write().unwrap();
+    store.prune(now());
+    let reply = match action {
+        "" | "list" => return store.display(),
+        "rule" => {
+            let (op, rule) = rest.split_once(' ').unwrap_or((rest, ""));
+            let rule = rule.trim();
+            match op {
+                "add" if !rule.is_empty() => {
+                    if let Err(e) = Regex::new(rule) {
+                        return format!("无效的正则: {e}");
+                    }
+                    store.rules.push(rule.to_string());
+                    format!("已添加内容规则 {}: {rule}", store.rules.len() - 1)
+                }
+                "del" => match rule.parse::<usize>() {
+                    Ok(index) if index < store.rules.len() => {
+                        let removed = store.rules.remove(index);
+                        format!("已删除内容规则 {index}: {removed}")
+                    }
+                    _ => return format!("没有序号为 {rule} 的内容规则"),
+                },
+                _ => return "用法: rule add <regex> | rule del <序号>".to_string(),
+            }
+        }
+        "allow" | "deny" | "clear" => {
+            let parts: Vec<&str> = rest.split_whitespace().collect();
+            let (user, room) = match parts.as_slice() {
+                [user] => (*user, current_room),
+                [user, room] => (*user, *room),
+                _ => return format!("用法: {action} <user-id> [room-id]"),
+            };
+            let list = match action {
+                "allow" => Some(RoomList::Allow),
+                "deny" => Some(RoomList::Deny),
+                _ => None,
+            };
+            let changed = store.set_room(platform, room, user, list);
+            match (list, changed) {
+                (_, false) => format!("房间 {room} 的名单没有变化"),
+                (Some(RoomList::Allow), true) => format!("已把 {user} 加入房间 {room} 的允许名单"),
+                (Some(RoomList::Deny), true) => format!("已把 {user} 加入房间 {room} 的拒绝名单"),
+                (None, true) => format!("已把 {user} 移出房间 {room} 的名单"),
+            }
+        }
+        _ => return format!("未知的操作: {action}"),
+    };
+    store.compile();
+    let snapshot = store.clone();
+    drop(store);
+    snapshot.save_to_file();
+    reply
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(store: &ModerationStore, user: &str, room: &str, content: &str, now: i64) -> Verdict {
+        store.check(Platform::Tailchat, user, room, content, now)
+    }
+
+    #[test]
+    fn duration() {
+        assert_eq!(parse_duration("30s"), Some(30));
+        assert_eq!(parse_duration("10"), Some(600));
+        assert_eq!(parse_duration("2h"), Some(7200));
+        assert_eq!(parse_duration("1d"), Some(86400));
+        assert_eq!(parse_duration("0m"), None);
+        assert_eq!(parse_duration("1w"), None);
+        assert_eq!(parse_duration("abc"), None);
+    }
+
+    #[test]
+    fn mute_expiry() {
+        let mut store = ModerationStore::default();
+        store.mute(Platform::Tailchat, "a", Some(100));
+        store.mute(Platform::Tailchat, "b", None);
+        assert_eq!(check(&store, "a", "r", "", 50), Verdict::Muted { until: Some(100) });
+        assert_eq!(check(&store, "a", "r", "", 100), Verdict::Pass);
+        assert_eq!(check(&store, "b", "r", "", 100), Verdict::Muted { until: None });
+        // 别的平台不受影响
+        assert!(store.check(Platform::Ica, "a", "r", "", 50).is_pass());
+
+        assert_eq!(store.prune(100), 1);
+        assert!(store.unmute(Platform::Tailchat, "b"));
+        assert!(store.mutes.is_empty());
+    }
+
+    #[test]
+    fn room_lists() {
+        let mut store = ModerationStore::default();
+        assert!(store.set_room(Platform::Tailchat, "r", "a", Some(RoomList::Deny)));
+        assert_eq!(check(&store, "a", "r", "", 0), Verdict::RoomDenied);
+        assert!(check(&store, "a", "other", "", 0).is_pass());
+
+        assert!(store.set_room(Platform::Tailchat, "r", "a", Some(RoomList::Allow)));
+        assert!(check(&store, "a", "r", "", 0).is_pass());
+        assert_eq!(check(&store, "b", "r", "", 0), Verdict::NotAllowed);
+
+        assert!(store.set_room(Platform::Tailchat, "r", "a", None));
+        assert!(!store.set_room(Platform::Tailchat, "r", "a", None));
+        assert!(store.rooms.is_empty());
+    }
+
+    #[test]
+    fn content_rules() {
+        let mut store = ModerationStore {
+            rules: vec!["广告".to_string(), "(".to_string()],
+            ..Default::default()
+        };
+        store.compile();
+        assert_eq!(check(&store, "a", "r", "出售广告位", 0), Verdict::Content("广告".to_string()));
+        assert!(check(&store, "a", "r", "你好", 0).is_pass());
+        // 无效的正则被跳过
+        assert_eq!(store.compiled.len(), 1);
+    }
+}
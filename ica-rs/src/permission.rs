@@ -0,0 +1,513 @@
+//! 基于角色的权限系统, 包括角色定义、授权持久化和权限节点检查。
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::sync::{LazyLock, RwLock};
+
+use serde::{Deserialize, Serialize};
+use tracing::{Level, event};
+
+use crate::data_struct::Platform;
+use crate::{MainStatus, instance};
+
+pub const DEFAULT_PERMISSION_FILE: &str = r#"# 这个文件是由 shenbot 自动生成的, 可以通过 /bot-grant 和 /bot-revoke 修改
+# 手动修改后需要用 /bot-config-reload 或 SIGHUP 重载配置才会生效"#;
+
+/// 角色, 按权限从低到高排序
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// 被封禁, 没有任何权限
+    Banned,
+    /// 普通用户
+    User,
+    /// 协管, QQ 群主/群管理默认为这个角色
+    Moderator,
+    /// 管理员
+    Admin,
+    /// 所有者, 即配置文件里 `admin_list` 中的人
+    Owner,
+}
+
+impl Role {
+    /// 返回配置文件和命令里使用的角色名称。
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Banned => "banned",
+            Role::User => "user",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+            Role::Owner => "owner",
+        }
+    }
+
+    /// 从角色名称解析角色，大小写不敏感。
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "banned" | "ban" => Some(Role::Banned),
+            "user" => Some(Role::User),
+            "moderator" | "mod" => Some(Role::Moderator),
+            "admin" => Some(Role::Admin),
+            "owner" => Some(Role::Owner),
+            _ => None,
+        }
+    }
+
+    /// 将 QQ 群内的身份 (`Message.role`) 映射为默认角色
+    pub fn from_group_role(role: &str) -> Option<Self> {
+        match role {
+            "owner" | "admin" => Some(Role::Moderator),
+            _ => None,
+        }
+    }
+
+    /// 返回展示给用户看的中文名称。
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            Role::Banned => "已封禁",
+            Role::User => "普通用户",
+            Role::Moderator => "协管",
+            Role::Admin => "管理员",
+            Role::Owner => "所有者",
+        }
+    }
+}
+
+impl Display for Role {
+    /// 将当前值写入格式化输出。
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}({})", self.display_name(), self.as_str())
+    }
+}
+
+/// 内置的权限节点
+pub mod nodes {
+    /// 启用/禁用插件
+    pub const PLUGIN_MANAGE: &str = "bot.plugin.manage";
+    /// 重载插件
+    pub const PLUGIN_RELOAD: &str = "bot.plugin.reload";
+    /// 拉取历史消息
+    pub const FETCH: &str = "bot.fetch";
+    /// 查看发送队列状态
+    pub const QUEUE: &str = "bot.queue";
+    /// 查看定时任务
+    pub const JOBS: &str = "bot.jobs";
+    /// 授予/撤销角色
+    pub const PERMISSION_GRANT: &str = "bot.permission.grant";
+    /// 处理 QQ 登录验证
+    pub const LOGIN: &str = "bot.login";
+    /// 禁言和过滤规则
+    pub const MODERATION: &str = "bot.moderation";
+    /// 重载主配置
+    pub const CONFIG_RELOAD: &str = "bot.config.reload";
+}
+
+/// 返回内置权限节点的默认最低角色。
+fn default_node_role(node: &str) -> Option<Role> {
+    match node {
+        nodes::PLUGIN_MANAGE
+        | nodes::PLUGIN_RELOAD
+        | nodes::PERMISSION_GRANT
+        | nodes::LOGIN
+        | nodes::CONFIG_RELOAD
+        // 禁言和过滤规则对整个平台生效, 不能交给群主/群管理
+        | nodes::MODERATION => Some(Role::Admin),
+        nodes::FETCH | nodes::QUEUE | nodes::JOBS => Some(Role::Moderator),
+        _ => None,
+    }
+}
+
+/// 持久化在权限文件里的数据
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PermissionStore {
+    /// 权限节点 -> 最低角色
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub nodes: HashMap<String, Role>,
+    /// 平台 -> 用户 -> 角色
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub grants: HashMap<String, HashMap<String, Role>>,
+    /// 平台 -> 房间 -> 用户 -> 角色
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub rooms: HashMap<String, HashMap<String, HashMap<String, Role>>>,
+    /// 最近一次看到的 QQ 群身份, 不会持久化
+    ///
+    /// 房间 -> 用户 -> 角色
+    #[serde(skip)]
+    pub group_roles: HashMap<String, HashMap<String, Role>>,
+}
+
+impl PermissionStore {
+    /// 返回某个用户的授权, `room` 为 `None` 时返回平台级授权。
+    pub fn get_grant(&self, platform: Platform, user: &str, room: Option<&str>) -> Option<Role> {
+        match room {
+            Some(room) => self
+                .rooms
+                .get(platform.as_str())
+                .and_then(|rooms| rooms.get(room))
+                .and_then(|users| users.get(user))
+                .copied(),
+            None => self.grants.get(platform.as_str()).and_then(|users| users.get(user)).copied(),
+        }
+    }
+
+    /// 设置某个用户的授权
+    ///
+    /// `role` 为 `None` 时撤销授权, 返回之前的授权
+    pub fn set_grant(
+        &mut self,
+        platform: Platform,
+        user: &str,
+        room: Option<&str>,
+        role: Option<Role>,
+    ) -> Option<Role> {
+        let platform = platform.as_str().to_string();
+        match (room, role) {
+            (Some(room), Some(role)) => self
+                .rooms
+                .entry(platform)
+                .or_default()
+                .entry(room.to_string())
+                .or_default()
+                .insert(user.to_string(), role),
+            (None, Some(role)) => {
+                self.grants.entry(platform).or_default().insert(user.to_string(), role)
+            }
+            (Some(room), None) => {
+                let rooms = self.rooms.get_mut(&platform)?;
+                let users = rooms.get_mut(room)?;
+                let old = users.remove(user);
+                if users.is_empty() {
+                    rooms.remove(room);
+                }
+                if rooms.is_empty() {
+                    self.rooms.remove(&platform);
+                }
+                old
+            }
+            (None, None) => {
+                let users = self.grants.get_mut(&platform)?;
+                let old = users.remove(user);
+                if users.is_empty() {
+                    self.grants.remove(&platform);
+                }
+                old
+            }
+        }
+    }
+
+    /// 记录 QQ 群身份, 供没有消息上下文的查询使用。
+    pub fn observe_group_role(&mut self, room: &str, user: &str, group_role: &str) {
+        match Role::from_group_role(group_role) {
+            Some(role) => {
+                self.group_roles
+                    .entry(room.to_string())
+                    .or_default()
+                    .insert(user.to_string(), role);
+            }
+            None => {
+                if let Some(users) = self.group_roles.get_mut(room) {
+                    users.remove(user);
+                }
+            }
+        }
+    }
+
+    /// 计算用户在某个房间内实际的角色
+    ///
+    /// 优先级: 配置里的所有者 > 平台封禁 > 房间授权 > 平台授权 > QQ 群身份 > 普通用户
+    pub fn resolve(
+        &self,
+        platform: Platform,
+        user: &str,
+        room: Option<&str>,
+        group_role: Option<&str>,
+        is_owner: bool,
+    ) -> Role {
+        if is_owner {
+            return Role::Owner;
+        }
+        let global = self.get_grant(platform, user, None);
+        if global == Some(Role::Banned) {
+            return Role::Banned;
+        }
+        if let Some(role) = room.and_then(|room| self.get_grant(platform, user, Some(room))) {
+            return role;
+        }
+        if let Some(role) = global {
+            return role;
+        }
+        let group_role = match group_role {
+            Some(group_role) => Role::from_group_role(group_role),
+            None => match (platform, room) {
+                (Platform::Ica, Some(room)) => {
+                    self.group_roles.get(room).and_then(|users| users.get(user)).copied()
+                }
+                _ => None,
+            },
+        };
+        group_role.unwrap_or(Role::User)
+    }
+
+    /// 返回权限节点要求的最低角色
+    ///
+    /// 会逐级向上查找父节点, 比如 `a.b.c` -> `a.b` -> `a`, 都找不到时为普通用户
+    pub fn required_role(&self, node: &str) -> Role {
+        let mut current = node;
+        loop {
+            if let Some(role) = self.nodes.get(current).copied().or(default_node_role(current)) {
+                return role;
+            }
+            match current.rsplit_once('.') {
+                Some((parent, _)) => current = parent,
+                None => return Role::User,
+            }
+        }
+    }
+
+    /// 检查某个角色是否拥有某个权限节点
+    pub fn role_allows(&self, role: Role, node: &str) -> bool {
+        role != Role::Banned && role >= self.required_role(node)
+    }
+
+    /// 从权限文件读取
+    pub fn load_from_file() -> Self {
+        let path = permission_file_path();
+        if !path.exists() {
+            return Self::default();
+        }
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) => {
+                event!(Level::WARN, "读取权限文件 {:?} 失败: {}", path, e);
+                return Self::default();
+            }
+        };
+        match toml::from_str(&content) {
+            Ok(store) => store,
+            Err(e) => {
+                event!(Level::WARN, "解析权限文件 {:?} 失败, 将使用空的权限数据: {}", path, e);
+                Self::default()
+            }
+        }
+    }
+
+    /// 保存到权限文件
+    pub fn save_to_file(&self) {
+        let path = permission_file_path();
+        let data = match toml::to_string_pretty(self) {
+            Ok(data) => data,
+            Err(e) => {
+                event!(Level::WARN, "序列化权限数据失败: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = std::fs::write(&path, format!("{DEFAULT_PERMISSION_FILE}\n{data}")) {
+            event!(Level::WARN, "写入权限文件 {:?} 失败: {}", path, e);
+        }
+    }
+}
+
+/// 全局权限数据
+pub static PERMISSIONS: LazyLock<RwLock<PermissionStore>> =
+    LazyLock::new(|| RwLock::new(PermissionStore::default()));
+
+/// 返回权限文件路径。
+fn permission_file_path() -> PathBuf { PathBuf::from(&MainStatus::global_config().permission_path) }
+
+/// 从权限文件加载全局权限数据
+///
+/// 重载时保留内存里的 QQ 群身份
+pub fn init_permission() {
+    let mut store = PermissionStore::load_from_file();
+    event!(
+        Level::INFO,
+        "已加载权限数据: {} 条平台授权, {} 个房间的授权",
+        store.grants.values().map(|users| users.len()).sum::<usize>(),
+        store.rooms.values().map(|rooms| rooms.len()).sum::<usize>()
+    );
+    let mut current = PERMISSIONS.write().unwrap();
+    store.group_roles = std::mem::take(&mut current.group_roles);
+    *current = store;
+}
+
+/// 检查用户是否在当前实例配置的 `admin_list` 里。
+pub fn is_config_owner(platform: Platform, user: &str) -> bool {
+    let config = MainStatus::global_config();
+    match platform {
+        Platform::Ica => instance::pick(&config.ica_list(), |ica| &ica.id)
+            .is_some_and(|ica| ica.admin_list.iter().any(|id| id.to_string() == user)),
+        Platform::Tailchat => instance::pick(&config.tailchat_list(), |tailchat| &tailchat.id)
+            .is_some_and(|tailchat| tailchat.admin_list.iter().any(|id| id == user)),
+    }
+}
+
+/// 计算用户在某个房间内实际的角色。
+pub fn role_of(
+    platform: Platform,
+    user: &str,
+    room: Option<&str>,
+    group_role: Option<&str>,
+) -> Role {
+    let is_owner = is_config_owner(platform, user);
+    PERMISSIONS.read().unwrap().resolve(platform, user, room, group_role, is_owner)
+}
+
+/// 检查用户是否拥有某个权限节点。
+pub fn has_permission(
+    platform: Platform,
+    user: &str,
+    node: &str,
+    room: Option<&str>,
+    group_role: Option<&str>,
+) -> bool {
+    let role = role_of(platform, user, room, group_role);
+    PERMISSIONS.read().unwrap().role_allows(role, node)
+}
+
+/// 记录 QQ 群身份。
+pub fn observe_group_role(room: &str, user: &str, group_role: &str) {
+    PERMISSIONS.write().unwrap().observe_group_role(room, user, group_role);
+}
+
+/// 处理 `/bot-grant` 和 `/bot-revoke` 命令
+///
+/// `args` 为 `<user-id> [role] [room [room-id]]`, `role` 为 `None` 时为撤销
+///
+/// 操作者的角色按目标范围计算: 平台级授权只认平台级的角色, 房间授权只认在那个房间里的角色
+pub fn handle_grant_command(
+    args: &str,
+    grant: bool,
+    platform: Platform,
+    operator: &str,
+    operator_group_role: Option<&str>,
+    current_room: &str,
+) -> String {
+    let args: Vec<&str> = args.split_whitespace().collect();
+    let (user, rest) = match args.split_first() {
+        Some((user, rest)) => (*user, rest),
+        None => return "请指定用户".to_string(),
+    };
+    let (role, rest) = if grant {
+        match rest.split_first() {
+            Some((name, rest)) => match Role::from_name(name) {
+                Some(role) => (Some(role), rest),
+                None => return format!("未知的角色: {name}"),
+            },
+            None => return "请指定角色".to_string(),
+        }
+    } else {
+        (None, rest)
+    };
+    let room = match rest {
+        [] => None,
+        ["room"] => Some(current_room),
+        ["room", room] => Some(*room),
+        _ => return format!("无法解析作用范围: {}", rest.join(" ")),
+    };
+    if is_config_owner(platform, user) {
+        return "无法修改配置文件里的所有者".to_string();
+    }
+
+    let operator_role = match room {
+        None if is_config_owner(platform, operator) => Role::Owner,
+        None => {
+            let global = PERMISSIONS.read().unwrap().get_grant(platform, operator, None);
+            global.unwrap_or(Role::User)
+        }
+        Some(room) => {
+            // 只有当前房间才知道操作者的群身份, 其他房间用记录下来的
+            let group_role = operator_group_role.filter(|_| room == current_room);
+            role_of(platform, operator, Some(room), group_role)
+        }
+    };
+    // 目标的角色同样算上房间授权和群身份
+    let target_room = room.unwrap_or(current_room);
+    let target_role =
+        role_of(platform, user, room, None).max(role_of(platform, user, Some(target_room), None));
+
+    let mut store = PERMISSIONS.write().unwrap();
+    if !store.role_allows(operator_role, nodes::PERMISSION_GRANT) {
+        return match room {
+            Some(room) => format!("权限不足, 您在房间 {room} 没有授权权限"),
+            None => "权限不足, 平台级授权需要平台级的角色".to_string(),
+        };
+    }
+    // 非所有者只能管理比自己低的角色
+    if operator_role != Role::Owner
+        && (target_role >= operator_role || role.is_some_and(|role| role >= operator_role))
+    {
+        return "权限不足, 只能管理比自己低的角色".to_string();
+    }
+    let scope = match room {
+        Some(room) => format!("房间 {room} "),
+        None => String::new(),
+    };
+    let old = store.set_grant(platform, user, room, role);
+    // 写文件时不要占着锁, 否则会卡住所有权限检查
+    let snapshot = store.clone();
+    drop(store);
+    snapshot.save_to_file();
+    match (role, old) {
+        (Some(role), _) => format!("已在{scope}将 {user} 设为 {role}"),
+        (None, Some(old)) => format!("已撤销 {user} 在{scope}的角色 {old}"),
+        (None, None) => format!("{user} 在{scope}没有授权, 无变化"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_role_priority() {
+        let mut store = PermissionStore::default();
+        assert_eq!(store.resolve(Platform::Ica, "1", Some("-2"), None, false), Role::User);
+        assert_eq!(
+            store.resolve(Platform::Ica, "1", Some("-2"), Some("admin"), false),
+            Role::Moderator
+        );
+
+        store.set_grant(Platform::Ica, "1", None, Some(Role::Admin));
+        assert_eq!(
+            store.resolve(Platform::Ica, "1", Some("-2"), Some("admin"), false),
+            Role::Admin
+        );
+
+        store.set_grant(Platform::Ica, "1", Some("-2"), Some(Role::User));
+        assert_eq!(store.resolve(Platform::Ica, "1", Some("-2"), None, false), Role::User);
+        assert_eq!(store.resolve(Platform::Ica, "1", Some("-3"), None, false), Role::Admin);
+        assert_eq!(store.resolve(Platform::Tailchat, "1", None, None, false), Role::User);
+
+        store.set_grant(Platform::Ica, "1", None, Some(Role::Banned));
+        assert_eq!(store.resolve(Platform::Ica, "1", Some("-2"), None, false), Role::Banned);
+        assert_eq!(store.resolve(Platform::Ica, "1", Some("-2"), None, true), Role::Owner);
+
+        assert_eq!(store.set_grant(Platform::Ica, "1", Some("-2"), None), Some(Role::User));
+        assert!(store.rooms.is_empty());
+    }
+
+    #[test]
+    fn observed_group_role() {
+        let mut store = PermissionStore::default();
+        store.observe_group_role("-2", "1", "owner");
+        assert_eq!(store.resolve(Platform::Ica, "1", Some("-2"), None, false), Role::Moderator);
+        store.observe_group_role("-2", "1", "member");
+        assert_eq!(store.resolve(Platform::Ica, "1", Some("-2"), None, false), Role::User);
+    }
+
+    #[test]
+    fn node_inheritance() {
+        let mut store = PermissionStore::default();
+        assert_eq!(store.required_role("bot.fetch"), Role::Moderator);
+        assert_eq!(store.required_role("some_plugin.kick"), Role::User);
+        assert_eq!(store.required_role("bot.moderation"), Role::Admin);
+
+        store.nodes.insert("some_plugin".to_string(), Role::Admin);
+        assert_eq!(store.required_role("some_plugin.kick.all"), Role::Admin);
+        store.nodes.insert("bot.fetch".to_string(), Role::User);
+        assert!(store.role_allows(Role::User, "bot.fetch"));
+        assert!(!store.role_allows(Role::Banned, "bot.fetch"));
+        assert!(!store.role_allows(Role::Moderator, "bot.plugin.manage"));
+    }
+}
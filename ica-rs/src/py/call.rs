@@ -16,10 +16,13 @@ use tracing::{Level, event};
 
 use crate::MainStatus;
 use crate::data_struct::ica::all_rooms::JoinRequestRoom;
-use crate::data_struct::{ica, tailchat};
+use crate::data_struct::ica::messages::system::SystemEvent;
+use crate::data_struct::{Platform, ica, tailchat};
 use crate::error::PyPluginError;
-use crate::py::consts::{ica_func, tailchat_func};
+use crate::py::consts::{ica_func, sys_func, tailchat_func};
+use crate::py::storage::PluginScope;
 use crate::py::{PY_PLUGIN_STORAGE, class};
+use crate::tailchat::client::Connection;
 
 pub struct PyTaskList {
     lst: Vec<JoinHandle<()>>,
@@ -69,7 +72,15 @@ pub enum TaskType {
     IcaDeleteMessage,
     IcaJoinRequest,
     IcaLeaveMessage,
+    IcaJoinMessage,
+    IcaMuteMessage,
+    IcaSystemEvent,
+    IcaOnline,
+    IcaOffline,
     TailchatNewMessage,
+    TailchatMention,
+    TailchatInbox,
+    BotConfigReload,
 }
 
 impl TaskType {
@@ -81,7 +92,15 @@ impl TaskType {
             TaskType::IcaDeleteMessage => ica_func::DELETE_MESSAGE,
             TaskType::IcaJoinRequest => ica_func::JOIN_REQUEST,
             TaskType::IcaLeaveMessage => ica_func::LEAVE_MESSAGE,
+            TaskType::IcaJoinMessage => ica_func::JOIN_MESSAGE,
+            TaskType::IcaMuteMessage => ica_func::MUTE_MESSAGE,
+            TaskType::IcaSystemEvent => ica_func::SYSTEM_EVENT,
+            TaskType::IcaOnline => ica_func::ONLINE,
+            TaskType::IcaOffline => ica_func::OFFLINE,
             TaskType::TailchatNewMessage => tailchat_func::NEW_MESSAGE,
+            TaskType::TailchatMention => tailchat_func::MENTION,
+            TaskType::TailchatInbox => tailchat_func::INBOX,
+            TaskType::BotConfigReload => sys_func::ON_CONFIG_RELOAD,
         }
     }
 }
@@ -105,9 +124,33 @@ impl Display for TaskType {
             Self::IcaLeaveMessage => {
                 write!(f, "icalingua 的 退群消息")
             }
+            Self::IcaJoinMessage => {
+                write!(f, "icalingua 的 入群消息")
+            }
+            Self::IcaMuteMessage => {
+                write!(f, "icalingua 的 禁言消息")
+            }
+            Self::IcaSystemEvent => {
+                write!(f, "icalingua 的 系统事件")
+            }
+            Self::IcaOnline => {
+                write!(f, "icalingua 的 QQ 上线")
+            }
+            Self::IcaOffline => {
+                write!(f, "icalingua 的 QQ 离线")
+            }
             Self::TailchatNewMessage => {
                 write!(f, "Tailchat 的 新消息")
             }
+            Self::TailchatMention => {
+                write!(f, "Tailchat 的 @ 消息")
+            }
+            Self::TailchatInbox => {
+                write!(f, "Tailchat 的 收件箱")
+            }
+            Self::BotConfigReload => {
+                write!(f, "bot 的 配置重载")
+            }
         }
     }
 }
@@ -273,7 +316,9 @@ where
 }
 
 /// 调用 `plugins` 插件钩子。
-async fn call_plugins<F, A>(task_type: TaskType, func_name: &str, build_args: F)
+///
+/// 只会调用在 `scope` 范围内启用的插件
+async fn call_plugins<F, A>(task_type: TaskType, func_name: &str, scope: PluginScope, build_args: F)
 where
     F: Fn() -> A,
     A: for<'py> pyo3::call::PyCallArgs<'py> + Send + 'static,
@@ -284,7 +329,7 @@ where
     let mut tasks = Vec::new();
     {
         let storage = PY_PLUGIN_STORAGE.lock().await;
-        let plugins = storage.get_enabled_plugins();
+        let plugins = storage.get_enabled_plugins_in(&scope);
         for (plugin_id, plugin) in plugins.iter() {
             let args = build_args();
             if let Some(task) =
@@ -304,7 +349,8 @@ where
 
 /// 执行 new message 的 python 插件
 pub async fn ica_new_message_py(message: &ica::messages::NewMessage, client: &Client) {
-    call_plugins(TaskType::IcaNewMessage, ica_func::NEW_MESSAGE, || {
+    let scope = PluginScope::room(Platform::Ica, message.room_id);
+    call_plugins(TaskType::IcaNewMessage, ica_func::NEW_MESSAGE, scope, || {
         let msg = class::ica::NewMessagePy::new(message);
         let client = class::ica::IcaClientPy::new(client);
         (msg, client)
@@ -314,7 +360,8 @@ pub async fn ica_new_message_py(message: &ica::messages::NewMessage, client: &Cl
 
 /// 调用 Python 插件的 Icalingua 系统消息钩子。
 pub async fn ica_system_message_py(message: &ica::messages::NewMessage, client: &Client) {
-    call_plugins(TaskType::IcaSystemMessage, ica_func::SYSTEM_MESSAGE, || {
+    let scope = PluginScope::room(Platform::Ica, message.room_id);
+    call_plugins(TaskType::IcaSystemMessage, ica_func::SYSTEM_MESSAGE, scope, || {
         let msg = class::ica::NewMessagePy::new(message);
         let client = class::ica::IcaClientPy::new(client);
         (msg, client)
@@ -322,9 +369,56 @@ pub async fn ica_system_message_py(message: &ica::messages::NewMessage, client:
     .await;
 }
 
+/// 调用 Python 插件的 Icalingua 系统事件钩子
+///
+/// 先调用通用的 `on_ica_system_event`, 再按事件类型调用对应的专用钩子
+pub async fn ica_system_event_py(
+    event: &SystemEvent,
+    message: &ica::messages::NewMessage,
+    client: &Client,
+) {
+    let build_args = || {
+        let event = class::ica::IcaSystemEventPy::new(event, message);
+        let client = class::ica::IcaClientPy::new(client);
+        (event, client)
+    };
+    let scope = PluginScope::room(Platform::Ica, message.room_id);
+    call_plugins(TaskType::IcaSystemEvent, ica_func::SYSTEM_EVENT, scope.clone(), build_args).await;
+
+    let dedicated = match event {
+        SystemEvent::MemberJoin { .. } => Some(TaskType::IcaJoinMessage),
+        event if event.is_leave() => Some(TaskType::IcaLeaveMessage),
+        event if event.is_mute() => Some(TaskType::IcaMuteMessage),
+        _ => None,
+    };
+    if let Some(task_type) = dedicated {
+        call_plugins(task_type, task_type.py_func_str(), scope, build_args).await;
+    }
+}
+
+/// 调用 Python 插件的 QQ 上线钩子。
+pub async fn ica_online_py(client: &Client) {
+    let scope = PluginScope::Platform(Platform::Ica);
+    call_plugins(TaskType::IcaOnline, ica_func::ONLINE, scope, || {
+        (class::ica::IcaClientPy::new(client),)
+    })
+    .await;
+}
+
+/// 调用 Python 插件的 QQ 离线钩子, `reason` 为 bridge 给的原因。
+pub async fn ica_offline_py(reason: &str, client: &Client) {
+    let scope = PluginScope::Platform(Platform::Ica);
+    call_plugins(TaskType::IcaOffline, ica_func::OFFLINE, scope, || {
+        (reason.to_string(), class::ica::IcaClientPy::new(client))
+    })
+    .await;
+}
+
 /// 调用 Python 插件的 Icalingua 删除消息钩子。
 pub async fn ica_delete_message_py(msg_id: ica::MessageId, client: &Client) {
-    call_plugins(TaskType::IcaDeleteMessage, ica_func::DELETE_MESSAGE, || {
+    // 撤回事件里只有消息 id, 只能按平台筛选
+    let scope = PluginScope::Platform(Platform::Ica);
+    call_plugins(TaskType::IcaDeleteMessage, ica_func::DELETE_MESSAGE, scope, || {
         let client = class::ica::IcaClientPy::new(client);
         (msg_id.clone(), client)
     })
@@ -332,10 +426,17 @@ pub async fn ica_delete_message_py(msg_id: ica::MessageId, client: &Client) {
 }
 
 /// 调用 Python 插件的 Icalingua 入群申请钩子。
-pub async fn ica_join_request_py(event: JoinRequestRoom, client: &Client) {
-    call_plugins(TaskType::IcaJoinRequest, ica_func::JOIN_REQUEST, || {
+///
+/// `handled` 为自动审批的结果, 未处理时为 `None`
+pub async fn ica_join_request_py(event: JoinRequestRoom, handled: Option<bool>, client: &Client) {
+    let scope = if event.is_group() {
+        PluginScope::room(Platform::Ica, event.room_id())
+    } else {
+        PluginScope::Platform(Platform::Ica)
+    };
+    call_plugins(TaskType::IcaJoinRequest, ica_func::JOIN_REQUEST, scope, || {
+        let event = class::ica::IcaJoinRequestPy::new(&event, client, handled);
         let client = class::ica::IcaClientPy::new(client);
-        let event = class::ica::IcaJoinRequestPy::new(&event);
         (event, client)
     })
     .await;
@@ -344,12 +445,52 @@ pub async fn ica_join_request_py(event: JoinRequestRoom, client: &Client) {
 /// 调用 Python 插件的 Tailchat 新消息钩子。
 pub async fn tailchat_new_message_py(
     message: &tailchat::messages::ReceiveMessage,
-    client: &Client,
+    client: &Connection,
+) {
+    let scope = PluginScope::room(Platform::Tailchat, &message.converse_id);
+    call_plugins(TaskType::TailchatNewMessage, tailchat_func::NEW_MESSAGE, scope, || {
+        let msg = class::tailchat::TailchatReceiveMessagePy::from_recive_message(message);
+        let client = class::tailchat::TailchatClientPy::new(client);
+        (msg, client)
+    })
+    .await;
+}
+
+/// 调用 Python 插件的 Tailchat 被 @ 钩子。
+pub async fn tailchat_mention_py(
+    message: &tailchat::messages::ReceiveMessage,
+    client: &Connection,
 ) {
-    call_plugins(TaskType::TailchatNewMessage, tailchat_func::NEW_MESSAGE, || {
+    let scope = PluginScope::room(Platform::Tailchat, &message.converse_id);
+    call_plugins(TaskType::TailchatMention, tailchat_func::MENTION, scope, || {
         let msg = class::tailchat::TailchatReceiveMessagePy::from_recive_message(message);
         let client = class::tailchat::TailchatClientPy::new(client);
         (msg, client)
     })
     .await;
 }
+
+/// 调用 Python 插件的 Tailchat 收件箱钩子。
+pub async fn tailchat_inbox_py(item: &tailchat::messages::InboxItem, client: &Connection) {
+    let scope = match &item.message {
+        Some(message) => PluginScope::room(Platform::Tailchat, &message.converse_id),
+        None => PluginScope::Platform(Platform::Tailchat),
+    };
+    call_plugins(TaskType::TailchatInbox, tailchat_func::INBOX, scope, || {
+        let item = class::tailchat::TailchatInboxItemPy::new(item);
+        let client = class::tailchat::TailchatClientPy::new(client);
+        (item, client)
+    })
+    .await;
+}
+
+/// 调用 Python 插件的主配置重载钩子, 参数为变化了的配置项。
+pub async fn bot_config_reload_py(changes: &[String]) {
+    call_plugins(
+        TaskType::BotConfigReload,
+        sys_func::ON_CONFIG_RELOAD,
+        PluginScope::Global,
+        || (changes.to_vec(),),
+    )
+    .await;
+}
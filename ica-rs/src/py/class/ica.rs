@@ -1,5 +1,6 @@
 //! 暴露给 Python 插件的 Icalingua 消息、房间和客户端类型。
 
+use std::collections::HashMap;
 use std::time::SystemTime;
 
 use pyo3::{PyResult, exceptions::PyRuntimeError, pyclass, pymethods};
@@ -8,99 +9,118 @@ use tokio::runtime::Runtime;
 use tracing::{Level, event};
 
 use crate::MainStatus;
+use crate::config::IcaConfig;
+use crate::data_struct::Platform;
 use crate::data_struct::ica::group_members::GroupMember;
 use crate::data_struct::ica::messages::raw::RawSendMessage;
+use crate::data_struct::ica::messages::system::SystemEvent;
 use crate::data_struct::ica::messages::{
     DeleteMessage, MessageTrait, NewMessage, ReplyMessage, SendMessage,
 };
 use crate::data_struct::ica::{MessageId, RoomId, RoomIdTrait, UserId, all_rooms};
+use crate::error::{ClientResult, IcaError};
+use crate::ica::client::queue::{self, Priority};
 use crate::ica::client::{
-    delete_message, get_group_members, get_muted_group_members, send_message, send_poke,
-    send_room_sign_in, send_string_message, set_group_ban,
+    delete_message, find_group_members, get_group_member, get_group_members,
+    get_muted_group_members, handle_request, invalidate_group_members, kick_group_member,
+    leave_group, mention_member, send_message_confirmed, send_message_with_priority, send_poke,
+    send_room_sign_in, send_string_message, set_group_admin, set_group_ban, set_group_card,
+    set_group_special_title, set_group_whole_ban,
 };
+use crate::instance;
+use crate::permission;
 use crate::py::PY_PLUGIN_STORAGE;
+use crate::py::storage::PluginScope;
+use crate::status::ica as ica_status;
 
 #[pyclass]
 #[pyo3(name = "IcaStatus")]
-pub struct IcaStatusPy {}
+pub struct IcaStatusPy {
+    /// 状态所属的实例
+    pub instance: String,
+}
 
 #[pymethods]
 impl IcaStatusPy {
     #[new]
-    /// 构造供 Python 调用的新实例。
-    pub fn py_new() -> Self { Self {} }
+    /// 构造供 Python 调用的新实例, 使用第一个实例的状态。
+    pub fn py_new() -> Self { Self::new() }
+    #[getter]
+    /// 返回状态所属的实例 id
+    ///
+    /// 添加自 2.0.4
+    pub fn get_instance_id(&self) -> String { self.instance.clone() }
     #[getter]
     /// 返回 `qq_login` 对应的数据。
-    pub fn get_qq_login(&self) -> bool { MainStatus::global_ica_status().qq_login }
+    pub fn get_qq_login(&self) -> bool { self.read(|status| status.qq_login) }
     #[getter]
     /// 返回 `online` 对应的数据。
-    pub fn get_online(&self) -> bool { MainStatus::global_ica_status().online_status.online }
+    pub fn get_online(&self) -> bool { self.read(|status| status.online_status.online) }
     #[getter]
     /// 返回 `self_id` 对应的数据。
-    pub fn get_self_id(&self) -> i64 { MainStatus::global_ica_status().online_status.qqid }
+    pub fn get_self_id(&self) -> i64 { self.read(|status| status.online_status.qqid) }
     #[getter]
     /// 返回 `nick_name` 对应的数据。
-    pub fn get_nick_name(&self) -> String {
-        MainStatus::global_ica_status().online_status.nick.clone()
-    }
+    pub fn get_nick_name(&self) -> String { self.read(|status| status.online_status.nick.clone()) }
     #[getter]
     /// 返回 `loaded_messages_count` 对应的数据。
     pub fn get_loaded_messages_count(&self) -> u64 {
-        MainStatus::global_ica_status().current_loaded_messages_count
+        self.read(|status| status.current_loaded_messages_count)
     }
     #[getter]
     /// 返回 `ica_version` 对应的数据。
     pub fn get_ica_version(&self) -> String {
-        MainStatus::global_ica_status().online_status.icalingua_info.ica_version.clone()
+        self.read(|status| status.online_status.icalingua_info.ica_version.clone())
     }
 
     #[getter]
     /// 返回 `os_info` 对应的数据。
     pub fn get_os_info(&self) -> String {
-        MainStatus::global_ica_status().online_status.icalingua_info.os_info.clone()
+        self.read(|status| status.online_status.icalingua_info.os_info.clone())
     }
 
     #[getter]
     /// 返回 `resident_set_size` 对应的数据。
     pub fn get_resident_set_size(&self) -> String {
-        MainStatus::global_ica_status()
-            .online_status
-            .icalingua_info
-            .resident_set_size
-            .clone()
+        self.read(|status| status.online_status.icalingua_info.resident_set_size.clone())
     }
 
     #[getter]
     /// 返回 `heap_used` 对应的数据。
     pub fn get_heap_used(&self) -> String {
-        MainStatus::global_ica_status().online_status.icalingua_info.heap_used.clone()
+        self.read(|status| status.online_status.icalingua_info.heap_used.clone())
     }
 
     #[getter]
     /// 返回 `load` 对应的数据。
     pub fn get_load(&self) -> String {
-        MainStatus::global_ica_status().online_status.icalingua_info.load.clone()
+        self.read(|status| status.online_status.icalingua_info.load.clone())
     }
     #[getter]
     /// 获取当前用户加入的所有房间
     ///
     /// 添加自 2.0.1
     pub fn get_rooms(&self) -> Vec<IcaRoomPy> {
-        MainStatus::global_ica_status().rooms.iter().map(|r| r.into()).collect()
+        self.read(|status| status.rooms.iter().map(|r| r.into()).collect())
     }
     #[getter]
     /// 获取所有管理员
     ///
     /// 添加自 2.0.1
-    pub fn get_admins(&self) -> Vec<UserId> { MainStatus::global_config().ica().admin_list.clone() }
+    pub fn get_admins(&self) -> Vec<UserId> { self.config().admin_list }
     #[getter]
     /// 获取所有被屏蔽的人
     ///
     /// (好像没啥用就是了, 反正被过滤的不会给到插件)
     ///
     /// 添加自 2.0.1
-    pub fn get_filtered(&self) -> Vec<UserId> {
-        MainStatus::global_config().ica().filter_list.clone()
+    pub fn get_filtered(&self) -> Vec<UserId> { self.config().filter_list }
+    #[getter]
+    /// 获取每种 bridge 事件解析失败的次数
+    ///
+    /// 添加自 2.0.4
+    pub fn get_parse_failures(&self) -> HashMap<String, u64> {
+        instance::sync_scope(self.instance.clone(), crate::ica::decode::failure_counts)
     }
 }
 
@@ -110,8 +130,23 @@ impl Default for IcaStatusPy {
 }
 
 impl IcaStatusPy {
-    /// 创建并初始化对应的数据结构。
-    pub fn new() -> Self { Self {} }
+    /// 创建当前实例的状态。
+    pub fn new() -> Self { Self::of(MainStatus::global_config().ica_id()) }
+
+    /// 创建指定实例的状态。
+    pub fn of(instance: String) -> Self { Self { instance } }
+
+    /// 从所属实例的运行状态里读取一个值, 实例不存在时返回默认值。
+    fn read<T: Default>(&self, f: impl FnOnce(&ica_status::MainStatus) -> T) -> T {
+        MainStatus::ica_status_of(&self.instance)
+            .map(|status| f(&status.read().unwrap()))
+            .unwrap_or_default()
+    }
+
+    /// 返回所属实例的配置。
+    fn config(&self) -> IcaConfig {
+        instance::sync_scope(self.instance.clone(), || MainStatus::global_config().ica())
+    }
 }
 
 #[derive(Clone)]
@@ -208,6 +243,8 @@ impl IcaGroupMemberPy {
 #[pyo3(name = "NewMessage")]
 pub struct NewMessagePy {
     pub msg: NewMessage,
+    /// 收到消息的实例
+    pub instance: String,
 }
 
 #[pymethods]
@@ -234,7 +271,14 @@ impl NewMessagePy {
     pub fn get_sender_name(&self) -> String { self.msg.sender_name().clone() }
     #[getter]
     /// 返回 `is_from_self` 对应的数据。
-    pub fn get_is_from_self(&self) -> bool { self.msg.is_from_self() }
+    pub fn get_is_from_self(&self) -> bool {
+        instance::sync_scope(self.instance.clone(), || self.msg.is_from_self())
+    }
+    #[getter]
+    /// 返回收到消息的实例 id
+    ///
+    /// 添加自 2.0.4
+    pub fn get_instance_id(&self) -> String { self.instance.clone() }
     #[getter]
     /// 返回 `is_reply` 对应的数据。
     pub fn get_is_reply(&self) -> bool { self.msg.is_reply() }
@@ -271,8 +315,13 @@ impl NewMessagePy {
 }
 
 impl NewMessagePy {
-    /// 创建并初始化对应的数据结构。
-    pub fn new(msg: &NewMessage) -> Self { Self { msg: msg.clone() } }
+    /// 创建并初始化对应的数据结构, 实例取当前实例。
+    pub fn new(msg: &NewMessage) -> Self {
+        Self {
+            msg: msg.clone(),
+            instance: MainStatus::global_config().ica_id(),
+        }
+    }
 }
 
 #[pyclass]
@@ -325,6 +374,13 @@ impl SendMessagePy {
     pub fn set_img(&mut self, file: Vec<u8>, file_type: String, as_sticker: bool) {
         self.msg.set_img(&file, &file_type, as_sticker);
     }
+    /// 在消息开头 @ 某人, name 为显示的名称
+    ///
+    /// 添加自 2.0.4
+    pub fn add_mention(&mut self, user_id: UserId, name: String) -> Self {
+        self.msg.add_mention(user_id, &name);
+        self.clone()
+    }
     /// 移除消息回复引用。
     pub fn remove_reply(&mut self) -> Self {
         self.msg.reply_to = None;
@@ -355,11 +411,24 @@ impl DeleteMessagePy {
     pub fn new(msg: DeleteMessage) -> Self { Self { msg } }
 }
 
+/// 解析 Python 传入的发送优先级, 无法识别时使用普通优先级。
+fn parse_priority(priority: Option<String>) -> Priority {
+    match priority {
+        Some(name) => Priority::from_name(&name).unwrap_or_else(|| {
+            event!(Level::WARN, "未知的发送优先级 {}, 使用 normal", name);
+            Priority::Normal
+        }),
+        None => Priority::Normal,
+    }
+}
+
 #[derive(Clone)]
 #[pyclass(from_py_object)]
 #[pyo3(name = "IcaClient")]
 pub struct IcaClientPy {
     pub client: Client,
+    /// 客户端所属的实例
+    pub instance: String,
 }
 
 #[pymethods]
@@ -370,17 +439,26 @@ impl IcaClientPy {
     pub fn send_room_sign_in(&self, room_id: RoomId) -> bool {
         tokio::task::block_in_place(|| {
             let rt = Runtime::new().unwrap();
-            rt.block_on(send_room_sign_in(&self.client, room_id))
+            rt.block_on(self.scoped(send_room_sign_in(&self.client, room_id)))
         })
     }
 
+    /// 判断当前 bridge 是否支持某个接口, 比如 `"getGroupMembers"`、`"sendGroupPoke"`
+    ///
+    /// 还没完成鉴权时总是返回 `True`
+    ///
+    /// 添加自 2.0.4
+    pub fn supports(&self, feature: &str) -> bool {
+        instance::sync_scope(self.instance.clone(), || crate::ica::capability::supports(feature))
+    }
+
     /// 戳一戳
     ///
     /// 添加自 1.6.5 版本
     pub fn send_poke(&self, room_id: RoomId, user_id: UserId) -> bool {
         tokio::task::block_in_place(|| {
             let rt = Runtime::new().unwrap();
-            rt.block_on(send_poke(&self.client, room_id, user_id))
+            rt.block_on(self.scoped(send_poke(&self.client, room_id, user_id)))
         })
     }
 
@@ -390,7 +468,100 @@ impl IcaClientPy {
     pub fn set_group_ban(&self, room_id: RoomId, user_id: UserId, duration: u64) -> bool {
         tokio::task::block_in_place(|| {
             let rt = Runtime::new().unwrap();
-            rt.block_on(set_group_ban(&self.client, room_id, user_id, duration))
+            rt.block_on(self.scoped(set_group_ban(&self.client, room_id, user_id, duration)))
+        })
+    }
+
+    /// 把指定成员踢出群
+    ///
+    /// reject_add_request 为 True 时不再接受此人的加群申请
+    ///
+    /// 添加自 2.0.4
+    #[pyo3(signature = (room_id, user_id, reject_add_request = false))]
+    pub fn kick_group_member(
+        &self,
+        room_id: RoomId,
+        user_id: UserId,
+        reject_add_request: bool,
+    ) -> PyResult<()> {
+        block_on_group_action(self.scoped(kick_group_member(
+            &self.client,
+            room_id,
+            user_id,
+            reject_add_request,
+        )))
+    }
+
+    /// 开启或关闭全员禁言
+    ///
+    /// 添加自 2.0.4
+    pub fn set_group_whole_ban(&self, room_id: RoomId, enable: bool) -> PyResult<()> {
+        block_on_group_action(self.scoped(set_group_whole_ban(&self.client, room_id, enable)))
+    }
+
+    /// 设置群名片, card 为空字符串时清除名片
+    ///
+    /// 添加自 2.0.4
+    pub fn set_group_card(&self, room_id: RoomId, user_id: UserId, card: String) -> PyResult<()> {
+        block_on_group_action(self.scoped(set_group_card(&self.client, room_id, user_id, &card)))
+    }
+
+    /// 设置群专属头衔, duration 单位为秒, -1 为永久
+    ///
+    /// 添加自 2.0.4
+    #[pyo3(signature = (room_id, user_id, title, duration = -1))]
+    pub fn set_group_special_title(
+        &self,
+        room_id: RoomId,
+        user_id: UserId,
+        title: String,
+        duration: i64,
+    ) -> PyResult<()> {
+        block_on_group_action(self.scoped(set_group_special_title(
+            &self.client,
+            room_id,
+            user_id,
+            &title,
+            duration,
+        )))
+    }
+
+    /// 设置或取消管理员
+    ///
+    /// 添加自 2.0.4
+    pub fn set_group_admin(&self, room_id: RoomId, user_id: UserId, enable: bool) -> PyResult<()> {
+        block_on_group_action(self.scoped(set_group_admin(&self.client, room_id, user_id, enable)))
+    }
+
+    /// 退出群聊
+    ///
+    /// 添加自 2.0.4
+    pub fn leave_group(&self, room_id: RoomId) -> PyResult<()> {
+        block_on_group_action(self.scoped(leave_group(&self.client, room_id)))
+    }
+
+    /// 处理好友/加群申请
+    ///
+    /// request_type 为 `"friend"` 或 `"group"`, flag 来自 `IcaJoinRequest.flag`
+    ///
+    /// 添加自 2.0.4
+    #[pyo3(signature = (request_type, flag, approve, reason = None))]
+    pub fn handle_request(
+        &self,
+        request_type: String,
+        flag: String,
+        approve: bool,
+        reason: Option<String>,
+    ) -> bool {
+        tokio::task::block_in_place(|| {
+            let rt = Runtime::new().unwrap();
+            rt.block_on(self.scoped(handle_request(
+                &self.client,
+                &request_type,
+                &flag,
+                approve,
+                reason.as_deref(),
+            )))
         })
     }
 
@@ -399,28 +570,109 @@ impl IcaClientPy {
         tokio::task::block_in_place(|| {
             let rt = Runtime::new()
                 .map_err(|error| PyRuntimeError::new_err(format!("创建运行时失败: {error}")))?;
-            rt.block_on(get_group_members(&self.client, room_id))
+            rt.block_on(self.scoped(get_group_members(&self.client, room_id)))
                 .map(|members| members.into_iter().map(Into::into).collect())
                 .map_err(|error| PyRuntimeError::new_err(error.to_string()))
         })
     }
 
+    /// 按 QQ 号获取群成员, 优先使用缓存
+    ///
+    /// 添加自 2.0.4
+    pub fn get_member(
+        &self,
+        room_id: RoomId,
+        user_id: UserId,
+    ) -> PyResult<Option<IcaGroupMemberPy>> {
+        tokio::task::block_in_place(|| {
+            let rt = Runtime::new()
+                .map_err(|error| PyRuntimeError::new_err(format!("创建运行时失败: {error}")))?;
+            rt.block_on(self.scoped(get_group_member(&self.client, room_id, user_id)))
+                .map(|member| member.map(Into::into))
+                .map_err(|error| PyRuntimeError::new_err(error.to_string()))
+        })
+    }
+
+    /// 按群名片或昵称查找群成员, 优先使用缓存
+    ///
+    /// 添加自 2.0.4
+    pub fn find_members(&self, room_id: RoomId, name: String) -> PyResult<Vec<IcaGroupMemberPy>> {
+        tokio::task::block_in_place(|| {
+            let rt = Runtime::new()
+                .map_err(|error| PyRuntimeError::new_err(format!("创建运行时失败: {error}")))?;
+            rt.block_on(self.scoped(find_group_members(&self.client, room_id, &name)))
+                .map(|members| members.into_iter().map(Into::into).collect())
+                .map_err(|error| PyRuntimeError::new_err(error.to_string()))
+        })
+    }
+
+    /// 让某个群的成员缓存失效, 返回之前是否有缓存
+    ///
+    /// 添加自 2.0.4
+    pub fn invalidate_members(&self, room_id: RoomId) -> bool {
+        instance::sync_scope(self.instance.clone(), || invalidate_group_members(room_id))
+    }
+
+    /// 在消息开头 @ 某个群成员, 名称从成员缓存里取
+    ///
+    /// 添加自 2.0.4
+    pub fn mention(&self, message: SendMessagePy, user_id: UserId) -> PyResult<SendMessagePy> {
+        let mut msg = message.msg;
+        tokio::task::block_in_place(|| {
+            let rt = Runtime::new()
+                .map_err(|error| PyRuntimeError::new_err(format!("创建运行时失败: {error}")))?;
+            rt.block_on(self.scoped(mention_member(&self.client, &mut msg, user_id)))
+                .map_err(|error| PyRuntimeError::new_err(error.to_string()))
+        })?;
+        Ok(SendMessagePy::new(msg))
+    }
+
     /// 获取指定群聊中当前仍处于禁言中的成员。
     pub fn get_muted_group_members(&self, room_id: RoomId) -> PyResult<Vec<IcaGroupMemberPy>> {
         tokio::task::block_in_place(|| {
             let rt = Runtime::new()
                 .map_err(|error| PyRuntimeError::new_err(format!("创建运行时失败: {error}")))?;
-            rt.block_on(get_muted_group_members(&self.client, room_id))
+            rt.block_on(self.scoped(get_muted_group_members(&self.client, room_id)))
                 .map(|members| members.into_iter().map(Into::into).collect())
                 .map_err(|error| PyRuntimeError::new_err(error.to_string()))
         })
     }
 
     /// 发送 `message` 请求或消息。
-    pub fn send_message(&self, message: SendMessagePy) -> bool {
+    ///
+    /// 消息会进入发送队列限速, `priority` 可选 `high`/`normal`/`low`, 默认为 `normal`
+    ///
+    /// `priority` 参数添加自 2.0.4
+    #[pyo3(signature = (message, priority = None))]
+    pub fn send_message(&self, message: SendMessagePy, priority: Option<String>) -> bool {
+        let priority = parse_priority(priority);
         tokio::task::block_in_place(|| {
             let rt = Runtime::new().unwrap();
-            rt.block_on(send_message(&self.client, &message.msg))
+            rt.block_on(self.scoped(send_message_with_priority(
+                &self.client,
+                &message.msg,
+                priority,
+            )))
+        })
+    }
+
+    /// 发送消息并等待送达确认, 返回消息 ID
+    ///
+    /// 发送失败、被 bridge 拒绝或者等待确认超时都会抛出 `RuntimeError`
+    ///
+    /// 添加自 2.0.4
+    #[pyo3(signature = (message, priority = None))]
+    pub fn send_message_confirmed(
+        &self,
+        message: SendMessagePy,
+        priority: Option<String>,
+    ) -> PyResult<MessageId> {
+        let priority = parse_priority(priority);
+        tokio::task::block_in_place(|| {
+            let rt = Runtime::new()
+                .map_err(|error| PyRuntimeError::new_err(format!("创建运行时失败: {error}")))?;
+            rt.block_on(self.scoped(send_message_confirmed(&self.client, &message.msg, priority)))
+                .map_err(|error| PyRuntimeError::new_err(error.to_string()))
         })
     }
 
@@ -435,21 +687,21 @@ impl IcaClientPy {
         let msg = RawSendMessage::string_to_json(&raw_msg, room_id);
         tokio::task::block_in_place(|| {
             let rt = Runtime::new().unwrap();
-            rt.block_on(send_string_message(&self.client, &msg))
+            rt.block_on(self.scoped(send_string_message(&self.client, &msg)))
         })
     }
 
     /// 发送 `and_warn` 请求或消息。
     pub fn send_and_warn(&self, message: SendMessagePy) -> bool {
         event!(Level::WARN, message.msg.content);
-        self.send_message(message)
+        self.send_message(message, None)
     }
 
     /// 请求删除指定消息。
     pub fn delete_message(&self, message: DeleteMessagePy) -> bool {
         tokio::task::block_in_place(|| {
             let rt = Runtime::new().unwrap();
-            rt.block_on(delete_message(&self.client, &message.msg))
+            rt.block_on(self.scoped(delete_message(&self.client, &message.msg)))
         })
     }
 
@@ -464,7 +716,7 @@ impl IcaClientPy {
         let msg = DeleteMessage::new(room_id, msg_id);
         tokio::task::block_in_place(|| {
             let rt = Runtime::new().unwrap();
-            rt.block_on(delete_message(&self.client, &msg))
+            rt.block_on(self.scoped(delete_message(&self.client, &msg)))
         })
     }
 
@@ -484,7 +736,12 @@ impl IcaClientPy {
 
     #[getter]
     /// 返回 `status` 对应的数据。
-    pub fn get_status(&self) -> IcaStatusPy { IcaStatusPy::new() }
+    pub fn get_status(&self) -> IcaStatusPy { IcaStatusPy::of(self.instance.clone()) }
+    #[getter]
+    /// 返回客户端所属的实例 id
+    ///
+    /// 添加自 2.0.4
+    pub fn get_instance_id(&self) -> String { self.instance.clone() }
     #[getter]
     /// 返回 `version` 对应的数据。
     pub fn get_version(&self) -> String { crate::VERSION.to_string() }
@@ -501,6 +758,21 @@ impl IcaClientPy {
     /// 返回 `startup_time` 对应的数据。
     pub fn get_startup_time(&self) -> SystemTime { crate::start_up_time() }
 
+    #[getter]
+    /// 返回发送队列的统计数据
+    ///
+    /// 包含 `enqueued`/`sent`/`failed`/`retried`/`dropped`/`pending`
+    ///
+    /// 添加自 2.0.4
+    pub fn get_queue_metrics(&self) -> HashMap<String, u64> {
+        instance::sync_scope(self.instance.clone(), queue::metrics)
+            .unwrap_or_default()
+            .as_pairs()
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect()
+    }
+
     #[getter]
     /// 返回 `py_tasks_count` 对应的数据。
     pub fn get_py_tasks_count(&self) -> usize {
@@ -520,7 +792,7 @@ impl IcaClientPy {
     /// 同步状态到配置文件
     /// 这样关闭的时候就会保存状态
     pub fn sync_status_to_file(&self) {
-        let storage = PY_PLUGIN_STORAGE.blocking_lock();
+        let mut storage = PY_PLUGIN_STORAGE.blocking_lock();
         storage.sync_status_to_file();
     }
 
@@ -536,16 +808,79 @@ impl IcaClientPy {
         storage.get_status(&plugin_name)
     }
 
+    /// 设置某个插件在指定房间内的状态
+    ///
+    /// `status` 为 `None` 时移除房间设置, 恢复继承
+    ///
+    /// 添加自 2.0.4
+    #[pyo3(signature = (plugin_name, room_id, status = None))]
+    pub fn set_plugin_room_status(
+        &self,
+        plugin_name: String,
+        room_id: RoomId,
+        status: Option<bool>,
+    ) -> bool {
+        let mut storage = PY_PLUGIN_STORAGE.blocking_lock();
+        let scope = PluginScope::room(Platform::Ica, room_id);
+        storage.get_status(&plugin_name).is_some()
+            && storage.set_scoped_status(&plugin_name, &scope, status).is_ok()
+    }
+
+    /// 返回某个插件在指定房间内实际生效的状态
+    ///
+    /// 添加自 2.0.4
+    pub fn get_plugin_room_status(&self, plugin_name: String, room_id: RoomId) -> Option<bool> {
+        let storage = PY_PLUGIN_STORAGE.blocking_lock();
+        storage.get_scoped_status(&plugin_name, &PluginScope::room(Platform::Ica, room_id))
+    }
+
+    /// 设置某个插件在当前平台上的状态
+    ///
+    /// `status` 为 `None` 时移除平台设置, 恢复继承
+    ///
+    /// 添加自 2.0.4
+    #[pyo3(signature = (plugin_name, status = None))]
+    pub fn set_plugin_platform_status(&self, plugin_name: String, status: Option<bool>) -> bool {
+        let mut storage = PY_PLUGIN_STORAGE.blocking_lock();
+        let scope = PluginScope::Platform(Platform::Ica);
+        storage.get_status(&plugin_name).is_some()
+            && storage.set_scoped_status(&plugin_name, &scope, status).is_ok()
+    }
+
     /// 重新加载插件
     ///
     /// 返回是否成功
     pub fn reload_plugin(&self, plugin_name: String) -> bool {
         let mut storage = PY_PLUGIN_STORAGE.blocking_lock();
-        storage
-            .storage
-            .get_mut(&plugin_name)
-            .map(|p| p.reload_self(None).is_ok())
-            .unwrap_or(false)
+        storage.reload_plugin(&plugin_name, None).is_ok()
+    }
+
+    /// 检查用户是否拥有某个权限节点
+    ///
+    /// 权限节点用 `.` 分级, 比如 `my_plugin.admin`, 未配置的节点所有未封禁用户都有权限
+    ///
+    /// 添加自 2.0.4
+    #[pyo3(signature = (user_id, node, room_id = None))]
+    pub fn has_permission(&self, user_id: UserId, node: String, room_id: Option<RoomId>) -> bool {
+        let room = room_id.map(|room| room.to_string());
+        permission::has_permission(
+            Platform::Ica,
+            &user_id.to_string(),
+            &node,
+            room.as_deref(),
+            None,
+        )
+    }
+
+    /// 返回用户的角色名称 (owner/admin/moderator/user/banned)
+    ///
+    /// 添加自 2.0.4
+    #[pyo3(signature = (user_id, room_id = None))]
+    pub fn get_role(&self, user_id: UserId, room_id: Option<RoomId>) -> String {
+        let room = room_id.map(|room| room.to_string());
+        permission::role_of(Platform::Ica, &user_id.to_string(), room.as_deref(), None)
+            .as_str()
+            .to_string()
     }
 
     /// 向 Python 插件日志记录调试信息。
@@ -563,31 +898,117 @@ impl IcaClientPy {
 }
 
 impl IcaClientPy {
-    /// 创建并初始化对应的数据结构。
+    /// 创建绑定到当前实例的客户端。
     pub fn new(client: &Client) -> Self {
         Self {
             client: client.clone(),
+            instance: MainStatus::global_config().ica_id(),
         }
     }
+
+    /// 让请求运行在客户端所属实例的作用域里。
+    fn scoped<F: Future>(&self, future: F) -> impl Future<Output = F::Output> {
+        instance::scope(self.instance.clone(), future)
+    }
+}
+
+/// 在临时运行时里等待群管理请求, 错误转换为 Python 的 `RuntimeError`。
+fn block_on_group_action(action: impl Future<Output = ClientResult<(), IcaError>>) -> PyResult<()> {
+    tokio::task::block_in_place(|| {
+        let rt = Runtime::new()
+            .map_err(|error| PyRuntimeError::new_err(format!("创建运行时失败: {error}")))?;
+        rt.block_on(action).map_err(|error| PyRuntimeError::new_err(error.to_string()))
+    })
 }
 
 #[pyclass]
 #[pyo3(name = "IcaJoinRequest")]
 pub struct IcaJoinRequestPy {
     pub inner: all_rooms::JoinRequestRoom,
+    pub client: Client,
+    /// 收到申请的实例
+    pub instance: String,
+    /// 是否已经被处理 (自动审批或插件调用过), 同意为 `true`
+    pub handled: Option<bool>,
 }
 
 impl IcaJoinRequestPy {
     /// 创建并初始化对应的数据结构。
-    pub fn new(event: &all_rooms::JoinRequestRoom) -> Self {
+    pub fn new(event: &all_rooms::JoinRequestRoom, client: &Client, handled: Option<bool>) -> Self {
         Self {
             inner: event.clone(),
+            client: client.clone(),
+            instance: MainStatus::global_config().ica_id(),
+            handled,
         }
     }
+
+    /// 让请求运行在收到申请的实例的作用域里。
+    fn scoped<F: Future>(&self, future: F) -> impl Future<Output = F::Output> {
+        instance::scope(self.instance.clone(), future)
+    }
+
+    /// 向 bridge 发送处理结果, 成功后记录下来。
+    fn respond(&mut self, approve: bool, reason: Option<&str>) -> bool {
+        if let Some(handled) = self.handled {
+            event!(
+                Level::WARN,
+                "申请 {} 已经被{}过了",
+                self.inner.flag,
+                if handled { "同意" } else { "拒绝" }
+            );
+            return false;
+        }
+        let success = tokio::task::block_in_place(|| {
+            let rt = Runtime::new().unwrap();
+            rt.block_on(self.scoped(handle_request(
+                &self.client,
+                &self.inner.request_type,
+                &self.inner.flag,
+                approve,
+                reason,
+            )))
+        });
+        if success {
+            self.handled = Some(approve);
+        }
+        success
+    }
 }
 
 #[pymethods]
 impl IcaJoinRequestPy {
+    /// 同意这个申请
+    ///
+    /// 添加自 2.0.4
+    pub fn approve(&mut self) -> bool { self.respond(true, None) }
+
+    /// 拒绝这个申请, `reason` 只对加群申请有效
+    ///
+    /// 添加自 2.0.4
+    #[pyo3(signature = (reason = None))]
+    pub fn reject(&mut self, reason: Option<String>) -> bool {
+        self.respond(false, reason.as_deref())
+    }
+
+    #[getter]
+    /// 返回处理结果, 同意为 `True`, 拒绝为 `False`, 还没处理为 `None`
+    ///
+    /// 配置了自动审批策略时, 插件收到的申请可能已经被处理了
+    ///
+    /// 添加自 2.0.4
+    pub fn get_handled(&self) -> Option<bool> { self.handled }
+    #[getter]
+    /// 返回收到申请的实例 id
+    ///
+    /// 添加自 2.0.4
+    pub fn get_instance_id(&self) -> String { self.instance.clone() }
+    #[getter]
+    /// 返回从 `comment` 里取出的入群答案
+    ///
+    /// 添加自 2.0.4
+    pub fn get_answer(&self) -> String { self.inner.answer().to_string() }
+
     #[getter]
     /// 返回 `comment` 对应的数据。
     pub fn get_comment(&self) -> String { self.inner.comment.clone() }
@@ -622,3 +1043,92 @@ impl IcaJoinRequestPy {
     /// 返回 `flag` 对应的数据。
     pub fn get_flag(&self) -> String { self.inner.flag.clone() }
 }
+
+#[pyclass]
+#[pyo3(name = "IcaSystemEvent")]
+/// 从系统消息解析出来的事件
+///
+/// 添加自 2.0.4
+pub struct IcaSystemEventPy {
+    pub event: SystemEvent,
+    pub msg: NewMessage,
+    /// 收到事件的实例
+    pub instance: String,
+}
+
+impl IcaSystemEventPy {
+    /// 创建并初始化对应的数据结构, 实例取当前实例。
+    pub fn new(event: &SystemEvent, msg: &NewMessage) -> Self {
+        Self {
+            event: event.clone(),
+            msg: msg.clone(),
+            instance: MainStatus::global_config().ica_id(),
+        }
+    }
+}
+
+#[pymethods]
+impl IcaSystemEventPy {
+    /// 返回适合 Python 展示的字符串。
+    pub fn __str__(&self) -> String { format!("{:?}", self.event) }
+    #[getter]
+    /// 返回事件类型
+    ///
+    /// member_join / member_leave / member_kick / member_mute / member_unmute /
+    /// whole_mute / admin_change / recall / group_rename / unknown
+    pub fn get_kind(&self) -> &'static str { self.event.kind() }
+    #[getter]
+    /// 返回事件所在的房间 id。
+    pub fn get_room_id(&self) -> RoomId { self.msg.room_id }
+    #[getter]
+    /// 返回被操作的人的名称 (通知里显示的名字, 不是 QQ 号)。
+    pub fn get_target(&self) -> Option<String> { self.event.target().map(str::to_string) }
+    #[getter]
+    /// 返回操作者的名称。
+    pub fn get_operator(&self) -> Option<String> { self.event.operator().map(str::to_string) }
+    #[getter]
+    /// 返回禁言时长的原始文本, 只有 member_mute 有。
+    pub fn get_duration(&self) -> Option<String> {
+        match &self.event {
+            SystemEvent::MemberMute { duration, .. } => Some(duration.clone()),
+            _ => None,
+        }
+    }
+    #[getter]
+    /// 返回开关状态, 只有 whole_mute 和 admin_change 有。
+    pub fn get_enabled(&self) -> Option<bool> {
+        match &self.event {
+            SystemEvent::WholeMute { enabled, .. } | SystemEvent::AdminChange { enabled, .. } => {
+                Some(*enabled)
+            }
+            _ => None,
+        }
+    }
+    #[getter]
+    /// 返回新的群名, 只有 group_rename 有。
+    pub fn get_group_name(&self) -> Option<String> {
+        match &self.event {
+            SystemEvent::GroupRename { name, .. } => Some(name.clone()),
+            _ => None,
+        }
+    }
+    #[getter]
+    /// 返回是否为成员离开 (包括被踢)。
+    pub fn get_is_leave(&self) -> bool { self.event.is_leave() }
+    #[getter]
+    /// 返回通知原文。
+    pub fn get_content(&self) -> String { self.msg.content().clone() }
+    #[getter]
+    /// 返回原始的系统消息。
+    pub fn get_message(&self) -> NewMessagePy {
+        NewMessagePy {
+            msg: self.msg.clone(),
+            instance: self.instance.clone(),
+        }
+    }
+    #[getter]
+    /// 返回收到事件的实例 id
+    ///
+    /// 添加自 2.0.4
+    pub fn get_instance_id(&self) -> String { self.instance.clone() }
+}
@@ -96,11 +96,17 @@ fn rs_api_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<ica::SendMessagePy>()?;
     m.add_class::<ica::IcaRoomPy>()?;
     m.add_class::<ica::IcaGroupMemberPy>()?;
+    m.add_class::<ica::IcaSystemEventPy>()?;
     // tailchat define
     m.add_class::<tailchat::TailchatReceiveMessagePy>()?;
     m.add_class::<tailchat::TailchatSendingMessagePy>()?;
     m.add_class::<tailchat::TailchatClientPy>()?;
     m.add_class::<tailchat::TailchatStatusPy>()?;
+    m.add_class::<tailchat::TailchatInboxItemPy>()?;
+    m.add_class::<tailchat::TailchatGroupPy>()?;
+    m.add_class::<tailchat::TailchatPanelPy>()?;
+    m.add_class::<tailchat::TailchatConversePy>()?;
+    m.add_class::<tailchat::TailchatSegmentPy>()?;
 
     Ok(())
 }
@@ -1,9 +1,18 @@
 //! 暴露给 Python 插件的定时任务控制类型。
 
-use std::time::Duration;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
 
-use pyo3::{Bound, Py, PyTraverseError, PyVisit, Python, pyclass, pymethods, types::PyFunction};
-use tracing::{Level, event};
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::types::PyAnyMethods;
+use pyo3::{
+    Bound, Py, PyResult, PyTraverseError, PyVisit, Python, pyclass, pymethods, types::PyFunction,
+};
+
+use crate::py::scheduler::{self, Schedule, cron::CronSchedule};
+
+/// 给没有名字的一次性任务编号
+static ONCE_JOB_COUNTER: AtomicU64 = AtomicU64::new(0);
 
 #[derive(Debug)]
 #[pyclass]
@@ -13,6 +22,8 @@ use tracing::{Level, event};
 /// 给 Python 侧使用
 ///
 /// add: 0.9.0
+///
+/// 0.9.3: 支持固定间隔/cron 任务, 可以取消, 插件卸载时会自动取消
 pub struct SchedulerPy {
     /// 回调函数
     ///
@@ -20,6 +31,37 @@ pub struct SchedulerPy {
     pub callback: Py<PyFunction>,
     /// 预计等待时间
     pub schdule_time: Duration,
+    /// 触发方式
+    pub schedule: Schedule,
+    /// 任务名称, 为空时自动生成
+    pub name: Option<String>,
+    /// 是否持久化下次运行时间
+    pub persist: bool,
+    /// 已经启动的任务 (所属插件模块名, 任务名)
+    pub job: Option<(String, String)>,
+}
+
+impl SchedulerPy {
+    /// 创建一个指定触发方式的计划。
+    fn with_schedule(
+        func: Bound<'_, PyFunction>,
+        schedule: Schedule,
+        name: Option<String>,
+        persist: bool,
+    ) -> Self {
+        let schdule_time = match &schedule {
+            Schedule::Once(wait) | Schedule::Interval(wait) => *wait,
+            Schedule::Cron(_) => Duration::ZERO,
+        };
+        Self {
+            callback: func.unbind(),
+            schdule_time,
+            schedule,
+            name,
+            persist,
+            job: None,
+        }
+    }
 }
 
 #[pymethods]
@@ -31,33 +73,110 @@ impl SchedulerPy {
     }
 
     #[new]
-    /// 创建并初始化对应的数据结构。
-    pub fn new(func: Bound<'_, PyFunction>, schdule_time: Duration) -> Self {
-        Self {
-            callback: func.unbind(),
-            schdule_time,
+    #[pyo3(signature = (func, schdule_time, name = None))]
+    /// 创建一个一次性任务
+    pub fn new(func: Bound<'_, PyFunction>, schdule_time: Duration, name: Option<String>) -> Self {
+        Self::with_schedule(func, Schedule::Once(schdule_time), name, false)
+    }
+
+    /// 创建一个固定间隔任务
+    ///
+    /// 添加自 0.9.3
+    #[staticmethod]
+    #[pyo3(signature = (func, interval, name = None, persist = false))]
+    pub fn interval(
+        func: Bound<'_, PyFunction>,
+        interval: Duration,
+        name: Option<String>,
+        persist: bool,
+    ) -> PyResult<Self> {
+        if interval.is_zero() {
+            return Err(PyValueError::new_err("间隔不能为 0"));
         }
+        Ok(Self::with_schedule(func, Schedule::Interval(interval), name, persist))
+    }
+
+    /// 创建一个 cron 任务, 表达式为 `分 时 日 月 周`, 使用本地时间
+    ///
+    /// 添加自 0.9.3
+    #[staticmethod]
+    #[pyo3(signature = (func, expr, name = None, persist = false))]
+    pub fn cron(
+        func: Bound<'_, PyFunction>,
+        expr: &str,
+        name: Option<String>,
+        persist: bool,
+    ) -> PyResult<Self> {
+        let cron = CronSchedule::parse(expr).map_err(PyValueError::new_err)?;
+        Ok(Self::with_schedule(func, Schedule::Cron(cron), name, persist))
     }
 
     /// 开始
-    pub fn start(&self, py: Python<'_>) {
-        let wait = self.schdule_time;
-        let cb = self.callback.clone_ref(py);
-        tokio::spawn(async move {
-            let second = Duration::from_secs(1);
-            if wait > second {
-                let big_sleep = wait.checked_sub(second).unwrap();
-                tokio::time::sleep(big_sleep).await;
-                tokio::time::sleep(second).await;
-            } else {
-                tokio::time::sleep(wait).await;
-            }
-            Python::attach(|py| {
-                event!(Level::INFO, "正在调用计划 {:?}", wait);
-                if let Err(e) = cb.call0(py) {
-                    event!(Level::WARN, "调用时出现错误 {}", e);
+    ///
+    /// 同一个插件里同名的任务会替换掉之前的
+    pub fn start(&mut self, py: Python<'_>) -> PyResult<()> {
+        let callback = self.callback.bind(py);
+        let owner: String = callback
+            .getattr("__module__")
+            .and_then(|module| module.extract::<String>().map_err(Into::into))
+            .unwrap_or_else(|_| "unknown".to_string());
+        let name = match &self.name {
+            Some(name) => name.clone(),
+            None => {
+                let func_name: String = callback
+                    .getattr("__name__")
+                    .and_then(|name| name.extract::<String>().map_err(Into::into))
+                    .unwrap_or_else(|_| "job".to_string());
+                match self.schedule {
+                    // 一次性任务经常会用同一个函数注册好几次, 不能互相顶掉
+                    Schedule::Once(_) => {
+                        let id = ONCE_JOB_COUNTER.fetch_add(1, Ordering::Relaxed);
+                        format!("{func_name}#{id}")
+                    }
+                    _ => func_name,
                 }
-            });
-        });
+            }
+        };
+        scheduler::add_job(
+            &owner,
+            &name,
+            self.schedule.clone(),
+            self.callback.clone_ref(py).into_any(),
+            self.persist,
+        )
+        .map_err(PyRuntimeError::new_err)?;
+        self.job = Some((owner, name));
+        Ok(())
+    }
+
+    /// 取消任务, 返回是否成功
+    ///
+    /// 添加自 0.9.3
+    pub fn cancel(&mut self) -> bool {
+        match self.job.take() {
+            Some((owner, name)) => scheduler::cancel_job(&owner, &name),
+            None => false,
+        }
+    }
+
+    #[getter]
+    /// 返回任务名称, 未启动且没有指定名称时为 `None`。
+    pub fn get_name(&self) -> Option<String> {
+        self.job.as_ref().map(|(_, name)| name.clone()).or_else(|| self.name.clone())
+    }
+
+    #[getter]
+    /// 返回下次运行时间, 未启动或已结束时为 `None`。
+    pub fn get_next_run(&self) -> Option<SystemTime> {
+        let (owner, name) = self.job.as_ref()?;
+        scheduler::job_next_run(owner, name).map(SystemTime::from)
+    }
+
+    /// 列出所有插件的定时任务
+    ///
+    /// 添加自 0.9.3
+    #[staticmethod]
+    pub fn list_jobs() -> Vec<String> {
+        scheduler::list_jobs().iter().map(|job| job.to_string()).collect()
     }
 }
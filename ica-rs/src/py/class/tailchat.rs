@@ -1,53 +1,373 @@
 //! 暴露给 Python 插件的 Tailchat 消息和客户端类型。
 
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
 use std::time::SystemTime;
 
 use pyo3::prelude::*;
 
-use rust_socketio::asynchronous::Client;
 use tokio::runtime::Runtime;
 use tracing::{debug, info, warn};
 
-use crate::data_struct::tailchat::messages::{ReceiveMessage, SendingFile, SendingMessage};
+use crate::MainStatus;
+use crate::data_struct::Platform;
+use crate::data_struct::tailchat::bbcode::Segment;
+use crate::data_struct::tailchat::messages::{
+    FileSource, InboxItem, ReceiveMessage, ReplyMeta, SendingFile, SendingMessage,
+};
+use crate::data_struct::tailchat::status::{ConverseInfo, GroupInfo, GroupPanel};
 use crate::data_struct::tailchat::{ConverseId, GroupId, MessageId, UserId};
+use crate::error::{ClientResult, TailchatError};
+use crate::instance;
+use crate::permission;
 use crate::py::PY_PLUGIN_STORAGE;
-use crate::tailchat::client::send_message;
+use crate::py::storage::PluginScope;
+use crate::status::tailchat as tailchat_status;
+use crate::tailchat::client::{self, Connection, send_message};
+use crate::tailchat::directory;
 
 #[pyclass]
 #[pyo3(name = "TailchatClient")]
 pub struct TailchatClientPy {
-    pub client: Client,
+    pub client: Connection,
+    /// 客户端所属的实例
+    pub instance: String,
 }
 
 impl TailchatClientPy {
-    /// 创建并初始化对应的数据结构。
-    pub fn new(client: &Client) -> Self {
+    /// 创建绑定到当前实例的客户端。
+    pub fn new(client: &Connection) -> Self {
         Self {
             client: client.clone(),
+            instance: MainStatus::global_config().tailchat_id(),
+        }
+    }
+
+    /// 在客户端所属实例的作用域里执行一个消息操作, 失败时记录原因后返回 `false`。
+    fn block_on_action<F>(&self, name: &str, action: F) -> bool
+    where
+        F: Future<Output = ClientResult<(), TailchatError>>,
+    {
+        let result = tokio::task::block_in_place(|| {
+            let rt = Runtime::new().unwrap();
+            rt.block_on(instance::scope(self.instance.clone(), action))
+        });
+        match result {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("{} 失败: {}", name, e);
+                false
+            }
         }
     }
 }
 
 #[pyclass]
 #[pyo3(name = "TailchatStatus")]
-/// 预留?
-pub struct TailchatStatusPy {}
+/// 登录信息和加入的服务器、会话
+///
+/// 添加自 2.0.1
+pub struct TailchatStatusPy {
+    /// 状态所属的实例
+    pub instance: String,
+}
+
+impl TailchatStatusPy {
+    /// 创建指定实例的状态。
+    pub fn of(instance: String) -> Self { Self { instance } }
+
+    /// 返回所属实例的运行状态, 还没登录时为 `None`。
+    fn status(&self) -> Option<Arc<RwLock<tailchat_status::MainStatus>>> {
+        MainStatus::tailchat_status_of(&self.instance)
+    }
+
+    /// 从运行状态里读取一个值, 还没登录时返回默认值。
+    fn read<T: Default>(&self, f: impl FnOnce(&tailchat_status::MainStatus) -> T) -> T {
+        self.status().map(|status| f(&status.read().unwrap())).unwrap_or_default()
+    }
+}
+
+#[pymethods]
+impl TailchatStatusPy {
+    #[getter]
+    /// 返回状态所属的实例 id。
+    pub fn get_instance_id(&self) -> String { self.instance.clone() }
+    #[getter]
+    /// 返回是否已经登录。
+    pub fn get_login(&self) -> bool { self.read(|status| status.login) }
+    #[getter]
+    /// 返回 bot 自己的用户ID。
+    pub fn get_user_id(&self) -> UserId { self.read(|status| status.user_id.clone()) }
+    #[getter]
+    /// 返回 bot 的昵称。
+    pub fn get_nick_name(&self) -> String { self.read(|status| status.nick_name.clone()) }
+    #[getter]
+    /// 返回 bot 的邮箱。
+    pub fn get_email(&self) -> String { self.read(|status| status.email.clone()) }
+    #[getter]
+    /// 返回 bot 的头像地址。
+    pub fn get_avatar(&self) -> String { self.read(|status| status.avatar.clone()) }
+    #[getter]
+    /// 返回加入的所有服务器。
+    pub fn get_groups(&self) -> Vec<TailchatGroupPy> {
+        self.read(|status| status.groups.iter().cloned().map(TailchatGroupPy::from).collect())
+    }
+    #[getter]
+    /// 返回所有私信/多人会话。
+    pub fn get_converses(&self) -> Vec<TailchatConversePy> {
+        self.read(|status| status.converses.iter().cloned().map(TailchatConversePy::from).collect())
+    }
+    #[getter]
+    /// 返回 tailchat 配置里的管理员。
+    pub fn get_admins(&self) -> Vec<UserId> {
+        instance::sync_scope(self.instance.clone(), || {
+            MainStatus::global_config().tailchat().admin_list
+        })
+    }
+    /// 按ID查找服务器。
+    pub fn get_group(&self, group_id: GroupId) -> Option<TailchatGroupPy> {
+        self.read(|status| {
+            status.groups.iter().find(|group| group.id == group_id).cloned().map(Into::into)
+        })
+    }
+    /// 返回会话所属的服务器, 私信/多人会话返回 `None`。
+    pub fn group_of(&self, converse_id: ConverseId) -> Option<TailchatGroupPy> {
+        self.read(|status| status.group_of(&converse_id).cloned().map(Into::into))
+    }
+}
+
+#[derive(Clone)]
+#[pyclass(from_py_object)]
+#[pyo3(name = "TailchatGroup")]
+/// 服务器
+///
+/// 添加自 2.0.1
+pub struct TailchatGroupPy {
+    pub inner: GroupInfo,
+}
+
+impl From<GroupInfo> for TailchatGroupPy {
+    /// 将来源值转换为当前类型。
+    fn from(inner: GroupInfo) -> Self { Self { inner } }
+}
+
+#[pymethods]
+impl TailchatGroupPy {
+    #[getter]
+    /// 返回服务器ID。
+    pub fn get_group_id(&self) -> GroupId { self.inner.id.clone() }
+    #[getter]
+    /// 返回服务器名称。
+    pub fn get_name(&self) -> String { self.inner.name.clone() }
+    #[getter]
+    /// 返回服务器所有者。
+    pub fn get_owner(&self) -> UserId { self.inner.owner.clone() }
+    #[getter]
+    /// 返回所有成员的用户ID。
+    pub fn get_members(&self) -> Vec<UserId> {
+        self.inner.members.iter().map(|member| member.user_id.clone()).collect()
+    }
+    #[getter]
+    /// 返回所有面板。
+    pub fn get_panels(&self) -> Vec<TailchatPanelPy> {
+        self.inner.panels.iter().cloned().map(TailchatPanelPy::from).collect()
+    }
+    #[getter]
+    /// 返回所有文字频道。
+    pub fn get_text_panels(&self) -> Vec<TailchatPanelPy> {
+        self.inner.text_panels().cloned().map(TailchatPanelPy::from).collect()
+    }
+    /// 判断某个用户是否在这个服务器里。
+    pub fn has_member(&self, user_id: UserId) -> bool { self.inner.has_member(&user_id) }
+}
+
+#[derive(Clone)]
+#[pyclass(from_py_object)]
+#[pyo3(name = "TailchatPanel")]
+/// 服务器里的面板, 文字频道的ID就是会话ID
+///
+/// 添加自 2.0.1
+pub struct TailchatPanelPy {
+    pub inner: GroupPanel,
+}
+
+impl From<GroupPanel> for TailchatPanelPy {
+    /// 将来源值转换为当前类型。
+    fn from(inner: GroupPanel) -> Self { Self { inner } }
+}
+
+#[pymethods]
+impl TailchatPanelPy {
+    #[getter]
+    /// 返回面板ID。
+    pub fn get_panel_id(&self) -> ConverseId { self.inner.id.clone() }
+    #[getter]
+    /// 返回面板名称。
+    pub fn get_name(&self) -> String { self.inner.name.clone() }
+    #[getter]
+    /// 返回面板类型, 0 为文字频道, 1 为分组, 2 为插件面板。
+    pub fn get_panel_type(&self) -> i64 { self.inner.panel_type }
+    #[getter]
+    /// 返回所属分组的面板ID。
+    pub fn get_parent_id(&self) -> Option<String> { self.inner.parent_id.clone() }
+    /// 判断是否为文字频道。
+    pub fn is_text(&self) -> bool { self.inner.is_text() }
+}
+
+#[derive(Clone)]
+#[pyclass(from_py_object)]
+#[pyo3(name = "TailchatConverse")]
+/// 私信/多人会话
+///
+/// 添加自 2.0.1
+pub struct TailchatConversePy {
+    pub inner: ConverseInfo,
+}
+
+impl From<ConverseInfo> for TailchatConversePy {
+    /// 将来源值转换为当前类型。
+    fn from(inner: ConverseInfo) -> Self { Self { inner } }
+}
+
+#[pymethods]
+impl TailchatConversePy {
+    #[getter]
+    /// 返回会话ID。
+    pub fn get_converse_id(&self) -> ConverseId { self.inner.id.clone() }
+    #[getter]
+    /// 返回会话类型, `DM` / `Multi`。
+    pub fn get_converse_type(&self) -> String { self.inner.converse_type.clone() }
+    #[getter]
+    /// 返回所有成员的用户ID。
+    pub fn get_members(&self) -> Vec<UserId> { self.inner.members.clone() }
+    #[getter]
+    /// 返回多人会话的名称。
+    pub fn get_name(&self) -> Option<String> { self.inner.name.clone() }
+}
 
 #[pyclass]
 #[pyo3(name = "TailchatReceiveMessage")]
 pub struct TailchatReceiveMessagePy {
     pub message: ReceiveMessage,
+    /// 收到消息的实例
+    pub instance: String,
 }
 
 impl TailchatReceiveMessagePy {
-    /// 从 `recive_message` 构造当前值。
+    /// 从 `recive_message` 构造当前值, 实例取当前实例。
     pub fn from_recive_message(msg: &ReceiveMessage) -> Self {
         Self {
             message: msg.clone(),
+            instance: MainStatus::global_config().tailchat_id(),
+        }
+    }
+}
+
+#[pyclass]
+#[pyo3(name = "TailchatInboxItem")]
+/// 收件箱里的一条记录, 被 @ 时会收到
+///
+/// 添加自 2.0.1
+pub struct TailchatInboxItemPy {
+    pub item: InboxItem,
+    /// 收到记录的实例
+    pub instance: String,
+}
+
+impl TailchatInboxItemPy {
+    /// 从收件箱记录构造当前值, 实例取当前实例。
+    pub fn new(item: &InboxItem) -> Self {
+        Self {
+            item: item.clone(),
+            instance: MainStatus::global_config().tailchat_id(),
         }
     }
 }
 
+#[derive(Clone)]
+#[pyclass(from_py_object)]
+#[pyo3(name = "TailchatSegment")]
+/// 消息内容里的一段
+///
+/// `kind` 为 `text` / `mention` / `image` / `file` / `url` / `code` / `emoji` / `raw`,
+/// 不适用的字段为 `None`
+///
+/// 添加自 2.0.1
+pub struct TailchatSegmentPy {
+    pub inner: Segment,
+}
+
+impl From<Segment> for TailchatSegmentPy {
+    /// 将来源值转换为当前类型。
+    fn from(inner: Segment) -> Self { Self { inner } }
+}
+
+#[pymethods]
+impl TailchatSegmentPy {
+    #[getter]
+    /// 返回这一段的类型。
+    pub fn get_kind(&self) -> &'static str {
+        match self.inner {
+            Segment::Text(_) => "text",
+            Segment::Mention { .. } => "mention",
+            Segment::Image { .. } => "image",
+            Segment::FileCard { .. } => "file",
+            Segment::Url { .. } => "url",
+            Segment::Code(_) => "code",
+            Segment::Emoji(_) => "emoji",
+            Segment::Raw { .. } => "raw",
+        }
+    }
+    #[getter]
+    /// 返回文本内容: 文本、代码、表情名、@ 的昵称、链接文字、文件名或者未知标签里的内容。
+    pub fn get_text(&self) -> Option<String> {
+        match &self.inner {
+            Segment::Text(text) | Segment::Code(text) | Segment::Emoji(text) => Some(text.clone()),
+            Segment::Mention { nickname, .. } => Some(nickname.clone()),
+            Segment::Url { label, .. } => label.clone(),
+            Segment::FileCard { name, .. } => Some(name.clone()),
+            Segment::Raw { body, .. } => Some(body.clone()),
+            Segment::Image { .. } => None,
+        }
+    }
+    #[getter]
+    /// 返回 @ 的用户ID。
+    pub fn get_user_id(&self) -> Option<UserId> {
+        match &self.inner {
+            Segment::Mention { user_id, .. } => Some(user_id.clone()),
+            _ => None,
+        }
+    }
+    #[getter]
+    /// 返回图片、文件或链接的地址。
+    pub fn get_url(&self) -> Option<String> {
+        match &self.inner {
+            Segment::Image { url, .. }
+            | Segment::FileCard { url, .. }
+            | Segment::Url { url, .. } => Some(url.clone()),
+            _ => None,
+        }
+    }
+    #[getter]
+    /// 返回图片宽度。
+    pub fn get_width(&self) -> Option<u32> {
+        match self.inner {
+            Segment::Image { width, .. } => width,
+            _ => None,
+        }
+    }
+    #[getter]
+    /// 返回图片高度。
+    pub fn get_height(&self) -> Option<u32> {
+        match self.inner {
+            Segment::Image { height, .. } => height,
+            _ => None,
+        }
+    }
+    /// 返回这一段的 BBCode。
+    pub fn __str__(&self) -> String { self.inner.to_string() }
+}
+
 #[derive(Clone)]
 #[pyclass(from_py_object)]
 #[pyo3(name = "TailchatSendingMessage")]
@@ -57,12 +377,9 @@ pub struct TailchatSendingMessagePy {
 
 #[pymethods]
 impl TailchatClientPy {
-    /// 发送 `message` 请求或消息。
+    /// 发送 `message` 请求或消息, 附件会先上传。
     pub fn send_message(&self, message: TailchatSendingMessagePy) -> bool {
-        tokio::task::block_in_place(|| {
-            let rt = tokio::runtime::Runtime::new().unwrap();
-            rt.block_on(send_message(&self.client, &message.message))
-        })
+        self.block_on_action("发送消息", send_message(&self.client, &message.message))
     }
 
     /// 发送 `and_warn` 请求或消息。
@@ -70,6 +387,65 @@ impl TailchatClientPy {
         warn!("{}", message.message.content);
         self.send_message(message)
     }
+
+    /// 撤回一条消息, 只能撤回自己的或者有权限管理的消息
+    ///
+    /// 添加自 2.0.1
+    pub fn recall_message(&self, msg_id: MessageId) -> bool {
+        self.block_on_action("撤回消息", client::recall_message(&self.client, &msg_id))
+    }
+
+    /// 删除一条消息, 需要服务器的管理权限
+    ///
+    /// 添加自 2.0.1
+    pub fn delete_message(&self, msg_id: MessageId) -> bool {
+        self.block_on_action("删除消息", client::delete_message(&self.client, &msg_id))
+    }
+
+    /// 编辑一条自己发的消息
+    ///
+    /// 添加自 2.0.1
+    pub fn edit_message(&self, msg_id: MessageId, content: String) -> bool {
+        self.block_on_action("编辑消息", client::edit_message(&self.client, &msg_id, &content))
+    }
+
+    /// 打开和某个用户的私信会话, 返回会话ID, 失败时返回 `None`
+    ///
+    /// 添加自 2.0.1
+    pub fn create_dm(&self, user_id: UserId) -> Option<ConverseId> {
+        let result = tokio::task::block_in_place(|| {
+            let rt = Runtime::new().unwrap();
+            rt.block_on(instance::scope(
+                self.instance.clone(),
+                client::create_dm(&self.client, &user_id),
+            ))
+        });
+        result.inspect_err(|e| warn!("打开和 {} 的私信失败: {}", user_id, e)).ok()
+    }
+
+    /// 私信某个用户, 需要时会先打开私信会话
+    ///
+    /// 添加自 2.0.1
+    pub fn send_private_message(&self, user_id: UserId, content: String) -> bool {
+        let sending = async {
+            client::send_private_message(&self.client, &user_id, &content).await.map(|_| ())
+        };
+        self.block_on_action("发送私信", sending)
+    }
+
+    /// 给一条消息添加表情回应, `emoji` 为 `:thumbsup:` 这样的短代码
+    ///
+    /// 添加自 2.0.1
+    pub fn add_reaction(&self, msg_id: MessageId, emoji: String) -> bool {
+        self.block_on_action("添加回应", client::add_reaction(&self.client, &msg_id, &emoji))
+    }
+
+    /// 移除自己给一条消息添加的表情回应
+    ///
+    /// 添加自 2.0.1
+    pub fn remove_reaction(&self, msg_id: MessageId, emoji: String) -> bool {
+        self.block_on_action("移除回应", client::remove_reaction(&self.client, &msg_id, &emoji))
+    }
     #[getter]
     /// 返回 `version` 对应的数据。
     pub fn get_version(&self) -> String { crate::VERSION.to_string() }
@@ -80,6 +456,52 @@ impl TailchatClientPy {
     /// 返回 `client_id` 对应的数据。
     pub fn get_client_id(&self) -> String { crate::client_id() }
     #[getter]
+    /// 返回客户端所属的实例 id
+    ///
+    /// 添加自 2.0.1
+    pub fn get_instance_id(&self) -> String { self.instance.clone() }
+    #[getter]
+    /// 返回客户端所属实例的状态
+    ///
+    /// 添加自 2.0.1
+    pub fn get_status(&self) -> TailchatStatusPy { TailchatStatusPy::of(self.instance.clone()) }
+    /// 重新获取加入的服务器和私信会话
+    ///
+    /// 添加自 2.0.1
+    pub fn refresh_directory(&self) -> bool {
+        self.block_on_action("刷新服务器和会话", directory::refresh(&self.client))
+    }
+    /// 获取会话的历史消息, 每次最多 50 条, 按时间从新到旧
+    ///
+    /// `before` 为上一页最旧的消息ID, 不填时从最新的开始; 获取失败时返回空列表
+    ///
+    /// 添加自 2.0.1
+    #[pyo3(signature = (converse_id, before = None))]
+    pub fn fetch_history(
+        &self,
+        converse_id: ConverseId,
+        before: Option<MessageId>,
+    ) -> Vec<TailchatReceiveMessagePy> {
+        let result = tokio::task::block_in_place(|| {
+            let rt = Runtime::new().unwrap();
+            let fetching = directory::fetch_history(&self.client, &converse_id, before.as_ref());
+            rt.block_on(instance::scope(self.instance.clone(), fetching))
+        });
+        match result {
+            Ok(messages) => messages
+                .into_iter()
+                .map(|message| TailchatReceiveMessagePy {
+                    message,
+                    instance: self.instance.clone(),
+                })
+                .collect(),
+            Err(e) => {
+                warn!("获取 {} 的历史消息失败: {}", converse_id, e);
+                Vec::new()
+            }
+        }
+    }
+    #[getter]
     /// 返回 `tailchat_version` 对应的数据。
     pub fn get_tailchat_version(&self) -> String { crate::TAILCHAT_VERSION.to_string() }
     #[getter]
@@ -105,7 +527,7 @@ impl TailchatClientPy {
     /// 同步状态到配置文件
     /// 这样关闭的时候就会保存状态
     pub fn sync_status_to_file(&self) {
-        let storage = PY_PLUGIN_STORAGE.blocking_lock();
+        let mut storage = PY_PLUGIN_STORAGE.blocking_lock();
         storage.sync_status_to_file();
     }
 
@@ -121,16 +543,78 @@ impl TailchatClientPy {
         storage.get_status(&plugin_name)
     }
 
+    /// 设置某个插件在指定房间内的状态
+    ///
+    /// `status` 为 `None` 时移除房间设置, 恢复继承
+    ///
+    /// 添加自 2.0.1
+    #[pyo3(signature = (plugin_name, room_id, status = None))]
+    pub fn set_plugin_room_status(
+        &self,
+        plugin_name: String,
+        room_id: ConverseId,
+        status: Option<bool>,
+    ) -> bool {
+        let mut storage = PY_PLUGIN_STORAGE.blocking_lock();
+        let scope = PluginScope::room(Platform::Tailchat, room_id);
+        storage.get_status(&plugin_name).is_some()
+            && storage.set_scoped_status(&plugin_name, &scope, status).is_ok()
+    }
+
+    /// 返回某个插件在指定房间内实际生效的状态
+    ///
+    /// 添加自 2.0.1
+    pub fn get_plugin_room_status(&self, plugin_name: String, room_id: ConverseId) -> Option<bool> {
+        let storage = PY_PLUGIN_STORAGE.blocking_lock();
+        storage.get_scoped_status(&plugin_name, &PluginScope::room(Platform::Tailchat, room_id))
+    }
+
+    /// 设置某个插件在当前平台上的状态
+    ///
+    /// `status` 为 `None` 时移除平台设置, 恢复继承
+    ///
+    /// 添加自 2.0.1
+    #[pyo3(signature = (plugin_name, status = None))]
+    pub fn set_plugin_platform_status(&self, plugin_name: String, status: Option<bool>) -> bool {
+        let mut storage = PY_PLUGIN_STORAGE.blocking_lock();
+        let scope = PluginScope::Platform(Platform::Tailchat);
+        storage.get_status(&plugin_name).is_some()
+            && storage.set_scoped_status(&plugin_name, &scope, status).is_ok()
+    }
+
     /// 重新加载插件
     ///
     /// 返回是否成功
     pub fn reload_plugin(&self, plugin_name: String) -> bool {
         let mut storage = PY_PLUGIN_STORAGE.blocking_lock();
-        storage
-            .storage
-            .get_mut(&plugin_name)
-            .map(|p| p.reload_self(None).is_ok())
-            .unwrap_or(false)
+        storage.reload_plugin(&plugin_name, None).is_ok()
+    }
+
+    /// 检查用户是否拥有某个权限节点
+    ///
+    /// 权限节点用 `.` 分级, 比如 `my_plugin.admin`, 未配置的节点所有未封禁用户都有权限
+    ///
+    /// 添加自 2.0.1
+    #[pyo3(signature = (user_id, node, room_id = None))]
+    pub fn has_permission(
+        &self,
+        user_id: UserId,
+        node: String,
+        room_id: Option<ConverseId>,
+    ) -> bool {
+        let room = room_id;
+        permission::has_permission(Platform::Tailchat, &user_id, &node, room.as_deref(), None)
+    }
+
+    /// 返回用户的角色名称 (owner/admin/moderator/user/banned)
+    ///
+    /// 添加自 2.0.1
+    #[pyo3(signature = (user_id, room_id = None))]
+    pub fn get_role(&self, user_id: UserId, room_id: Option<ConverseId>) -> String {
+        let room = room_id;
+        permission::role_of(Platform::Tailchat, &user_id, room.as_deref(), None)
+            .as_str()
+            .to_string()
     }
 
     #[pyo3(signature = (content, converse_id, group_id = None))]
@@ -166,7 +650,77 @@ impl TailchatReceiveMessagePy {
     pub fn get_is_reply(&self) -> bool { self.message.is_reply() }
     #[getter]
     /// 返回 `is_from_self` 对应的数据。
-    pub fn get_is_from_self(&self) -> bool { self.message.is_from_self() }
+    pub fn get_is_from_self(&self) -> bool {
+        instance::sync_scope(self.instance.clone(), || self.message.is_from_self())
+    }
+    #[getter]
+    /// 返回收到消息的实例 id
+    ///
+    /// 添加自 2.0.1
+    pub fn get_instance_id(&self) -> String { self.instance.clone() }
+    #[getter]
+    /// 返回 bot 自己是否被 @ 了
+    ///
+    /// 添加自 2.0.1
+    pub fn get_is_mentioned(&self) -> bool {
+        instance::sync_scope(self.instance.clone(), || self.message.is_mentioned())
+    }
+    #[getter]
+    /// 返回被 @ 的人的ID
+    ///
+    /// 添加自 2.0.1
+    pub fn get_mentions(&self) -> Vec<UserId> { self.message.mentions().to_vec() }
+    #[getter]
+    /// 返回被回复的消息ID, 不是回复时为 `None`
+    ///
+    /// 添加自 2.0.1
+    pub fn get_reply_id(&self) -> Option<MessageId> {
+        self.message.reply().map(|reply| reply.msg_id.clone())
+    }
+    #[getter]
+    /// 返回被回复的消息的发送者ID, 不是回复时为 `None`
+    ///
+    /// 添加自 2.0.1
+    pub fn get_reply_author(&self) -> Option<UserId> {
+        self.message.reply().map(|reply| reply.author.clone())
+    }
+    /// 判断某个用户是否被 @ 了
+    ///
+    /// 添加自 2.0.1
+    pub fn is_mentioned(&self, user_id: UserId) -> bool { self.message.mentions_user(&user_id) }
+    #[getter]
+    /// 返回解析后的消息内容
+    ///
+    /// 添加自 2.0.1
+    pub fn get_segments(&self) -> Vec<TailchatSegmentPy> {
+        self.message
+            .rich_content()
+            .segments
+            .into_iter()
+            .map(TailchatSegmentPy::from)
+            .collect()
+    }
+    #[getter]
+    /// 返回去掉 BBCode 标签后的纯文本
+    ///
+    /// 添加自 2.0.1
+    pub fn get_plain_text(&self) -> String { self.message.rich_content().plain_text() }
+    #[getter]
+    /// 返回是否为私信/多人会话里的消息 (没有服务器ID)
+    ///
+    /// 添加自 2.0.1
+    pub fn get_is_dm(&self) -> bool { self.message.is_dm() }
+    #[getter]
+    /// 返回是否为服务器里的消息
+    ///
+    /// 添加自 2.0.1
+    pub fn get_is_group(&self) -> bool { self.message.is_group() }
+    /// 私信回复这条消息的发送者, 返回是否成功
+    ///
+    /// 添加自 2.0.1
+    pub fn reply_privately(&self, client: PyRef<TailchatClientPy>, content: String) -> bool {
+        client.send_private_message(self.message.sender_id.clone(), content)
+    }
     #[getter]
     /// 返回 `msg_id` 对应的数据。
     pub fn get_msg_id(&self) -> MessageId { self.message.msg_id.clone() }
@@ -196,6 +750,80 @@ impl TailchatReceiveMessagePy {
     }
 }
 
+#[pymethods]
+impl TailchatInboxItemPy {
+    #[getter]
+    /// 返回记录ID。
+    pub fn get_inbox_id(&self) -> String { self.item.inbox_id.clone() }
+    #[getter]
+    /// 返回记录类型, 被 @ 时为 `message`。
+    pub fn get_kind(&self) -> String { self.item.kind.clone() }
+    #[getter]
+    /// 返回是否已读。
+    pub fn get_readed(&self) -> bool { self.item.readed }
+    #[getter]
+    /// 返回收到记录的实例 id。
+    pub fn get_instance_id(&self) -> String { self.instance.clone() }
+    #[getter]
+    /// 返回消息ID, 不是消息记录时为 `None`。
+    pub fn get_msg_id(&self) -> Option<MessageId> {
+        self.item.message.as_ref().map(|msg| msg.msg_id.clone())
+    }
+    #[getter]
+    /// 返回发送者ID, 不是消息记录时为 `None`。
+    pub fn get_sender_id(&self) -> Option<UserId> {
+        self.item.message.as_ref().map(|msg| msg.author.clone())
+    }
+    #[getter]
+    /// 返回服务器ID, 私聊或不是消息记录时为 `None`。
+    pub fn get_group_id(&self) -> Option<GroupId> {
+        self.item.message.as_ref().and_then(|msg| msg.group_id.clone())
+    }
+    #[getter]
+    /// 返回会话ID, 不是消息记录时为 `None`。
+    pub fn get_converse_id(&self) -> Option<ConverseId> {
+        self.item.message.as_ref().map(|msg| msg.converse_id.clone())
+    }
+    #[getter]
+    /// 返回消息内容, 不是消息记录时为 `None`。
+    pub fn get_content(&self) -> Option<String> {
+        self.item.message.as_ref().map(|msg| msg.content().to_string())
+    }
+    /// 构造回复这条消息的新消息, 不是消息记录时为 `None`。
+    pub fn reply_with(&self, content: String) -> Option<TailchatSendingMessagePy> {
+        let msg = self.item.message.as_ref()?;
+        let meta = ReplyMeta {
+            mentions: vec![msg.author.clone()],
+            reply_id: msg.msg_id.clone(),
+            reply_author: msg.author.clone(),
+            reply_content: msg.content().to_string(),
+        };
+        Some(TailchatSendingMessagePy {
+            message: SendingMessage::new(
+                content,
+                msg.converse_id.clone(),
+                msg.group_id.clone(),
+                Some(meta),
+            ),
+        })
+    }
+}
+
+/// 不指定附件名时使用路径里的文件名。
+fn path_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+impl TailchatSendingMessagePy {
+    /// 在内容后面追加一段 BBCode, 返回更新后的值。
+    fn append(&mut self, segment: Segment) -> Self {
+        self.message.content.push_str(&segment.to_string());
+        self.clone()
+    }
+}
+
 #[pymethods]
 impl TailchatSendingMessagePy {
     #[getter]
@@ -228,12 +856,90 @@ impl TailchatSendingMessagePy {
         self.message.meta = None;
         self.clone()
     }
-    /// 更新 `img` 对应的数据。
+    /// 追加文本
+    ///
+    /// 添加自 2.0.1
+    pub fn add_text(&mut self, text: String) -> Self { self.append(Segment::Text(text)) }
+    /// 追加 `[at=<user_id>]<nickname>[/at]`
+    ///
+    /// 添加自 2.0.1
+    pub fn add_mention(&mut self, user_id: UserId, nickname: String) -> Self {
+        self.append(Segment::Mention { user_id, nickname })
+    }
+    /// 追加已经上传好的图片
+    ///
+    /// 添加自 2.0.1
+    #[pyo3(signature = (url, width = None, height = None))]
+    pub fn add_image_url(&mut self, url: String, width: Option<u32>, height: Option<u32>) -> Self {
+        self.append(Segment::Image { url, width, height })
+    }
+    /// 追加链接, `label` 为 `None` 时直接显示地址
+    ///
+    /// 添加自 2.0.1
+    #[pyo3(signature = (url, label = None))]
+    pub fn add_url(&mut self, url: String, label: Option<String>) -> Self {
+        self.append(Segment::Url { url, label })
+    }
+    /// 追加代码块
+    ///
+    /// 添加自 2.0.1
+    pub fn add_code(&mut self, code: String) -> Self { self.append(Segment::Code(code)) }
+    /// 追加表情
+    ///
+    /// 添加自 2.0.1
+    pub fn add_emoji(&mut self, name: String) -> Self { self.append(Segment::Emoji(name)) }
+    /// 更新 `img` 对应的数据, 会替换掉已有的附件。
     pub fn set_img(&mut self, file: Vec<u8>, file_name: String) {
-        let file = SendingFile::Image {
-            file,
+        self.message.set_file(SendingFile::Image {
+            file: FileSource::Bytes(file),
             name: file_name,
-        };
-        self.message.add_img(file);
+        });
     }
+    /// 追加一张图片, 可以多次调用追加多张
+    ///
+    /// 添加自 2.0.1
+    pub fn add_img(&mut self, file: Vec<u8>, file_name: String) -> Self {
+        self.message.add_file(SendingFile::Image {
+            file: FileSource::Bytes(file),
+            name: file_name,
+        });
+        self.clone()
+    }
+    /// 追加一张本地图片, 发送时才读取
+    ///
+    /// 添加自 2.0.1
+    pub fn add_image_path(&mut self, path: PathBuf, file_name: Option<String>) -> Self {
+        let name = file_name.unwrap_or_else(|| path_name(&path));
+        self.message.add_file(SendingFile::Image {
+            file: FileSource::Path(path),
+            name,
+        });
+        self.clone()
+    }
+    /// 追加一个文件附件
+    ///
+    /// 添加自 2.0.1
+    pub fn add_file(&mut self, file: Vec<u8>, file_name: String) -> Self {
+        self.message.add_file(SendingFile::File {
+            file: FileSource::Bytes(file),
+            name: file_name,
+        });
+        self.clone()
+    }
+    /// 追加一个本地文件附件, 发送时才读取
+    ///
+    /// 添加自 2.0.1
+    pub fn add_file_path(&mut self, path: PathBuf, file_name: Option<String>) -> Self {
+        let name = file_name.unwrap_or_else(|| path_name(&path));
+        self.message.add_file(SendingFile::File {
+            file: FileSource::Path(path),
+            name,
+        });
+        self.clone()
+    }
+    #[getter]
+    /// 附件数量
+    ///
+    /// 添加自 2.0.1
+    pub fn get_file_count(&self) -> usize { self.message.files.len() }
 }
@@ -6,10 +6,32 @@ pub mod ica_func {
     ///
     /// added: ica 2.0.1
     pub const JOIN_REQUEST: &str = "on_ica_join_request";
-    /// icalingua 的 退群通知
+    /// icalingua 的 退群通知 (包括被踢)
     ///
     /// added: ica 2.0.1
+    ///
+    /// 2.0.4 起才会真正被调用
     pub const LEAVE_MESSAGE: &str = "on_ica_leave_message";
+    /// icalingua 的 入群通知
+    ///
+    /// added: ica 2.0.4
+    pub const JOIN_MESSAGE: &str = "on_ica_join_message";
+    /// icalingua 的 禁言通知 (包括解除禁言和全员禁言)
+    ///
+    /// added: ica 2.0.4
+    pub const MUTE_MESSAGE: &str = "on_ica_mute_message";
+    /// icalingua 的 结构化系统事件, 所有系统消息都会调用
+    ///
+    /// added: ica 2.0.4
+    pub const SYSTEM_EVENT: &str = "on_ica_system_event";
+    /// QQ 上线 (包括掉线后恢复)
+    ///
+    /// added: ica 2.0.4
+    pub const ONLINE: &str = "on_ica_online";
+    /// QQ 离线
+    ///
+    /// added: ica 2.0.4
+    pub const OFFLINE: &str = "on_ica_offline";
     /// icalingua 的 新消息
     pub const NEW_MESSAGE: &str = "on_ica_message";
     /// icalingua 的 消息撤回
@@ -24,6 +46,14 @@ pub mod ica_func {
 pub mod tailchat_func {
     /// 新消息
     pub const NEW_MESSAGE: &str = "on_tailchat_message";
+    /// bot 被 @ 的新消息, 会在 `on_tailchat_message` 之后调用
+    ///
+    /// added: tailchat 2.0.1
+    pub const MENTION: &str = "on_tailchat_mention";
+    /// 收件箱新记录 (`notify:chat.inbox.append`)
+    ///
+    /// added: tailchat 2.0.1
+    pub const INBOX: &str = "on_tailchat_inbox";
 }
 
 /// 系统事件
@@ -40,6 +70,10 @@ pub mod sys_func {
     ///
     /// added: bot 0.9.0
     pub const ON_RELOAD: &str = "on_reload";
+    /// 主配置重载后的事件, 参数为变化了的配置项
+    ///
+    /// added: bot 0.9.3
+    pub const ON_CONFIG_RELOAD: &str = "on_bot_config_reload";
     /// Mainfest
     ///
     /// added: bot 0.9.0
@@ -4,14 +4,15 @@ use tracing::{Level, event};
 
 /// 初始化 `py_vm`。
 pub fn init_py_vm() {
-    let cli_args = std::env::args().collect::<Vec<String>>();
-
-    if cli_args.contains(&"-env".to_string()) {
-        let env_path = cli_args.iter().find(|&arg| arg != "-env").expect("未找到 -env 参数的值");
-        event!(Level::INFO, "找到 -env 参数: {} 正在初始化", env_path);
+    if let Some(env_path) = &crate::cli::args().venv {
+        event!(Level::INFO, "找到 --venv 参数: {} 正在初始化", env_path);
         // 判断一下是否有 VIRTUAL_ENV 环境变量
         if let Ok(virtual_env) = std::env::var("VIRTUAL_ENV") {
-            event!(Level::WARN, "找到 VIRTUAL_ENV 环境变量: {} 将会被 -env 参数覆盖", virtual_env);
+            event!(
+                Level::WARN,
+                "找到 VIRTUAL_ENV 环境变量: {} 将会被 --venv 参数覆盖",
+                virtual_env
+            );
         }
         init_py_with_env_path(env_path);
     } else {
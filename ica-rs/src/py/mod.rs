@@ -10,10 +10,13 @@ pub mod consts;
 pub mod init;
 /// 加载 `plugin` 子模块。
 pub mod plugin;
+/// 加载 `scheduler` 子模块。
+pub mod scheduler;
 /// 加载 `storage` 子模块。
 pub mod storage;
 
 use std::sync::LazyLock;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use colored::Colorize;
 use pyo3::{PyErr, Python, types::PyTracebackMethods};
@@ -28,6 +31,12 @@ use storage::PyPluginStorage;
 pub static PY_PLUGIN_STORAGE: LazyLock<Mutex<PyPluginStorage>> =
     LazyLock::new(|| Mutex::new(PyPluginStorage::new()));
 
+/// 是否已经初始化过 python
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// 返回是否已经初始化过 python, 启动时没有启用的话重载配置也不会初始化。
+pub fn is_initialized() -> bool { INITIALIZED.load(Ordering::Relaxed) }
+
 /// Python 侧初始化
 pub async fn init_py() {
     // 从 全局配置中获取 python 插件路径
@@ -42,10 +51,14 @@ pub async fn init_py() {
     // 内部初始化
     init::init_py_vm();
 
+    // 插件的 on_load 里可能会注册定时任务
+    scheduler::init_scheduler();
+
     let mut storage = PY_PLUGIN_STORAGE.lock().await;
     storage.load_plugins();
 
     event!(Level::DEBUG, "python 插件列表: {}", storage.display_plugins(true));
+    INITIALIZED.store(true, Ordering::Relaxed);
 
     event!(Level::INFO, "python 初始化完成")
 }
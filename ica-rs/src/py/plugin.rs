@@ -12,7 +12,7 @@ use pyo3::{
 };
 use tracing::{Level, event};
 
-use crate::py::{class::manifest::PluginManifestPy, consts::sys_func};
+use crate::py::{class::manifest::PluginManifestPy, consts::sys_func, scheduler};
 use crate::{MainStatus, error::PyPluginInitError};
 
 #[derive(Debug)]
@@ -87,6 +87,14 @@ impl PyPlugin {
     /// 返回插件文件路径。
     pub fn plugin_path(&self) -> PathBuf { self.plugin_path.clone() }
 
+    /// 返回加载插件时使用的模块名 (即文件名), 定时任务按这个归属。
+    pub fn module_name(&self) -> String {
+        self.plugin_path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
     /// 返回插件源码哈希。
     pub fn plugin_hash(&self) -> blake3::Hash { self.hash_result }
 
@@ -194,6 +202,8 @@ impl PyPlugin {
         if !self.active {
             return Ok(());
         }
+        // 不管 on_unload 成功与否, 插件的定时任务都不该继续跑了
+        scheduler::cancel_owner(&self.module_name());
         self.call_on_unload_func()?;
         self.active = false;
         Ok(())
@@ -206,7 +216,11 @@ impl PyPlugin {
         })
     }
 
-    /// 重新加载当前插件。
+    /// 重新加载当前插件
+    ///
+    /// 重新加载后不会自动启用, 由 [`PyPluginStorage::reload_plugin`] 按各范围的设置决定
+    ///
+    /// [`PyPluginStorage::reload_plugin`]: crate::py::storage::PyPluginStorage::reload_plugin
     pub fn reload_self(&mut self, reload_config: Option<bool>) -> Result<(), PyPluginInitError> {
         if self.manifest.need_config_file() && reload_config.unwrap_or(false) {
             event!(
@@ -241,9 +255,6 @@ impl PyPlugin {
         self.manifest = manifest;
         self.hash_result = hash_result;
         self.init_self()?;
-        if self.enabled {
-            self.activate()?;
-        }
         Ok(())
     }
 
@@ -0,0 +1,435 @@
+//! Python 插件的定时任务: 一次性、固定间隔和 cron 任务, 按插件归属取消, 可选持久化下次运行时间。
+
+/// 加载 `cron` 子模块。
+pub mod cron;
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::sync::{Arc, LazyLock, Mutex, OnceLock};
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use pyo3::{Py, PyAny, Python};
+use serde::{Deserialize, Serialize};
+use tokio::runtime::Handle;
+use tokio::task::AbortHandle;
+use tracing::{Level, event};
+
+use crate::MainStatus;
+use cron::CronSchedule;
+
+pub const JOBS_FILE_NAME: &str = "jobs.toml";
+
+/// 任务的触发方式
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    /// 等待一段时间后执行一次
+    Once(Duration),
+    /// 每隔一段时间执行一次
+    Interval(Duration),
+    /// 按 cron 表达式执行
+    Cron(CronSchedule),
+}
+
+impl Schedule {
+    /// 返回首次运行时间。
+    pub fn first_run(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        match self {
+            Schedule::Once(wait) | Schedule::Interval(wait) => {
+                Some(now + chrono::Duration::from_std(*wait).ok()?)
+            }
+            Schedule::Cron(cron) => cron.next_after(now),
+        }
+    }
+
+    /// 返回执行完一次之后的下次运行时间
+    ///
+    /// 固定间隔任务按计划时间累加, 落后太多时从当前时间重新计算
+    pub fn next_run(
+        &self,
+        planned: DateTime<Local>,
+        now: DateTime<Local>,
+    ) -> Option<DateTime<Local>> {
+        match self {
+            Schedule::Once(_) => None,
+            Schedule::Interval(wait) => {
+                let wait = chrono::Duration::from_std(*wait).ok()?;
+                let next = planned + wait;
+                Some(if next <= now { now + wait } else { next })
+            }
+            Schedule::Cron(cron) => cron.next_after(now),
+        }
+    }
+}
+
+impl Display for Schedule {
+    /// 将当前值写入格式化输出。
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Schedule::Once(wait) => write!(f, "一次性({wait:?})"),
+            Schedule::Interval(wait) => write!(f, "每 {wait:?}"),
+            Schedule::Cron(cron) => write!(f, "cron `{cron}`"),
+        }
+    }
+}
+
+/// 任务的只读信息
+#[derive(Debug, Clone)]
+pub struct JobInfo {
+    /// 所属插件的模块名
+    pub owner: String,
+    /// 任务名称
+    pub name: String,
+    /// 触发方式
+    pub schedule: String,
+    /// 下次运行时间
+    pub next_run: Option<DateTime<Local>>,
+    /// 已运行次数
+    pub run_count: u64,
+    /// 是否持久化
+    pub persist: bool,
+}
+
+impl Display for JobInfo {
+    /// 将当前值写入格式化输出。
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let next_run = match self.next_run {
+            Some(time) => time.format("%Y-%m-%d %H:%M:%S").to_string(),
+            None => "无".to_string(),
+        };
+        write!(
+            f,
+            "{}/{} {} 下次: {} 已运行: {}{}",
+            self.owner,
+            self.name,
+            self.schedule,
+            next_run,
+            self.run_count,
+            if self.persist { " (持久化)" } else { "" }
+        )
+    }
+}
+
+/// 注册表里的一个任务
+struct JobEntry {
+    schedule: Schedule,
+    next_run: Option<DateTime<Local>>,
+    run_count: u64,
+    persist: bool,
+    /// 每次注册都会变化, 用于让被替换掉的旧任务自行退出
+    generation: u64,
+    abort: Option<AbortHandle>,
+}
+
+/// 持久化到 `jobs.toml` 的数据
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct PersistedJobs {
+    /// `插件模块名/任务名` -> 下次运行时间 (RFC 3339)
+    #[serde(default)]
+    next_run: BTreeMap<String, String>,
+}
+
+/// 全局任务注册表
+#[derive(Default)]
+struct JobRegistry {
+    /// (插件模块名, 任务名) -> 任务
+    jobs: BTreeMap<(String, String), JobEntry>,
+    persisted: PersistedJobs,
+    /// 持久化数据每改一次加一, 防止旧快照覆盖新快照
+    persisted_version: u64,
+    generation: u64,
+}
+
+impl JobRegistry {
+    /// 返回持久化用的键。
+    fn persist_key(owner: &str, name: &str) -> String { format!("{owner}/{name}") }
+
+    /// 返回持久化的下次运行时间。
+    fn persisted_next_run(&self, owner: &str, name: &str) -> Option<DateTime<Local>> {
+        let text = self.persisted.next_run.get(&Self::persist_key(owner, name))?;
+        DateTime::parse_from_rfc3339(text).ok().map(|time| time.with_timezone(&Local))
+    }
+
+    /// 更新持久化的下次运行时间, `None` 时删除
+    ///
+    /// 返回要写入文件的快照, 需要在释放 `JOBS` 之后交给 [`save_persisted`]
+    #[must_use]
+    fn set_persisted(
+        &mut self,
+        owner: &str,
+        name: &str,
+        next_run: Option<DateTime<Local>>,
+    ) -> (u64, PersistedJobs) {
+        let key = Self::persist_key(owner, name);
+        match next_run {
+            Some(time) => {
+                self.persisted.next_run.insert(key, time.to_rfc3339());
+            }
+            None => {
+                self.persisted.next_run.remove(&key);
+            }
+        }
+        self.persisted_version += 1;
+        (self.persisted_version, self.persisted.clone())
+    }
+}
+
+static JOBS: LazyLock<Mutex<JobRegistry>> = LazyLock::new(|| Mutex::new(JobRegistry::default()));
+
+/// 已经写入文件的快照版本
+static SAVED_VERSION: Mutex<u64> = Mutex::new(0);
+
+/// 主运行时的句柄, 任务都跑在这上面
+///
+/// Python 调用 Rust 的时候可能处于临时创建的运行时里, 不能直接 `tokio::spawn`
+static RUNTIME: OnceLock<Handle> = OnceLock::new();
+
+/// 返回任务持久化文件路径。
+fn jobs_file_path() -> PathBuf {
+    let mut path = PathBuf::from(MainStatus::global_config().py().config_path);
+    path.push(JOBS_FILE_NAME);
+    path
+}
+
+/// 保存持久化数据, 比已经写入的版本旧的快照会被跳过。
+fn save_persisted((version, persisted): (u64, PersistedJobs)) {
+    let mut saved = SAVED_VERSION.lock().unwrap();
+    if version <= *saved {
+        return;
+    }
+    *saved = version;
+    let path = jobs_file_path();
+    match toml::to_string_pretty(&persisted) {
+        Ok(data) => {
+            if let Err(e) = std::fs::write(&path, data) {
+                event!(Level::WARN, "写入任务文件 {:?} 失败: {}", path, e);
+            }
+        }
+        Err(e) => event!(Level::WARN, "序列化任务数据失败: {}", e),
+    }
+}
+
+/// 初始化定时任务系统
+///
+/// 需要在主运行时里, 并且在加载插件之前调用
+pub fn init_scheduler() {
+    if let Ok(handle) = Handle::try_current() {
+        let _ = RUNTIME.set(handle);
+    }
+    let path = jobs_file_path();
+    let persisted = match std::fs::read_to_string(&path) {
+        Ok(content) => toml::from_str(&content).unwrap_or_else(|e| {
+            event!(Level::WARN, "解析任务文件 {:?} 失败, 将忽略: {}", path, e);
+            PersistedJobs::default()
+        }),
+        Err(_) => PersistedJobs::default(),
+    };
+    JOBS.lock().unwrap().persisted = persisted;
+}
+
+/// 注册一个任务, 同一个插件下同名的任务会被替换
+///
+/// 返回首次运行时间
+pub fn add_job(
+    owner: &str,
+    name: &str,
+    schedule: Schedule,
+    callback: Py<PyAny>,
+    persist: bool,
+) -> Result<DateTime<Local>, String> {
+    let handle = RUNTIME
+        .get()
+        .cloned()
+        .or_else(|| Handle::try_current().ok())
+        .ok_or_else(|| "定时任务系统未初始化".to_string())?;
+    let now = Local::now();
+    let mut registry = JOBS.lock().unwrap();
+    let first_run = match registry.persisted_next_run(owner, name) {
+        // 错过的任务立刻补跑一次
+        Some(time) if persist && !matches!(schedule, Schedule::Once(_)) => time.max(now),
+        _ => schedule.first_run(now).ok_or_else(|| format!("任务 {name} 不会再触发"))?,
+    };
+
+    registry.generation += 1;
+    let generation = registry.generation;
+    let key = (owner.to_string(), name.to_string());
+    if let Some(old) = registry.jobs.remove(&key)
+        && let Some(abort) = old.abort
+    {
+        abort.abort();
+    }
+    let task = handle.spawn(run_job(
+        key.clone(),
+        generation,
+        schedule.clone(),
+        Arc::new(callback),
+        first_run,
+    ));
+    registry.jobs.insert(
+        key,
+        JobEntry {
+            schedule,
+            next_run: Some(first_run),
+            run_count: 0,
+            persist,
+            generation,
+            abort: Some(task.abort_handle()),
+        },
+    );
+    let snapshot = persist.then(|| registry.set_persisted(owner, name, Some(first_run)));
+    drop(registry);
+    if let Some(snapshot) = snapshot {
+        save_persisted(snapshot);
+    }
+    event!(Level::DEBUG, "已注册任务 {}/{}, 首次运行: {}", owner, name, first_run);
+    Ok(first_run)
+}
+
+/// 任务的运行循环。
+async fn run_job(
+    key: (String, String),
+    generation: u64,
+    schedule: Schedule,
+    callback: Arc<Py<PyAny>>,
+    mut planned: DateTime<Local>,
+) {
+    loop {
+        let wait = (planned - Local::now()).to_std().unwrap_or(Duration::ZERO);
+        tokio::time::sleep(wait).await;
+
+        let cb = callback.clone();
+        let job_name = format!("{}/{}", key.0, key.1);
+        let result = tokio::task::spawn_blocking(move || {
+            Python::attach(|py| {
+                event!(Level::INFO, "正在运行任务 {}", job_name);
+                cb.call0(py)
+                    .map(|_| ())
+                    .map_err(|e| crate::py::get_py_err_traceback(&e, Some(py)))
+            })
+        })
+        .await;
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => event!(Level::WARN, "任务 {}/{} 运行出错 {}", key.0, key.1, e),
+            Err(e) => event!(Level::WARN, "任务 {}/{} 运行失败 {}", key.0, key.1, e),
+        }
+
+        let next = schedule.next_run(planned, Local::now());
+        let snapshot = {
+            let mut registry = JOBS.lock().unwrap();
+            let persist = match registry.jobs.get_mut(&key) {
+                Some(entry) if entry.generation == generation => {
+                    entry.run_count += 1;
+                    entry.next_run = next;
+                    entry.persist
+                }
+                // 已经被取消或替换了
+                _ => return,
+            };
+            let snapshot = persist.then(|| registry.set_persisted(&key.0, &key.1, next));
+            if next.is_none() {
+                registry.jobs.remove(&key);
+            }
+            snapshot
+        };
+        if let Some(snapshot) = snapshot {
+            save_persisted(snapshot);
+        }
+        match next {
+            Some(next) => planned = next,
+            None => return,
+        }
+    }
+}
+
+/// 取消一个任务, 同时删除它的持久化数据
+pub fn cancel_job(owner: &str, name: &str) -> bool {
+    let mut registry = JOBS.lock().unwrap();
+    let Some(entry) = registry.jobs.remove(&(owner.to_string(), name.to_string())) else {
+        return false;
+    };
+    if let Some(abort) = entry.abort {
+        abort.abort();
+    }
+    let snapshot = entry.persist.then(|| registry.set_persisted(owner, name, None));
+    drop(registry);
+    if let Some(snapshot) = snapshot {
+        save_persisted(snapshot);
+    }
+    true
+}
+
+/// 停止某个插件的所有任务
+///
+/// 用于插件卸载/重载, 会保留持久化数据, 以便插件重新注册时接着运行
+pub fn cancel_owner(owner: &str) -> usize {
+    let mut registry = JOBS.lock().unwrap();
+    let keys: Vec<_> = registry.jobs.keys().filter(|(o, _)| o == owner).cloned().collect();
+    for key in keys.iter() {
+        if let Some(entry) = registry.jobs.remove(key)
+            && let Some(abort) = entry.abort
+        {
+            abort.abort();
+        }
+    }
+    if !keys.is_empty() {
+        event!(Level::INFO, "已停止插件 {} 的 {} 个任务", owner, keys.len());
+    }
+    keys.len()
+}
+
+/// 返回某个任务的下次运行时间。
+pub fn job_next_run(owner: &str, name: &str) -> Option<DateTime<Local>> {
+    let registry = JOBS.lock().unwrap();
+    registry
+        .jobs
+        .get(&(owner.to_string(), name.to_string()))
+        .and_then(|entry| entry.next_run)
+}
+
+/// 返回所有任务的信息。
+pub fn list_jobs() -> Vec<JobInfo> {
+    let registry = JOBS.lock().unwrap();
+    registry
+        .jobs
+        .iter()
+        .map(|((owner, name), entry)| JobInfo {
+            owner: owner.clone(),
+            name: name.clone(),
+            schedule: entry.schedule.to_string(),
+            next_run: entry.next_run,
+            run_count: entry.run_count,
+            persist: entry.persist,
+        })
+        .collect()
+}
+
+/// 返回用于 `/bot-jobs` 的任务列表文本。
+pub fn display_jobs() -> String {
+    let jobs = list_jobs();
+    if jobs.is_empty() {
+        return "当前没有定时任务".to_string();
+    }
+    let lines: Vec<String> = jobs.iter().map(|job| job.to_string()).collect();
+    format!("共 {} 个定时任务:\n{}", jobs.len(), lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn interval_catch_up() {
+        let schedule = Schedule::Interval(Duration::from_secs(60));
+        let planned = Local.with_ymd_and_hms(2024, 2, 18, 10, 0, 0).unwrap();
+        let now = planned + chrono::Duration::seconds(5);
+        assert_eq!(schedule.next_run(planned, now), Some(planned + chrono::Duration::minutes(1)));
+
+        let late = planned + chrono::Duration::minutes(10);
+        assert_eq!(schedule.next_run(planned, late), Some(late + chrono::Duration::minutes(1)));
+
+        assert_eq!(Schedule::Once(Duration::from_secs(1)).next_run(planned, now), None);
+    }
+}
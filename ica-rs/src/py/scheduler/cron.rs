@@ -0,0 +1,224 @@
+//! 5 段 cron 表达式 (分 时 日 月 周) 的解析和下次触发时间计算。
+
+use std::fmt::Display;
+
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike};
+
+/// 查找下次触发时间时最多跳跃的次数, 防止不可能的表达式 (比如 2 月 31 日) 死循环
+const MAX_SEARCH_STEPS: usize = 100_000;
+
+/// 解析好的 cron 表达式
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// 日 字段是否以 `*` 开头
+    any_day: bool,
+    /// 周 字段是否以 `*` 开头
+    any_weekday: bool,
+    source: String,
+}
+
+/// 解析 cron 的一个字段, 返回由允许值组成的位图。
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut bits = 0_u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step.parse().map_err(|_| format!("无效的步长: {part}"))?;
+                if step == 0 {
+                    return Err(format!("步长不能为 0: {part}"));
+                }
+                (range, step)
+            }
+            None => (part, 1),
+        };
+        let parse_num = |text: &str| -> Result<u32, String> {
+            let value: u32 = text.parse().map_err(|_| format!("无效的数字: {text}"))?;
+            if value < min || value > max {
+                return Err(format!("{value} 超出范围 {min}-{max}"));
+            }
+            Ok(value)
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((start, end)) => (parse_num(start)?, parse_num(end)?),
+                // `5/10` 表示从 5 开始每 10 个
+                None if part.contains('/') => (parse_num(range)?, max),
+                None => {
+                    let value = parse_num(range)?;
+                    (value, value)
+                }
+            },
+        };
+        if start > end {
+            return Err(format!("范围起点大于终点: {part}"));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+impl CronSchedule {
+    /// 解析 `分 时 日 月 周` 格式的 cron 表达式
+    ///
+    /// 支持 `*`、`a-b`、`*/n`、`a-b/n`、`a/n` 和逗号分隔的列表, 周日可以写成 0 或 7
+    pub fn parse(expr: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!("cron 表达式需要 5 段, 实际为 {} 段", fields.len()));
+        };
+        let mut weekdays = parse_field(weekday, 0, 7)?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays & !(1 << 7)) | 1;
+        }
+        Ok(Self {
+            minutes: parse_field(minute, 0, 59)?,
+            hours: parse_field(hour, 0, 23)?,
+            days: parse_field(day, 1, 31)?,
+            months: parse_field(month, 1, 12)?,
+            weekdays,
+            any_day: day.starts_with('*'),
+            any_weekday: weekday.starts_with('*'),
+            source: expr.split_whitespace().collect::<Vec<_>>().join(" "),
+        })
+    }
+
+    /// 判断某一天是否满足 日/周 字段
+    ///
+    /// 和标准 cron 一样, 两个字段都不以 `*` 开头时满足其一即可, 否则两个都要满足
+    fn day_matches(&self, date: NaiveDate) -> bool {
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+        if self.any_day || self.any_weekday {
+            day && weekday
+        } else {
+            day || weekday
+        }
+    }
+
+    /// 返回严格晚于 `after` 的下一个触发时间 (本地时间, 不含时区)。
+    pub fn next_after_naive(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let mut time = after.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        for _ in 0..MAX_SEARCH_STEPS {
+            let date = time.date();
+            if self.months & (1 << date.month()) == 0 {
+                let (year, month) = match date.month() {
+                    12 => (date.year() + 1, 1),
+                    month => (date.year(), month + 1),
+                };
+                time = NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+                continue;
+            }
+            if !self.day_matches(date) {
+                time = date.succ_opt()?.and_hms_opt(0, 0, 0)?;
+                continue;
+            }
+            if self.hours & (1 << time.hour()) == 0 {
+                time = date.and_hms_opt(time.hour(), 0, 0)? + Duration::hours(1);
+                continue;
+            }
+            if self.minutes & (1 << time.minute()) == 0 {
+                time += Duration::minutes(1);
+                continue;
+            }
+            return Some(time);
+        }
+        None
+    }
+
+    /// 返回严格晚于 `after` 的下一个触发时间
+    ///
+    /// 落在夏令时空档里的时间会被跳过
+    pub fn next_after(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        let mut naive = after.naive_local();
+        for _ in 0..16 {
+            naive = self.next_after_naive(naive)?;
+            if let Some(time) = Local.from_local_datetime(&naive).earliest() {
+                return Some(time);
+            }
+        }
+        None
+    }
+}
+
+impl Display for CronSchedule {
+    /// 将当前值写入格式化输出。
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(text: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn parse_and_reject() {
+        assert!(CronSchedule::parse("*/15 9-18 * * 1-5").is_ok());
+        assert!(CronSchedule::parse("0 0 1,15 * 7").is_ok());
+        assert!(CronSchedule::parse("61 * * * *").is_err());
+        assert!(CronSchedule::parse("* * *").is_err());
+        assert!(CronSchedule::parse("*/0 * * * *").is_err());
+        assert!(CronSchedule::parse("5-1 * * * *").is_err());
+    }
+
+    #[test]
+    fn next_run_time() {
+        let every_15 = CronSchedule::parse("*/15 * * * *").unwrap();
+        assert_eq!(
+            every_15.next_after_naive(time("2024-02-18 10:07")),
+            Some(time("2024-02-18 10:15"))
+        );
+        assert_eq!(
+            every_15.next_after_naive(time("2024-02-18 10:45")),
+            Some(time("2024-02-18 11:00"))
+        );
+
+        // 2024-02-16 是周五
+        let workday = CronSchedule::parse("0 9 * * 1-5").unwrap();
+        assert_eq!(
+            workday.next_after_naive(time("2024-02-16 10:00")),
+            Some(time("2024-02-19 09:00"))
+        );
+
+        let sunday = CronSchedule::parse("30 8 * * 7").unwrap();
+        assert_eq!(
+            sunday.next_after_naive(time("2024-02-16 10:00")),
+            Some(time("2024-02-18 08:30"))
+        );
+
+        let new_year = CronSchedule::parse("0 0 1 1 *").unwrap();
+        assert_eq!(
+            new_year.next_after_naive(time("2024-02-16 10:00")),
+            Some(time("2025-01-01 00:00"))
+        );
+
+        // 日和周同时限制时满足其一即可
+        let either = CronSchedule::parse("0 0 13 * 5").unwrap();
+        assert_eq!(
+            either.next_after_naive(time("2024-02-14 00:00")),
+            Some(time("2024-02-16 00:00"))
+        );
+
+        // 日字段以 `*` 开头时两个都要满足, 2024-02-26 和 03-04 是偶数日的周一
+        let odd_monday = CronSchedule::parse("0 0 */2 * 1").unwrap();
+        assert_eq!(
+            odd_monday.next_after_naive(time("2024-02-20 00:00")),
+            Some(time("2024-03-11 00:00"))
+        );
+
+        let impossible = CronSchedule::parse("0 0 31 2 *").unwrap();
+        assert_eq!(impossible.next_after_naive(time("2024-02-16 10:00")), None);
+    }
+}
@@ -1,20 +1,191 @@
 //! Python 插件发现、加载、状态管理和持久化存储。
 
-use std::{collections::HashMap, path::PathBuf};
+use std::{collections::HashMap, fmt::Display, path::PathBuf};
 
 use colored::Colorize;
 use serde::{Deserialize, Serialize};
 use tracing::{Level, event, span};
 
+use crate::data_struct::Platform;
 use crate::{MainStatus, error::PyPluginInitError, py::plugin::PyPlugin};
 
 pub const CONFIG_FILE_NAME: &str = "plugins.toml";
 pub const DEFAULT_CONFIG: &str = r#"# 这个文件是由 shenbot 自动生成的, 请 **谨慎** 修改
 # 请不要修改这个文件, 除非你知道你在做什么"#;
 
+/// 插件启用状态的作用范围
+///
+/// 生效顺序: 房间 > 平台 > 全局
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PluginScope {
+    /// 全局默认状态
+    Global,
+    /// 某个平台上的所有房间
+    Platform(Platform),
+    /// 某个平台上的某个房间/会话
+    Room(Platform, String),
+}
+
+impl PluginScope {
+    /// 创建一个房间作用范围。
+    pub fn room(platform: Platform, room: impl ToString) -> Self {
+        Self::Room(platform, room.to_string())
+    }
+
+    /// 解析管理命令里插件名之后的作用范围参数
+    ///
+    /// - 无参数 / `global`: 全局
+    /// - `platform`: 当前平台
+    /// - `room`: 当前房间
+    /// - `room <room-id>`: 当前平台的指定房间
+    pub fn from_command_args(
+        args: &[&str],
+        platform: Platform,
+        current_room: &str,
+    ) -> Option<Self> {
+        match args {
+            [] | ["global"] => Some(Self::Global),
+            ["platform"] => Some(Self::Platform(platform)),
+            ["room"] => Some(Self::room(platform, current_room)),
+            ["room", room] => Some(Self::room(platform, room)),
+            _ => None,
+        }
+    }
+}
+
+impl Display for PluginScope {
+    /// 将当前值写入格式化输出。
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Global => write!(f, "全局"),
+            Self::Platform(platform) => write!(f, "平台 {platform}"),
+            Self::Room(platform, room) => write!(f, "房间 {platform}:{room}"),
+        }
+    }
+}
+
+/// 插件在平台/房间上的单独启禁设置
+///
+/// 没有设置的范围会继承上一级的状态
+#[derive(Debug, Clone, Default)]
+pub struct PluginOverrides {
+    /// 平台 -> 插件 -> 是否启用
+    pub platform: HashMap<String, HashMap<String, bool>>,
+    /// 平台 -> 房间 -> 插件 -> 是否启用
+    pub room: HashMap<String, HashMap<String, HashMap<String, bool>>>,
+}
+
+impl PluginOverrides {
+    /// 返回某个范围上显式设置的状态, 不包含继承来的状态
+    pub fn get(&self, plugin_id: &str, scope: &PluginScope) -> Option<bool> {
+        match scope {
+            PluginScope::Global => None,
+            PluginScope::Platform(platform) => {
+                self.platform.get(platform.as_str())?.get(plugin_id).copied()
+            }
+            PluginScope::Room(platform, room) => {
+                self.room.get(platform.as_str())?.get(room)?.get(plugin_id).copied()
+            }
+        }
+    }
+
+    /// 设置某个范围上的状态
+    ///
+    /// `status` 为 `None` 时清除设置, 恢复继承
+    pub fn set(&mut self, plugin_id: &str, scope: &PluginScope, status: Option<bool>) {
+        match scope {
+            PluginScope::Global => {}
+            PluginScope::Platform(platform) => {
+                let key = platform.as_str().to_string();
+                match status {
+                    Some(status) => {
+                        self.platform.entry(key).or_default().insert(plugin_id.to_string(), status);
+                    }
+                    None => {
+                        if let Some(plugins) = self.platform.get_mut(&key) {
+                            plugins.remove(plugin_id);
+                            if plugins.is_empty() {
+                                self.platform.remove(&key);
+                            }
+                        }
+                    }
+                }
+            }
+            PluginScope::Room(platform, room) => {
+                let key = platform.as_str().to_string();
+                match status {
+                    Some(status) => {
+                        self.room
+                            .entry(key)
+                            .or_default()
+                            .entry(room.clone())
+                            .or_default()
+                            .insert(plugin_id.to_string(), status);
+                    }
+                    None => {
+                        if let Some(rooms) = self.room.get_mut(&key) {
+                            if let Some(plugins) = rooms.get_mut(room) {
+                                plugins.remove(plugin_id);
+                                if plugins.is_empty() {
+                                    rooms.remove(room);
+                                }
+                            }
+                            if rooms.is_empty() {
+                                self.room.remove(&key);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// 计算插件在某个范围内实际生效的状态
+    pub fn resolve(&self, plugin_id: &str, global: bool, scope: &PluginScope) -> bool {
+        match scope {
+            PluginScope::Global => global,
+            PluginScope::Platform(_) => self.get(plugin_id, scope).unwrap_or(global),
+            PluginScope::Room(platform, _) => self
+                .get(plugin_id, scope)
+                .or_else(|| self.get(plugin_id, &PluginScope::Platform(*platform)))
+                .unwrap_or(global),
+        }
+    }
+
+    /// 插件是否在任意平台/房间上被单独启用
+    pub fn any_enabled(&self, plugin_id: &str) -> bool {
+        self.platform.values().any(|plugins| plugins.get(plugin_id) == Some(&true))
+            || self
+                .room
+                .values()
+                .flat_map(|rooms| rooms.values())
+                .any(|plugins| plugins.get(plugin_id) == Some(&true))
+    }
+
+    /// 插件的单独设置数量
+    pub fn count(&self, plugin_id: &str) -> usize {
+        let platform =
+            self.platform.values().filter(|plugins| plugins.contains_key(plugin_id)).count();
+        let room = self
+            .room
+            .values()
+            .flat_map(|rooms| rooms.values())
+            .filter(|plugins| plugins.contains_key(plugin_id))
+            .count();
+        platform + room
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PluginStatus {
+    /// 全局默认状态
     pub plugins: HashMap<String, bool>,
+    /// 平台 -> 插件 -> 是否启用
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub platform: HashMap<String, HashMap<String, bool>>,
+    /// 平台 -> 房间 -> 插件 -> 是否启用
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub room: HashMap<String, HashMap<String, HashMap<String, bool>>>,
 }
 
 impl PluginStatus {
@@ -28,16 +199,23 @@ impl PluginStatus {
     }
 
     /// 将 storage 的状态同步到 配置文件
+    ///
+    /// 上次同步之后 bot 没有改过的插件以文件里的为准, 免得覆盖掉 `plugins enable/disable` 的修改
     pub fn sync_from_storage(&mut self, storage: &PyPluginStorage) {
         // event!(Level::INFO, "同步插件状态");
 
         storage.storage.iter().for_each(|(name, plugin)| {
+            let state = plugin.is_enable();
             if let Some(v) = self.plugins.get_mut(name) {
-                *v = plugin.is_enable()
+                if storage.synced.get(name) != Some(&state) {
+                    *v = state
+                }
             } else {
-                self.plugins.insert(name.to_string(), plugin.is_enable());
+                self.plugins.insert(name.to_string(), state);
             }
         });
+        self.platform = storage.overrides.platform.clone();
+        self.room = storage.overrides.room.clone();
     }
 
     /// 把当前配置同步到 TOML 存储。
@@ -72,6 +250,11 @@ impl PluginStatus {
                 self.plugins.insert(name.to_string(), old_state);
             }
         });
+        storage.overrides = PluginOverrides {
+            platform: self.platform.clone(),
+            room: self.room.clone(),
+        };
+        storage.synced = self.plugins.clone();
     }
 
     /// 保存 `to_file` 数据。
@@ -90,6 +273,8 @@ impl PluginStatus {
         if !cfg_path.is_file() {
             return Self {
                 plugins: HashMap::new(),
+                platform: HashMap::new(),
+                room: HashMap::new(),
             };
         }
         let content = std::fs::read_to_string(cfg_path).expect("Failed to read config.");
@@ -100,6 +285,10 @@ impl PluginStatus {
 #[derive(Debug)]
 pub struct PyPluginStorage {
     pub storage: HashMap<String, PyPlugin>,
+    /// 平台/房间级别的启禁设置
+    pub overrides: PluginOverrides,
+    /// 上次和状态文件同步时各插件的全局状态
+    synced: HashMap<String, bool>,
 }
 
 impl PyPluginStorage {
@@ -107,6 +296,8 @@ impl PyPluginStorage {
     pub fn new() -> Self {
         Self {
             storage: HashMap::new(),
+            overrides: PluginOverrides::default(),
+            synced: HashMap::new(),
         }
     }
 
@@ -172,13 +363,26 @@ impl PyPluginStorage {
         self.apply_lifecycle();
         status.sync_from_storage(self);
         status.save_to_file();
+        self.mark_synced();
     }
 
-    /// 将插件启用状态写入状态文件。
-    pub fn sync_status_to_file(&self) {
+    /// 将插件启用状态写入状态文件
+    ///
+    /// 运行期间在外部修改过的全局状态会被保留
+    pub fn sync_status_to_file(&mut self) {
         let mut status = PluginStatus::load_from_file();
         status.sync_from_storage(self);
         status.save_to_file();
+        self.mark_synced();
+    }
+
+    /// 记下当前的全局状态, 之后保存时只写回和它不同的。
+    fn mark_synced(&mut self) {
+        self.synced = self
+            .storage
+            .iter()
+            .map(|(id, plugin)| (id.clone(), plugin.is_enable()))
+            .collect();
     }
 
     /// 向插件存储加入插件。
@@ -188,9 +392,12 @@ impl PyPluginStorage {
     }
 
     /// 执行指定插件生命周期钩子。
+    ///
+    /// 只要插件在任意范围内启用, 就需要保持加载状态
     fn apply_lifecycle(&mut self) {
-        for plugin in self.storage.values_mut() {
-            if plugin.is_enable() {
+        let overrides = &self.overrides;
+        for (id, plugin) in self.storage.iter_mut() {
+            if plugin.is_enable() || overrides.any_enabled(id) {
                 if let Err(e) = plugin.activate() {
                     event!(Level::WARN, "插件 {} 启动失败: {e}", plugin.id_and_name());
                     plugin.set_enable(false);
@@ -239,7 +446,10 @@ impl PyPluginStorage {
         let total_count = self.storage.len();
 
         let format_display_plugin = |plugin: &PyPlugin| {
-            let name = plugin.id_and_name();
+            let name = match self.overrides.count(plugin.id()) {
+                0 => plugin.id_and_name(),
+                count => format!("{} (+{count} 条平台/房间设置)", plugin.id_and_name()),
+            };
             if plugin.is_enable() {
                 if color {
                     name.green().to_string()
@@ -274,7 +484,8 @@ impl PyPluginStorage {
                 hasher.finalize()
             };
             if new_hash != plugin.plugin_hash() {
-                plugin.reload_self(Some(true))?;
+                let plugin_id = plugin.id().to_string();
+                self.reload_plugin(&plugin_id, Some(true))?;
                 return Ok(true);
             }
             return Ok(false);
@@ -282,6 +493,18 @@ impl PyPluginStorage {
         Ok(false)
     }
 
+    /// 重新加载插件, 之后按全局和平台/房间的设置决定是否启用。
+    pub fn reload_plugin(
+        &mut self,
+        plugin_id: &str,
+        reload_config: Option<bool>,
+    ) -> Result<(), PyPluginInitError> {
+        let plugin = self.storage.get_mut(plugin_id).ok_or(PyPluginInitError::PluginNotFound)?;
+        plugin.reload_self(reload_config)?;
+        self.apply_lifecycle();
+        Ok(())
+    }
+
     /// 返回 `plugin_by_path` 对应的数据。
     pub fn get_plugin_by_path(&self, path: &PathBuf) -> Option<&PyPlugin> {
         self.storage.iter().find(|(_, p)| &p.plugin_path() == path).map(|p| p.1)
@@ -307,17 +530,157 @@ impl PyPluginStorage {
                     return Err(e);
                 }
             } else {
-                plugin.deactivate()?;
+                // 在别的范围内还启用着的话就不能卸载
+                if !self.overrides.any_enabled(plugin_id) {
+                    plugin.deactivate()?;
+                }
                 plugin.set_enable(false);
             }
         }
         Ok(())
     }
 
+    /// 返回插件在某个范围内实际生效的状态。
+    pub fn get_scoped_status(&self, plugin_id: &str, scope: &PluginScope) -> Option<bool> {
+        self.storage
+            .get(plugin_id)
+            .map(|p| self.overrides.resolve(plugin_id, p.is_enable(), scope))
+    }
+
+    /// 设置插件在某个范围内的状态
+    ///
+    /// `status` 为 `None` 时清除该范围的设置, 恢复继承
+    pub fn set_scoped_status(
+        &mut self,
+        plugin_id: &str,
+        scope: &PluginScope,
+        status: Option<bool>,
+    ) -> Result<(), PyPluginInitError> {
+        if !self.storage.contains_key(plugin_id) {
+            return Ok(());
+        }
+        if let PluginScope::Global = scope {
+            return match status {
+                Some(status) => self.set_status(plugin_id, status),
+                None => Ok(()),
+            };
+        }
+        self.overrides.set(plugin_id, scope, status);
+        let need_active = self.overrides.any_enabled(plugin_id);
+        if let Some(plugin) = self.storage.get_mut(plugin_id) {
+            if need_active || plugin.is_enable() {
+                plugin.activate()?;
+            } else {
+                plugin.deactivate()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 处理 `/bot-enable` `/bot-disable` `/bot-inherit` 命令
+    ///
+    /// `args` 为命令后面的部分: `<plugin> [global|platform|room [room-id]]`
+    ///
+    /// 返回需要回复的内容
+    pub fn handle_status_command(
+        &mut self,
+        args: &str,
+        status: Option<bool>,
+        platform: Platform,
+        current_room: &str,
+    ) -> String {
+        let mut args = args.split_whitespace();
+        let Some(name) = args.next() else {
+            return "请指定插件".to_string();
+        };
+        let scope_args = args.collect::<Vec<&str>>();
+        let Some(scope) = PluginScope::from_command_args(&scope_args, platform, current_room)
+        else {
+            return "无法解析作用范围, 可用: global / platform / room [room-id]".to_string();
+        };
+        let Some(global) = self.get_status(name) else {
+            return "未找到插件".to_string();
+        };
+        let action = match status {
+            Some(true) => "启用",
+            Some(false) => "禁用",
+            None => "恢复继承",
+        };
+        match (&scope, status) {
+            (PluginScope::Global, None) => {
+                return "全局状态没有可以继承的, 请使用 enable/disable".to_string();
+            }
+            (PluginScope::Global, Some(status)) if status == global => {
+                return format!("无变化, 插件已经{action}");
+            }
+            (PluginScope::Global, _) => {}
+            (scope, status) if self.overrides.get(name, scope) == status => {
+                return format!("无变化, 插件在{scope}已经是{action}状态");
+            }
+            _ => {}
+        }
+        match self.set_scoped_status(name, &scope, status) {
+            Ok(_) => match scope {
+                PluginScope::Global => format!("{action}插件完成"),
+                scope => format!("已在{scope}{action}插件"),
+            },
+            Err(e) => format!("{action}插件失败, 错误: \n{e}"),
+        }
+    }
+
     /// 返回 `enabled_plugins` 对应的数据。
     pub fn get_enabled_plugins(&self) -> HashMap<&String, &PyPlugin> {
         self.storage.iter().filter(|(_, p)| p.is_enable()).collect()
     }
+
+    /// 返回在某个范围内生效并且已经加载的插件。
+    pub fn get_enabled_plugins_in(&self, scope: &PluginScope) -> HashMap<&String, &PyPlugin> {
+        self.storage
+            .iter()
+            .filter(|(id, p)| p.is_active() && self.overrides.resolve(id, p.is_enable(), scope))
+            .collect()
+    }
     /// 返回 `all_plugins` 对应的数据。
     pub fn get_all_plugins(&self) -> HashMap<&String, &PyPlugin> { self.storage.iter().collect() }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// 验证房间设置优先于平台设置, 平台设置优先于全局状态。
+    fn resolve_scoped_status() {
+        let mut overrides = PluginOverrides::default();
+        let platform = PluginScope::Platform(Platform::Ica);
+        let room = PluginScope::room(Platform::Ica, -123);
+        let other_room = PluginScope::room(Platform::Ica, -456);
+        let tailchat = PluginScope::room(Platform::Tailchat, "converse");
+
+        assert!(overrides.resolve("demo", true, &room));
+        overrides.set("demo", &platform, Some(false));
+        assert!(!overrides.resolve("demo", true, &room));
+        assert!(overrides.resolve("demo", true, &tailchat));
+        overrides.set("demo", &room, Some(true));
+        assert!(overrides.resolve("demo", true, &room));
+        assert!(!overrides.resolve("demo", true, &other_room));
+        assert!(overrides.any_enabled("demo"));
+        assert_eq!(overrides.count("demo"), 2);
+
+        overrides.set("demo", &room, None);
+        overrides.set("demo", &platform, None);
+        assert!(overrides.room.is_empty());
+        assert!(overrides.platform.is_empty());
+    }
+
+    #[test]
+    /// 验证命令作用范围参数的解析。
+    fn parse_command_scope() {
+        let parse = |args: &[&str]| PluginScope::from_command_args(args, Platform::Ica, "-1");
+        assert_eq!(parse(&[]), Some(PluginScope::Global));
+        assert_eq!(parse(&["platform"]), Some(PluginScope::Platform(Platform::Ica)));
+        assert_eq!(parse(&["room"]), Some(PluginScope::room(Platform::Ica, "-1")));
+        assert_eq!(parse(&["room", "-2"]), Some(PluginScope::room(Platform::Ica, "-2")));
+        assert_eq!(parse(&["somewhere"]), None);
+    }
+}
@@ -0,0 +1,377 @@
+//! 主配置的热重载。
+//!
+//! 收到 SIGHUP、配置文件被修改 (`[reload] watch`) 或者管理员发送 `/bot-config-reload-<client-id>` 时
+//! 重新读取配置文件, 检查通过后和当前配置比较:
+//! 管理员/过滤列表、提醒房间等直接生效, 权限和过滤数据重新读取,
+//! 新增/停用的实例会被启动/停止, 连接参数变化的实例会被重连, 最后调用插件的 `on_bot_config_reload`。
+//! Python 插件配置和发送限速需要重启进程才会生效。
+
+use std::path::Path;
+use std::sync::{LazyLock, OnceLock};
+use std::time::{Duration, SystemTime};
+
+use colored::Colorize;
+use tokio::sync::Mutex;
+use tracing::{Level, event};
+
+use crate::config::check;
+use crate::config::{BotConfig, IcaConfig, TailchatConfig};
+use crate::data_struct::Platform;
+use crate::{MainStatus, backend, moderation, permission, py};
+
+/// 当前使用的配置文件路径
+static CONFIG_PATH: OnceLock<String> = OnceLock::new();
+
+/// 同一时间只进行一次重载
+static RELOADING: LazyLock<Mutex<()>> = LazyLock::new(|| Mutex::new(()));
+
+/// 记下配置文件路径, 重载时从这里读取。
+pub fn set_config_path(path: String) { CONFIG_PATH.get_or_init(|| path); }
+
+/// 新旧配置的差异, 以及需要做的操作
+#[derive(Debug, Default, PartialEq)]
+pub struct ConfigDiff {
+    /// 变化了的配置项, 如 `ica.default.admin_list`
+    pub changed: Vec<String>,
+    /// 新增的实例
+    pub start: Vec<(Platform, String)>,
+    /// 被删除或者停用的实例
+    pub stop: Vec<(Platform, String)>,
+    /// 连接参数变化, 需要重连的实例
+    pub reconnect: Vec<(Platform, String)>,
+    /// 需要重启进程才会生效的配置项
+    pub restart_required: Vec<String>,
+}
+
+/// 把实例列表格式化为 `平台/id, ...`。
+fn format_instances(instances: &[(Platform, String)]) -> String {
+    instances
+        .iter()
+        .map(|(platform, id)| format!("{}/{}", platform.as_str(), id))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl ConfigDiff {
+    /// 配置是否没有变化。
+    pub fn is_empty(&self) -> bool { self.changed.is_empty() }
+
+    /// 返回给管理员看的说明。
+    pub fn summary(&self) -> String {
+        if self.is_empty() {
+            return "配置没有变化, 已重新读取权限和过滤数据".to_string();
+        }
+        let mut lines = vec![format!("配置已重载, 变化: {}", self.changed.join(", "))];
+        if !self.start.is_empty() {
+            lines.push(format!("启动实例: {}", format_instances(&self.start)));
+        }
+        if !self.stop.is_empty() {
+            lines.push(format!("停止实例: {}", format_instances(&self.stop)));
+        }
+        if !self.reconnect.is_empty() {
+            lines.push(format!("重连实例: {}", format_instances(&self.reconnect)));
+        }
+        if !self.restart_required.is_empty() {
+            lines.push(format!("需要重启才会生效: {}", self.restart_required.join(", ")));
+        }
+        lines.join("\n")
+    }
+
+    /// 比较一个配置项, 变化时记下 `key` 并返回 `true`。
+    fn field<T: PartialEq>(&mut self, key: String, old: &T, new: &T) -> bool {
+        let changed = old != new;
+        if changed {
+            self.changed.push(key);
+        }
+        changed
+    }
+}
+
+/// 比较某个平台的实例列表, 记下增删的实例, 返回两边都有的实例。
+fn match_instances<'a, T>(
+    diff: &mut ConfigDiff,
+    platform: Platform,
+    old: Vec<&'a T>,
+    new: Vec<&'a T>,
+    id_of: fn(&T) -> &str,
+) -> Vec<(&'a T, &'a T)> {
+    let mut pairs = Vec::new();
+    for old_config in &old {
+        let id = id_of(old_config);
+        match new.iter().find(|new_config| id_of(new_config) == id) {
+            Some(new_config) => pairs.push((*old_config, *new_config)),
+            None => {
+                diff.changed.push(format!("{}.{}", platform.as_str(), id));
+                diff.stop.push((platform, id.to_string()));
+            }
+        }
+    }
+    for new_config in &new {
+        let id = id_of(new_config);
+        if !old.iter().any(|old_config| id_of(old_config) == id) {
+            diff.changed.push(format!("{}.{}", platform.as_str(), id));
+            diff.start.push((platform, id.to_string()));
+        }
+    }
+    pairs
+}
+
+/// 比较一个 Icalingua 实例的配置。
+fn diff_ica(diff: &mut ConfigDiff, old: &IcaConfig, new: &IcaConfig) {
+    let key = |field: &str| format!("ica.{}.{field}", new.id);
+    // 这些只在连接时使用
+    let mut reconnect = diff.field(key("host"), &old.host, &new.host);
+    reconnect |= diff.field(key("private_key"), &old.private_key, &new.private_key);
+    reconnect |= diff.field(key("self_id"), &old.self_id, &new.self_id);
+    reconnect |= diff.field(key("capabilities"), &old.capabilities, &new.capabilities);
+    if reconnect {
+        diff.reconnect.push((Platform::Ica, new.id.clone()));
+    }
+    // 发送队列只会创建一次
+    if diff.field(key("rate_limit"), &old.rate_limit, &new.rate_limit) {
+        diff.restart_required.push(key("rate_limit"));
+    }
+    diff.field(key("notice_room"), &old.notice_room, &new.notice_room);
+    diff.field(key("notice_start"), &old.notice_start, &new.notice_start);
+    diff.field(key("admin_list"), &old.admin_list, &new.admin_list);
+    diff.field(key("filter_list"), &old.filter_list, &new.filter_list);
+    diff.field(key("join_policy"), &old.join_policy, &new.join_policy);
+    diff.field(key("member_cache_ttl"), &old.member_cache_ttl, &new.member_cache_ttl);
+    diff.field(key("login"), &old.login, &new.login);
+    diff.field(key("quarantine_path"), &old.quarantine_path, &new.quarantine_path);
+}
+
+/// 比较一个 Tailchat 实例的配置。
+fn diff_tailchat(diff: &mut ConfigDiff, old: &TailchatConfig, new: &TailchatConfig) {
+    let key = |field: &str| format!("tailchat.{}.{field}", new.id);
+    let mut reconnect = diff.field(key("host"), &old.host, &new.host);
+    reconnect |= diff.field(key("app_id"), &old.app_id, &new.app_id);
+    reconnect |= diff.field(key("app_secret"), &old.app_secret, &new.app_secret);
+    reconnect |= diff.field(key("callback"), &old.callback, &new.callback);
+    if reconnect {
+        diff.reconnect.push((Platform::Tailchat, new.id.clone()));
+    }
+    diff.field(key("notice_room"), &old.notice_room, &new.notice_room);
+    diff.field(key("notice_start"), &old.notice_start, &new.notice_start);
+    diff.field(key("admin_list"), &old.admin_list, &new.admin_list);
+    diff.field(key("filter_list"), &old.filter_list, &new.filter_list);
+}
+
+/// 返回启用了的 Icalingua 实例。
+fn enabled_ica(config: &BotConfig) -> Vec<&IcaConfig> {
+    if config.check_ica() {
+        config.ica_list()
+    } else {
+        Vec::new()
+    }
+}
+
+/// 返回启用了的 Tailchat 实例。
+fn enabled_tailchat(config: &BotConfig) -> Vec<&TailchatConfig> {
+    if config.check_tailchat() {
+        config.tailchat_list()
+    } else {
+        Vec::new()
+    }
+}
+
+/// 比较新旧配置。
+pub fn diff(old: &BotConfig, new: &BotConfig) -> ConfigDiff {
+    let mut diff = ConfigDiff::default();
+    for (old_config, new_config) in
+        match_instances(&mut diff, Platform::Ica, enabled_ica(old), enabled_ica(new), |c| {
+            c.id.as_str()
+        })
+    {
+        diff_ica(&mut diff, old_config, new_config);
+    }
+    for (old_config, new_config) in match_instances(
+        &mut diff,
+        Platform::Tailchat,
+        enabled_tailchat(old),
+        enabled_tailchat(new),
+        |c| c.id.as_str(),
+    ) {
+        diff_tailchat(&mut diff, old_config, new_config);
+    }
+
+    // Python 解释器和插件目录只在启动时初始化
+    if diff.field("enable_py".to_string(), &old.enable_py, &new.enable_py) {
+        diff.restart_required.push("enable_py".to_string());
+    }
+    if diff.field("py".to_string(), &old.py, &new.py) {
+        diff.restart_required.push("py".to_string());
+    }
+    diff.field("permission_path".to_string(), &old.permission_path, &new.permission_path);
+    diff.field("moderation_path".to_string(), &old.moderation_path, &new.moderation_path);
+    diff.field("alert".to_string(), &old.alert, &new.alert);
+    diff.field("reload".to_string(), &old.reload, &new.reload);
+    diff
+}
+
+/// 重新读取配置文件并应用到正在运行的部分, 返回给管理员看的说明
+///
+/// 配置有错误时保留当前配置
+pub async fn reload(trigger: &str) -> String {
+    let _guard = RELOADING.lock().await;
+    let Some(path) = CONFIG_PATH.get() else {
+        return "还没有加载过配置文件".to_string();
+    };
+    event!(Level::INFO, "{}", format!("重新加载配置文件 {path} ({trigger})").cyan());
+    let new = match check::check_file(path) {
+        Ok((config, warnings)) => {
+            for warning in warnings {
+                event!(Level::WARN, "{}", warning);
+            }
+            config
+        }
+        Err(issues) => {
+            event!(Level::ERROR, "{}", format!("配置文件 {path} 有问题, 保留当前配置:").red());
+            for issue in &issues {
+                event!(Level::ERROR, "{}", issue);
+            }
+            return format!("配置文件有 {} 个问题, 保留当前配置, 详情见日志", issues.len());
+        }
+    };
+
+    let diff = diff(&MainStatus::global_config(), &new);
+    MainStatus::update_static_config(new);
+    // 文件可能被手动修改过, 总是重新读取
+    permission::init_permission();
+    moderation::init_moderation();
+
+    let config = MainStatus::global_config();
+    for (platform, id) in diff.stop.iter().chain(diff.reconnect.iter()) {
+        backend::stop(*platform, id).await;
+    }
+    for (platform, id) in diff.start.iter().chain(diff.reconnect.iter()) {
+        match platform {
+            Platform::Ica => {
+                if let Some(ica) = config.ica_list().into_iter().find(|ica| &ica.id == id) {
+                    backend::start_ica(ica.clone());
+                }
+            }
+            Platform::Tailchat => {
+                if let Some(tailchat) =
+                    config.tailchat_list().into_iter().find(|tailchat| &tailchat.id == id)
+                {
+                    backend::start_tailchat(tailchat.clone());
+                }
+            }
+        }
+    }
+
+    if !diff.is_empty() && config.check_py() && py::is_initialized() {
+        py::call::bot_config_reload_py(&diff.changed).await;
+    }
+    let summary = diff.summary();
+    event!(Level::INFO, "{}", summary);
+    summary
+}
+
+/// 返回配置文件的修改时间, 读不到时返回 `None`。
+fn modified_time(path: &str) -> Option<SystemTime> {
+    Path::new(path).metadata().and_then(|meta| meta.modified()).ok()
+}
+
+/// 定时检查配置文件的修改时间, 启用了 `[reload] watch` 时变化后自动重载。
+pub async fn watch_config() {
+    let Some(path) = CONFIG_PATH.get() else {
+        return;
+    };
+    let mut last = modified_time(path);
+    loop {
+        let reload_config = MainStatus::global_config().reload.clone();
+        tokio::time::sleep(Duration::from_secs(reload_config.interval.max(1))).await;
+        let current = modified_time(path);
+        if current.is_some() && current != last && reload_config.watch {
+            reload("配置文件被修改").await;
+        }
+        last = current;
+    }
+}
+
+/// 收到 SIGHUP 时重载配置。
+#[cfg(unix)]
+pub async fn listen_sighup() {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            event!(Level::WARN, "无法监听 SIGHUP, 只能用命令重载配置: {}", e);
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        reload("SIGHUP").await;
+    }
+}
+
+/// 不是 unix 平台, 没有 SIGHUP。
+#[cfg(not(unix))]
+pub async fn listen_sighup() {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = r#"
+enable_ica = true
+enable_tailchat = true
+
+[ica]
+private_key = "00"
+host = "http://127.0.0.1:6789"
+self_id = 1
+admin_list = [2]
+
+[tailchat]
+host = "http://127.0.0.1:11000"
+app_id = "app"
+app_secret = "secret"
+notice_room = []
+"#;
+
+    fn parse(source: &str) -> BotConfig { toml::from_str(source).unwrap() }
+
+    #[test]
+    fn live_changes() {
+        let old = parse(BASE);
+        assert!(diff(&old, &old).is_empty());
+
+        let new = parse(&BASE.replace("admin_list = [2]", "admin_list = [2, 3]"));
+        let changes = diff(&old, &new);
+        assert_eq!(changes.changed, vec!["ica.default.admin_list".to_string()]);
+        assert!(changes.reconnect.is_empty() && changes.start.is_empty());
+        assert!(changes.stop.is_empty() && changes.restart_required.is_empty());
+    }
+
+    #[test]
+    fn reconnect_and_instances() {
+        let old = parse(BASE);
+        let new = parse(&format!(
+            "{}\n[[ica_instances]]\nid = \"second\"\nprivate_key = \"00\"\nhost = \"http://b\"\nself_id = 3\n",
+            BASE.replace("app_secret = \"secret\"", "app_secret = \"other\"")
+        ));
+        let changes = diff(&old, &new);
+        assert_eq!(changes.start, vec![(Platform::Ica, "second".to_string())]);
+        assert_eq!(changes.reconnect, vec![(Platform::Tailchat, "default".to_string())]);
+        assert!(changes.changed.contains(&"tailchat.default.app_secret".to_string()));
+
+        let disabled = parse(&BASE.replace("enable_tailchat = true", "enable_tailchat = false"));
+        assert_eq!(diff(&old, &disabled).stop, vec![(Platform::Tailchat, "default".to_string())]);
+    }
+
+    #[test]
+    fn restart_required() {
+        let old = parse(BASE);
+        let new = parse(&BASE.replace(
+            "admin_list = [2]",
+            "admin_list = [2]\n[ica.rate_limit]\nglobal_per_minute = 10",
+        ));
+        let changes = diff(&old, &new);
+        assert_eq!(changes.restart_required, vec!["ica.default.rate_limit".to_string()]);
+        assert!(changes.summary().contains("需要重启才会生效"));
+    }
+}
@@ -1,90 +1,124 @@
 //! 机器人配置及各后端运行状态的全局访问接口。
 
-use crate::MAIN_STATUS;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
 use crate::config::BotConfig;
 
-#[derive(Debug, Clone)]
-pub struct BotStatus {
-    pub config: Option<BotConfig>,
-    pub ica_status: Option<ica::MainStatus>,
-    pub tailchat_status: Option<tailchat::MainStatus>,
+/// 当前配置, 重载时整份换掉, 已经取出的快照不受影响
+static CONFIG: RwLock<Option<Arc<BotConfig>>> = RwLock::new(None);
+
+/// 实例 id -> 运行状态
+type StatusMap<T> = RwLock<BTreeMap<String, Arc<RwLock<T>>>>;
+
+/// 实例 id -> Icalingua 状态
+static ICA_STATUS: StatusMap<ica::MainStatus> = RwLock::new(BTreeMap::new());
+/// 实例 id -> Tailchat 状态
+static TAILCHAT_STATUS: StatusMap<tailchat::MainStatus> = RwLock::new(BTreeMap::new());
+
+/// 设置某个实例的状态, 已有的状态原地替换, 已经取出的句柄也能看到新状态。
+fn set_status<T>(map: &StatusMap<T>, id: &str, status: T) {
+    let mut map = map.write().unwrap();
+    match map.get(id) {
+        Some(current) => *current.write().unwrap() = status,
+        None => {
+            map.insert(id.to_string(), Arc::new(RwLock::new(status)));
+        }
+    }
 }
 
+/// 返回某个实例的状态句柄。
+fn status_of<T>(map: &StatusMap<T>, id: &str) -> Option<Arc<RwLock<T>>> {
+    map.read().unwrap().get(id).cloned()
+}
+
+/// 机器人运行状态的访问接口
+///
+/// 闭包执行期间会持有对应实例状态的锁, 不要在里面等待或者再次访问同一个实例的状态
+#[derive(Debug, Clone)]
+pub struct BotStatus;
+
 impl BotStatus {
     /// 更新 `static_config` 状态。
     pub fn update_static_config(config: BotConfig) {
-        unsafe {
-            MAIN_STATUS.config = Some(config);
-        }
+        *CONFIG.write().unwrap() = Some(Arc::new(config));
     }
-    /// 更新 `ica_status` 状态。
+    /// 更新当前实例的 `ica_status` 状态。
     pub fn update_ica_status(status: ica::MainStatus) {
-        unsafe {
-            MAIN_STATUS.ica_status = Some(status);
-        }
+        set_status(&ICA_STATUS, &Self::global_config().ica_id(), status);
     }
-    /// 更新 `tailchat_status` 状态。
+    /// 更新当前实例的 `tailchat_status` 状态。
     pub fn update_tailchat_status(status: tailchat::MainStatus) {
-        unsafe {
-            MAIN_STATUS.tailchat_status = Some(status);
-        }
+        set_status(&TAILCHAT_STATUS, &Self::global_config().tailchat_id(), status);
+    }
+
+    /// 设置 Icalingua 实例的初始状态, 实例重启时旧的状态 (登录、房间等) 会被清空。
+    pub fn init_ica_status(id: &str, enable: bool) {
+        let status = ica::MainStatus {
+            enable,
+            qq_login: false,
+            current_loaded_messages_count: 0,
+            rooms: Vec::new(),
+            online_status: ica::OnlineData::default(),
+            members: ica::MemberDirectory::default(),
+        };
+        set_status(&ICA_STATUS, id, status);
     }
 
     /// 使用配置初始化全局运行状态。
     pub fn static_init(config: BotConfig) {
-        unsafe {
-            MAIN_STATUS.ica_status = Some(ica::MainStatus {
-                enable: config.check_ica(),
-                qq_login: false,
-                current_loaded_messages_count: 0,
-                rooms: Vec::new(),
-                online_status: ica::OnlineData::default(),
-            });
-            MAIN_STATUS.config = Some(config);
+        for ica in config.ica_list() {
+            Self::init_ica_status(&ica.id, config.check_ica());
         }
+        Self::update_static_config(config);
     }
 
-    /// 返回全局机器人配置。
-    pub fn global_config() -> &'static BotConfig {
-        unsafe {
-            let ptr = &raw const MAIN_STATUS.config;
-            (*ptr).as_ref().unwrap()
-        }
+    /// 返回当前全局机器人配置的快照。
+    pub fn global_config() -> Arc<BotConfig> {
+        CONFIG.read().unwrap().clone().expect("config not initialized")
     }
 
-    /// 返回全局 Icalingua 状态。
-    pub fn global_ica_status() -> &'static ica::MainStatus {
-        unsafe {
-            let ptr = &raw const MAIN_STATUS.ica_status;
-            (*ptr).as_ref().unwrap()
-        }
+    /// 返回指定实例的 Icalingua 状态。
+    pub fn ica_status_of(id: &str) -> Option<Arc<RwLock<ica::MainStatus>>> {
+        status_of(&ICA_STATUS, id)
     }
-    /// 返回全局 Tailchat 状态。
-    pub fn global_tailchat_status() -> &'static tailchat::MainStatus {
-        unsafe {
-            let ptr = &raw const MAIN_STATUS.tailchat_status;
-            (*ptr).as_ref().unwrap()
-        }
+    /// 返回指定实例的 Tailchat 状态, 还没登录时为 `None`。
+    pub fn tailchat_status_of(id: &str) -> Option<Arc<RwLock<tailchat::MainStatus>>> {
+        status_of(&TAILCHAT_STATUS, id)
     }
 
-    /// 返回可修改的全局 Icalingua 状态。
-    pub fn global_ica_status_mut() -> &'static mut ica::MainStatus {
-        unsafe {
-            let ptr = &raw mut MAIN_STATUS.ica_status;
-            (*ptr).as_mut().unwrap()
-        }
+    /// 读取当前实例的 Icalingua 状态, 实例不存在时返回 `None`。
+    pub fn with_ica_status<R>(f: impl FnOnce(&ica::MainStatus) -> R) -> Option<R> {
+        let status = Self::ica_status_of(&Self::global_config().ica_id())?;
+        let status = status.read().unwrap();
+        Some(f(&status))
     }
-    /// 返回可修改的全局 Tailchat 状态。
-    pub fn global_tailchat_status_mut() -> &'static mut tailchat::MainStatus {
-        unsafe {
-            let ptr = &raw mut MAIN_STATUS.tailchat_status;
-            (*ptr).as_mut().unwrap()
-        }
+    /// 读取当前实例的 Tailchat 状态, 还没登录时返回 `None`。
+    pub fn with_tailchat_status<R>(f: impl FnOnce(&tailchat::MainStatus) -> R) -> Option<R> {
+        let status = Self::tailchat_status_of(&Self::global_config().tailchat_id())?;
+        let status = status.read().unwrap();
+        Some(f(&status))
+    }
+
+    /// 修改当前实例的 Icalingua 状态, 实例不存在时返回 `None`。
+    pub fn with_ica_status_mut<R>(f: impl FnOnce(&mut ica::MainStatus) -> R) -> Option<R> {
+        let status = Self::ica_status_of(&Self::global_config().ica_id())?;
+        let mut status = status.write().unwrap();
+        Some(f(&mut status))
+    }
+    /// 修改当前实例的 Tailchat 状态, 还没登录时返回 `None`。
+    pub fn with_tailchat_status_mut<R>(
+        f: impl FnOnce(&mut tailchat::MainStatus) -> R,
+    ) -> Option<R> {
+        let status = Self::tailchat_status_of(&Self::global_config().tailchat_id())?;
+        let mut status = status.write().unwrap();
+        Some(f(&mut status))
     }
 }
 
 pub mod ica {
     use crate::data_struct::ica::all_rooms::Room;
+    pub use crate::data_struct::ica::group_members::MemberDirectory;
     pub use crate::data_struct::ica::online_data::OnlineData;
 
     #[derive(Debug, Clone)]
@@ -99,6 +133,8 @@ pub mod ica {
         pub rooms: Vec<Room>,
         /// 在线数据 (Icalingua 信息)
         pub online_status: OnlineData,
+        /// 群成员缓存
+        pub members: MemberDirectory,
     }
 
     impl MainStatus {
@@ -110,7 +146,8 @@ pub mod ica {
 }
 
 pub mod tailchat {
-    use crate::data_struct::tailchat::UserId;
+    use crate::data_struct::tailchat::status::{ConverseInfo, GroupInfo};
+    use crate::data_struct::tailchat::{ConverseId, UserId};
 
     #[derive(Debug, Clone)]
     pub struct MainStatus {
@@ -128,6 +165,10 @@ pub mod tailchat {
         pub jwt_token: String,
         /// avatar
         pub avatar: String,
+        /// 加入的服务器
+        pub groups: Vec<GroupInfo>,
+        /// 私信/多人会话
+        pub converses: Vec<ConverseInfo>,
     }
 
     impl MainStatus {
@@ -141,5 +182,30 @@ pub mod tailchat {
         pub fn update_jwt_token(&mut self, jwt_token: String) { self.jwt_token = jwt_token; }
         /// 更新 `avatar` 状态。
         pub fn update_avatar(&mut self, avatar: String) { self.avatar = avatar; }
+        /// 更新 `groups` 状态。
+        pub fn update_groups(&mut self, groups: Vec<GroupInfo>) { self.groups = groups; }
+        /// 更新 `converses` 状态。
+        pub fn update_converses(&mut self, converses: Vec<ConverseInfo>) {
+            self.converses = converses;
+        }
+        /// 添加或替换一个私信/多人会话。
+        pub fn upsert_converse(&mut self, converse: ConverseInfo) {
+            match self.converses.iter_mut().find(|old| old.id == converse.id) {
+                Some(old) => *old = converse,
+                None => self.converses.push(converse),
+            }
+        }
+        /// 返回和某个用户的私信会话。
+        pub fn dm_with(&self, user_id: &str) -> Option<&ConverseInfo> {
+            self.converses.iter().find(|converse| {
+                converse.is_dm() && converse.members.iter().any(|member| member == user_id)
+            })
+        }
+        /// 返回会话所属的服务器, 私信/多人会话返回 `None`。
+        pub fn group_of(&self, converse_id: &ConverseId) -> Option<&GroupInfo> {
+            self.groups
+                .iter()
+                .find(|group| group.panels.iter().any(|panel| &panel.id == converse_id))
+        }
     }
 }
@@ -1,7 +1,11 @@
 //! Tailchat Socket.IO 客户端入口和事件注册。
 
+/// 加载 `callback` 子模块。
+pub mod callback;
 /// 加载 `client` 子模块。
 pub mod client;
+/// 加载 `directory` 子模块。
+pub mod directory;
 /// 加载 `events` 子模块。
 pub mod events;
 
@@ -17,16 +21,52 @@ use serde_json::{Value, json};
 use tracing::{Level, event, span};
 
 use crate::config::TailchatConfig;
+use crate::data_struct::tailchat::messages::SendingMessage;
 use crate::data_struct::tailchat::status::{BotStatus, LoginData};
 use crate::error::{ClientResult, TailchatError};
-use crate::{StopGetter, async_any_callback_with_state, async_callback_with_state, version_str};
+use crate::tailchat::client::Connection;
+use crate::{
+    StopGetter, async_any_callback_with_state, async_callback_with_state, instance, version_str,
+};
 
-/// 启动 Tailchat Socket.IO 客户端。
+/// 以 HTTP 回调模式运行, 不连接 Socket.IO。
+async fn start_callback(
+    config: TailchatConfig,
+    stop_reciver: StopGetter,
+) -> ClientResult<(), TailchatError> {
+    let listener = callback::bind(&config.callback).await?;
+    let client = Connection::Http;
+    if let Err(e) = directory::refresh(&client).await {
+        event!(Level::WARN, "获取 tailchat 服务器和会话失败: {}", e);
+    }
+    client::set_notice_client(Some(client.clone()));
+
+    if config.notice_start {
+        event!(Level::INFO, "正在发送启动消息");
+        for (group, con) in config.notice_room {
+            event!(Level::INFO, "发送启动消息到: {}|{}", con, group);
+            let startup_msg = SendingMessage::new_without_meta(
+                format!("{}\n启动成功", version_str()),
+                con,
+                Some(group),
+            );
+            if let Err(e) = client::send_message(&client, &startup_msg).await {
+                event!(Level::ERROR, "发送启动消息失败: {}", e);
+            }
+        }
+    }
+
+    callback::serve(listener, config.callback, stop_reciver).await;
+    client::set_notice_client(None);
+    Ok(())
+}
+
+/// 启动 Tailchat 客户端, 配置了回调模式时改为监听 HTTP 回调。
 pub async fn start_tailchat(
     config: TailchatConfig,
     stop_reciver: StopGetter,
 ) -> ClientResult<(), TailchatError> {
-    let span = span!(Level::INFO, "Tailchat Client");
+    let span = span!(Level::INFO, "Tailchat Client", instance = %config.id);
     let _enter = span.enter();
 
     event!(Level::INFO, "tailchat-async-rs v{} initing", crate::TAILCHAT_VERSION);
@@ -69,21 +109,40 @@ pub async fn start_tailchat(
 
     status.update_to_global();
 
+    if config.callback.enable {
+        return start_callback(config, stop_reciver).await;
+    }
+
     let sharded_status = BotStatus::new(status.user_id.clone());
     let sharded_status = Arc::new(sharded_status);
 
-    let socket = ClientBuilder::new(config.host)
+    // 所有事件回调都运行在这个实例的作用域里
+    let id = config.id.as_str();
+    let socket = ClientBuilder::new(config.host.clone())
         .auth(json!({"token": status.jwt.clone()}))
         .transport_type(TransportType::Websocket)
-        .on_any(async_any_callback_with_state!(events::any_event, sharded_status.clone()))
+        .on_any(instance::any_callback(
+            id,
+            async_any_callback_with_state!(events::any_event, sharded_status.clone()),
+        ))
         .on(
             "notify:chat.message.add",
-            async_callback_with_state!(events::on_message, sharded_status.clone()),
+            instance::callback(
+                id,
+                async_callback_with_state!(events::on_message, sharded_status.clone()),
+            ),
+        )
+        .on(
+            "notify:chat.message.delete",
+            instance::callback(id, async_callback!(events::on_msg_delete)),
+        )
+        .on(
+            "notify:chat.inbox.append",
+            instance::callback(id, async_callback!(events::on_inbox_append)),
         )
-        .on("notify:chat.message.delete", async_callback!(events::on_msg_delete))
         .on(
             "notify:chat.converse.updateDMConverse",
-            async_callback!(events::on_converse_update),
+            instance::callback(id, async_callback!(events::on_converse_update)),
         )
         // .on("notify:chat.message.update", wrap_callback!(events::on_message))
         // .on("notify:chat.message.addReaction", wrap_callback!(events::on_msg_update))
@@ -99,17 +158,20 @@ pub async fn start_tailchat(
     socket.emit("chat.converse.findAndJoinRoom", json!([])).await.unwrap();
 
     event!(Level::INFO, "{}", "tailchat 已经加入房间".green());
+    if let Err(e) = directory::refresh(&Connection::Socket(socket.clone())).await {
+        event!(Level::WARN, "获取 tailchat 服务器和会话失败: {}", e);
+    }
+    client::set_notice_client(Some(Connection::Socket(socket.clone())));
 
     if config.notice_start {
         event!(Level::INFO, "正在发送启动消息");
         for (group, con) in config.notice_room {
             event!(Level::INFO, "发送启动消息到: {}|{}", con, group);
-            let startup_msg =
-                crate::data_struct::tailchat::messages::SendingMessage::new_without_meta(
-                    format!("{}\n启动成功", version_str()),
-                    con.clone(),
-                    Some(group.clone()),
-                );
+            let startup_msg = SendingMessage::new_without_meta(
+                format!("{}\n启动成功", version_str()),
+                con.clone(),
+                Some(group.clone()),
+            );
             // 反正是 tailchat, 不需要等, 直接发
             if let Err(e) = socket.emit("chat.message.sendMessage", startup_msg.as_value()).await {
                 event!(Level::ERROR, "发送启动消息失败: {}", e);
@@ -118,6 +180,7 @@ pub async fn start_tailchat(
     }
 
     stop_reciver.await.ok();
+    client::set_notice_client(None);
     event!(Level::INFO, "socketio client stopping");
     match socket.disconnect().await {
         Ok(_) => {
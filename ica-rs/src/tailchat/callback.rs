@@ -0,0 +1,342 @@
+//! Tailchat OpenAPI 机器人的 HTTP 回调模式。
+//!
+//! 机器人配置了回调地址时, 被 @ 后服务端会把收件箱记录 POST 过来。
+//! 这个模式下不连接 Socket.IO, 在本地监听一个 HTTP 端口接收回调, 适合放在不支持长连接的反向代理后面;
+//! 回复和其他 action 都通过 OpenAPI 的 HTTP 接口发送 (见 [`Connection::Http`])。
+
+use std::time::Duration;
+
+use colored::Colorize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{Level, event};
+
+use crate::config::TailchatCallbackConfig;
+use crate::data_struct::Platform;
+use crate::data_struct::tailchat::messages::InboxItem;
+use crate::error::{ClientResult, TailchatError};
+use crate::moderation;
+use crate::py::call::tailchat_inbox_py;
+use crate::tailchat::client::Connection;
+use crate::tailchat::events::handle_message;
+use crate::{MainStatus, StopGetter, instance};
+
+/// 请求头的最大长度
+const MAX_HEAD_LEN: usize = 16 * 1024;
+/// 请求体的最大长度
+const MAX_BODY_LEN: usize = 1024 * 1024;
+/// 读取一个请求的超时时间
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 收到的一个 HTTP 请求
+#[derive(Debug, Default)]
+struct Request {
+    method: String,
+    /// 不带查询参数的路径
+    path: String,
+    /// 查询参数里的 `token`
+    token: Option<String>,
+    /// 请求头, 名字为小写
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    /// 返回请求头的值, 名字不区分大小写。
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// 回调被拒绝的原因, 对应返回的状态码
+#[derive(Debug, PartialEq, Eq)]
+enum Rejected {
+    MethodNotAllowed,
+    NotFound,
+    Unauthorized,
+    BadRequest,
+    Forbidden,
+}
+
+impl Rejected {
+    /// 返回状态行里的状态码和原因。
+    fn status(&self) -> &'static str {
+        match self {
+            Self::MethodNotAllowed => "405 Method Not Allowed",
+            Self::NotFound => "404 Not Found",
+            Self::Unauthorized => "401 Unauthorized",
+            Self::BadRequest => "400 Bad Request",
+            Self::Forbidden => "403 Forbidden",
+        }
+    }
+}
+
+/// 解析请求行和请求头, `head` 不包括结尾的空行。
+fn parse_head(head: &str) -> Option<Request> {
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next()?.split(' ');
+    let method = request_line.next()?.to_string();
+    let target = request_line.next()?;
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let token = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == "token")
+        .map(|(_, value)| value.to_string());
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim().to_ascii_lowercase(), value.trim().to_string()))
+        .collect();
+    Some(Request {
+        method,
+        path: path.to_string(),
+        token,
+        headers,
+        body: Vec::new(),
+    })
+}
+
+/// 常数时间比较 token, 先哈希成定长再逐字节比较, 不泄露长度和相同的前缀。
+fn token_matches(token: &str, expected: &str) -> bool {
+    let token = blake3::hash(token.as_bytes());
+    let expected = blake3::hash(expected.as_bytes());
+    let mut diff = 0;
+    for (a, b) in token.as_bytes().iter().zip(expected.as_bytes()) {
+        diff |= a ^ b;
+    }
+    diff == 0
+}
+
+/// 检查回调是否可信, 返回里面的收件箱记录
+///
+/// 依次检查方法、路径、`token` (查询参数或 `X-Callback-Token` 请求头) 和收件人是不是自己,
+/// 没有配置 `token` 时拒绝所有回调
+fn verify(
+    config: &TailchatCallbackConfig,
+    request: &Request,
+    bot_id: &str,
+) -> Result<InboxItem, Rejected> {
+    if request.method != "POST" {
+        return Err(Rejected::MethodNotAllowed);
+    }
+    if request.path != config.path {
+        return Err(Rejected::NotFound);
+    }
+    let Some(expected) = config.token.as_deref().filter(|token| !token.is_empty()) else {
+        return Err(Rejected::Unauthorized);
+    };
+    let token = request.token.as_deref().or_else(|| request.header("x-callback-token"));
+    if !token.is_some_and(|token| token_matches(token, expected)) {
+        return Err(Rejected::Unauthorized);
+    }
+    let item: InboxItem =
+        serde_json::from_slice(&request.body).map_err(|_| Rejected::BadRequest)?;
+    if item.user_id != bot_id {
+        return Err(Rejected::Forbidden);
+    }
+    Ok(item)
+}
+
+/// 从连接里读出一个请求, 格式不对或者太大时返回 `None`。
+async fn read_request(stream: &mut TcpStream) -> Option<Request> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let head_end = loop {
+        if let Some(pos) = buf.windows(4).position(|window| window == b"\r\n\r\n") {
+            break pos;
+        }
+        if buf.len() > MAX_HEAD_LEN {
+            return None;
+        }
+        let len = stream.read(&mut chunk).await.ok()?;
+        if len == 0 {
+            return None;
+        }
+        buf.extend_from_slice(&chunk[..len]);
+    };
+    let mut request = parse_head(std::str::from_utf8(&buf[..head_end]).ok()?)?;
+    // 只支持 Content-Length, 分块传输的请求体直接拒绝, 免得读错
+    if request.header("transfer-encoding").is_some() {
+        return None;
+    }
+    let body_len: usize = match request.header("content-length") {
+        Some(len) => len.parse().ok()?,
+        None => 0,
+    };
+    if body_len > MAX_BODY_LEN {
+        return None;
+    }
+    let mut body = buf.split_off(head_end + 4);
+    while body.len() < body_len {
+        let len = stream.read(&mut chunk).await.ok()?;
+        if len == 0 {
+            return None;
+        }
+        body.extend_from_slice(&chunk[..len]);
+    }
+    body.truncate(body_len);
+    request.body = body;
+    Some(request)
+}
+
+/// 写回一个 JSON 响应并关闭连接。
+async fn respond(stream: &mut TcpStream, status: &str, body: &str) {
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    if let Err(e) = stream.write_all(response.as_bytes()).await {
+        event!(Level::WARN, "tailchat 回调响应写入失败: {}", e);
+    }
+    let _ = stream.shutdown().await;
+}
+
+/// 处理一个回调连接。
+async fn handle_connection(mut stream: TcpStream, config: TailchatCallbackConfig) {
+    let Ok(Some(request)) = tokio::time::timeout(READ_TIMEOUT, read_request(&mut stream)).await
+    else {
+        respond(&mut stream, "400 Bad Request", r#"{"result":false}"#).await;
+        return;
+    };
+    // 还没登录时 bot_id 为空, 所有回调都会被拒绝
+    let bot_id =
+        MainStatus::with_tailchat_status(|status| status.user_id.clone()).unwrap_or_default();
+    let item = match verify(&config, &request, &bot_id) {
+        Ok(item) => item,
+        Err(rejected) => {
+            event!(
+                Level::WARN,
+                "拒绝了 tailchat 回调 {} {}: {}",
+                request.method,
+                request.path,
+                rejected.status().red()
+            );
+            respond(&mut stream, rejected.status(), r#"{"result":false}"#).await;
+            return;
+        }
+    };
+    // 先响应, 免得插件处理太久让服务端超时重试
+    respond(&mut stream, "200 OK", r#"{"result":true}"#).await;
+
+    event!(Level::INFO, "tailchat_callback {}", item.to_string().yellow());
+    let client = Connection::Http;
+    if let Some(message) = &item.message
+        && !moderation::check_message(
+            Platform::Tailchat,
+            &message.author,
+            &message.converse_id,
+            message.content(),
+        )
+        .is_pass()
+    {
+        return;
+    }
+    tailchat_inbox_py(&item, &client).await;
+    if let Some(message) = item.to_receive_message() {
+        handle_message(message, client).await;
+    }
+}
+
+/// 监听回调端口。
+pub async fn bind(config: &TailchatCallbackConfig) -> ClientResult<TcpListener, TailchatError> {
+    TcpListener::bind(&config.listen)
+        .await
+        .map_err(|e| TailchatError::CallbackBindFailed(config.listen.clone(), e))
+}
+
+/// 接收回调, 直到收到停止信号。
+pub async fn serve(
+    listener: TcpListener,
+    config: TailchatCallbackConfig,
+    mut stop_reciver: StopGetter,
+) {
+    event!(
+        Level::INFO,
+        "{}",
+        format!("tailchat 回调模式已启动, 监听 {}{}", config.listen, config.path).green()
+    );
+    let id = MainStatus::global_config().tailchat_id();
+    loop {
+        tokio::select! {
+            _ = &mut stop_reciver => break,
+            accepted = listener.accept() => match accepted {
+                Ok((stream, addr)) => {
+                    event!(Level::DEBUG, "tailchat 回调连接 {}", addr);
+                    tokio::spawn(instance::scope(
+                        id.clone(),
+                        handle_connection(stream, config.clone()),
+                    ));
+                }
+                Err(e) => event!(Level::WARN, "tailchat 回调连接失败: {}", e),
+            },
+        }
+    }
+    event!(Level::INFO, "tailchat 回调模式已停止");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, target: &str, body: &str) -> Request {
+        let mut request =
+            parse_head(&format!("{method} {target} HTTP/1.1\r\nHost: bot\r\nX-Callback-Token: t2"))
+                .unwrap();
+        request.body = body.as_bytes().to_vec();
+        request
+    }
+
+    #[test]
+    fn parse_request_head() {
+        let request = request("POST", "/tailchat/callback?a=1&token=t1", "");
+        assert_eq!(request.path, "/tailchat/callback");
+        assert_eq!(request.token.as_deref(), Some("t1"));
+        assert_eq!(request.header("host"), Some("bot"));
+        assert!(parse_head("").is_none());
+    }
+
+    #[test]
+    fn compare_token() {
+        assert!(token_matches("t1", "t1"));
+        assert!(!token_matches("t1", "t2"));
+        assert!(!token_matches("t", "t1"));
+        assert!(!token_matches("", "t1"));
+    }
+
+    #[test]
+    fn verify_callback() {
+        let config = TailchatCallbackConfig {
+            token: Some("t1".to_string()),
+            ..Default::default()
+        };
+        let body = r#"{"_id": "i1", "userId": "bot", "type": "message"}"#;
+        let ok = request("POST", "/tailchat/callback?token=t1", body);
+        assert_eq!(verify(&config, &ok, "bot").unwrap().inbox_id, "i1");
+
+        let check = |method, target, body, bot_id| {
+            verify(&config, &request(method, target, body), bot_id).unwrap_err()
+        };
+        assert_eq!(
+            check("GET", "/tailchat/callback?token=t1", body, "bot"),
+            Rejected::MethodNotAllowed
+        );
+        assert_eq!(check("POST", "/other?token=t1", body, "bot"), Rejected::NotFound);
+        // 请求头里的 t2 也不对
+        assert_eq!(check("POST", "/tailchat/callback", body, "bot"), Rejected::Unauthorized);
+        assert_eq!(check("POST", "/tailchat/callback?token=t1", "{", "bot"), Rejected::BadRequest);
+        assert_eq!(
+            check("POST", "/tailchat/callback?token=t1", body, "other"),
+            Rejected::Forbidden
+        );
+
+        // 没有配置 token 时全部拒绝
+        let open = TailchatCallbackConfig::default();
+        assert_eq!(
+            verify(&open, &request("POST", "/tailchat/callback", body), "bot").unwrap_err(),
+            Rejected::Unauthorized
+        );
+    }
+}
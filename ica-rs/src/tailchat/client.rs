@@ -1,96 +1,216 @@
 //! Tailchat 房间加入及消息发送请求封装。
 
-use crate::data_struct::tailchat::messages::SendingMessage;
+use crate::MainStatus;
+use crate::data_struct::tailchat::bbcode::image_size;
+use crate::data_struct::tailchat::messages::{FileSource, SendingFile, SendingMessage};
+use crate::data_struct::tailchat::status::ConverseInfo;
+use crate::data_struct::tailchat::{ConverseId, MessageId, UserId};
+use crate::error::{ClientResult, TailchatError};
+use crate::instance;
 // use crate::data_struct::tailchat::{ConverseId, GroupId, MessageId, UserId};
 
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+
 use colored::Colorize;
+use futures_util::future::BoxFuture;
 use reqwest::multipart;
+use rust_socketio::Payload;
 use rust_socketio::asynchronous::Client;
 use serde_json::{Value, json};
+use tokio::sync::oneshot;
 use tracing::{Level, event, span};
 
-/// 发送 `message` 请求或消息。
-pub async fn send_message(client: &Client, message: &SendingMessage) -> bool {
+/// 等待服务端 ACK 的时间
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// 上传附件的超时时间
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(120);
+/// 探测图片宽高时最多读取的字节数
+const PROBE_LEN: u64 = 256 * 1024;
+/// 上传缓存最多记录的附件数, 满了之后清空重新记
+const UPLOAD_CACHE_LIMIT: usize = 1024;
+
+/// 所有实例共用的 HTTP 客户端, token 按请求带上
+static HTTP_CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .timeout(UPLOAD_TIMEOUT)
+        .build()
+        .expect("http client build failed")
+});
+
+/// (服务地址, 附件内容的 blake3) -> 上传后的地址
+static UPLOADED: LazyLock<Mutex<HashMap<(String, String), String>>> =
+    LazyLock::new(Default::default);
+
+/// 实例 id -> 连上的 Tailchat 客户端, 给其他平台和其他实例往提醒房间转发消息用
+static NOTICE_CLIENTS: LazyLock<Mutex<HashMap<String, Connection>>> =
+    LazyLock::new(Default::default);
+
+/// 和 Tailchat 服务通信的方式
+#[derive(Clone)]
+pub enum Connection {
+    /// Socket.IO 长连接
+    Socket(Client),
+    /// 回调模式下没有长连接, 通过 OpenAPI 的 HTTP 接口调用 action
+    Http,
+}
+
+/// 记录 (或清掉) 当前实例的 Tailchat 客户端。
+pub fn set_notice_client(client: Option<Connection>) {
+    let id = MainStatus::global_config().tailchat_id();
+    let mut clients = NOTICE_CLIENTS.lock().unwrap();
+    match client {
+        Some(client) => clients.insert(id, client),
+        None => clients.remove(&id),
+    };
+}
+
+/// 通过除了 `skipped` 以外的实例往各自的提醒房间发送一条文本, 返回是否至少发出了一条
+///
+/// 没有连上的 Tailchat 时直接返回 `false`
+pub async fn send_notice(text: &str, skipped: Option<&str>) -> bool {
+    let clients: Vec<(String, Connection)> = NOTICE_CLIENTS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(id, _)| Some(id.as_str()) != skipped)
+        .map(|(id, client)| (id.clone(), client.clone()))
+        .collect();
+    let mut sent = false;
+    for (id, client) in clients {
+        sent |= instance::scope(id, async {
+            let mut sent = false;
+            for (group, con) in MainStatus::global_config().tailchat().notice_room {
+                let message = SendingMessage::new_without_meta(text.to_string(), con, Some(group));
+                match send_message(&client, &message).await {
+                    Ok(()) => sent = true,
+                    Err(e) => event!(Level::WARN, "提醒发送失败: {}", e),
+                }
+            }
+            sent
+        })
+        .await;
+    }
+    sent
+}
+
+/// 附件内容的 blake3 和图片的宽高
+///
+/// 本地文件在阻塞线程里流式读取, 不整个读进内存
+async fn inspect_file(file: &SendingFile) -> std::io::Result<(String, Option<(u32, u32)>)> {
+    let is_image = file.is_image();
+    match file.source() {
+        FileSource::Bytes(data) => {
+            let size = if is_image { image_size(data) } else { None };
+            Ok((blake3::hash(data).to_hex().to_string(), size))
+        }
+        FileSource::Path(path) => {
+            let path = path.clone();
+            tokio::task::spawn_blocking(move || {
+                let mut hasher = blake3::Hasher::new();
+                hasher.update_reader(std::fs::File::open(&path)?)?;
+                let size = if is_image {
+                    let mut head = Vec::new();
+                    std::fs::File::open(&path)?.take(PROBE_LEN).read_to_end(&mut head)?;
+                    image_size(&head)
+                } else {
+                    None
+                };
+                Ok((hasher.finalize().to_hex().to_string(), size))
+            })
+            .await
+            .map_err(std::io::Error::other)?
+        }
+    }
+}
+
+/// 把附件 POST 到 `{host}/upload`, 返回服务端给出的地址。
+async fn post_file(host: &str, file: &SendingFile) -> ClientResult<String, TailchatError> {
+    let name = file.file_name().to_string();
+    // 感谢 https://stackoverflow.com/questions/65814450/how-to-post-a-file-using-reqwest
+    let part = match file.source() {
+        FileSource::Bytes(data) => multipart::Part::bytes(data.clone()),
+        FileSource::Path(path) => multipart::Part::file(path)
+            .await
+            .map_err(|e| TailchatError::ReadFileFailed(name.clone(), e))?,
+    };
+    let form_data = multipart::Form::new().part("file", part.file_name(name.clone()));
+    let token =
+        MainStatus::with_tailchat_status(|status| status.jwt_token.clone()).unwrap_or_default();
+
+    event!(Level::INFO, "uploading file {}", name);
+    let resp = HTTP_CLIENT
+        .post(format!("{host}/upload"))
+        .header("X-Token", token)
+        .multipart(form_data)
+        .send()
+        .await?;
+    let status = resp.status();
+    let text = resp.text().await?;
+    if !status.is_success() {
+        return Err(TailchatError::UploadFailed(name, format!("{status}: {text}")));
+    }
+    let data: Value = serde_json::from_str(&text)
+        .map_err(|e| TailchatError::UploadFailed(name.clone(), format!("{e}: {text}")))?;
+    match data["url"].as_str() {
+        Some(url) => Ok(url.to_string()),
+        None => Err(TailchatError::UploadFailed(name, format!("响应里没有 url: {text}"))),
+    }
+}
+
+/// 上传一个附件, 返回附件在消息里的 BBCode
+///
+/// 同一个服务上传过内容相同的附件时直接复用之前的地址
+pub async fn upload_file(file: &SendingFile) -> ClientResult<String, TailchatError> {
+    let (hash, size) = inspect_file(file)
+        .await
+        .map_err(|e| TailchatError::ReadFileFailed(file.file_name().to_string(), e))?;
+    let host = MainStatus::global_config().tailchat().host;
+    let key = (host, hash);
+    let cached = UPLOADED.lock().unwrap().get(&key).cloned();
+    let url = match cached {
+        Some(url) => {
+            event!(Level::DEBUG, "附件 {} 已上传过, 复用 {}", file.file_name(), url);
+            url
+        }
+        None => {
+            let url = post_file(&key.0, file).await?;
+            let mut uploaded = UPLOADED.lock().unwrap();
+            if uploaded.len() >= UPLOAD_CACHE_LIMIT {
+                uploaded.clear();
+            }
+            uploaded.insert(key, url.clone());
+            url
+        }
+    };
+    Ok(file.gen_markdown(&url, size))
+}
+
+/// 发送消息, 附件会先上传, 再按顺序接在内容后面。
+pub async fn send_message(
+    client: &Connection,
+    message: &SendingMessage,
+) -> ClientResult<(), TailchatError> {
     let span = span!(Level::INFO, "tailchat send message");
     let _enter = span.enter();
     let mut value: Value = message.as_value();
     if message.contain_file() {
-        // 处理文件
-        let mut header = reqwest::header::HeaderMap::new();
-        header.append(
-            "X-Token",
-            crate::MainStatus::global_tailchat_status().jwt_token.clone().parse().unwrap(),
-        );
-        let file_client = match reqwest::ClientBuilder::new().default_headers(header).build() {
-            Ok(client) => client,
-            Err(e) => {
-                event!(Level::ERROR, "file_client build failed:{}", format!("{e:#?}").red());
-                return false;
-            }
-        };
-        // 感谢 https://stackoverflow.com/questions/65814450/how-to-post-a-file-using-reqwest
-        let upload_url =
-            format!("{}/upload", crate::MainStatus::global_config().tailchat().host.clone());
-        let file_body =
-            multipart::Part::stream(message.file.file_data()).file_name(message.file.file_name());
-        let form_data = multipart::Form::new().part("file", file_body);
-
-        event!(Level::INFO, "sending file message");
-        let data = match file_client.post(&upload_url).multipart(form_data).send().await {
-            Ok(resp) => {
-                if resp.status().is_success() {
-                    match resp.text().await {
-                        Ok(text) => match serde_json::from_str::<Value>(&text) {
-                            Ok(json) => json,
-                            Err(e) => {
-                                event!(
-                                    Level::ERROR,
-                                    "file uploaded, but response parse failed:{}",
-                                    format!("{e:#?}").red()
-                                );
-                                return false;
-                            }
-                        },
-                        Err(e) => {
-                            event!(
-                                Level::ERROR,
-                                "file uploaded, but failed to get response:{}",
-                                format!("{e:#?}").red()
-                            );
-                            return false;
-                        }
-                    }
-                } else {
-                    event!(Level::ERROR, "file upload faild:{}", format!("{resp:#?}").red());
-                    return false;
-                }
-            }
-            Err(e) => {
-                event!(
-                    Level::ERROR,
-                    "file upload failed while posting data:{}",
-                    format!("{e:#?}").red()
-                );
-                return false;
-            }
-        };
-        let content = format!(
-            "{}{}",
-            message.content,
-            message.file.gen_markdown(data["url"].as_str().unwrap())
-        );
+        let mut content = message.content.clone();
+        for file in &message.files {
+            content.push_str(&upload_file(file).await?);
+        }
         value["content"] = json!(content);
     }
-    match client.emit("chat.message.sendMessage", value).await {
-        Ok(_) => {
-            event!(Level::DEBUG, "send message {}", format!("{message:#?}").cyan());
-            true
-        }
-        Err(e) => {
-            event!(Level::WARN, "send message failed:{}", format!("{e:#?}").red());
-            false
+    match client {
+        Connection::Socket(client) => client.emit("chat.message.sendMessage", value).await?,
+        Connection::Http => {
+            request(client, "chat.message.sendMessage", value).await?;
         }
     }
+    event!(Level::DEBUG, "send message {}", format!("{message:#?}").cyan());
+    Ok(())
 }
 
 /// 请求加入 Tailchat 会话房间。
@@ -108,3 +228,245 @@ pub async fn emit_join_room(client: &Client) -> bool {
         }
     }
 }
+
+/// 解析 Tailchat 的 ACK
+///
+/// 成功时为 `{"result": true, "data": ...}`, 失败时为 `{"result": false, "message": ...}`
+fn parse_ack(action: &str, payload: Payload) -> ClientResult<Value, TailchatError> {
+    let value = match payload {
+        Payload::Text(mut values) => match values.first() {
+            Some(Value::Array(args)) if values.len() == 1 => args.first().cloned(),
+            _ => (!values.is_empty()).then(|| values.swap_remove(0)),
+        },
+        _ => None,
+    };
+    let Some(value) = value else {
+        return Err(TailchatError::InvalidResponse(action.to_string(), "空的 ACK".to_string()));
+    };
+    match value.get("result").and_then(Value::as_bool) {
+        Some(true) => Ok(value.get("data").cloned().unwrap_or(Value::Null)),
+        Some(false) => {
+            let message = value.get("message").and_then(Value::as_str).unwrap_or("未知错误");
+            Err(TailchatError::RequestFailed(action.to_string(), message.to_string()))
+        }
+        None => Err(TailchatError::InvalidResponse(action.to_string(), value.to_string())),
+    }
+}
+
+/// 解析 OpenAPI HTTP 接口的响应
+///
+/// 成功时为 `{"code": 200, "data": ...}`, 失败时带 `message`
+fn parse_http_response(
+    action: &str,
+    success: bool,
+    text: &str,
+) -> ClientResult<Value, TailchatError> {
+    let value: Value = match serde_json::from_str(text) {
+        Ok(value) => value,
+        Err(_) if !success => {
+            return Err(TailchatError::RequestFailed(action.to_string(), text.to_string()));
+        }
+        Err(e) => {
+            return Err(TailchatError::InvalidResponse(action.to_string(), format!("{e}: {text}")));
+        }
+    };
+    let code_ok = value.get("code").and_then(Value::as_i64).is_none_or(|code| code == 200);
+    if success && code_ok {
+        return Ok(value.get("data").cloned().unwrap_or(Value::Null));
+    }
+    let message = value.get("message").and_then(Value::as_str).unwrap_or(text);
+    Err(TailchatError::RequestFailed(action.to_string(), message.to_string()))
+}
+
+/// 通过 OpenAPI 的 HTTP 接口调用一个 action
+///
+/// `chat.message.sendMessage` 对应 `POST {host}/api/chat/message/sendMessage`
+async fn http_request(action: &str, data: Value) -> ClientResult<Value, TailchatError> {
+    let host = MainStatus::global_config().tailchat().host;
+    let url = format!("{}/api/{}", host.trim_end_matches('/'), action.replace('.', "/"));
+    let token =
+        MainStatus::with_tailchat_status(|status| status.jwt_token.clone()).unwrap_or_default();
+    let resp = HTTP_CLIENT.post(url).header("X-Token", token).json(&data).send().await?;
+    let success = resp.status().is_success();
+    let text = resp.text().await?;
+    parse_http_response(action, success, &text)
+}
+
+/// 调用一个 Tailchat action 并等待结果, 返回 `data`。
+pub async fn request(
+    client: &Connection,
+    action: &str,
+    data: Value,
+) -> ClientResult<Value, TailchatError> {
+    match client {
+        Connection::Socket(client) => socket_request(client, action, data).await,
+        Connection::Http => http_request(action, data).await,
+    }
+}
+
+/// 通过 Socket.IO 调用一个 action 并等待 ACK。
+async fn socket_request(
+    client: &Client,
+    action: &str,
+    data: Value,
+) -> ClientResult<Value, TailchatError> {
+    let (sender, receiver) = oneshot::channel();
+    let sender = Arc::new(Mutex::new(Some(sender)));
+    client
+        .emit_with_ack(
+            action,
+            data,
+            REQUEST_TIMEOUT,
+            move |payload: Payload, _client: Client| -> BoxFuture<'static, ()> {
+                let sender = sender.clone();
+                Box::pin(async move {
+                    if let Some(sender) = sender.lock().unwrap().take() {
+                        let _ = sender.send(payload);
+                    }
+                })
+            },
+        )
+        .await?;
+    let payload = tokio::time::timeout(REQUEST_TIMEOUT, receiver)
+        .await
+        .map_err(|_| TailchatError::RequestTimeout(action.to_string()))?
+        .map_err(|_| TailchatError::RequestTimeout(action.to_string()))?;
+    parse_ack(action, payload)
+}
+
+/// 撤回一条消息, 只能撤回自己的或者有权限管理的消息。
+pub async fn recall_message(
+    client: &Connection,
+    msg_id: &MessageId,
+) -> ClientResult<(), TailchatError> {
+    request(client, "chat.message.recallMessage", json!({"messageId": msg_id})).await?;
+    Ok(())
+}
+
+/// 删除一条消息, 需要服务器的管理权限。
+pub async fn delete_message(
+    client: &Connection,
+    msg_id: &MessageId,
+) -> ClientResult<(), TailchatError> {
+    request(client, "chat.message.deleteMessage", json!({"messageId": msg_id})).await?;
+    Ok(())
+}
+
+/// 编辑一条自己发的消息。
+pub async fn edit_message(
+    client: &Connection,
+    msg_id: &MessageId,
+    content: &str,
+) -> ClientResult<(), TailchatError> {
+    request(
+        client,
+        "chat.message.editMessage",
+        json!({"messageId": msg_id, "content": content}),
+    )
+    .await?;
+    Ok(())
+}
+
+/// 给一条消息添加表情回应, `emoji` 为 `:thumbsup:` 这样的短代码。
+pub async fn add_reaction(
+    client: &Connection,
+    msg_id: &MessageId,
+    emoji: &str,
+) -> ClientResult<(), TailchatError> {
+    request(client, "chat.message.addReaction", json!({"messageId": msg_id, "emoji": emoji}))
+        .await?;
+    Ok(())
+}
+
+/// 移除自己给一条消息添加的表情回应。
+pub async fn remove_reaction(
+    client: &Connection,
+    msg_id: &MessageId,
+    emoji: &str,
+) -> ClientResult<(), TailchatError> {
+    request(
+        client,
+        "chat.message.removeReaction",
+        json!({"messageId": msg_id, "emoji": emoji}),
+    )
+    .await?;
+    Ok(())
+}
+
+/// 打开和某个用户的私信会话, 返回会话ID
+///
+/// 已经有私信时直接返回, 没有时创建一个并记到目录里
+pub async fn create_dm(
+    client: &Connection,
+    user_id: &UserId,
+) -> ClientResult<ConverseId, TailchatError> {
+    let existing = MainStatus::with_tailchat_status(|status| {
+        status.dm_with(user_id).map(|converse| converse.id.clone())
+    });
+    if let Some(converse_id) = existing.flatten() {
+        return Ok(converse_id);
+    }
+    let action = "chat.converse.createDMConverse";
+    let data = request(client, action, json!({"memberIds": [user_id]})).await?;
+    let converse: ConverseInfo = serde_json::from_value(data.clone())
+        .map_err(|e| TailchatError::InvalidResponse(action.to_string(), format!("{e}: {data}")))?;
+    let converse_id = converse.id.clone();
+    MainStatus::with_tailchat_status_mut(|status| status.upsert_converse(converse));
+    Ok(converse_id)
+}
+
+/// 私信某个用户, 需要时会先打开私信会话。
+pub async fn send_private_message(
+    client: &Connection,
+    user_id: &UserId,
+    content: &str,
+) -> ClientResult<ConverseId, TailchatError> {
+    let converse_id = create_dm(client, user_id).await?;
+    let message = SendingMessage::new_without_meta(content.to_string(), converse_id.clone(), None);
+    send_message(client, &message).await?;
+    Ok(converse_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn http_response() {
+        let ok = parse_http_response("a", true, r#"{"code": 200, "data": {"_id": "m1"}}"#);
+        assert_eq!(ok.unwrap()["_id"], "m1");
+
+        match parse_http_response("a", false, r#"{"code": 401, "message": "jwt expired"}"#) {
+            Err(TailchatError::RequestFailed(_, message)) => assert_eq!(message, "jwt expired"),
+            other => panic!("{other:?}"),
+        }
+        match parse_http_response("a", false, "Bad Gateway") {
+            Err(TailchatError::RequestFailed(_, message)) => assert_eq!(message, "Bad Gateway"),
+            other => panic!("{other:?}"),
+        }
+        assert!(matches!(
+            parse_http_response("a", true, "<html>"),
+            Err(TailchatError::InvalidResponse(..))
+        ));
+    }
+
+    #[test]
+    fn ack_result() {
+        let ok = Payload::Text(vec![json!({"result": true, "data": {"_id": "m1"}})]);
+        assert_eq!(parse_ack("a", ok).unwrap()["_id"], "m1");
+
+        let wrapped = Payload::Text(vec![json!([{"result": true}])]);
+        assert_eq!(parse_ack("a", wrapped).unwrap(), Value::Null);
+
+        let failed = Payload::Text(vec![json!({"result": false, "message": "没有权限"})]);
+        match parse_ack("a", failed) {
+            Err(TailchatError::RequestFailed(_, message)) => assert_eq!(message, "没有权限"),
+            other => panic!("{other:?}"),
+        }
+
+        assert!(matches!(
+            parse_ack("a", Payload::Text(vec![])),
+            Err(TailchatError::InvalidResponse(..))
+        ));
+    }
+}
@@ -0,0 +1,131 @@
+//! Tailchat 的服务器、会话目录和历史消息。
+//!
+//! 启动时和收到 `updateDMConverse` 时刷新当前实例加入的服务器 (包括面板和成员) 以及私信会话,
+//! 结果保存在 [`crate::status::tailchat::MainStatus`] 里。
+
+use colored::Colorize;
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+use tracing::{Level, event};
+
+use crate::MainStatus;
+use crate::data_struct::tailchat::messages::ReceiveMessage;
+use crate::data_struct::tailchat::status::{ConverseInfo, GroupInfo};
+use crate::data_struct::tailchat::{ConverseId, MessageId};
+use crate::error::{ClientResult, TailchatError};
+use crate::tailchat::client::{Connection, request};
+
+/// 逐项解析一个列表, 解析失败的项会记录日志后跳过
+///
+/// 不是列表时返回空列表
+fn parse_list<T: DeserializeOwned>(kind: &str, value: Value) -> Vec<T> {
+    let Value::Array(items) = value else {
+        event!(Level::WARN, "tailchat {} 不是列表: {}", kind, value.to_string().red());
+        return Vec::new();
+    };
+    items
+        .into_iter()
+        .filter_map(|item| match serde_json::from_value(item.clone()) {
+            Ok(item) => Some(item),
+            Err(e) => {
+                event!(Level::WARN, "tailchat {} 解析失败 {}: {}", kind, item.to_string().red(), e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// 获取加入的所有服务器。
+pub async fn fetch_groups(client: &Connection) -> ClientResult<Vec<GroupInfo>, TailchatError> {
+    let data = request(client, "group.getUserGroups", json!({})).await?;
+    Ok(parse_list("group", data))
+}
+
+/// 获取一个会话的信息。
+pub async fn fetch_converse(
+    client: &Connection,
+    converse_id: &ConverseId,
+) -> ClientResult<ConverseInfo, TailchatError> {
+    let action = "chat.converse.findConverseInfo";
+    let data = request(client, action, json!({"converseId": converse_id})).await?;
+    serde_json::from_value(data.clone())
+        .map_err(|e| TailchatError::InvalidResponse(action.to_string(), format!("{e}: {data}")))
+}
+
+/// 获取所有私信/多人会话, 单个会话获取失败时跳过。
+pub async fn fetch_converses(
+    client: &Connection,
+) -> ClientResult<Vec<ConverseInfo>, TailchatError> {
+    let data = request(client, "user.dmlist.getAllConverse", json!({})).await?;
+    let mut converses = Vec::new();
+    for converse_id in parse_list::<ConverseId>("dmlist", data) {
+        match fetch_converse(client, &converse_id).await {
+            Ok(converse) => converses.push(converse),
+            Err(e) => event!(Level::WARN, "获取会话 {} 失败: {}", converse_id, e),
+        }
+    }
+    Ok(converses)
+}
+
+/// 刷新当前实例的服务器和会话目录。
+pub async fn refresh(client: &Connection) -> ClientResult<(), TailchatError> {
+    let groups = fetch_groups(client).await?;
+    let converses = fetch_converses(client).await?;
+    event!(
+        Level::INFO,
+        "tailchat 目录已刷新: {} 个服务器, {} 个私信会话",
+        groups.len(),
+        converses.len()
+    );
+    MainStatus::with_tailchat_status_mut(|status| {
+        status.update_groups(groups);
+        status.update_converses(converses);
+    });
+    Ok(())
+}
+
+/// 获取会话的历史消息
+///
+/// 每次最多 50 条, 按时间从新到旧; `before` 为上一页最旧的消息ID, 不填时从最新的开始
+pub async fn fetch_history(
+    client: &Connection,
+    converse_id: &ConverseId,
+    before: Option<&MessageId>,
+) -> ClientResult<Vec<ReceiveMessage>, TailchatError> {
+    let mut data = json!({"converseId": converse_id});
+    if let Some(before) = before {
+        data["startId"] = json!(before);
+    }
+    let data = request(client, "chat.message.fetchConverseMessage", data).await?;
+    Ok(parse_list("history message", data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_groups() {
+        let data = json!([
+            {
+                "_id": "g1",
+                "name": "测试",
+                "owner": "u1",
+                "members": [{"userId": "u1", "roles": []}, {"userId": "bot"}],
+                "panels": [
+                    {"id": "p0", "name": "分组", "type": 1},
+                    {"id": "c1", "name": "大厅", "type": 0, "parentId": "p0"}
+                ],
+                "roles": []
+            },
+            {"name": "缺少 _id"}
+        ]);
+        let groups: Vec<GroupInfo> = parse_list("group", data);
+        assert_eq!(groups.len(), 1);
+        assert!(groups[0].has_member("bot"));
+        let text: Vec<_> = groups[0].text_panels().map(|panel| panel.id.as_str()).collect();
+        assert_eq!(text, ["c1"]);
+
+        assert!(parse_list::<GroupInfo>("group", json!({})).is_empty());
+    }
+}
@@ -7,12 +7,22 @@ use rust_socketio::asynchronous::Client;
 use rust_socketio::{Event, Payload};
 use tracing::{Level, event, info};
 
-use crate::data_struct::tailchat::messages::ReceiveMessage;
+use crate::data_struct::Platform;
+use crate::data_struct::tailchat::messages::{InboxItem, ReceiveMessage, SendingMessage};
 use crate::data_struct::tailchat::status::{BotStatus, UpdateDMConverse};
+use crate::moderation;
+use crate::permission::{self, PERMISSIONS, Role, nodes};
 use crate::py::PY_PLUGIN_STORAGE;
-use crate::py::call::tailchat_new_message_py;
-use crate::tailchat::client::{emit_join_room, send_message};
-use crate::{MainStatus, VERSION, client_id, help_msg, version_str};
+use crate::py::call::{tailchat_inbox_py, tailchat_mention_py, tailchat_new_message_py};
+use crate::tailchat::client::{Connection, emit_join_room, send_message};
+use crate::{MainStatus, VERSION, client_id, help_msg, instance, reload, version_str};
+
+/// 发送一条回复, 失败时只记日志。
+async fn send_reply(client: &Connection, reply: &SendingMessage) {
+    if let Err(e) = send_message(client, reply).await {
+        event!(Level::WARN, "tailchat 回复发送失败: {}", e);
+    }
+}
 
 /// 所有
 pub async fn any_event(event: Event, payload: Payload, _client: Client, _status: Arc<BotStatus>) {
@@ -21,12 +31,11 @@ pub async fn any_event(event: Event, payload: Payload, _client: Client, _status:
         "notify:chat.message.add",
         "notify:chat.message.delete",
         "notify:chat.converse.updateDMConverse",
+        "notify:chat.inbox.append", // 被 @ 之类的事件
         // 也许以后会用到
         "notify:chat.message.update",
         "notify:chat.message.addReaction",
         "notify:chat.message.removeReaction",
-        // 忽略的
-        "notify:chat.inbox.append", // 被 @ 之类的事件
     ];
     match &event {
         Event::Custom(event_name) => {
@@ -79,61 +88,210 @@ pub async fn on_message(payload: Payload, client: Client, _status: Arc<BotStatus
                 return;
             }
         };
-        event!(Level::INFO, "tailchat_msg {}", message.to_string().yellow());
+        handle_message(message, Connection::Socket(client)).await;
+    }
+}
 
-        if !message.is_reply() {
-            if message.content == "/bot-rs" {
-                let reply = message.reply_with(&version_str());
-                send_message(&client, &reply).await;
-            } else if message.content == "/bot-ls" {
-                let reply = message.reply_with(&format!(
-                    "shenbot-py v{}-{}\n{}",
-                    VERSION,
-                    client_id(),
-                    if MainStatus::global_config().check_py() {
-                        let storage = PY_PLUGIN_STORAGE.lock().await;
-                        storage.display_plugins(false)
-                    } else {
-                        "未启用 Python 插件".to_string()
-                    }
+/// 处理收到的一条消息, Socket.IO 和回调模式共用。
+pub async fn handle_message(message: ReceiveMessage, client: Connection) {
+    if !moderation::check_message(
+        Platform::Tailchat,
+        &message.sender_id,
+        &message.converse_id,
+        &message.content,
+    )
+    .is_pass()
+    {
+        return;
+    }
+    event!(Level::INFO, "tailchat_msg {}", message.to_string().yellow());
+
+    let role = permission::role_of(
+        Platform::Tailchat,
+        &message.sender_id,
+        Some(&message.converse_id),
+        None,
+    );
+    if !message.is_reply() && role != Role::Banned {
+        if message.content == "/bot-rs" {
+            let reply = message.reply_with(&version_str());
+            send_reply(&client, &reply).await;
+        } else if message.content == "/bot-ls" {
+            let reply = message.reply_with(&format!(
+                "shenbot-py v{}-{}\n{}",
+                VERSION,
+                client_id(),
+                if MainStatus::global_config().check_py() {
+                    let storage = PY_PLUGIN_STORAGE.lock().await;
+                    storage.display_plugins(false)
+                } else {
+                    "未启用 Python 插件".to_string()
+                }
+            ));
+            send_reply(&client, &reply).await;
+        } else if message.content == "/bot-permission" {
+            let reply = message.reply_with(&format!("您的权限: {role}"));
+            send_reply(&client, &reply).await;
+        } else if message.content == "/bot-help" {
+            let reply = message.reply_with(&help_msg());
+            send_reply(&client, &reply).await;
+        }
+        if role >= Role::Moderator {
+            // 管理区
+            let client_id = client_id();
+            let allows = |node: &str| PERMISSIONS.read().unwrap().role_allows(role, node);
+            if message.content.starts_with(&format!("/bot-enable-{client_id}"))
+                && allows(nodes::PLUGIN_MANAGE)
+            {
+                if let Some((_, args)) = message.content.split_once(" ") {
+                    let mut storage = PY_PLUGIN_STORAGE.lock().await;
+                    let reply = message.reply_with(&storage.handle_status_command(
+                        args,
+                        Some(true),
+                        Platform::Tailchat,
+                        &message.converse_id,
+                    ));
+                    send_reply(&client, &reply).await;
+                }
+            } else if message.content.starts_with(&format!("/bot-disable-{client_id}"))
+                && allows(nodes::PLUGIN_MANAGE)
+            {
+                if let Some((_, args)) = message.content.split_once(" ") {
+                    let mut storage = PY_PLUGIN_STORAGE.lock().await;
+                    let reply = message.reply_with(&storage.handle_status_command(
+                        args,
+                        Some(false),
+                        Platform::Tailchat,
+                        &message.converse_id,
+                    ));
+                    send_reply(&client, &reply).await;
+                }
+            } else if message.content.starts_with(&format!("/bot-inherit-{client_id}"))
+                && allows(nodes::PLUGIN_MANAGE)
+            {
+                if let Some((_, args)) = message.content.split_once(" ") {
+                    let mut storage = PY_PLUGIN_STORAGE.lock().await;
+                    let reply = message.reply_with(&storage.handle_status_command(
+                        args,
+                        None,
+                        Platform::Tailchat,
+                        &message.converse_id,
+                    ));
+                    send_reply(&client, &reply).await;
+                }
+            } else if (message.content.starts_with(&format!("/bot-mute-{client_id}"))
+                || message.content.starts_with(&format!("/bot-unmute-{client_id}")))
+                && allows(nodes::MODERATION)
+            {
+                if let Some((command, args)) = message.content.split_once(" ") {
+                    let mute = command.starts_with("/bot-mute");
+                    let reply = message.reply_with(&moderation::handle_mute_command(
+                        args,
+                        mute,
+                        Platform::Tailchat,
+                    ));
+                    send_reply(&client, &reply).await;
+                }
+            } else if message.content.starts_with(&format!("/bot-filter-{client_id}"))
+                && allows(nodes::MODERATION)
+            {
+                let args = message.content.split_once(" ").map_or("", |(_, args)| args);
+                let reply = message.reply_with(&moderation::handle_filter_command(
+                    args,
+                    Platform::Tailchat,
+                    &message.converse_id,
                 ));
-                send_message(&client, &reply).await;
-            } else if message.content == "/bot-help" {
-                let reply = message.reply_with(&help_msg());
-                send_message(&client, &reply).await;
-            }
-            if MainStatus::global_config().tailchat().admin_list.contains(&message.sender_id) {
-                // admin 区
-                let client_id = client_id();
-                let mut storage = PY_PLUGIN_STORAGE.lock().await;
-                if message.content.starts_with(&format!("/bot-enable-{client_id}")) {
-                    if let Some((_, name)) = message.content.split_once(" ") {
-                        let reply = match storage.get_status(name) {
-                            None => message.reply_with("未找到插件"),
-                            Some(true) => message.reply_with("无变化, 插件已经启用"),
-                            Some(false) => match storage.set_status(name, true) {
-                                Ok(_) => message.reply_with("启用插件完成"),
-                                Err(e) => message.reply_with(&format!("启用插件失败, 错误: \n{e}")),
-                            },
-                        };
-                        send_message(&client, &reply).await;
-                    }
-                } else if message.content.starts_with(&format!("/bot-disable-{client_id}"))
-                    && let Some((_, name)) = message.content.split_once(" ")
+                send_reply(&client, &reply).await;
+            } else if message.content == format!("/bot-jobs-{client_id}") && allows(nodes::JOBS) {
+                let reply = message.reply_with(&crate::py::scheduler::display_jobs());
+                send_reply(&client, &reply).await;
+            } else if message.content == format!("/bot-config-reload-{client_id}")
+                && allows(nodes::CONFIG_RELOAD)
+            {
+                // 重载时可能会重连这个实例, 不在它的事件回调里等
+                let client = client.clone();
+                let message = message.clone();
+                let id = MainStatus::global_config().tailchat_id();
+                tokio::spawn(instance::scope(id, async move {
+                    let summary = reload::reload("管理员命令").await;
+                    send_reply(&client, &message.reply_with(&summary)).await;
+                }));
+            } else if cfg!(feature = "ica")
+                && (message.content == "/bot-ica-login"
+                    || message.content.starts_with("/bot-ica-login "))
+                && allows(nodes::LOGIN)
+            {
+                #[cfg(feature = "ica")]
                 {
-                    let reply = match storage.get_status(name) {
-                        None => message.reply_with("未找到插件"),
-                        Some(false) => message.reply_with("无变化, 插件已经禁用"),
-                        Some(true) => match storage.set_status(name, false) {
-                            Ok(_) => message.reply_with("禁用插件完成"),
-                            Err(e) => message.reply_with(&format!("禁用插件失败, 错误: \n{e}")),
-                        },
-                    };
-                    send_message(&client, &reply).await;
+                    let args = message.content.trim_start_matches("/bot-ica-login");
+                    let reply = message.reply_with(&crate::ica::login::handle_command(args).await);
+                    send_reply(&client, &reply).await;
+                }
+            } else if message.content.starts_with(&format!("/bot-grant-{client_id}"))
+                && allows(nodes::PERMISSION_GRANT)
+            {
+                if let Some((_, args)) = message.content.split_once(" ") {
+                    let reply = message.reply_with(&permission::handle_grant_command(
+                        args,
+                        true,
+                        Platform::Tailchat,
+                        &message.sender_id,
+                        None,
+                        &message.converse_id,
+                    ));
+                    send_reply(&client, &reply).await;
                 }
+            } else if message.content.starts_with(&format!("/bot-revoke-{client_id}"))
+                && allows(nodes::PERMISSION_GRANT)
+                && let Some((_, args)) = message.content.split_once(" ")
+            {
+                let reply = message.reply_with(&permission::handle_grant_command(
+                    args,
+                    false,
+                    Platform::Tailchat,
+                    &message.sender_id,
+                    None,
+                    &message.converse_id,
+                ));
+                send_reply(&client, &reply).await;
+            }
+        }
+    }
+    if role == Role::Banned {
+        return;
+    }
+    tailchat_new_message_py(&message, &client).await;
+    if message.is_mentioned() && !message.is_from_self() {
+        tailchat_mention_py(&message, &client).await;
+    }
+}
+
+/// 处理 `notify:chat.inbox.append` 事件, 被 @ 时会收到。
+pub async fn on_inbox_append(payload: Payload, client: Client) {
+    if let Payload::Text(values) = payload
+        && let Some(value) = values.first()
+    {
+        let item: InboxItem = match serde_json::from_value(value.clone()) {
+            Ok(v) => v,
+            Err(e) => {
+                event!(Level::WARN, "tailchat inbox {}", value.to_string().red());
+                event!(Level::WARN, "tailchat inbox {}", format!("{e:?}").red());
+                return;
             }
+        };
+        if let Some(message) = &item.message
+            && !moderation::check_message(
+                Platform::Tailchat,
+                &message.author,
+                &message.converse_id,
+                message.content(),
+            )
+            .is_pass()
+        {
+            return;
         }
-        tailchat_new_message_py(&message, &client).await;
+        event!(Level::INFO, "tailchat_inbox {}", item.to_string().yellow());
+        tailchat_inbox_py(&item, &Connection::Socket(client)).await;
     }
 }
 /// 处理 `msg_delete` 事件。
@@ -160,5 +318,7 @@ pub async fn on_converse_update(payload: Payload, client: Client) {
             }
         };
         info!("更新会话 {}", format!("{update_info:?}").cyan());
+        // 在事件回调里等不到 ACK, 直接用推送的数据更新目录
+        MainStatus::with_tailchat_status_mut(|status| status.upsert_converse(update_info.into()));
     }
 }